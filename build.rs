@@ -0,0 +1,31 @@
+use std::env;
+use std::process::Command;
+
+/*
+ ビルドしたときの情報を環境変数としてsrc/version.rsに渡す。
+ ZIPPER_GIT_HASH:  ビルドしたコミット（gitが無い場合はunknown）
+ ZIPPER_FEATURES:  有効にしたfeature（defaultと、optionalな依存クレートの名前は除く）
+ ZIPPER_TARGET:    ビルドしたターゲット
+ */
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|name| name.to_lowercase().replace('_', "-")))
+        .filter(|name| !["default", "chrono", "encoding-rs", "miniz-oxide", "serde-json"].contains(&name.as_str()))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=ZIPPER_GIT_HASH={}", hash);
+    println!("cargo:rustc-env=ZIPPER_FEATURES={}", features.join(","));
+    println!("cargo:rustc-env=ZIPPER_TARGET={}", env::var("TARGET").unwrap_or_default());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+}
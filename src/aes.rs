@@ -0,0 +1,495 @@
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::time::SystemTime;
+
+use crate::extra::parse_extra_fields;
+use crate::ZipError;
+
+pub(crate) const AES_EXTRA_ID: u16 = 0x9901;                // WinZipのAES暗号の拡張フィールドの番号
+const AES_EXTRA_LEN: u16 = 7;                    // AESの拡張フィールドのデータの大きさ
+const WRITE_VERSION: u16 = 2;                    // 書き込むときのバージョン（AE-2。crc32を書かない）
+const WRITE_STRENGTH: u8 = 3;                    // 書き込むときの鍵の長さ（256bit）
+const PBKDF2_ITERATIONS: u32 = 1000;             // 鍵を作るときのPBKDF2の繰り返し回数
+pub(crate) const VERIFIER_SIZE: usize = 2;       // データの前に付くパスワードを確かめる値の大きさ
+pub(crate) const AUTH_CODE_SIZE: usize = 10;     // データの後ろに付く認証コード（HMAC-SHA1の先頭10byte）の大きさ
+const BLOCK_SIZE: usize = 16;                    // AESのブロックの大きさ
+const SHA1_BLOCK_SIZE: usize = 64;               // SHA-1で1度に処理する大きさ
+
+/*
+ AESの拡張フィールド（0x9901）に書かれている情報
+ version:   1（AE-1）か2（AE-2）。AE-2ではcrc32を書かず、認証コードだけで確かめる
+ strength:  鍵の長さ（1: 128bit, 2: 192bit, 3: 256bit）
+ method:    暗号化する前の実際の圧縮方法
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AesInfo {
+    pub(crate) version: u16,
+    pub(crate) strength: u8,
+    pub(crate) method: u16,
+}
+
+impl AesInfo {
+    /*
+     拡張フィールドからAESの情報を読み込む。AESの拡張フィールドがない場合はNoneを返す。
+     */
+    pub(crate) fn from_extra(extra: &[u8], name: &str) -> Result<Option<AesInfo>, ZipError> {
+        let field = match parse_extra_fields(extra)?.into_iter().find(|field| field.id == AES_EXTRA_ID) {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        let info = match field.data.as_slice() {
+            [v0, v1, b'A', b'E', strength, m0, m1] => AesInfo {
+                version: u16::from_le_bytes([*v0, *v1]),
+                strength: *strength,
+                method: u16::from_le_bytes([*m0, *m1]),
+            },
+            _ => return Err(ZipError::InvalidArchive(format!("invalid AES extra field of {}", name))),
+        };
+        if !(1..=2).contains(&info.version) || !(1..=3).contains(&info.strength) {
+            return Err(ZipError::Unsupported(format!("AES version {} strength {} of {}", info.version, info.strength, name)));
+        }
+        Ok(Some(info))
+    }
+
+    fn key_len(&self) -> usize {
+        8 + 8 * self.strength as usize
+    }
+
+    /*
+     データの前に付くソルトの大きさ（鍵の長さの半分）
+     */
+    pub(crate) fn salt_len(&self) -> usize {
+        self.key_len() / 2
+    }
+
+    /*
+     暗号化したことで増える大きさ（ソルト、パスワードを確かめる値、認証コード）
+     */
+    pub(crate) fn overhead(&self) -> u64 {
+        (self.salt_len() + VERIFIER_SIZE + AUTH_CODE_SIZE) as u64
+    }
+}
+
+/*
+ WinZipのAES暗号で暗号化されたデータを復号するためのもの
+ 鍵はパスワードとソルトからPBKDF2-HMAC-SHA1で作り、カウンターは1から始まるリトルエンディアンの128bitの数にする。
+ cipher:     AESの暗号化の鍵（CTRモードのため、復号でも暗号化を使う）
+ counter:    次に暗号化するカウンターの値
+ keystream:  カウンターを暗号化したもの
+ used:       keystreamのうち使い終わったバイト数
+ mac:        暗号化されたデータのHMAC-SHA1
+ */
+pub(crate) struct AesStream {
+    cipher: Aes,
+    counter: u128,
+    keystream: [u8; BLOCK_SIZE],
+    used: usize,
+    mac: Hmac,
+}
+
+impl AesStream {
+    /*
+     passwordとsaltから鍵を作り、verifierと合うかを確かめる。合わない場合はWrongPasswordにする。
+     */
+    pub(crate) fn new(password: &[u8], info: &AesInfo, salt: &[u8], verifier: [u8; VERIFIER_SIZE], name: &str) -> Result<Self, ZipError> {
+        let (stream, derived_verifier) = AesStream::derive(password, info, salt);
+        if derived_verifier != verifier {
+            return Err(ZipError::WrongPassword(name.to_string()));
+        }
+        Ok(stream)
+    }
+
+    /*
+     passwordとsaltから鍵を作り、パスワードを確かめる値と一緒に返す
+     */
+    fn derive(password: &[u8], info: &AesInfo, salt: &[u8]) -> (Self, [u8; VERIFIER_SIZE]) {
+        let key_len = info.key_len();
+        let mut derived = vec![0; 2 * key_len + VERIFIER_SIZE];
+        pbkdf2(password, salt, PBKDF2_ITERATIONS, &mut derived);
+        let (keys, derived_verifier) = derived.split_at(2 * key_len);
+        let (key, auth_key) = keys.split_at(key_len);
+        let mut verifier = [0; VERIFIER_SIZE];
+        verifier.copy_from_slice(derived_verifier);
+        let stream = AesStream {
+            cipher: Aes::new(key),
+            counter: 0,
+            keystream: [0; BLOCK_SIZE],
+            used: BLOCK_SIZE,
+            mac: Hmac::new(auth_key),
+        };
+        (stream, verifier)
+    }
+
+    /*
+     暗号化されたデータをそのまま復号する
+     */
+    pub(crate) fn decrypt(&mut self, buf: &mut [u8]) {
+        self.mac.update(buf);
+        self.apply_keystream(buf);
+    }
+
+    /*
+     データをそのまま暗号化する（認証コードは暗号化した後のデータから作る）
+     */
+    fn encrypt(&mut self, buf: &mut [u8]) {
+        self.apply_keystream(buf);
+        self.mac.update(buf);
+    }
+
+    /*
+     CTRモードのため、暗号化も復号もカウンターを暗号化したものとのxorにする
+     */
+    fn apply_keystream(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            if self.used == BLOCK_SIZE {
+                self.counter = self.counter.wrapping_add(1);
+                self.keystream = self.cipher.encrypt(self.counter.to_le_bytes());
+                self.used = 0;
+            }
+            *byte ^= self.keystream.get(self.used).copied().unwrap_or_default();
+            self.used += 1;
+        }
+    }
+
+    /*
+     これまでのデータの認証コード
+     */
+    fn auth_code(&self) -> [u8; AUTH_CODE_SIZE] {
+        let mut code = [0; AUTH_CODE_SIZE];
+        code.copy_from_slice(&self.mac.clone().finish()[..AUTH_CODE_SIZE]);
+        code
+    }
+
+    /*
+     これまでに復号したデータの認証コードがcodeと合うかを確かめる。合わない場合はAuthenticationFailedにする。
+     */
+    pub(crate) fn verify(&self, code: &[u8; AUTH_CODE_SIZE], name: &str) -> Result<(), ZipError> {
+        if self.auth_code() != *code {
+            return Err(ZipError::AuthenticationFailed(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/*
+ WinZipのAES-256（AE-2）でdataを暗号化する。
+ ソルト、パスワードを確かめる値、暗号化したデータ、認証コードを並べたものと、
+ ヘッダーに付けるAESの拡張フィールド（methodは暗号化する前の圧縮方法）を返す。
+ */
+pub(crate) fn encrypt(password: &[u8], method: u16, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let info = AesInfo { version: WRITE_VERSION, strength: WRITE_STRENGTH, method };
+    let salt = random_bytes(info.salt_len());
+    let (mut stream, verifier) = AesStream::derive(password, &info, &salt);
+    let mut encrypted = Vec::with_capacity(data.len() + info.overhead() as usize);
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend_from_slice(&verifier);
+    let start = encrypted.len();
+    encrypted.extend_from_slice(data);
+    if let Some(body) = encrypted.get_mut(start..) {
+        stream.encrypt(body);
+    }
+    encrypted.extend_from_slice(&stream.auth_code());
+
+    let mut field = Vec::with_capacity(4 + AES_EXTRA_LEN as usize);
+    field.extend_from_slice(&AES_EXTRA_ID.to_le_bytes());
+    field.extend_from_slice(&AES_EXTRA_LEN.to_le_bytes());
+    field.extend_from_slice(&info.version.to_le_bytes());
+    field.extend_from_slice(b"AE");
+    field.push(info.strength);
+    field.extend_from_slice(&method.to_le_bytes());
+    (encrypted, field)
+}
+
+/*
+ ソルトに使うlenバイトの乱数。OSの乱数（/dev/urandom）を使い、読めない場合は
+ プロセスごとにランダムな鍵を持つRandomStateのハッシュに、時刻と何バイト目かを入れて作る。
+ ソルトは同じパスワードで同じ鍵にならないためのもので、秘密である必要はない。
+ */
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    if File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut bytes)).is_ok() {
+        return bytes;
+    }
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+    let state = RandomState::new();
+    for (index, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_u128(now);
+        hasher.write_usize(index);
+        let value = hasher.finish().to_le_bytes();
+        chunk.copy_from_slice(&value[..chunk.len()]);
+    }
+    bytes
+}
+
+/*
+ AESの置換表（GF(2^8)での逆数にアフィン変換をしたもの）
+ */
+const SBOX: [u8; 256] = sbox();
+
+const fn sbox() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut p: u8 = 1;
+    let mut q: u8 = 1;
+
+    /*
+     pに3を掛け、qを3で割っていくと、qはpの逆数になる
+     */
+    loop {
+        p = p ^ (p << 1) ^ if p & 0x80 != 0 { 0x1b } else { 0 };
+        q ^= q << 1;
+        q ^= q << 2;
+        q ^= q << 4;
+        if q & 0x80 != 0 {
+            q ^= 0x09;
+        }
+        table[p as usize] = q ^ q.rotate_left(1) ^ q.rotate_left(2) ^ q.rotate_left(3) ^ q.rotate_left(4) ^ 0x63;
+        if p == 1 {
+            break;
+        }
+    }
+    table[0] = 0x63;
+    table
+}
+
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn sub_byte(byte: u8) -> u8 {
+    SBOX[byte as usize]
+}
+
+/*
+ AES（128、192、256bit）の暗号化だけを行うもの
+ round_keys:  ラウンドごとの鍵（ラウンドの数+1個）
+ */
+#[derive(Clone)]
+struct Aes {
+    round_keys: Vec<[u8; BLOCK_SIZE]>,
+}
+
+impl Aes {
+    fn new(key: &[u8]) -> Self {
+        let key_words = key.len() / 4;
+        let rounds = key_words + 6;
+        let mut words: Vec<[u8; 4]> = key.chunks_exact(4).map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]]).collect();
+        let mut rcon = 1u8;
+        for i in key_words..4 * (rounds + 1) {
+            let mut word = words[i - 1];
+            if i % key_words == 0 {
+                word = [sub_byte(word[1]) ^ rcon, sub_byte(word[2]), sub_byte(word[3]), sub_byte(word[0])];
+                rcon = xtime(rcon);
+            } else if key_words > 6 && i % key_words == 4 {
+                word = word.map(sub_byte);
+            }
+            let previous = words[i - key_words];
+            words.push([word[0] ^ previous[0], word[1] ^ previous[1], word[2] ^ previous[2], word[3] ^ previous[3]]);
+        }
+        let round_keys = words.chunks_exact(4).map(|chunk| {
+            let mut round_key = [0u8; BLOCK_SIZE];
+            for (i, word) in chunk.iter().enumerate() {
+                round_key[4 * i..4 * i + 4].copy_from_slice(word);
+            }
+            round_key
+        }).collect();
+        Aes { round_keys }
+    }
+
+    fn encrypt(&self, mut block: [u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        let last = self.round_keys.len() - 1;
+        add_round_key(&mut block, &self.round_keys[0]);
+        for (round, round_key) in self.round_keys.iter().enumerate().skip(1) {
+            block = block.map(sub_byte);
+            shift_rows(&mut block);
+            if round != last {
+                mix_columns(&mut block);
+            }
+            add_round_key(&mut block, round_key);
+        }
+        block
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK_SIZE], round_key: &[u8; BLOCK_SIZE]) {
+    for (byte, key) in block.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+/*
+ ブロックは列ごとに4byteずつ並んでいるため、i行目の要素はi, i+4, i+8, i+12になる
+ */
+fn shift_rows(block: &mut [u8; BLOCK_SIZE]) {
+    let state = *block;
+    for column in 0..4 {
+        for row in 0..4 {
+            block[4 * column + row] = state[4 * ((column + row) % 4) + row];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; BLOCK_SIZE]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+/*
+ SHA-1のハッシュを少しずつ計算するためのもの
+ state:     今までのブロックを処理した後の状態
+ buffer:    まだ1ブロックに満たない入力
+ buffered:  bufferに入っているバイト数
+ len:       今までに入力したバイト数
+ */
+#[derive(Clone)]
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    buffer: [u8; SHA1_BLOCK_SIZE],
+    buffered: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    pub(crate) fn new() -> Self {
+        Sha1 {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0],
+            buffer: [0; SHA1_BLOCK_SIZE],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            if self.buffered == 0 && data.len() >= SHA1_BLOCK_SIZE {
+                let (block, rest) = data.split_at(SHA1_BLOCK_SIZE);
+                self.compress(block);
+                data = rest;
+                continue;
+            }
+            let take = data.len().min(SHA1_BLOCK_SIZE - self.buffered);
+            let (head, rest) = data.split_at(take);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(head);
+            self.buffered += take;
+            data = rest;
+            if self.buffered == SHA1_BLOCK_SIZE {
+                let block = self.buffer;
+                self.compress(&block);
+                self.buffered = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.buffered != SHA1_BLOCK_SIZE - 8 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+        let mut digest = [0u8; 20];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(&self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, bytes) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/*
+ HMAC-SHA1
+ inner:  鍵とipadを入力した後に、メッセージを入力していくもの
+ outer:  鍵とopadを入力したもの（最後にinnerの結果を入力する）
+ */
+#[derive(Clone)]
+struct Hmac {
+    inner: Sha1,
+    outer: Sha1,
+}
+
+impl Hmac {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; SHA1_BLOCK_SIZE];
+        if key.len() > SHA1_BLOCK_SIZE {
+            let mut hash = Sha1::new();
+            hash.update(key);
+            block[..20].copy_from_slice(&hash.finish());
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha1::new();
+        inner.update(&block.map(|byte| byte ^ 0x36));
+        let mut outer = Sha1::new();
+        outer.update(&block.map(|byte| byte ^ 0x5c));
+        Hmac { inner, outer }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    fn finish(self) -> [u8; 20] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+/*
+ PBKDF2-HMAC-SHA1でpasswordとsaltからoutの長さの鍵を作る
+ */
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    let hmac = Hmac::new(password);
+    for (index, chunk) in out.chunks_mut(20).enumerate() {
+        let mut mac = hmac.clone();
+        mac.update(salt);
+        mac.update(&(index as u32 + 1).to_be_bytes());
+        let mut u = mac.finish();
+        let mut block = u;
+        for _ in 1..iterations {
+            let mut mac = hmac.clone();
+            mac.update(&u);
+            u = mac.finish();
+            for (byte, value) in block.iter_mut().zip(&u) {
+                *byte ^= value;
+            }
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
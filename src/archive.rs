@@ -0,0 +1,1279 @@
+use std::collections::{BTreeSet, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{self, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Take};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::unix_time;
+use crate::decode::entry_path;
+use crate::error::{Context, Phase};
+use crate::extra::{extended_mtime, parse_extra_fields};
+use crate::glob::NameIndex;
+use crate::inflate::Inflater;
+use crate::names::decode_name;
+use crate::volumes::Volumes;
+use crate::zipcrypto::{encryption, password_for, Decrypter, Encryption, FLAG_ENCRYPTED};
+use crate::{Clock, Crc32, DecodeOptions, EntryReport, NameDecoder, Permissions, Report, Stats, SystemClock, ZipError, METHOD_DEFLATED, METHOD_STORED};
+
+/*
+ ローカルヘッダーの先頭の印（PK0304）、セントラルヘッダーの先頭の印（PK0102）、
+ エンドセントラルヘッダーの先頭の印（PK0506）、データディスクリプタの先頭の印（PK0708、付いていない場合もある）、
+ ZIP64のエンドセントラルヘッダーの先頭の印（PK0606）とその位置を示すロケーターの先頭の印（PK0607）
+ */
+pub(crate) const LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+pub(crate) const CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+pub(crate) const END_SIGNATURE: u32 = 0x0605_4b50;
+pub(crate) const DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+
+/*
+ それぞれのヘッダーの固定長の部分の大きさ
+ */
+pub(crate) const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_HEADER_SIZE: usize = 22;
+const ZIP64_END_HEADER_SIZE: usize = 56;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+
+/*
+ ZIP64の拡張情報の拡張フィールドの番号と、値がそこに書かれていることを示す値
+ */
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const ZIP64_MARK: u64 = 0xffff_ffff;
+
+/*
+ エンドセントラルヘッダーの後ろに付けられるコメントの最大の長さ
+ */
+const MAX_COMMENT_LEN: usize = 0xffff;
+
+/*
+ read_to_vecで、ヘッダーに書かれたサイズを信じて先に確保する最大の大きさ（これより大きい場合は読みながら広げる）
+ */
+const MAX_PREALLOCATE: u64 = 64 * 1024 * 1024;
+
+/*
+ zipの形式の日時（ローカル時刻）を分けたもの
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DosDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DosDateTime {
+    /*
+     ヘッダーに書かれた（時刻, 日付）から作る
+     */
+    pub fn from_dos(time: u16, date: u16) -> Self {
+        DosDateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0f) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /*
+     ヘッダーに書く（時刻, 日付）に戻す（from_dosの逆）
+     */
+    pub fn to_dos(&self) -> (u16, u16) {
+        let time = (self.hour as u16) << 11 | (self.minute as u16) << 5 | (self.second / 2) as u16;
+        let date = (self.year.saturating_sub(1980)) << 9 | (self.month as u16) << 5 | self.day as u16;
+        (time, date)
+    }
+
+    /*
+     clockのタイムゾーンのローカル時刻として、UNIX時間にする。
+     月や日が0になっているなど、日時として正しくない場合はNoneを返す。
+     */
+    pub fn to_unix(&self, clock: &dyn Clock) -> Option<i64> {
+        if !(1..=12).contains(&self.month) || !(1..=31).contains(&self.day) || self.hour > 23 || self.minute > 59 || self.second > 59 {
+            return None;
+        }
+        let local = unix_time(self.year as i64, self.month as u32, self.day as u32, self.hour as u32, self.minute as u32, self.second as u32);
+
+        /*
+         オフセットはUNIX時間から求めるため、ローカル時刻をUTCとみなして1度ずらした時刻でもう1度求める（夏時間の切り替わりのため）
+         */
+        let guess = local - clock.offset(local);
+        Some(local - clock.offset(guess))
+    }
+}
+
+/*
+ セントラルヘッダーに書かれた1つのエントリの情報
+ name:             エントリの名前
+ method:           圧縮方法（0: 無圧縮, 8: deflate, 99: AESで暗号化されている）
+ flags:            汎用フラグ
+ crc32:            展開した後のデータのcrc32
+ compressed_size:  圧縮した後のサイズ
+ size:             展開した後のサイズ
+ modified:         最終更新日時
+ permissions:      作成したOSと外部属性から求めた属性
+ extra:            セントラルヘッダーの拡張フィールド
+ offset:           対応するローカルヘッダーの位置
+ comment:          セントラルヘッダーに書かれたエントリのコメント（名前と同じ文字コードで読む。ない場合は空）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    pub modified: DosDateTime,
+    pub permissions: Permissions,
+    pub extra: Vec<u8>,
+    pub offset: u64,
+    pub comment: String,
+}
+
+impl ZipEntry {
+    /*
+     ディレクトリのエントリかどうか
+     */
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /*
+     暗号化されたエントリかどうか（汎用フラグのbit0）
+     */
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    /*
+     圧縮方法の名前（listで表示するもの）
+     */
+    pub fn method_name(&self) -> String {
+        match self.method {
+            METHOD_STORED => "Stored".to_string(),
+            METHOD_DEFLATED => "Defl".to_string(),
+            method => method_label(method).map_or_else(|| format!("M{}", method), str::to_string),
+        }
+    }
+
+    /*
+     最終更新日時。拡張タイムスタンプ（0x5455）があればそれを、なければDOSの日時をOSのタイムゾーンのローカル時刻として使う。
+     */
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        modified_time(&self.modified, &self.extra)
+    }
+}
+
+/*
+ このクレートでは展開できないが、よく使われる圧縮方法の名前（知らない番号の場合はNone）
+ */
+pub(crate) fn method_label(method: u16) -> Option<&'static str> {
+    match method {
+        1 => Some("Shrink"),
+        6 => Some("Implode"),
+        9 => Some("Deflate64"),
+        12 => Some("BZip2"),
+        14 => Some("LZMA"),
+        93 => Some("Zstd"),
+        95 => Some("XZ"),
+        96 => Some("JPEG"),
+        97 => Some("WavPack"),
+        98 => Some("PPMd"),
+        99 => Some("AES"),
+        _ => None,
+    }
+}
+
+/*
+ ZipArchive::verifyで見つかった壊れたエントリ
+ name:   エントリの名前
+ error:  展開したときに起きたエラー（CrcMismatchやInvalidDeflateなど）
+ */
+#[derive(Debug)]
+pub struct Damaged {
+    pub name: String,
+    pub error: ZipError,
+}
+
+/*
+ 既にあるzipを読み込むためのもの
+ openではエンドセントラルヘッダーとセントラルヘッダーだけを読み、圧縮されたデータには触れない。
+ from_sliceで開いた場合はメモリ上のデータを借りて読むため、'dはそのデータを借りている間になる（openで開いた場合は何でもよい）。
+ file:     読み込んでいるzip（エントリのデータを読み出すときに使う）
+ entries:  セントラルヘッダーに書かれた順のエントリ
+ names:    エントリの名前からentriesの位置を引くための表（同じ名前が複数ある場合は最初のもの）
+ index:    パターンに当てはまるエントリを探すための、名前を並べ替えた索引（最初にパターンで探すときに1度だけ作る）
+ password: 暗号化されたエントリを開くときに使うパスワード（set_passwordで指定する）
+ comment:  エンドセントラルヘッダーに書かれたzip全体のコメント
+ */
+#[derive(Debug)]
+pub struct ZipArchive<'d> {
+    file: Backing<'d>,
+    entries: Vec<ZipEntry>,
+    names: HashMap<String, usize>,
+    index: OnceLock<NameIndex>,
+    password: Option<Vec<u8>>,
+    comment: String,
+}
+
+impl<'d> ZipArchive<'d> {
+    /*
+     pathのzipを開く。UTF-8のフラグが立っていない名前はCP437として読む。
+     分割されたzip（archive.z01, archive.z02, …, archive.zip）は、最後の.zipのパスを渡すと全てをつなげて読む。
+     */
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ZipError> {
+        ZipArchive::open_with(path, &DecodeOptions::new())
+    }
+
+    /*
+     optionsを使ってpathのzipを開く。UTF-8のフラグが立っていない名前はoptions.name_decoderで読み、
+     options.passwordがある場合はset_passwordと同じようにそれを使う。
+     */
+    pub fn open_with(path: impl AsRef<Path>, options: &DecodeOptions) -> Result<Self, ZipError> {
+        let path = path.as_ref().to_path_buf();
+        let path_name = path.display().to_string();
+        let mut file = Volumes::open(&path)?;
+        let len = file.seek(SeekFrom::End(0)).context(&path_name, Phase::Read)?;
+        let tail_len = len.min((END_HEADER_SIZE + MAX_COMMENT_LEN) as u64);
+        let mut tail = vec![0; tail_len as usize];
+        file.seek(SeekFrom::Start(len - tail_len))
+            .and_then(|_| file.read_exact(&mut tail))
+            .context_at(&path_name, Phase::Read, len - tail_len)?;
+        let end = find_end(&tail).ok_or_else(|| ZipError::InvalidArchive("end of central directory record is missing".to_string()))?;
+        let directory = central_directory(&tail, end, len - tail_len + end as u64, len, |pos, buf| {
+            file.seek(SeekFrom::Start(pos))
+                .and_then(|_| file.read_exact(buf))
+                .context_at(&path_name, Phase::Read, pos)
+        })?;
+
+        if directory.disk as usize + 1 != file.count() {
+            return Err(ZipError::InvalidArchive(format!(
+                "{} is volume {} of a split archive but {} volumes were found", path_name, directory.disk + 1, file.count()
+            )));
+        }
+
+        let mut central = vec![0; directory.size];
+        file.seek(SeekFrom::Start(directory.start))
+            .and_then(|_| file.read_exact(&mut central))
+            .context_at(&path_name, Phase::Read, directory.start)?;
+        let entries = parse_central(&central, directory.count, directory.base, Some(&file), &*options.name_decoder)?;
+        let comment = options.name_decoder.decode(end_comment(&tail, end));
+        Ok(ZipArchive::with_entries(Backing::Files { volumes: file, path }, entries, comment, options))
+    }
+
+    /*
+     メモリ上のzipのデータを、コピーせずに借りて開く（埋め込んだファイルや、ダウンロードしたデータを読む場合に使う）。
+     エントリを読み出すときもdataから直接読み、無圧縮のエントリはstored_sliceでdataの一部としてそのまま取り出せる。
+     UTF-8のフラグが立っていない名前はCP437として読む。分割されたzipには対応していない。
+     */
+    pub fn from_slice(data: &'d [u8]) -> Result<Self, ZipError> {
+        ZipArchive::from_slice_with(data, &DecodeOptions::new())
+    }
+
+    /*
+     optionsを使ってメモリ上のzipを開く（optionsの使い方はopen_withと同じ）
+     */
+    pub fn from_slice_with(data: &'d [u8], options: &DecodeOptions) -> Result<Self, ZipError> {
+        let len = data.len() as u64;
+        let tail_start = data.len().saturating_sub(END_HEADER_SIZE + MAX_COMMENT_LEN);
+        let tail = data.get(tail_start..).unwrap_or_default();
+        let end = find_end(tail).ok_or_else(|| ZipError::InvalidArchive("end of central directory record is missing".to_string()))?;
+        let read_at = |pos: u64, buf: &mut [u8]| -> Result<(), ZipError> {
+            let bytes = usize::try_from(pos).ok()
+                .and_then(|start| data.get(start..start.checked_add(buf.len())?))
+                .ok_or_else(|| ZipError::InvalidArchive(format!("truncated record at {}", pos)))?;
+            buf.copy_from_slice(bytes);
+            Ok(())
+        };
+        let directory = central_directory(tail, end, (tail_start + end) as u64, len, read_at)?;
+        if directory.disk > 0 {
+            return Err(ZipError::Unsupported("reading a split archive from memory".to_string()));
+        }
+        let mut central = vec![0; directory.size];
+        read_at(directory.start, &mut central)?;
+        let entries = parse_central(&central, directory.count, directory.base, None, &*options.name_decoder)?;
+        let comment = options.name_decoder.decode(end_comment(tail, end));
+        Ok(ZipArchive::with_entries(Backing::Memory(data), entries, comment, options))
+    }
+
+    /*
+     セントラルヘッダーから読んだentriesで作る（名前の表を作り、options.passwordを使うようにする）
+     */
+    fn with_entries(file: Backing<'d>, entries: Vec<ZipEntry>, comment: String, options: &DecodeOptions) -> Self {
+        let mut names = HashMap::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            names.entry(entry.name.clone()).or_insert(index);
+        }
+        let password = options.password.as_ref().map(|password| password.as_bytes().to_vec());
+        ZipArchive { file, entries, names, index: OnceLock::new(), password, comment }
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /*
+     zip全体のコメント（ない場合は空）。フラグがないため、UTF-8のフラグが立っていない名前と同じ文字コードで読む。
+     */
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /*
+     エントリの情報をセントラルヘッダーの順に返すイテレーター（for entry in &archive とも書ける）
+     データは読まないため、filterやpartitionで選んでから、位置や名前でopen_entryなどに渡す。
+     */
+    pub fn iter(&self) -> slice::Iter<'_, ZipEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /*
+     ZipCrypto（PKWAREの従来の暗号）で暗号化されたエントリを開くときに使うパスワードを指定する
+     パスワードがないまま暗号化されたエントリを開くとPasswordRequired、パスワードが違うとWrongPasswordになる。
+     */
+    pub fn set_password(&mut self, password: &str) {
+        self.password = Some(password.as_bytes().to_vec());
+    }
+
+    /*
+     セントラルヘッダーに書かれたサイズから、エントリを拡張子ごとと一番上のディレクトリごとに集計する
+     */
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        for entry in &self.entries {
+            stats.add(&entry.name, entry.size, entry.compressed_size);
+        }
+        stats
+    }
+
+    /*
+     nameという名前のエントリを探す
+     openで作った表を引くため、エントリの数によらず一定の時間で見つかる。
+     */
+    pub fn by_name(&self, name: &str) -> Option<&ZipEntry> {
+        self.index_of(name).and_then(|index| self.entries.get(index))
+    }
+
+    /*
+     index番目（セントラルヘッダーの順）のエントリを返す
+     */
+    pub fn by_index(&self, index: usize) -> Option<&ZipEntry> {
+        self.entries.get(index)
+    }
+
+    /*
+     nameという名前のエントリが何番目かを返す（open_entryに渡すのに使う）
+     */
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /*
+     index番目のエントリの展開したデータをReadとして読み出せるようにする。
+     データは読み込んだ分だけ展開するため、大きなエントリでも全体をメモリに置かない。
+     最後まで読み込んだときにcrc32とサイズを確かめ、食い違う場合はエラーを返す。
+     暗号化されたエントリはset_passwordで指定したパスワードで復号する。
+     */
+    pub fn open_entry(&mut self, index: usize) -> Result<EntryReader<'_>, ZipError> {
+        let password = self.password.clone();
+        self.open_entry_with(index, password.as_deref())
+    }
+
+    /*
+     open_entryで、暗号化されたエントリの復号に使うパスワードを指定できるようにしたもの
+     */
+    fn open_entry_with(&mut self, index: usize, password: Option<&[u8]>) -> Result<EntryReader<'_>, ZipError> {
+        let entry = self.entry_at(index)?.clone();
+        open_entry_in(self.file.borrow(), entry, password)
+    }
+
+    /*
+     open_entryと同じだが、zipを開き直してZipArchiveを借りないEntryReaderを返す。
+     FUSEのように、開いたままの複数のエントリを行き来しながら読む場合に使う。
+     */
+    pub fn open_entry_detached(&self, index: usize) -> Result<EntryReader<'d>, ZipError> {
+        let entry = self.entry_at(index)?.clone();
+        open_entry_in(self.file.reopen()?, entry, self.password.as_deref())
+    }
+
+    /*
+     全てのエントリをdestの中に展開する。
+     書き込む前に全てのエントリの名前を調べ、destの外を指すもの（..や絶対パス、ドライブ文字）があればUnsafePathにする。
+     */
+    pub fn extract_all(&mut self, dest: impl AsRef<Path>) -> Result<Report, ZipError> {
+        self.extract_all_with(dest, &DecodeOptions::default())
+    }
+
+    /*
+     extract_allにオプションを指定できるようにしたもの。
+     options.allow_unsafe_pathsの場合は名前を調べない。
+     options.passwordを指定した場合は、set_passwordで指定したものの代わりにそれを使う。
+     options.jobsが2以上の場合は、その数のスレッドで複数のエントリを同時に展開する。
+     */
+    pub fn extract_all_with(&mut self, dest: impl AsRef<Path>, options: &DecodeOptions) -> Result<Report, ZipError> {
+        self.extract_where(dest.as_ref(), options, 0..self.entries.len())
+    }
+
+    /*
+     名前がpatternsのどれかに当てはまるエントリだけをdestの中に展開する。
+     パターンでは * と ** と ? が使える（*.json や、docs/ の後に ** を続けたものなど）。/ を含まないパターンはファイル名だけと比べる。
+     */
+    pub fn extract_matching<S: AsRef<str>>(&mut self, dest: impl AsRef<Path>, patterns: &[S]) -> Result<Report, ZipError> {
+        self.extract_matching_with(dest, patterns, &DecodeOptions::default())
+    }
+
+    /*
+     extract_matchingにオプションを指定できるようにしたもの（オプションの使い方はextract_all_withと同じ）
+     */
+    pub fn extract_matching_with<S: AsRef<str>>(&mut self, dest: impl AsRef<Path>, patterns: &[S], options: &DecodeOptions) -> Result<Report, ZipError> {
+        let selected: BTreeSet<usize> = patterns.iter().flat_map(|pattern| self.indices_matching(pattern.as_ref())).collect();
+        self.extract_where(dest.as_ref(), options, selected)
+    }
+
+    /*
+     名前がpatternに当てはまるエントリの位置を、セントラルヘッダーの順に返す（パターンの書き方はextract_matchingと同じ）。
+     名前を並べ替えた索引を使い、パターンの最初の * や ? より前の部分から始まる名前だけを比べるため、
+     エントリが多いzipでも全てのエントリを調べずに済む（ただし、*.json のように * から始まるパターンでは全てを調べる）。
+     */
+    pub fn indices_matching(&self, pattern: &str) -> Vec<usize> {
+        let name = |index: usize| self.entries.get(index).map_or("", |entry| entry.name.as_str());
+        let mut indices = self.index.get_or_init(|| NameIndex::new(self.entries.len(), name)).matching(pattern, name);
+        indices.sort_unstable();
+        indices
+    }
+
+    /*
+     selectedの位置のエントリだけを展開する。暗号化されたエントリのパスワードを先に全て確かめてから、
+     ディレクトリを作り、ファイルを位置の順に展開する。
+     options.jobsが2以上の場合は、ファイルをその数のスレッドで分けて展開する。
+     */
+    pub(crate) fn extract_where<I: IntoIterator<Item = usize>>(&mut self, dest: &Path, options: &DecodeOptions, selected: I) -> Result<Report, ZipError> {
+        let selected = selected.into_iter()
+            .map(|index| {
+                let entry = self.entry_at(index)?;
+                Ok((entry.clone(), entry_path(dest, &entry.name, options.allow_unsafe_paths)?))
+            })
+            .collect::<Result<Vec<_>, ZipError>>()?;
+        let password = options.password.as_ref().map(|password| password.as_bytes().to_vec()).or_else(|| self.password.clone());
+        let (dirs, files): (Vec<_>, Vec<_>) = selected.into_iter().partition(|(entry, _)| entry.is_dir());
+
+        /*
+         パスワードが違うエントリがあれば、何も書き込まないうちにエラーにする（開くときに暗号化ヘッダーなどを確かめる）
+         */
+        for (entry, _) in files.iter().filter(|(entry, _)| entry.is_encrypted()) {
+            open_entry_in(self.file.borrow(), entry.clone(), password.as_deref()).context_at(&entry.name, Phase::Extract, entry.offset)?;
+        }
+        for (entry, path) in &dirs {
+            create_dir_all(path).context_at(&entry.name, Phase::Extract, entry.offset)?;
+        }
+        if options.jobs > 1 && files.len() > 1 {
+            self.extract_parallel(&files, password.as_deref(), options.jobs, options.special_bits)?;
+        } else {
+            for (entry, path) in &files {
+                extract_file(self.file.borrow(), entry, path, password.as_deref(), options.special_bits).context_at(&entry.name, Phase::Extract, entry.offset)?;
+            }
+        }
+
+        let mut report = Report::new();
+        for (entry, _) in files {
+            report.entries.push(EntryReport {
+                name: entry.name,
+                before_size: entry.size,
+                after_size: entry.compressed_size,
+                crc32: entry.crc32,
+                profile: None,
+                changed: false,
+                retries: 0,
+                cut_at: None,
+                cached: false,
+            });
+        }
+
+        /*
+         ディレクトリの日時は中に展開すると変わり、読み取り専用にすると中に展開できなくなるため、全て展開した後に設定する
+         */
+        for (entry, path) in dirs.iter().rev() {
+            restore_dir(path, Some(entry.permissions), entry.modified_time(), options.special_bits).context(&entry.name, Phase::Extract)?;
+        }
+        Ok(report)
+    }
+
+    /*
+     全てのエントリを展開して捨て、crc32とサイズがセントラルヘッダーと合っているかを確かめる。
+     ファイルには何も書き込まない。壊れていたエントリを、見つかった順に返す（空なら壊れたエントリはない）。
+     */
+    pub fn verify(&mut self) -> Vec<Damaged> {
+        let mut damaged = Vec::new();
+        for index in 0..self.entries.len() {
+            let name = match self.entries.get(index) {
+                Some(entry) if !entry.is_dir() => entry.name.clone(),
+                _ => continue,
+            };
+            let result = self.open_entry(index)
+                .and_then(|mut reader| io::copy(&mut reader, &mut io::sink()).map_err(into_zip_error));
+            if let Err(error) = result {
+                damaged.push(Damaged { name, error });
+            }
+        }
+        damaged
+    }
+
+    /*
+     filesをjobs個のスレッドで展開する。スレッドはそれぞれzipを開き直し（メモリ上のzipは同じデータを読み）、次のエントリを取り合って展開する。
+     エントリは圧縮されたデータが別々のため、どの順に展開してもよい。
+     途中で失敗した場合には、それより後のエントリは新しく展開し始めず、失敗したうちで最も前のエントリのエラーを返す。
+     */
+    fn extract_parallel(&self, files: &[(ZipEntry, PathBuf)], password: Option<&[u8]>, jobs: usize, special_bits: bool) -> Result<(), ZipError> {
+        let next = AtomicUsize::new(0);
+        let first_failed = AtomicUsize::new(usize::MAX);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs.min(files.len()))
+                .map(|_| {
+                    let (next, first_failed) = (&next, &first_failed);
+                    scope.spawn(move || {
+                        let mut file = self.file.reopen().map_err(|err| (0, err))?;
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index > first_failed.load(Ordering::Relaxed) {
+                                return Ok(());
+                            }
+                            let (entry, path) = match files.get(index) {
+                                Some(file) => file,
+                                None => return Ok(()),
+                            };
+                            if let Err(err) = extract_file(file.reborrow(), entry, path, password, special_bits).context_at(&entry.name, Phase::Extract, entry.offset) {
+                                first_failed.fetch_min(index, Ordering::Relaxed);
+                                return Err((index, err));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let mut failed: Option<(usize, ZipError)> = None;
+            for worker in workers {
+                match worker.join() {
+                    Ok(Ok(())) => {}
+                    Ok(Err((index, err))) => {
+                        if failed.as_ref().is_none_or(|(first, _)| index < *first) {
+                            failed = Some((index, err));
+                        }
+                    }
+                    Err(panic) => panic::resume_unwind(panic),
+                }
+            }
+            failed.map_or(Ok(()), |(_, err)| Err(err))
+        })
+    }
+
+    /*
+     index番目のエントリを返す。ない場合はエラーにする。
+     */
+    fn entry_at(&self, index: usize) -> Result<&ZipEntry, ZipError> {
+        self.entries.get(index).ok_or_else(|| ZipError::InvalidArchive(format!("no entry at index {}", index)))
+    }
+
+    /*
+     nameという名前のエントリをopen_entryで開く
+     */
+    pub fn open_by_name(&mut self, name: &str) -> Result<EntryReader<'_>, ZipError> {
+        let index = self.index_of(name)
+            .ok_or_else(|| ZipError::InvalidArchive(format!("no entry named {}", name)))?;
+        self.open_entry(index)
+    }
+
+    /*
+     index番目のエントリのデータを、展開も復号もせずにそのまま読み出せるようにする。
+     プロキシや作り直すツールが、deflateし直さずにエントリを別のzipへ写すのに使う。
+     圧縮方法やcrc32、サイズなどのヘッダーの情報はRawReader::entryで、ローカルヘッダーの拡張フィールドはlocal_extraで取り出せる。
+     セントラルヘッダーに書かれた圧縮後のサイズだけを読み、crc32は確かめない（データディスクリプタは含まない）。
+     */
+    pub fn open_raw(&mut self, index: usize) -> Result<RawReader<'_>, ZipError> {
+        let entry = self.entry_at(index)?.clone();
+        let mut file = self.file.borrow();
+        let (local, data_start) = seek_to_data(&mut file, &entry)?;
+        let mut local_extra = vec![0; read16(&local, 28) as usize];
+        let extra_start = data_start - local_extra.len() as u64;
+        file.seek(SeekFrom::Start(extra_start))
+            .and_then(|_| file.read_exact(&mut local_extra))
+            .context_at(&entry.name, Phase::Read, extra_start)?;
+        let data = BufReader::new(file).take(entry.compressed_size);
+        Ok(RawReader { data, local_extra, entry })
+    }
+
+    /*
+     open_rawで読み出せるデータを全てメモリ上に返す。ファイルが途中で終わっている場合はエラーにする。
+     */
+    pub fn read_raw(&mut self, index: usize) -> Result<Vec<u8>, ZipError> {
+        let mut reader = self.open_raw(index)?;
+        let size = reader.entry().compressed_size;
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATE) as usize);
+        reader.read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(ZipError::InvalidArchive(format!("compressed data of {} is truncated", reader.entry().name)));
+        }
+        Ok(data)
+    }
+
+    /*
+     from_sliceで開いたzipの、index番目のエントリのデータを、コピーせずにdataの一部として返す。
+     無圧縮で暗号化されていないエントリだけが対象で、それ以外のエントリやファイルから開いたzipではNoneを返す。
+     展開しないため、crc32は確かめない（確かめる場合は先にverifyを使う）。
+     */
+    pub fn stored_slice(&self, index: usize) -> Result<Option<&'d [u8]>, ZipError> {
+        let entry = self.entry_at(index)?;
+        let data = match self.file {
+            Backing::Memory(data) if entry.method == METHOD_STORED && !entry.is_encrypted() => data,
+            _ => return Ok(None),
+        };
+        let (_, data_start) = seek_to_data(&mut Cursor::new(data), entry)?;
+        let slice = usize::try_from(data_start).ok()
+            .zip(usize::try_from(entry.compressed_size).ok())
+            .and_then(|(start, len)| data.get(start..start.checked_add(len)?))
+            .ok_or_else(|| ZipError::InvalidArchive(format!("data of {} is truncated", entry.name)))?;
+        Ok(Some(slice))
+    }
+
+    /*
+     nameという名前のエントリを全て展開して、メモリ上に返す（設定ファイルなど、ファイルに書き出す必要がないものを読む場合に使う）。
+     セントラルヘッダーに書かれたサイズより多くは読まず、それを超えるデータが続く場合はエラーにする。
+     crc32の確かめ方や復号はopen_entryと同じ。
+     */
+    pub fn read_to_vec(&mut self, name: &str) -> Result<Vec<u8>, ZipError> {
+        let mut reader = self.open_by_name(name)?;
+        let size = reader.entry().size;
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATE) as usize);
+        reader.by_ref().take(size).read_to_end(&mut data).map_err(into_zip_error)?;
+
+        /*
+         サイズの分を読んだ後にもう1度読み、終わりであることとcrc32を確かめる
+         */
+        if reader.read(&mut [0u8; 1]).map_err(into_zip_error)? != 0 {
+            return Err(ZipError::InvalidArchive(format!("{} is larger than its recorded size", name)));
+        }
+        Ok(data)
+    }
+}
+
+/*
+ fileの中のentryのデータをReadとして読み出せるようにする（ZipArchive::open_entryを参照）
+ */
+fn open_entry_in<'a>(mut file: EntryFile<'a>, entry: ZipEntry, password: Option<&[u8]>) -> Result<EntryReader<'a>, ZipError> {
+    let saved_password = password.map(<[u8]>::to_vec);
+    let password = password_for(entry.flags, password, &entry.name)?;
+    let (local, data_start) = seek_to_data(&mut file, &entry)?;
+    let data = BufReader::new(file).take(entry.compressed_size);
+    let encryption = encryption(entry.flags, entry.method, entry.crc32, read16(&local, 10), &entry.extra, &entry.name)?;
+    let data = match (password, &encryption) {
+        (Some(password), Some(encryption)) => Decrypter::new(data, password, encryption, entry.compressed_size, &entry.name)
+            .context_at(&entry.name, Phase::Read, data_start)?,
+        _ => Decrypter::plain(data),
+    };
+    let source = match encryption.map_or(entry.method, |encryption| encryption.method(entry.method)) {
+        METHOD_STORED => Source::Stored(data),
+        METHOD_DEFLATED => Source::Deflated(Inflater::new(data)),
+        method => return Err(ZipError::UnsupportedMethod { name: entry.name.clone(), method }),
+    };
+    Ok(EntryReader { source, crc: Crc32::new(), read: 0, encryption, password: saved_password, entry })
+}
+
+/*
+ entryのローカルヘッダーの固定長の部分を読み、データの先頭にシークする。固定長の部分とデータの位置を返す。
+ */
+fn seek_to_data<F: Read + Seek>(file: &mut F, entry: &ZipEntry) -> Result<([u8; LOCAL_HEADER_SIZE], u64), ZipError> {
+    let mut local = [0u8; LOCAL_HEADER_SIZE];
+    file.seek(SeekFrom::Start(entry.offset))
+        .and_then(|_| file.read_exact(&mut local))
+        .context_at(&entry.name, Phase::Read, entry.offset)?;
+    if read32(&local, 0) != LOCAL_SIGNATURE {
+        return Err(ZipError::InvalidArchive(format!("invalid local header of {}", entry.name)));
+    }
+    let data_start = entry.offset + LOCAL_HEADER_SIZE as u64 + read16(&local, 26) as u64 + read16(&local, 28) as u64;
+    file.seek(SeekFrom::Start(data_start)).context_at(&entry.name, Phase::Read, data_start)?;
+    Ok((local, data_start))
+}
+
+/*
+ fileの中のentry（ディレクトリ以外）をpathに展開し、最終更新日時と属性を設定する
+ special_bitsがtrueの場合だけ、属性の特別なビット（setuid、setgid、スティッキー）も設定する。
+ */
+fn extract_file(file: EntryFile<'_>, entry: &ZipEntry, path: &Path, password: Option<&[u8]>, special_bits: bool) -> Result<(), ZipError> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut reader = open_entry_in(file, entry.clone(), password)?;
+
+    /*
+     最後にcrc32や認証コードが合わなかった場合は、確かめられなかったデータを残さないようにファイルを削除する
+     */
+    if let Err(err) = io::copy(&mut reader, &mut File::create(path)?) {
+        let _ = remove_file(path);
+        return Err(into_zip_error(err));
+    }
+    if let Some(modified) = entry.modified_time() {
+        set_modified(path, modified)?;
+    }
+    entry.permissions.restore(path, special_bits)?;
+    Ok(())
+}
+
+impl<'a> IntoIterator for &'a ZipArchive<'_> {
+    type Item = &'a ZipEntry;
+    type IntoIter = slice::Iter<'a, ZipEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/*
+ ZipArchiveが読むzipのデータ
+ Files:   ファイルから読む（分割されたzipでは全てのファイルをつなげたもの）。
+          pathは並列に展開するときなどに、スレッドごとに開き直すために使う
+ Memory:  from_sliceで渡されたメモリ上のデータを借りて読む
+ */
+#[derive(Debug)]
+enum Backing<'d> {
+    Files { volumes: Volumes, path: PathBuf },
+    Memory(&'d [u8]),
+}
+
+impl<'d> Backing<'d> {
+    /*
+     ZipArchiveが開いているものを借りて読む
+     */
+    fn borrow(&mut self) -> EntryFile<'_> {
+        match self {
+            Backing::Files { volumes, .. } => EntryFile::Borrowed(volumes),
+            Backing::Memory(data) => EntryFile::Memory(Cursor::new(*data)),
+        }
+    }
+
+    /*
+     ZipArchiveを借りずに読めるように、ファイルを開き直す（メモリ上のデータはそのまま借りる）
+     */
+    fn reopen(&self) -> Result<EntryFile<'d>, ZipError> {
+        match self {
+            Backing::Files { path, .. } => Ok(EntryFile::Owned(Volumes::open(path)?)),
+            Backing::Memory(data) => Ok(EntryFile::Memory(Cursor::new(*data))),
+        }
+    }
+}
+
+/*
+ EntryReaderが読むzipのファイル
+ Borrowed:  ZipArchiveが開いているものを借りる（open_entry）
+ Owned:     開き直したものを持つ（open_entry_detached）
+ Memory:    from_sliceで開いたメモリ上のデータを読む
+ */
+enum EntryFile<'a> {
+    Borrowed(&'a mut Volumes),
+    Owned(Volumes),
+    Memory(Cursor<&'a [u8]>),
+}
+
+impl EntryFile<'_> {
+    /*
+     同じものを読む、短い間だけ借りたEntryFileを作る（スレッドで続けて展開するときに使う）
+     */
+    fn reborrow(&mut self) -> EntryFile<'_> {
+        match self {
+            EntryFile::Borrowed(file) => EntryFile::Borrowed(file),
+            EntryFile::Owned(file) => EntryFile::Borrowed(file),
+            EntryFile::Memory(data) => EntryFile::Memory(Cursor::new(*data.get_ref())),
+        }
+    }
+}
+
+impl Read for EntryFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EntryFile::Borrowed(file) => file.read(buf),
+            EntryFile::Owned(file) => file.read(buf),
+            EntryFile::Memory(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for EntryFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            EntryFile::Borrowed(file) => file.seek(pos),
+            EntryFile::Owned(file) => file.seek(pos),
+            EntryFile::Memory(data) => data.seek(pos),
+        }
+    }
+}
+
+/*
+ エントリのデータの読み出し元
+ Stored:    無圧縮のデータをそのまま読み出す
+ Deflated:  deflateで圧縮されたデータを少しずつ展開する
+ Closed:    先頭から読み直すのに失敗して、読み出せなくなったもの
+ */
+enum Source<'a> {
+    Stored(Decrypter<Take<BufReader<EntryFile<'a>>>>),
+    Deflated(Inflater<Decrypter<Take<BufReader<EntryFile<'a>>>>>),
+    Closed,
+}
+
+impl<'a> Source<'a> {
+    /*
+     読み込んでいるzipのファイルを取り出す
+     */
+    fn into_file(self) -> Option<EntryFile<'a>> {
+        match self {
+            Source::Stored(data) => Some(data.into_inner().into_inner().into_inner()),
+            Source::Deflated(inflater) => Some(inflater.into_inner().into_inner().into_inner().into_inner()),
+            Source::Closed => None,
+        }
+    }
+}
+
+/*
+ 1つのエントリの展開したデータを読み出すためのもの（ZipArchive::open_entryで作る）
+ source:  データの読み出し元
+ crc:     今までに読み出したデータのcrc32
+ read:    今までに読み出したバイト数
+ encryption:  暗号化の方法（AE-2ではcrc32の代わりに認証コードで確かめ、ZipCryptoで壊れたデータになった場合はWrongPasswordにする）
+ password:    開いたときのパスワード（先頭から読み直すときに使う）
+ entry:   読み出しているエントリ
+ */
+pub struct EntryReader<'a> {
+    source: Source<'a>,
+    crc: Crc32,
+    read: u64,
+    encryption: Option<Encryption>,
+    password: Option<Vec<u8>>,
+    entry: ZipEntry,
+}
+
+impl EntryReader<'_> {
+    pub fn entry(&self) -> &ZipEntry {
+        &self.entry
+    }
+
+    /*
+     zipのファイルをデータの先頭に戻し、展開や復号を最初からやり直す
+     */
+    fn rewind(&mut self) -> Result<(), ZipError> {
+        let file = std::mem::replace(&mut self.source, Source::Closed).into_file()
+            .ok_or_else(|| ZipError::InvalidArchive(format!("reader of {} is closed", self.entry.name)))?;
+        let password = self.password.take();
+        *self = open_entry_in(file, self.entry.clone(), password.as_deref())?;
+        Ok(())
+    }
+
+    fn read_checked(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &mut self.source {
+            Source::Stored(data) => data.read(buf)?,
+            Source::Deflated(inflater) => inflater.read(buf)?,
+            Source::Closed => return Err(io::Error::other(format!("reader of {} is closed", self.entry.name))),
+        };
+        for &byte in buf.iter().take(len) {
+            self.crc.push_buf(byte);
+        }
+        self.read += len as u64;
+
+        /*
+         最後まで読み込んだら、crc32とサイズがセントラルヘッダーと合っているかを確かめる
+         */
+        if len == 0 && !buf.is_empty() {
+            /*
+             deflateの終わりの後に残っているデータを読み切って、AESの認証コードを確かめる
+             */
+            if let Source::Deflated(inflater) = &mut self.source {
+                io::copy(inflater.get_mut(), &mut io::sink())?;
+            }
+            let actual = self.crc.clone().get_crc32();
+            let check_crc = self.encryption.is_none_or(|encryption| encryption.checks_crc());
+            if (check_crc && actual != self.entry.crc32) || self.read != self.entry.size {
+                let err = ZipError::CrcMismatch { name: self.entry.name.clone(), expected: self.entry.crc32, actual };
+                return Err(io::Error::new(ErrorKind::InvalidData, err));
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_checked(buf).map_err(|err| {
+            let inner = err.get_ref().and_then(|inner| inner.downcast_ref::<ZipError>());
+            match (self.encryption, inner) {
+                (Some(encryption), Some(inner)) if encryption.blames_password(inner) => {
+                    io::Error::new(ErrorKind::InvalidData, ZipError::WrongPassword(self.entry.name.clone()))
+                }
+                _ => err,
+            }
+        })
+    }
+}
+
+/*
+ 展開したデータの中の位置に移る。後ろへ移る場合はその分を展開して読み飛ばし、
+ 前へ戻る場合はデータの先頭から展開し直す（deflateは途中から展開できないため）。
+ エントリの終わりより後ろを指定した場合は、終わりに移る。
+ */
+impl Seek for EntryReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.read.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.entry.size.checked_add_signed(offset),
+        };
+        let target = target.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        if target < self.read {
+            self.rewind().map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        }
+        let target = target.min(self.entry.size);
+        let skip = target - self.read;
+        io::copy(&mut self.by_ref().take(skip), &mut io::sink())?;
+        if self.read < target {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(target)
+    }
+}
+
+/*
+ 1つのエントリの圧縮されたままのデータを読み出すためのもの（ZipArchive::open_rawで作る）
+ data:         圧縮された（暗号化されている場合は暗号化された）ままのデータ
+ local_extra:  ローカルヘッダーの拡張フィールド（セントラルヘッダーのものと違う場合がある）
+ entry:        セントラルヘッダーに書かれたエントリの情報
+ */
+pub struct RawReader<'a> {
+    data: Take<BufReader<EntryFile<'a>>>,
+    local_extra: Vec<u8>,
+    entry: ZipEntry,
+}
+
+impl RawReader<'_> {
+    pub fn entry(&self) -> &ZipEntry {
+        &self.entry
+    }
+
+    pub fn local_extra(&self) -> &[u8] {
+        &self.local_extra
+    }
+}
+
+impl Read for RawReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+/*
+ EntryReaderやDecrypterから返されたio::Errorを、中に入っているZipErrorに戻す
+ */
+pub(crate) fn into_zip_error(err: io::Error) -> ZipError {
+    let kind = err.kind();
+    if err.get_ref().is_none() {
+        return ZipError::Io(err);
+    }
+    match err.into_inner().map(|inner| inner.downcast::<ZipError>()) {
+        Some(Ok(inner)) => *inner,
+        Some(Err(inner)) => ZipError::Io(io::Error::new(kind, inner)),
+        None => ZipError::Io(kind.into()),
+    }
+}
+
+/*
+ エントリの最終更新日時を求める（ZipEntry::modified_timeを参照）。UNIX時間が表せない場合はNoneを返す。
+ */
+pub(crate) fn modified_time(modified: &DosDateTime, extra: &[u8]) -> Option<SystemTime> {
+    let times = extended_mtime(extra).or_else(|| modified.to_unix(&SystemClock))?;
+    match u64::try_from(times) {
+        Ok(times) => SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(times)),
+        Err(_) => SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(times.unsigned_abs())),
+    }
+}
+
+/*
+ pathのファイルやディレクトリの最終更新日時を設定する。
+ Unix以外ではディレクトリを開けないため、ディレクトリの場合は何もしない。
+ */
+pub(crate) fn set_modified(path: &Path, modified: SystemTime) -> Result<(), io::Error> {
+    let file = match (path.is_dir(), cfg!(unix)) {
+        (false, _) => File::options().write(true).open(path)?,
+        (true, true) => File::open(path)?,
+        (true, false) => return Ok(()),
+    };
+    file.set_modified(modified)
+}
+
+/*
+ 展開したディレクトリに最終更新日時と属性を設定する（special_bitsはextract_fileと同じ）
+ */
+pub(crate) fn restore_dir(path: &Path, permissions: Option<Permissions>, modified: Option<SystemTime>, special_bits: bool) -> Result<(), io::Error> {
+    if let Some(modified) = modified {
+        set_modified(path, modified)?;
+    }
+    match permissions {
+        Some(permissions) => permissions.restore(path, special_bits),
+        None => Ok(()),
+    }
+}
+
+/*
+ dataの中からエンドセントラルヘッダーを後ろから探して、その位置を返す
+ */
+pub(crate) fn find_end(data: &[u8]) -> Option<usize> {
+    if data.len() < END_HEADER_SIZE {
+        return None;
+    }
+    (0..=data.len() - END_HEADER_SIZE).rev().find(|&pos| read32(data, pos) == END_SIGNATURE)
+}
+
+/*
+ tailのendの位置にあるエンドセントラルヘッダーの後ろのコメント。ファイルの終わりで切れている場合はそこまでにする。
+ */
+pub(crate) fn end_comment(tail: &[u8], end: usize) -> &[u8] {
+    let comment = tail.get(end + END_HEADER_SIZE..).unwrap_or_default();
+    let comment_len = read16(tail, end + 20) as usize;
+    comment.get(..comment_len).unwrap_or(comment)
+}
+
+/*
+ セントラルヘッダーの場所
+ count:  エントリの数
+ size:   セントラルヘッダー全体の大きさ
+ start:  最初のセントラルヘッダーの位置（ファイルの先頭から）
+ base:   zipの前に付けられたデータ（自己解凍形式のプログラムなど）の大きさ
+         zipに書かれた位置がzipの先頭からの位置になっている場合は、ファイルの先頭からの位置にするためにこれを足す
+ zip64:  ZIP64のエンドセントラルヘッダーから読んだかどうか
+ disk:   エンドセントラルヘッダーのあるディスクの番号（分割されたzipでは、最後のファイルの番号で、0から数える）
+ */
+pub(crate) struct CentralDirectory {
+    pub count: usize,
+    pub size: usize,
+    pub start: u64,
+    pub base: u64,
+    pub zip64: bool,
+    pub disk: u32,
+}
+
+/*
+ tailのendの位置にあるエンドセントラルヘッダーから、セントラルヘッダーの場所を読む。
+ end_posはエンドセントラルヘッダーのファイルの先頭からの位置、lenはファイル全体の長さ。
+ エンドセントラルヘッダーの直前にZIP64のロケーターがある場合は、それが示すZIP64のエンドセントラルヘッダーを
+ read_at(位置, 読み込む先)で読み、そちらに書かれた64bitの値を使う。
+ 自己解凍形式のように前にデータが付けられ、書かれた位置がzipの先頭からになっている場合は、
+ セントラルヘッダーが実際に終わる位置（エンドセントラルヘッダーの位置）とのずれをbaseにする。
+ */
+pub(crate) fn central_directory<F>(tail: &[u8], end: usize, end_pos: u64, len: u64, mut read_at: F) -> Result<CentralDirectory, ZipError>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<(), ZipError>,
+{
+    let locator = end.checked_sub(ZIP64_LOCATOR_SIZE).filter(|&locator| read32(tail, locator) == ZIP64_LOCATOR_SIGNATURE);
+    let (count, size, start, central_end, disk, zip64) = match locator {
+        Some(locator) => {
+            let record_start = read64(tail, locator + 8);
+
+            /*
+             前にデータが付けられている場合は書かれた位置にないため、ロケーターの直前を探す
+             */
+            let moved = (end_pos - ZIP64_LOCATOR_SIZE as u64).checked_sub(ZIP64_END_HEADER_SIZE as u64).filter(|&moved| moved != record_start);
+            let mut record = [0u8; ZIP64_END_HEADER_SIZE];
+            let mut found = None;
+            for record_pos in Some(record_start).into_iter().chain(moved) {
+                if record_pos.checked_add(ZIP64_END_HEADER_SIZE as u64).is_some_and(|record_end| record_end <= len) {
+                    read_at(record_pos, &mut record)?;
+                    if read32(&record, 0) == ZIP64_END_SIGNATURE {
+                        found = Some(record_pos);
+                        break;
+                    }
+                }
+            }
+            let record_pos = found.ok_or_else(|| ZipError::InvalidArchive(format!("invalid zip64 end of central directory record at {}", record_start)))?;
+            (read64(&record, 32), read64(&record, 40), read64(&record, 48), record_pos, read32(&record, 16), true)
+        }
+        None => (read16(tail, end + 10) as u64, read32(tail, end + 12) as u64, read32(tail, end + 16) as u64, end_pos, read16(tail, end + 4) as u32, false),
+    };
+    let mut base = central_end.saturating_sub(start.saturating_add(size));
+
+    /*
+     セントラルヘッダーとエンドセントラルヘッダーの間に別のデータがあるだけの場合もあるため、
+     ずらした位置にセントラルヘッダーがある場合だけbaseを使う
+     */
+    if base > 0 && count > 0 {
+        let mut signature = [0u8; 4];
+        let shifted = start.saturating_add(base);
+        if shifted.saturating_add(4) > len || read_at(shifted, &mut signature).is_err() || read32(&signature, 0) != CENTRAL_SIGNATURE {
+            base = 0;
+        }
+    }
+    let start = start.saturating_add(base);
+    if start.checked_add(size).is_none_or(|central_end| central_end > len) {
+        return Err(ZipError::InvalidArchive(format!("central directory at {} runs past the end of the file", start)));
+    }
+    let too_large = || ZipError::InvalidArchive(format!("central directory at {} is too large to read", start));
+    Ok(CentralDirectory {
+        count: usize::try_from(count).map_err(|_| too_large())?,
+        size: usize::try_from(size).map_err(|_| too_large())?,
+        start,
+        base,
+        zip64,
+        disk,
+    })
+}
+
+/*
+ count個のセントラルヘッダーを並べたものを読み込む
+ 名前とコメントはUTF-8のフラグが立っている場合はUTF-8として、立っていない場合はdecoderで読み込む。
+ サイズや位置が0xffffffffになっている場合は、ZIP64の拡張情報から読む。
+ ローカルヘッダーの位置にはbase（CentralDirectory.base）を足し、ファイルの先頭からの位置にする。
+ 分割されたzipのvolumesを渡した場合は、代わりにエントリのあるディスク（ファイル）の始まる位置を足す。
+ */
+pub(crate) fn parse_central(central: &[u8], count: usize, base: u64, volumes: Option<&Volumes>, decoder: &dyn NameDecoder) -> Result<Vec<ZipEntry>, ZipError> {
+    /*
+     countはファイルに書かれた値のため、セントラルヘッダーに入りきる数までしか先に確保しない
+     */
+    let mut entries = Vec::with_capacity(count.min(central.len() / CENTRAL_HEADER_SIZE));
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + CENTRAL_HEADER_SIZE > central.len() || read32(central, pos) != CENTRAL_SIGNATURE {
+            return Err(ZipError::InvalidArchive(format!("invalid central header at {}", pos)));
+        }
+        let name_len = read16(central, pos + 28) as usize;
+        let extra_len = read16(central, pos + 30) as usize;
+        let comment_len = read16(central, pos + 32) as usize;
+        let name_start = pos + CENTRAL_HEADER_SIZE;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        if next > central.len() {
+            return Err(ZipError::InvalidArchive(format!("truncated central header at {}", pos)));
+        }
+        let name = decode_name(central.get(name_start..extra_start).unwrap_or_default(), read16(central, pos + 8), decoder);
+        let extra = central.get(extra_start..extra_start + extra_len).unwrap_or_default().to_vec();
+        let comment = decode_name(central.get(extra_start + extra_len..next).unwrap_or_default(), read16(central, pos + 8), decoder);
+        let mut size = read32(central, pos + 24) as u64;
+        let mut compressed_size = read32(central, pos + 20) as u64;
+        let mut offset = read32(central, pos + 42) as u64;
+        read_zip64(&name, &extra, &mut [&mut size, &mut compressed_size, &mut offset])?;
+        let base = match volumes.filter(|volumes| volumes.count() > 1) {
+            Some(volumes) => {
+                let disk = read16(central, pos + 34) as u32;
+                volumes.disk_start(disk).ok_or_else(|| ZipError::InvalidArchive(format!("{} is on missing volume {}", name, disk + 1)))?
+            }
+            None => base,
+        };
+        entries.push(ZipEntry {
+            name,
+            method: read16(central, pos + 10),
+            flags: read16(central, pos + 8),
+            crc32: read32(central, pos + 16),
+            compressed_size,
+            size,
+            modified: DosDateTime::from_dos(read16(central, pos + 12), read16(central, pos + 14)),
+            permissions: Permissions::from_external_attributes(read16(central, pos + 4), read32(central, pos + 38)),
+            extra,
+            offset: offset.saturating_add(base),
+            comment,
+        });
+        pos = next;
+    }
+    Ok(entries)
+}
+
+/*
+ 0xffffffffになっているvaluesを、ZIP64の拡張情報（0x0001）に書かれた値で置き換える。
+ 拡張情報には0xffffffffになっているものだけが、展開後のサイズ、圧縮後のサイズ、ローカルヘッダーの位置の順に並んでいる。
+ valuesにはこの順に、ヘッダーに書かれているものだけを渡す。nameはエラーに付けるエントリの名前。
+ */
+pub(crate) fn read_zip64(name: &str, extra: &[u8], values: &mut [&mut u64]) -> Result<(), ZipError> {
+    if values.iter().all(|value| **value != ZIP64_MARK) {
+        return Ok(());
+    }
+    let field = parse_extra_fields(extra).context(name, Phase::Open)?
+        .into_iter()
+        .find(|field| field.id == ZIP64_EXTRA_ID)
+        .ok_or_else(|| ZipError::InvalidArchive(format!("zip64 extended information of {} is missing", name)))?;
+    let mut pos = 0;
+    for value in values.iter_mut().filter(|value| ***value == ZIP64_MARK) {
+        if pos + 8 > field.data.len() {
+            return Err(ZipError::InvalidArchive(format!("zip64 extended information of {} is truncated", name)));
+        }
+        **value = read64(&field.data, pos);
+        pos += 8;
+    }
+    Ok(())
+}
+
+/*
+ データの後ろに書かれたデータディスクリプタ（汎用フラグのbit3が立っているエントリで使う）
+ crc32:  データのcrc32
+ size:   展開した後のサイズ
+ len:    データディスクリプタの長さ（先頭の印があれば4だけ長く、ZIP64のエントリではサイズが8byteずつになる）
+ */
+pub(crate) struct DataDescriptor {
+    pub crc32: u32,
+    pub size: u64,
+    pub len: usize,
+}
+
+/*
+ dataのposの位置からデータディスクリプタを読む（圧縮後のサイズは読み方を選ぶのにだけ使う）。途中で切れている場合はNoneを返す。
+ 先頭の印は付いていない場合もあり、crc32がたまたま印と同じ値になることもあるため、
+ 圧縮後のサイズが分かっている場合は、そのサイズが書かれている方の読み方を選ぶ。
+ zip64はローカルヘッダーにZIP64の拡張情報があるエントリかどうか（サイズが8byteずつになる）。
+ */
+pub(crate) fn read_descriptor(data: &[u8], pos: usize, compressed_size: Option<u64>, zip64: bool) -> Option<DataDescriptor> {
+    let read_size = |pos| if zip64 { read64(data, pos) } else { read32(data, pos) as u64 };
+    let (unsigned_len, size_pos) = if zip64 { (20, 12) } else { (12, 8) };
+    let signed = read32(data, pos) == DESCRIPTOR_SIGNATURE
+        && pos + 4 + unsigned_len <= data.len()
+        && compressed_size.is_none_or(|size| read_size(pos + 8) == size);
+    let (start, len) = if signed { (pos + 4, unsigned_len + 4) } else { (pos, unsigned_len) };
+    if pos + len > data.len() {
+        return None;
+    }
+    Some(DataDescriptor {
+        crc32: read32(data, start),
+        size: read_size(start + size_pos),
+        len,
+    })
+}
+
+/*
+ 拡張フィールドにZIP64の拡張情報があるかどうか
+ */
+pub(crate) fn has_zip64(extra: &[u8]) -> bool {
+    parse_extra_fields(extra).is_ok_and(|fields| fields.iter().any(|field| field.id == ZIP64_EXTRA_ID))
+}
+
+/*
+ dataのposの位置からリトルエンディアンの値を読む
+ 範囲の外を指している場合は0を返す（呼び出し元で長さを確かめてから使う）。
+ */
+pub(crate) fn read16(data: &[u8], pos: usize) -> u16 {
+    data.get(pos..pos + 2).and_then(|bytes| bytes.try_into().ok()).map_or(0, u16::from_le_bytes)
+}
+
+pub(crate) fn read32(data: &[u8], pos: usize) -> u32 {
+    data.get(pos..pos + 4).and_then(|bytes| bytes.try_into().ok()).map_or(0, u32::from_le_bytes)
+}
+
+pub(crate) fn read64(data: &[u8], pos: usize) -> u64 {
+    data.get(pos..pos + 8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_le_bytes)
+}
@@ -0,0 +1,43 @@
+use crate::Token;
+
+/*
+ エントリごとに確保し直していたバッファをまとめて保持し、複数のエントリで使い回すためのもの。
+ 小さいファイルを大量に圧縮する際にアロケーションが何度も走るのを防ぐ。
+ window:      スライドウインドウ
+ matched:     一致を探している途中のバイト列
+ tokens:      LZ77で見つけたトークン
+ compressed:  圧縮後のデータ
+ */
+#[derive(Default)]
+pub struct Arena {
+    pub(crate) window: Vec<u8>,
+    pub(crate) matched: Vec<u8>,
+    pub(crate) tokens: Vec<Token>,
+    pub(crate) compressed: Vec<u8>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena::default()
+    }
+
+    /*
+     中身を空にする。確保した領域はそのまま残しておく。
+     */
+    pub(crate) fn clear(&mut self) {
+        self.window.clear();
+        self.matched.clear();
+        self.tokens.clear();
+        self.compressed.clear();
+    }
+
+    /*
+     現在確保している領域の合計バイト数を返す
+     */
+    pub fn capacity(&self) -> usize {
+        self.window.capacity()
+            + self.matched.capacity()
+            + self.tokens.capacity() * std::mem::size_of::<Token>()
+            + self.compressed.capacity()
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{metadata, read_to_string, File};
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+use crate::error::{Context, Phase};
+use crate::glob::glob_match;
+use crate::input::{collect_inputs, walk, with_permissions, FileEntry};
+use crate::order::{apply_order, parse_order};
+use crate::runtime::lend_arena;
+use crate::space::{check_space, worst_case_size};
+use crate::validate::validate_files;
+use crate::workspace::{temp_dir, write_output};
+use crate::writer::{archive_comment, compress_entry, Compressed};
+use crate::{dos_time, time_data, Arena, ArchiveWriter, Compression, CompressionCache, EncodeOptions, EntryOptions, Input, Metadata, NameEncoder, Placement, Report, UtcClock, Violation, ZipError, ZipperRuntime, META_NAME};
+
+/*
+ reproducibleの場合に、SOURCE_DATE_EPOCHが設定されていなければ使う日時（1980-01-01 00:00:00 UTC）
+ */
+const REPRODUCIBLE_TIME: i64 = 315_532_800;
+
+/*
+ build.rsやxtaskなどから使うためのzipの作成方法
+ build().level(6).reproducible(true).add_dir("assets")?.write_to(out)? のようにつなげて使う。
+ 途中でpanicすることはなく、失敗した場合は全てZipErrorとして返す。
+ options:       圧縮のオプション
+ reproducible:  trueの場合には、何度作成しても同じzipになるようにする
+ files:         格納するファイルのパスと、zipの中での名前、エントリごとのオプション
+ metadata:      指定された場合には、最初のエントリとして.zipper-meta.jsonを格納する
+ jobs:          圧縮に使うスレッドの数（0と1の場合は呼び出したスレッドだけで圧縮する）
+ cache:         指定された場合には、内容が変わっていないファイルの圧縮をキャッシュで省く
+ nest_dirs:     trueの場合には、一番上のディレクトリごとに中のファイルを別のzipにして、無圧縮で格納する
+ order:         先頭に並べるエントリの名前と、そのデータの位置
+ dirs:          格納するディレクトリ（この中には一時ファイルを置かない）
+ runtime:       指定された場合には、jobsの代わりにこのスレッドで圧縮し、Arenaも使い回す
+ encrypt:       名前のパターンと、それに当てはまるエントリを暗号化するパスワード（指定した順に調べる）
+ store:         名前のパターン（当てはまるエントリは圧縮せずに格納する）
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    options: EncodeOptions,
+    reproducible: bool,
+    files: Vec<FileEntry>,
+    metadata: Option<Metadata>,
+    jobs: usize,
+    cache: Option<CompressionCache>,
+    nest_dirs: bool,
+    order: Vec<(String, Option<Placement>)>,
+    dirs: Vec<PathBuf>,
+    runtime: Option<Arc<ZipperRuntime>>,
+    encrypt: Vec<(String, String)>,
+    store: Vec<String>,
+}
+
+/*
+ エントリを圧縮するスレッド
+ Scoped:   zipを作るたびに、この数のスレッドを作る（2未満の場合は呼び出したスレッドだけで圧縮する）
+ Runtime:  ZipperRuntimeのスレッドを使う
+ */
+#[derive(Clone, Copy)]
+enum Workers<'a> {
+    Scoped(usize),
+    Runtime(&'a ZipperRuntime),
+}
+
+/*
+ Builderを作成する
+ */
+pub fn build() -> Builder {
+    Builder::default()
+}
+
+impl Builder {
+    /*
+     圧縮レベルを指定する
+     */
+    pub fn level(mut self, level: u8) -> Self {
+        self.options.level = level;
+        self
+    }
+
+    /*
+     オプションをまとめて指定する
+     */
+    pub fn options(mut self, options: EncodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /*
+     trueの場合には、エントリを名前順に並べ、日時をSOURCE_DATE_EPOCH（なければ1980-01-01）に固定して、
+     何度作成しても同じzipになるようにする
+     */
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /*
+     jobs個のスレッドでエントリを並列に圧縮する。
+     圧縮したデータはメモリに置いておき、指定された順番で書き込むため、1つのスレッドで作成した場合と同じzipになる。
+     */
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /*
+     runtimeのスレッドでエントリを並列に圧縮し、Arenaもruntimeのものを使い回す（jobsは使わない）。
+     同じruntimeを複数のBuilderで同時に使ってもよい。
+     */
+    pub fn runtime(mut self, runtime: Arc<ZipperRuntime>) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /*
+     書き込み途中のzipを置くディレクトリを指定する。指定しない場合は環境変数TMPDIR、出力先のディレクトリの順に使う。
+     格納するディレクトリの中にある場合は使わない。
+     */
+    pub fn tmpdir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.tmpdir = Some(dir.into());
+        self
+    }
+
+    /*
+     エントリの名前をヘッダーに書き込む文字コードを指定する（Shift_JISなど）。
+     名前の中に表せない文字がある場合は、圧縮を始める前にUnrepresentableNameにする。
+     */
+    pub fn name_encoder(mut self, encoder: Arc<dyn NameEncoder>) -> Self {
+        self.options.name_encoder = encoder;
+        self
+    }
+
+    /*
+     圧縮したデータをcacheに保存しておき、次に同じ内容のファイルを格納するときに使う
+     */
+    pub fn cache(mut self, cache: CompressionCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /*
+     trueの場合には、一番上のディレクトリ（例えば assets/ ）ごとに中のファイルをメモリ上で別のzip（assets.zip）にして、
+     外側のzipに無圧縮で格納する。一部の配布の仕組みで必要になる形式。
+     */
+    pub fn nest_dirs(mut self, nest_dirs: bool) -> Self {
+        self.nest_dirs = nest_dirs;
+        self
+    }
+
+    /*
+     名前がpattern（*、**、?が使える。/ を含まない場合はファイル名と比べる）に当てはまるエントリを、
+     passwordでWinZipのAES-256で暗号化する。当てはまらないエントリは暗号化しない。
+     複数指定した場合は最初に当てはまったもののパスワードを使い、add_file_withでパスワードを指定したエントリはそれを使う。
+     */
+    pub fn encrypt(mut self, pattern: impl Into<String>, password: impl Into<String>) -> Self {
+        self.encrypt.push((pattern.into(), password.into()));
+        self
+    }
+
+    /*
+     名前がpattern（encryptと同じ書き方）に当てはまるエントリを、圧縮せずに格納する（Compression::Store）。
+     既に圧縮された画像やアーカイブ、EPUBのmimetypeのように無圧縮でなければならないものに使う。
+     */
+    pub fn store(mut self, pattern: impl Into<String>) -> Self {
+        self.store.push(pattern.into());
+        self
+    }
+
+    /*
+     拡張子がextensionsに含まれるエントリを、圧縮を試さずに格納する（指定しない場合はCOMPRESSED_EXTENSIONS）。
+     空にすると、storeで指定したもの以外の全てのエントリを圧縮する。
+     */
+    pub fn stored_extensions<S: Into<String>>(mut self, extensions: impl IntoIterator<Item = S>) -> Self {
+        self.options.stored_extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /*
+     zip全体のコメントを指定する。name_encoderの文字コードで書き込み、65535バイトを超える場合は何も書き込まずにエラーにする。
+     */
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.options.comment = Some(comment.into());
+        self
+    }
+
+    /*
+     nameのエントリを、先に指定したものの後ろに続けてzipの先頭に並べる。
+     placementを指定した場合には、データの位置をそれに合わせる。
+     */
+    pub fn pin(mut self, name: impl Into<String>, placement: Option<Placement>) -> Self {
+        self.order.push((name.into(), placement));
+        self
+    }
+
+    /*
+     並び順を指定するファイルを読み込んで、書かれたエントリを順にpinする。
+     1行に1つずつ 名前 [offset=n|align=n] の形で書く。
+     */
+    pub fn order_file(mut self, path: impl AsRef<Path>) -> Result<Self, ZipError> {
+        let text = read_to_string(path)?;
+        self.order.extend(parse_order(&text)?);
+        Ok(self)
+    }
+
+    /*
+     アーカイブの情報を.zipper-meta.jsonとして最初のエントリに格納する。
+     作成したときのオプションはwrite_toで自動的に追加される。
+     */
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /*
+     pathのファイルをnameという名前で格納する
+     */
+    pub fn add_file(self, path: impl AsRef<Path>, name: impl Into<String>) -> Result<Self, ZipError> {
+        self.add_file_with(path, name, EntryOptions::new())
+    }
+
+    /*
+     add_fileと同じだが、拡張フィールドなどのエントリごとのオプションを指定できる
+     属性を指定しない場合は、ファイルの属性を使う。
+     */
+    pub fn add_file_with(mut self, path: impl AsRef<Path>, name: impl Into<String>, entry: EntryOptions) -> Result<Self, ZipError> {
+        let path = path.as_ref();
+        let file_metadata = metadata(path).context(&path.display().to_string(), Phase::Open)?;
+        if !file_metadata.is_file() {
+            return Err(std::io::Error::other(format!("{} is not a file", path.display())).into());
+        }
+        self.files.push((path.to_path_buf(), name.into(), with_permissions(entry, &file_metadata)));
+        Ok(self)
+    }
+
+    /*
+     dirの中のファイルを全て格納する。zipの中での名前はdirからの相対パスになる。
+     */
+    pub fn add_dir(mut self, dir: impl AsRef<Path>) -> Result<Self, ZipError> {
+        walk(dir.as_ref(), "", &mut self.files)?;
+        self.dirs.push(dir.as_ref().to_path_buf());
+        Ok(self)
+    }
+
+    /*
+     ファイル、ディレクトリ、listfileをまとめて格納する。CLIのaddと同じ規則で名前を決める。
+     */
+    pub fn add_inputs(mut self, inputs: &[Input]) -> Result<Self, ZipError> {
+        collect_inputs(inputs, &mut self.files, &mut self.dirs)?;
+        Ok(self)
+    }
+
+    /*
+     書き込む前に、全てのエントリの名前、サイズ、日時などを調べ、見つかった問題をまとめて返す（空なら問題はない）。
+     write_fileやwrite_toも最初にこれを呼び、問題があれば何も書き込まずにInvalidEntriesにする。
+     */
+    pub fn validate(&self) -> Result<Vec<Violation>, ZipError> {
+        let mut options = self.options.clone();
+        let mut fixed_time = None;
+        if self.reproducible {
+            options.clock = Arc::new(UtcClock);
+            fixed_time = Some(source_date_epoch());
+        }
+        validate_files(&self.files, &options, fixed_time)
+    }
+
+    /*
+     zipを作成してoutputのファイルに書き込み、結果を返す。
+     書き込む前にエントリを調べ、一時ファイルを置くディレクトリと出力先の空き容量を確認する。一時ファイルに書き込んでから置き換えるため、
+     途中で失敗した場合にも書き込み途中のファイルは残らない。
+     */
+    pub fn write_file(self, output: impl AsRef<Path>) -> Result<Report, ZipError> {
+        let output = output.as_ref();
+        self.check()?;
+        let mut entries = Vec::new();
+        for (path, name, _) in &self.files {
+            entries.push((metadata(path).context(&path.display().to_string(), Phase::Open)?.len(), name.len()));
+        }
+        if let Some(metadata) = &self.metadata {
+            /*
+             write_toで追加するオプションの分も見込んでおく
+             */
+            entries.push((metadata.to_json().len() as u64 + 128, META_NAME.len()));
+        }
+        let tmpdir = self.options.tmpdir.clone();
+        let dirs = self.dirs.clone();
+        check_space(output, &temp_dir(tmpdir.as_deref(), output, &dirs)?, worst_case_size(entries))?;
+        write_output(output, tmpdir.as_deref(), &dirs, |file| self.write_checked(BufWriter::new(file)))
+    }
+
+    /*
+     zipを作成してoutputに書き込み、結果を返す。書き込む前にエントリを調べる。
+     */
+    pub fn write_to<W: Write>(self, output: W) -> Result<Report, ZipError> {
+        self.check()?;
+        self.write_checked(output)
+    }
+
+    /*
+     validateで問題が見つかった場合にInvalidEntriesにする
+     */
+    fn check(&self) -> Result<(), ZipError> {
+        let violations = self.validate()?;
+        if !violations.is_empty() {
+            return Err(ZipError::InvalidEntries(violations));
+        }
+        Ok(())
+    }
+
+    /*
+     checkで調べた後に、zipを作成してoutputに書き込む
+     */
+    fn write_checked<W: Write>(self, output: W) -> Result<Report, ZipError> {
+        let mut options = self.options;
+        let mut files = self.files;
+        let mut fixed_time = None;
+        if self.reproducible {
+            files.sort_by(|a, b| a.1.cmp(&b.1));
+            options.clock = Arc::new(UtcClock);
+            fixed_time = Some(source_date_epoch());
+        }
+        if !self.order.is_empty() {
+            files = apply_order(files, &self.order)?;
+        }
+        let store = &self.store;
+        let stored = |name: &str| store.iter().any(|pattern| glob_match(pattern, name)) || options.stores_extension(name);
+        for (_, _, entry) in files.iter_mut().filter(|(_, name, _)| stored(name)) {
+            entry.compression = Compression::Store;
+        }
+        for (_, name, entry) in files.iter_mut().filter(|(_, _, entry)| entry.password.is_none()) {
+            entry.password = self.encrypt.iter()
+                .find(|(pattern, _)| glob_match(pattern, name))
+                .map(|(_, password)| password.clone());
+        }
+
+        let mut writer = ArchiveWriter::new(output).with_comment(archive_comment(&options)?);
+        let runtime = self.runtime.as_deref();
+        let workers = runtime.map_or(Workers::Scoped(self.jobs), Workers::Runtime);
+        let mut arena = lend_arena(runtime);
+        if let Some(mut metadata) = self.metadata {
+            metadata.options.insert("engine".to_string(), format!("{:?}", options.engine).to_lowercase());
+            metadata.options.insert("level".to_string(), options.level.to_string());
+            metadata.options.insert("reproducible".to_string(), self.reproducible.to_string());
+            let time = dos_time(fixed_time.unwrap_or_else(|| options.clock.now()), &*options.clock);
+            let json = metadata.to_json();
+            writer.add_entry(&mut json.as_bytes(), META_NAME, time, &options, &EntryOptions::new(), &mut arena)?;
+        }
+        let cache = self.cache.as_ref();
+        if !self.nest_dirs {
+            write_files(&mut writer, &files, &options, cache, workers, fixed_time, &mut arena)?;
+            return Ok(writer.finish()?);
+        }
+        for (dir, group) in group_by_top_dir(files) {
+            match dir {
+                None => write_files(&mut writer, &group, &options, cache, workers, fixed_time, &mut arena)?,
+                Some(dir) => {
+                    let mut inner = ArchiveWriter::new(Vec::new());
+                    write_files(&mut inner, &group, &options, cache, workers, fixed_time, &mut arena)?;
+                    let (data, _) = inner.finish_into_inner()?;
+                    let time = dos_time(fixed_time.unwrap_or_else(|| options.clock.now()), &*options.clock);
+                    writer.add_stored(&data, &format!("{}.zip", dir), time, &options, &EntryOptions::new())?;
+                }
+            }
+        }
+        Ok(writer.finish()?)
+    }
+}
+
+/*
+ filesをwriterに書き込む。
+ workersのスレッドが2つ以上ある場合（ZipperRuntimeの場合は1つ以上）は並列に圧縮し、
+ cacheがある場合は内容が変わっていないファイルの圧縮を省く。
+ fixed_time:  指定された場合には全てのエントリの日時をこれにする
+ */
+fn write_files<W: Write>(writer: &mut ArchiveWriter<W>, files: &[FileEntry], options: &EncodeOptions, cache: Option<&CompressionCache>, workers: Workers, fixed_time: Option<i64>, arena: &mut Arena) -> Result<(), ZipError> {
+    let entry_time = |path: &Path| match fixed_time {
+        Some(time) => dos_time(time, &*options.clock),
+        None => time_data(&path.to_string_lossy(), &*options.clock),
+    };
+    let write = |index: usize, compressed| {
+        let (path, name, entry) = &files[index];
+        writer.add_compressed(compressed, name, entry_time(path), options, entry)
+    };
+    match workers {
+        Workers::Scoped(jobs) if jobs > 1 && files.len() > 1 => return compress_parallel(files, options, cache, jobs, write),
+        Workers::Runtime(runtime) if runtime.threads() > 0 && files.len() > 1 => return compress_pooled(runtime, files, options, cache, write),
+        _ => {}
+    }
+    if let Some(cache) = cache {
+        for (path, name, entry) in files {
+            let compressed = cache.compress_file(path, name, &entry.encode_options(options), arena)?;
+            writer.add_compressed(compressed, name, entry_time(path), options, entry)?;
+        }
+    } else {
+        for (path, name, entry) in files {
+            let mut input = File::open(path).context(&path.display().to_string(), Phase::Open)?;
+            writer.add_entry(&mut input, name, entry_time(path), options, entry, arena)?;
+        }
+    }
+    Ok(())
+}
+
+/*
+ filesを一番上のディレクトリごとにまとめる。
+ ディレクトリの中のファイルは、名前からそのディレクトリの部分を取り除いて (Some(ディレクトリ名), ファイル) にまとめる。
+ 一番上にあるファイルはそれぞれ (None, ファイル) のままにする。
+ それぞれのまとまりは、最初のファイルが現れた位置に並べる。
+ */
+fn group_by_top_dir(files: Vec<FileEntry>) -> Vec<(Option<String>, Vec<FileEntry>)> {
+    let mut groups: Vec<(Option<String>, Vec<FileEntry>)> = Vec::new();
+    for (path, name, entry) in files {
+        match name.split_once('/') {
+            Some((dir, rest)) => {
+                let file = (path, rest.to_string(), entry);
+                match groups.iter_mut().find(|(group, _)| group.as_deref() == Some(dir)) {
+                    Some((_, group)) => group.push(file),
+                    None => groups.push((Some(dir.to_string()), vec![file])),
+                }
+            }
+            None => groups.push((None, vec![(path, name, entry)])),
+        }
+    }
+    groups
+}
+
+/*
+ filesをjobs個のスレッドで圧縮し、圧縮できたものから順番にwriteに渡す。
+ 後ろのエントリが先に圧縮された場合には、それより前のエントリが揃うまでメモリに置いておく。
+ 途中で失敗した場合には、それより前のエントリを全て書き込んでから、そのエラーを返す。
+ */
+fn compress_parallel<F>(files: &[FileEntry], options: &EncodeOptions, cache: Option<&CompressionCache>, jobs: usize, mut write: F) -> Result<(), ZipError>
+where
+    F: FnMut(usize, Compressed) -> Result<(), ZipError>,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            let sender = sender.clone();
+            let (next, failed) = (&next, &failed);
+            scope.spawn(move || {
+                let mut arena = Arena::new();
+                while !failed.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let (path, name, entry) = match files.get(index) {
+                        Some(file) => file,
+                        None => break,
+                    };
+                    let result = compress_file(path, name, &entry.encode_options(options), cache, &mut arena);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        for (index, result) in receiver.iter() {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&written) {
+                if let Err(err) = result.and_then(|compressed| write(written, compressed)) {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+                written += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+/*
+ filesをruntimeのスレッドで圧縮し、compress_parallelと同じように順番にwriteに渡す。
+ runtimeのスレッドは呼び出し元より長く生きるため、それぞれのファイルのパス、名前、オプションを写して渡す。
+ 失敗したエントリより後ろのものは圧縮せず、それより前のエントリは全て書き込んでから、そのエラーを返す。
+ */
+fn compress_pooled<F>(runtime: &ZipperRuntime, files: &[FileEntry], options: &EncodeOptions, cache: Option<&CompressionCache>, mut write: F) -> Result<(), ZipError>
+where
+    F: FnMut(usize, Compressed) -> Result<(), ZipError>,
+{
+    let first_failed = Arc::new(AtomicUsize::new(usize::MAX));
+    let (sender, receiver) = channel();
+    for (index, (path, name, entry)) in files.iter().enumerate() {
+        let (path, name, options, cache) = (path.clone(), name.clone(), entry.encode_options(options).into_owned(), cache.cloned());
+        let (sender, first_failed) = (sender.clone(), Arc::clone(&first_failed));
+        let queued = runtime.execute(Box::new(move |arena| {
+            if index > first_failed.load(Ordering::Relaxed) {
+                return;
+            }
+            let result = compress_file(&path, &name, &options, cache.as_ref(), arena);
+            if result.is_err() {
+                first_failed.fetch_min(index, Ordering::Relaxed);
+            }
+            let _ = sender.send((index, result));
+        }));
+        if !queued {
+            break;
+        }
+    }
+    drop(sender);
+
+    let mut pending = BTreeMap::new();
+    let mut written = 0;
+    for (index, result) in receiver.iter() {
+        pending.insert(index, result);
+        while let Some(result) = pending.remove(&written) {
+            if let Err(err) = result.and_then(|compressed| write(written, compressed)) {
+                first_failed.fetch_min(written, Ordering::Relaxed);
+                return Err(err);
+            }
+            written += 1;
+        }
+    }
+    if written < files.len() {
+        return Err(std::io::Error::other(format!("worker threads stopped before compressing {}", files[written].1)).into());
+    }
+    Ok(())
+}
+
+/*
+ pathのファイルを圧縮する。cacheがある場合は、内容が変わっていなければキャッシュから取り出す。
+ */
+fn compress_file(path: &Path, name: &str, options: &EncodeOptions, cache: Option<&CompressionCache>, arena: &mut Arena) -> Result<Compressed, ZipError> {
+    match cache {
+        Some(cache) => cache.compress_file(path, name, options, arena),
+        None => File::open(path)
+            .context(&path.display().to_string(), Phase::Open)
+            .and_then(|mut input| compress_entry(&mut input, name, options, arena)),
+    }
+}
+
+/*
+ SOURCE_DATE_EPOCHが設定されていればその日時を、なければREPRODUCIBLE_TIMEを返す
+ */
+fn source_date_epoch() -> i64 {
+    env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(REPRODUCIBLE_TIME)
+}
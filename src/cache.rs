@@ -0,0 +1,104 @@
+use std::fs::{create_dir_all, read};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Context, Phase};
+use crate::workspace::TempFile;
+use crate::writer::{compress_entry, Compressed};
+use crate::{crc32, Arena, EncodeOptions, Profile, ZipError};
+
+/*
+ キャッシュのファイルの先頭に書き込む印
+ */
+const CACHE_MAGIC: &[u8; 4] = b"ZPC2";
+
+/*
+ 圧縮したデータをファイルの内容ごとに保存しておくディスク上のキャッシュ
+ CIのリリースのように、ほとんど変わらないファイルから何度もzipを作る場合に、
+ 変わっていないファイルの圧縮を省くために使う。
+ キーはファイルの内容のハッシュとサイズ、圧縮のオプション、このクレートのバージョンから作る。
+ 取り出したときにはcrc32も確かめ、食い違う場合には圧縮し直す。
+ dir:  キャッシュを置くディレクトリ
+ */
+#[derive(Debug, Clone)]
+pub struct CompressionCache {
+    dir: PathBuf,
+}
+
+impl CompressionCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        CompressionCache { dir: dir.into() }
+    }
+
+    /*
+     pathのファイルを圧縮する。キャッシュにあればそれを使い、なければ圧縮してキャッシュに入れる。
+     */
+    pub(crate) fn compress_file(&self, path: &Path, name: &str, options: &EncodeOptions, arena: &mut Arena) -> Result<Compressed, ZipError> {
+        let data = read(path).context(&path.display().to_string(), Phase::Read)?;
+        let crc = crc32(&data);
+        let key = self.dir.join(cache_key(&data, options));
+        if let Some(compressed) = load(&key, data.len(), crc) {
+            return Ok(compressed);
+        }
+        let compressed = compress_entry(&mut data.as_slice(), name, options, arena)?;
+        store(&self.dir, &key, &compressed).context(&key.display().to_string(), Phase::Write)?;
+        Ok(compressed)
+    }
+}
+
+/*
+ ファイルの内容と圧縮のオプションからキャッシュのファイル名を作る
+ ハッシュにはバージョンによって結果が変わらないようにFNV-1a（64bit）を使う。
+ */
+fn cache_key(data: &[u8], options: &EncodeOptions) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    let blocks = (options.block_max_symbols.unwrap_or(0), options.block_max_bytes.unwrap_or(0));
+    let sample = options.sample_len.unwrap_or(0);
+    format!("{:016x}-{}-{:?}-{}-{}x{}-{}-{}.bin", hash, data.len(), options.engine, options.level, blocks.0, blocks.1, sample, env!("CARGO_PKG_VERSION")).to_lowercase()
+}
+
+/*
+ キャッシュのファイルを読み込む。
+ 4byte: CACHE_MAGIC, 8byte: 圧縮前のサイズ, 4byte: crc32, 残り: 圧縮したデータ の順になっている。
+ ない場合や、サイズやcrc32が食い違う場合にはNoneを返す。
+ */
+fn load(key: &Path, file_size: usize, crc: u32) -> Option<Compressed> {
+    let cached = read(key).ok()?;
+    if cached.len() < 16 || &cached[0..4] != CACHE_MAGIC {
+        return None;
+    }
+    let cached_size = u64::from_le_bytes([cached[4], cached[5], cached[6], cached[7], cached[8], cached[9], cached[10], cached[11]]);
+    let cached_crc = u32::from_le_bytes([cached[12], cached[13], cached[14], cached[15]]);
+    if cached_size != file_size as u64 || cached_crc != crc {
+        return None;
+    }
+    Some(Compressed {
+        file_size: cached_size,
+        crc32: cached_crc,
+        data: cached[16..].to_vec(),
+        profile: Profile::new(),
+        cached: true,
+    })
+}
+
+/*
+ 圧縮したデータをキャッシュに書き込む。
+ 途中で止まっても壊れたキャッシュが残らないように、一時ファイルに書いてから名前を変える。
+ */
+fn store(dir: &Path, key: &Path, compressed: &Compressed) -> Result<(), io::Error> {
+    create_dir_all(dir)?;
+    let mut buffer = Vec::with_capacity(16 + compressed.data.len());
+    buffer.extend_from_slice(CACHE_MAGIC);
+    buffer.extend_from_slice(&compressed.file_size.to_le_bytes());
+    buffer.extend_from_slice(&compressed.crc32.to_le_bytes());
+    buffer.extend_from_slice(&compressed.data);
+    let name = key.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let (temp, mut file) = TempFile::create_in(dir, &name)?;
+    file.write_all(&buffer)?;
+    drop(file);
+    temp.persist(key)
+}
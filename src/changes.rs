@@ -0,0 +1,136 @@
+use std::fmt;
+use std::fs::{metadata, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use crate::decode::entry_path;
+use crate::error::{Context, Phase};
+use crate::input::walk;
+use crate::zipcrypto::encryption;
+use crate::{Crc32, ZipArchive, ZipEntry, ZipError, META_NAME};
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;  // ファイルのcrc32を計算するときに1度に読む大きさ
+
+/*
+ アーカイブを作った後にファイルがどう変わったか
+ Modified:     サイズは同じだが、内容（crc32）が違う
+ SizeChanged:  サイズが違う（内容は読まない）
+ TypeChanged:  ファイルだったものがディレクトリに、ディレクトリだったものがファイルになっている
+ Missing:      アーカイブにあるエントリのファイルがない
+ Added:        アーカイブにないファイルがある
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Modified { archived: u32, actual: u32 },
+    SizeChanged { archived: u64, actual: u64 },
+    TypeChanged,
+    Missing,
+    Added,
+}
+
+/*
+ 変わったファイルと、その変わり方
+ name:  エントリの名前（rootからの相対パス）
+ kind:  どのように変わったか
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        match &self.kind {
+            ChangeKind::Modified { archived, actual } => write!(f, "modified (crc32 {:08x} in the archive, {:08x} on disk)", archived, actual),
+            ChangeKind::SizeChanged { archived, actual } => write!(f, "size changed ({} bytes in the archive, {} bytes on disk)", archived, actual),
+            ChangeKind::TypeChanged => write!(f, "changed between a file and a directory"),
+            ChangeKind::Missing => write!(f, "missing"),
+            ChangeKind::Added => write!(f, "added"),
+        }
+    }
+}
+
+/*
+ archiveのエントリと、rootの中のファイルを比べて、アーカイブを作った後に変わったものを返す（空なら何も変わっていない）。
+ エントリは展開せず、ファイルを少しずつ読んでcrc32を計算し、セントラルヘッダーのcrc32と比べる。
+ サイズが違うファイルは読まずにSizeChangedにする。
+ AE-2で暗号化されたエントリはcrc32が書かれていないため、サイズだけを比べる。
+ エントリの後に、rootの中にあってアーカイブにないファイルをAddedとして続ける（空のディレクトリは含めない）。
+ .zipper-meta.jsonは比べない。
+ */
+pub fn verify_against_fs(archive: &ZipArchive, root: impl AsRef<Path>) -> Result<Vec<Change>, ZipError> {
+    let root = root.as_ref();
+    let mut changes = Vec::new();
+    for entry in archive.entries().iter().filter(|entry| entry.name != META_NAME) {
+        let path = entry_path(root, &entry.name, false)?;
+        if let Some(kind) = compare(entry, &path).context(&entry.name, Phase::Read)? {
+            changes.push(Change { name: entry.name.clone(), kind });
+        }
+    }
+
+    let mut files = Vec::new();
+    if root.is_dir() {
+        walk(root, "", &mut files)?;
+    }
+    for (_, name, _) in files {
+        if archive.index_of(&name).is_none() {
+            changes.push(Change { name, kind: ChangeKind::Added });
+        }
+    }
+    Ok(changes)
+}
+
+/*
+ 1つのエントリをpathのファイルと比べる。変わっていない場合はNoneを返す。
+ */
+fn compare(entry: &ZipEntry, path: &Path) -> Result<Option<ChangeKind>, io::Error> {
+    let file_metadata = match metadata(path) {
+        Ok(file_metadata) => file_metadata,
+        /*
+         途中のディレクトリがファイルに入れ替わっている場合も、エントリのファイルはないものとする
+         */
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => return Ok(Some(ChangeKind::Missing)),
+        Err(err) => return Err(err),
+    };
+    if entry.is_dir() != file_metadata.is_dir() {
+        return Ok(Some(ChangeKind::TypeChanged));
+    }
+    if entry.is_dir() {
+        return Ok(None);
+    }
+    if file_metadata.len() != entry.size {
+        return Ok(Some(ChangeKind::SizeChanged { archived: entry.size, actual: file_metadata.len() }));
+    }
+    let has_crc = encryption(entry.flags, entry.method, entry.crc32, 0, &entry.extra, &entry.name)
+        .ok()
+        .flatten()
+        .is_none_or(|encryption| encryption.checks_crc());
+    if !has_crc {
+        return Ok(None);
+    }
+    let actual = file_crc32(path)?;
+    if actual != entry.crc32 {
+        return Ok(Some(ChangeKind::Modified { archived: entry.crc32, actual }));
+    }
+    Ok(None)
+}
+
+/*
+ ファイルを全てメモリに読み込まずに、少しずつ読んでcrc32を計算する
+ */
+fn file_crc32(path: &Path) -> Result<u32, io::Error> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut crc = Crc32::new();
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(crc.get_crc32());
+        }
+        for &byte in buffer.iter().take(len) {
+            crc.push_buf(byte);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+#[cfg(feature = "local-time")]
+use chrono::prelude::*;
+
+/*
+ zipに書き込む日時を決めるための時計
+ テストで結果を固定したい場合や、WASMなどで時刻の取り方が違う場合に差し替えられるようにしている。
+ now:     現在のUNIX時間（秒）を返す
+ offset:  UNIX時間timesをその地域の時刻にするために足す秒数を返す
+ */
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> i64;
+
+    fn offset(&self, _times: i64) -> i64 {
+        0
+    }
+}
+
+/*
+ OSの時計と、OSに設定されているタイムゾーンを使う
+ local-timeのfeatureが無効な場合はタイムゾーンを取得できないため、UTCになる。
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(epoch) => epoch.as_secs() as i64,
+            Err(_) => 0,
+        }
+    }
+
+    #[cfg(feature = "local-time")]
+    fn offset(&self, times: i64) -> i64 {
+        Local.timestamp(times, 0).offset().fix().local_minus_utc() as i64
+    }
+}
+
+/*
+ OSの時計を使うが、タイムゾーンに関係なくUTCで書き込む
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UtcClock;
+
+impl Clock for UtcClock {
+    fn now(&self) -> i64 {
+        SystemClock.now()
+    }
+}
+
+/*
+ 常に同じ時刻を返す。UTCとして扱う。
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
+
+/*
+ UNIX時間を（年, 月, 日, 時, 分, 秒）にする
+ 外部のクレートを使わずに、1970年1月1日からの日数をグレゴリオ暦の日付にしている。
+ 下のurlのdays_from_civilの逆の計算を使った。
+ http://howardhinnant.github.io/date_algorithms.html
+ */
+pub(crate) fn civil_time(times: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = times.div_euclid(86400);
+    let seconds = times.rem_euclid(86400) as u32;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/*
+ （年, 月, 日, 時, 分, 秒）をUNIX時間にする（civil_timeの逆）
+ 上のurlのdays_from_civilの計算を使った。
+ */
+pub(crate) fn unix_time(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    days * 86400 + (hour * 3600 + minute * 60 + second) as i64
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::archive::into_zip_error;
+use crate::error::{Context, Phase};
+use crate::{DosDateTime, ZipArchive, ZipEntry, ZipError};
+
+const BUFFER_SIZE: usize = 64 * 1024;  // 中身を比べるときに1度に展開する大きさ
+
+/*
+ 2つのzipのエントリの違い（firstは1つ目のzip、secondは2つ目のzipの値）
+ Removed:             1つ目のzipにだけある
+ Added:               2つ目のzipにだけある
+ SizeChanged:         展開後のサイズが違う
+ CrcChanged:          展開後のサイズは同じだが、crc32が違う
+ ContentsChanged:     サイズとcrc32は同じだが、展開した中身が違う（中身を比べた場合だけ）
+ MethodChanged:       圧縮方法が違う
+ ModifiedChanged:     最終更新日時が違う
+ PermissionsChanged:  Unixのモードが違う
+ CommentChanged:      エントリのコメントが違う
+ ArchiveComment:      zip全体のコメントが違う（名前は空）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DifferenceKind {
+    Removed,
+    Added,
+    SizeChanged { first: u64, second: u64 },
+    CrcChanged { first: u32, second: u32 },
+    ContentsChanged,
+    MethodChanged { first: u16, second: u16 },
+    ModifiedChanged { first: DosDateTime, second: DosDateTime },
+    PermissionsChanged { first: u32, second: u32 },
+    CommentChanged,
+    ArchiveComment,
+}
+
+/*
+ 違いのあったエントリと、その違い
+ name:  エントリの名前
+ kind:  どのように違うか
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub name: String,
+    pub kind: DifferenceKind,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.kind == DifferenceKind::ArchiveComment {
+            return write!(f, "archive comment differs");
+        }
+        write!(f, "{}: ", self.name)?;
+        match &self.kind {
+            DifferenceKind::Removed => write!(f, "only in the first archive"),
+            DifferenceKind::Added => write!(f, "only in the second archive"),
+            DifferenceKind::SizeChanged { first, second } => write!(f, "size {} -> {} bytes", first, second),
+            DifferenceKind::CrcChanged { first, second } => write!(f, "crc32 {:08x} -> {:08x}", first, second),
+            DifferenceKind::ContentsChanged => write!(f, "contents differ with the same crc32"),
+            DifferenceKind::MethodChanged { first, second } => write!(f, "method {} -> {}", first, second),
+            DifferenceKind::ModifiedChanged { first, second } => write!(f, "modified {} -> {}", format_time(first), format_time(second)),
+            DifferenceKind::PermissionsChanged { first, second } => write!(f, "mode {:o} -> {:o}", first, second),
+            DifferenceKind::CommentChanged => write!(f, "comment differs"),
+            DifferenceKind::ArchiveComment => Ok(()),
+        }
+    }
+}
+
+fn format_time(time: &DosDateTime) -> String {
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", time.year, time.month, time.day, time.hour, time.minute, time.second)
+}
+
+/*
+ firstとsecondのzipを比べて、違いを返す（空なら同じ）。
+ エントリは名前で対応させ（同じ名前が複数ある場合は最初のもの）、1つ目のzipの順に違いを並べた後に、2つ目のzipにだけあるエントリを続ける。
+ サイズ、crc32、圧縮方法、日時、属性、コメントはセントラルヘッダーの値を比べる。
+ contentsがtrueの場合は、サイズとcrc32が同じエントリも両方を展開して中身を1バイトずつ比べる（暗号化されたエントリはそれぞれのパスワードで復号する）。
+ */
+pub fn compare_archives(first: &mut ZipArchive, second: &mut ZipArchive, contents: bool) -> Result<Vec<Difference>, ZipError> {
+    let mut differences = Vec::new();
+    if first.comment() != second.comment() {
+        differences.push(Difference { name: String::new(), kind: DifferenceKind::ArchiveComment });
+    }
+    let mut buffers = (vec![0u8; BUFFER_SIZE], vec![0u8; BUFFER_SIZE]);
+    for index in 0..first.len() {
+        let a = match first.by_index(index) {
+            Some(entry) if first.index_of(&entry.name) == Some(index) => entry.clone(),
+            _ => continue,
+        };
+        let b = match second.by_name(&a.name) {
+            Some(entry) => entry.clone(),
+            None => {
+                differences.push(Difference { name: a.name, kind: DifferenceKind::Removed });
+                continue;
+            }
+        };
+        let mut kinds = header_differences(&a, &b);
+        if contents && kinds.is_empty() && !a.is_dir() {
+            let same = same_contents(first, index, second, &a.name, &mut buffers).context(&a.name, Phase::Read)?;
+            if !same {
+                kinds.push(DifferenceKind::ContentsChanged);
+            }
+        }
+        differences.extend(kinds.into_iter().map(|kind| Difference { name: a.name.clone(), kind }));
+    }
+    for (index, entry) in second.iter().enumerate() {
+        if second.index_of(&entry.name) == Some(index) && first.index_of(&entry.name).is_none() {
+            differences.push(Difference { name: entry.name.clone(), kind: DifferenceKind::Added });
+        }
+    }
+    Ok(differences)
+}
+
+/*
+ セントラルヘッダーの値の違い
+ */
+fn header_differences(a: &ZipEntry, b: &ZipEntry) -> Vec<DifferenceKind> {
+    let mut kinds = Vec::new();
+    if a.size != b.size {
+        kinds.push(DifferenceKind::SizeChanged { first: a.size, second: b.size });
+    } else if a.crc32 != b.crc32 {
+        kinds.push(DifferenceKind::CrcChanged { first: a.crc32, second: b.crc32 });
+    }
+    if a.method != b.method {
+        kinds.push(DifferenceKind::MethodChanged { first: a.method, second: b.method });
+    }
+    if a.modified != b.modified {
+        kinds.push(DifferenceKind::ModifiedChanged { first: a.modified, second: b.modified });
+    }
+    if a.permissions != b.permissions {
+        kinds.push(DifferenceKind::PermissionsChanged { first: a.permissions.unix_mode(), second: b.permissions.unix_mode() });
+    }
+    if a.comment != b.comment {
+        kinds.push(DifferenceKind::CommentChanged);
+    }
+    kinds
+}
+
+/*
+ firstのindex番目のエントリとsecondのnameのエントリを少しずつ展開して、中身が同じかどうかを返す
+ */
+fn same_contents(first: &mut ZipArchive, index: usize, second: &mut ZipArchive, name: &str, buffers: &mut (Vec<u8>, Vec<u8>)) -> Result<bool, ZipError> {
+    let other = second.index_of(name).ok_or_else(|| ZipError::InvalidArchive(format!("{} is missing", name)))?;
+    let mut a = first.open_entry(index)?;
+    let mut b = second.open_entry(other)?;
+    loop {
+        let len = fill(&mut a, &mut buffers.0).map_err(into_zip_error)?;
+        let other_len = fill(&mut b, &mut buffers.1).map_err(into_zip_error)?;
+        if buffers.0.get(..len) != buffers.1.get(..other_len) {
+            return Ok(false);
+        }
+        if len == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/*
+ bufferがいっぱいになるか最後まで読むまでreaderから読み込み、読んだ長さを返す
+ */
+fn fill<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let mut filled = 0;
+    while let Some(rest) = buffer.get_mut(filled..).filter(|rest| !rest.is_empty()) {
+        match reader.read(rest)? {
+            0 => break,
+            len => filled += len,
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{create_dir_all, read, write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::archive::{central_directory, modified_time, restore_dir, set_modified, find_end, has_zip64, parse_central, read16, read32, read_descriptor, read_zip64, DataDescriptor, CENTRAL_SIGNATURE, END_SIGNATURE, LOCAL_HEADER_SIZE, LOCAL_SIGNATURE};
+use crate::error::{Context, Phase};
+use crate::inflate::{inflate, inflate_prefix};
+use crate::names::decode_name;
+use crate::zipcrypto::{check_password, decrypt, encryption, password_for, Encryption};
+use crate::{crc32, DecodeOptions, DosDateTime, Diagnostic, DiagnosticKind, EntryReport, NameDecoder, Permissions, Report, ZipEntry, ZipError, METHOD_DEFLATED, METHOD_STORED};
+
+/*
+ 知られている中で最も新しい仕様のバージョン（6.3）
+ 展開に必要なバージョンがこれより大きい場合は仕様から外れているものとする。
+ */
+const MAX_KNOWN_VERSION: u16 = 63;
+
+/*
+ input_zipのエントリを全てoutput_dirの中に展開する。
+ 仕様から外れている点があった場合にはNonstandardにする。
+ */
+pub fn decode(input_zip: &str, output_dir: &str) -> Result<Report, ZipError> {
+    decode_with(input_zip, output_dir, &DecodeOptions::default())
+}
+
+/*
+ decodeにオプションを指定できるようにしたもの。
+ 先頭からローカルヘッダーを順に読み、セントラルヘッダーが現れたところで終わりにする。
+ セントラルヘッダーはcrc32をローカルヘッダーと比べることと、展開したファイルに属性を設定することだけに使う。
+ 展開したデータのcrc32がヘッダーと食い違う場合にはCrcMismatchにする。
+ 暗号化されたエントリはoptions.passwordで復号する（パスワードがない場合は、何も書き込まずにPasswordRequiredにする）。
+ output_dirの外に書き込むような名前（..や絶対パス）のエントリがある場合には、何も書き込まずにエラーにする
+ （options.allow_unsafe_pathsの場合を除く）。
+ options.lenientの場合には、仕様から外れている点をReport.diagnosticsに記録して続ける。
+ */
+pub fn decode_with(input_zip: &str, output_dir: &str, options: &DecodeOptions) -> Result<Report, ZipError> {
+    let data = read(input_zip).context(input_zip, Phase::Read)?;
+    let output_dir = Path::new(output_dir);
+    let mut report = Report::new();
+    let mut diagnose = |name: &str, kind: DiagnosticKind| {
+        let diagnostic = Diagnostic { name: name.to_string(), kind };
+        if options.lenient {
+            report.diagnostics.push(diagnostic);
+            Ok(())
+        } else {
+            Err(ZipError::Nonstandard(diagnostic))
+        }
+    };
+
+    let central = match central_entries(&data, &*options.name_decoder)? {
+        Some(central) => central,
+        None => {
+            diagnose("", DiagnosticKind::MissingCentralDirectory)?;
+            HashMap::new()
+        }
+    };
+
+    /*
+     自己解凍形式のように前にデータが付けられている場合は、セントラルヘッダーに書かれた最初のローカルヘッダーから読む
+     */
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    if data.len() >= 4 && read32(&data, 0) != LOCAL_SIGNATURE {
+        pos = central.keys().min().map_or(0, |&offset| offset as usize);
+    }
+    while pos + 4 <= data.len() {
+        match read32(&data, pos) {
+            LOCAL_SIGNATURE => {}
+            CENTRAL_SIGNATURE | END_SIGNATURE => break,
+            signature => return Err(ZipError::InvalidArchive(format!("unknown signature 0x{:08x} at {}", signature, pos))),
+        }
+        if pos + LOCAL_HEADER_SIZE > data.len() {
+            return Err(ZipError::InvalidArchive(format!("truncated local header at {}", pos)));
+        }
+        let version = read16(&data, pos + 4);
+        let flags = read16(&data, pos + 6);
+        let method = read16(&data, pos + 8);
+        let name_len = read16(&data, pos + 26) as usize;
+        let extra_len = read16(&data, pos + 28) as usize;
+        let name_start = pos + LOCAL_HEADER_SIZE;
+        let data_start = name_start + name_len + extra_len;
+        if data_start > data.len() {
+            return Err(ZipError::InvalidArchive(format!("truncated entry at {}", pos)));
+        }
+        let name_bytes = data.get(name_start..name_start + name_len).unwrap_or_default();
+
+        /*
+         UTF-8のフラグを付け忘れたUTF-8の名前は、lenientの場合にはname_decoderで読まずにUTF-8として読む
+         */
+        let missing_utf8_flag = flags & 0x0800 == 0 && !name_bytes.is_ascii() && std::str::from_utf8(name_bytes).is_ok();
+        let name = if missing_utf8_flag && options.lenient {
+            String::from_utf8_lossy(name_bytes).into_owned()
+        } else {
+            decode_name(name_bytes, flags, &*options.name_decoder)
+        };
+        let extra = data.get(name_start + name_len..data_start).unwrap_or_default();
+        let central_entry = central.get(&(pos as u64));
+        let truncated = || ZipError::InvalidArchive(format!("truncated entry at {}", pos));
+
+        /*
+         データディスクリプタを使うエントリは、ローカルヘッダーのcrc32とサイズが0になっているため、データの後ろから読む
+         サイズが0xffffffffになっている場合は、ZIP64の拡張情報から読む。
+         */
+        let (local_crc, size, data_end, next) = if flags & 0x0008 != 0 {
+            let (data_end, descriptor) = descriptor_entry(&data, data_start, method, &name, extra, central_entry)
+                .context_at(&name, Phase::Read, pos as u64)?;
+            (descriptor.crc32, descriptor.size, data_end, data_end + descriptor.len)
+        } else {
+            let mut size = read32(&data, pos + 22) as u64;
+            let mut compressed_size = read32(&data, pos + 18) as u64;
+            read_zip64(&name, extra, &mut [&mut size, &mut compressed_size])?;
+            let data_end = data_end(&data, data_start, compressed_size).ok_or_else(truncated)?;
+            (read32(&data, pos + 14), size, data_end, data_end)
+        };
+        if next > data.len() {
+            return Err(truncated());
+        }
+
+        if version & 0xff > MAX_KNOWN_VERSION {
+            diagnose(&name, DiagnosticKind::VersionNeeded(version & 0xff))?;
+        }
+        if missing_utf8_flag {
+            diagnose(&name, DiagnosticKind::MissingUtf8Flag)?;
+        }
+        let central_crc = central_entry.map_or(local_crc, |entry| entry.crc32);
+        if central_crc != local_crc {
+            diagnose(&name, DiagnosticKind::CrcHeaderMismatch { local: local_crc, central: central_crc })?;
+        }
+
+        password_for(flags, options.password.as_deref().map(str::as_bytes), &name)?;
+        let encryption = encryption(flags, method, local_crc, read16(&data, pos + 10), extra, &name)?;
+        let permissions = central_entry.map(|entry| entry.permissions);
+        let modified = match central_entry {
+            Some(entry) => entry.modified_time(),
+            None => modified_time(&DosDateTime::from_dos(read16(&data, pos + 10), read16(&data, pos + 12)), extra),
+        };
+        let path = entry_path(output_dir, &name, options.allow_unsafe_paths)?;
+        entries.push(LocalEntry { name, offset: pos, path, method, crc: [local_crc, central_crc], size, permissions, modified, encryption, data: data_start..data_end });
+        pos = next;
+    }
+
+    /*
+     パスワードが違うエントリがあれば、何も書き込まないうちにエラーにする
+     */
+    if let Some(password) = options.password.as_deref() {
+        for entry in &entries {
+            if let (Some(encryption), Some(compressed)) = (&entry.encryption, data.get(entry.data.clone())) {
+                check_password(compressed, password.as_bytes(), encryption, &entry.name).context_at(&entry.name, Phase::Extract, entry.offset as u64)?;
+            }
+        }
+    }
+
+    for entry in &entries {
+        extract(&data, entry, options).context_at(&entry.name, Phase::Extract, entry.offset as u64)?;
+        report.entries.push(EntryReport {
+            name: entry.name.clone(),
+            before_size: entry.size,
+            after_size: entry.data.len() as u64,
+            crc32: entry.crc[0],
+            profile: None,
+            changed: false,
+            retries: 0,
+            cut_at: None,
+            cached: false,
+        });
+    }
+
+    /*
+     ディレクトリの日時は中に展開すると変わり、読み取り専用にすると中に展開できなくなるため、全て展開した後に設定する
+     */
+    for entry in entries.iter().rev().filter(|entry| entry.name.ends_with('/')) {
+        restore_dir(&entry.path, entry.permissions, entry.modified, options.special_bits).context(&entry.name, Phase::Extract)?;
+    }
+    Ok(report)
+}
+
+/*
+ ローカルヘッダーから読み取った1つのエントリ
+ name:         エントリの名前
+ offset:       ローカルヘッダーの位置
+ path:         展開先のパス
+ method:       圧縮方法
+ crc:          ローカルヘッダーとセントラルヘッダーのcrc32（セントラルヘッダーがない場合は同じ値）
+ size:         展開した後のサイズ
+ permissions:  セントラルヘッダーに書かれた属性（セントラルヘッダーがない場合はNone）
+ modified:     最終更新日時（セントラルヘッダーがない場合はローカルヘッダーから求める）
+ encryption:   暗号化の方法（暗号化されていない場合はNone）
+ data:         圧縮されたデータの範囲
+ */
+struct LocalEntry {
+    name: String,
+    offset: usize,
+    path: PathBuf,
+    method: u16,
+    crc: [u32; 2],
+    size: u64,
+    permissions: Option<Permissions>,
+    modified: Option<SystemTime>,
+    encryption: Option<Encryption>,
+    data: std::ops::Range<usize>,
+}
+
+/*
+ 1つのエントリを展開してentry.pathに書き込む
+ データのcrc32はローカルヘッダーとセントラルヘッダーのどちらかと合えばよいものとする。
+ ファイルには最終更新日時と属性も設定する（ディレクトリはdecode_withで全て展開した後に設定する）。
+ 属性の特別なビットは、options.special_bitsの場合だけ設定する。
+ */
+fn extract(data: &[u8], entry: &LocalEntry, options: &DecodeOptions) -> Result<(), ZipError> {
+    let path = &entry.path;
+    if entry.name.ends_with('/') {
+        create_dir_all(path)?;
+        return Ok(());
+    }
+    let compressed = data.get(entry.data.clone())
+        .ok_or_else(|| ZipError::InvalidArchive(format!("truncated entry {}", entry.name)))?;
+    let decrypted = match (&entry.encryption, options.password.as_deref()) {
+        (Some(encryption), Some(password)) => Some(decrypt(compressed, password.as_bytes(), encryption, &entry.name)?),
+        _ => None,
+    };
+    let compressed = decrypted.as_deref().unwrap_or(compressed);
+    let contents = match check_contents(compressed, entry) {
+        Err(err) if entry.encryption.is_some_and(|encryption| encryption.blames_password(&err)) => {
+            return Err(ZipError::WrongPassword(entry.name.clone()));
+        }
+        result => result?,
+    };
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    write(path, &contents)?;
+    if let Some(modified) = entry.modified {
+        set_modified(path, modified)?;
+    }
+    if let Some(permissions) = entry.permissions {
+        permissions.restore(path, options.special_bits)?;
+    }
+    Ok(())
+}
+
+/*
+ 復号したcompressedを展開し、crc32とサイズがヘッダーと合っているかを確かめる
+ */
+fn check_contents(compressed: &[u8], entry: &LocalEntry) -> Result<Vec<u8>, ZipError> {
+    let contents = match entry.encryption.map_or(entry.method, |encryption| encryption.method(entry.method)) {
+        METHOD_DEFLATED => inflate(compressed)?,
+        METHOD_STORED => compressed.to_vec(),
+        method => return Err(ZipError::UnsupportedMethod { name: entry.name.clone(), method }),
+    };
+    let actual = crc32(&contents);
+    let check_crc = entry.encryption.is_none_or(|encryption| encryption.checks_crc());
+    if (check_crc && !entry.crc.contains(&actual)) || contents.len() as u64 != entry.size {
+        return Err(ZipError::CrcMismatch { name: entry.name.clone(), expected: entry.crc[0], actual });
+    }
+    Ok(contents)
+}
+
+/*
+ データディスクリプタを使うエントリの、データの終わりの位置とデータディスクリプタを読む
+ 圧縮後のサイズはセントラルヘッダーから取る。セントラルヘッダーがない場合はdeflateのデータを展開して終わりを探す
+ （無圧縮のデータは終わりが分からないためエラーにする）。
+ */
+fn descriptor_entry(data: &[u8], data_start: usize, method: u16, name: &str, extra: &[u8], central_entry: Option<&ZipEntry>) -> Result<(usize, DataDescriptor), ZipError> {
+    let compressed_size = match (central_entry, method) {
+        (Some(entry), _) => entry.compressed_size,
+        (None, METHOD_DEFLATED) => inflate_prefix(data.get(data_start..).unwrap_or_default())?.1 as u64,
+        (None, method) => return Err(ZipError::Unsupported(format!("data descriptor of {} without a central header (compression method {})", name, method))),
+    };
+    let truncated = || ZipError::InvalidArchive("data descriptor is truncated".to_string());
+    let data_end = data_end(data, data_start, compressed_size).ok_or_else(truncated)?;
+    let descriptor = read_descriptor(data, data_end, Some(compressed_size), has_zip64(extra)).ok_or_else(truncated)?;
+    Ok((data_end, descriptor))
+}
+
+/*
+ data_startからcompressed_sizeバイトのデータの終わりの位置を返す。dataの中に収まらない場合はNoneを返す。
+ */
+fn data_end(data: &[u8], data_start: usize, compressed_size: u64) -> Option<usize> {
+    usize::try_from(compressed_size).ok()
+        .and_then(|size| data_start.checked_add(size))
+        .filter(|&end| end <= data.len())
+}
+
+/*
+ エンドセントラルヘッダーを後ろから探し、セントラルヘッダーに書かれた
+ エントリをローカルヘッダーの位置ごとに返す。エンドセントラルヘッダーがない場合はNoneを返す。
+ */
+fn central_entries(data: &[u8], decoder: &dyn NameDecoder) -> Result<Option<HashMap<u64, ZipEntry>>, ZipError> {
+    let end = match find_end(data) {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let directory = central_directory(data, end, end as u64, data.len() as u64, |pos, buf| {
+        let start = pos as usize;
+        let bytes = data.get(start..start + buf.len())
+            .ok_or_else(|| ZipError::InvalidArchive(format!("truncated record at {}", pos)))?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    })?;
+    if directory.disk > 0 {
+        return Err(ZipError::Unsupported("decoding a split archive (use extract, which reads all volumes)".to_string()));
+    }
+    let start = directory.start as usize;
+    let entries = parse_central(data.get(start..start + directory.size).unwrap_or_default(), directory.count, directory.base, None, decoder)?;
+    Ok(Some(entries.into_iter().map(|entry| (entry.offset, entry)).collect()))
+}
+
+/*
+ エントリの名前から展開先のパスを作る
+ output_dirの外を指すことがないように、..や絶対パス、ドライブ文字（C:など）を含む名前はUnsafePathにする。
+ zipを作ったOSによっては \ を区切り文字として使うため、/ と \ のどちらも区切りとして調べる。
+ allow_unsafe_pathsの場合は調べずにそのままつなげる。
+ */
+pub(crate) fn entry_path(output_dir: &Path, name: &str, allow_unsafe_paths: bool) -> Result<PathBuf, ZipError> {
+    let relative = Path::new(name);
+    if allow_unsafe_paths {
+        return Ok(output_dir.join(relative));
+    }
+    let absolute = name.starts_with(['/', '\\']);
+    let drive = matches!(name.as_bytes(), [letter, b':', ..] if letter.is_ascii_alphabetic());
+    let parent = name.split(['/', '\\']).any(|part| part == "..");
+    let outside = relative.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if absolute || drive || parent || outside {
+        return Err(ZipError::UnsafePath(name.to_string()));
+    }
+    Ok(output_dir.join(relative))
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use crate::aes::Sha1;
+use crate::error::{Context, Phase};
+use crate::scan::read_entry;
+use crate::{ZipArchive, ZipError};
+
+const BUFFER_SIZE: usize = 64 * 1024;  // 展開したデータを受け取るバッファの大きさ
+
+/*
+ dedup_reportで見つかった、展開した内容が同じエントリの組
+ size:             展開後のサイズ（組のどのエントリでも同じ）
+ sha1:             展開したデータのSHA-1
+ names:            エントリの名前（セントラルヘッダーの順）
+ compressed_size:  組の全てのエントリの圧縮後のサイズの合計
+ savings:          1つだけ残した場合に減る圧縮後のサイズ（最も小さいもの以外の合計）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub sha1: [u8; 20],
+    pub names: Vec<String>,
+    pub compressed_size: u64,
+    pub savings: u64,
+}
+
+/*
+ archiveのファイルのエントリのうち、展開した内容が同じものを組にして、減らせるサイズの大きい順に返す。
+ ヘッダーのサイズとcrc32が同じエントリだけをopen_entryで展開してSHA-1を比べるため、他と重ならないエントリは展開しない。
+ 展開できないエントリがあった場合はエラーにする。
+ */
+pub fn dedup_report(archive: &mut ZipArchive) -> Result<Vec<DuplicateGroup>, ZipError> {
+    let mut candidates: HashMap<(u64, u32), Vec<usize>> = HashMap::new();
+    for (index, entry) in archive.iter().enumerate().filter(|(_, entry)| !entry.is_dir()) {
+        candidates.entry((entry.size, entry.crc32)).or_default().push(index);
+    }
+    let mut candidates: Vec<Vec<usize>> = candidates.into_values().filter(|indices| indices.len() > 1).collect();
+    candidates.sort_unstable();
+
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut groups = Vec::new();
+    for indices in candidates {
+        let mut by_digest: Vec<([u8; 20], Vec<usize>)> = Vec::new();
+        for index in indices {
+            let (name, declared) = match archive.by_index(index) {
+                Some(entry) => (entry.name.clone(), entry.size),
+                None => continue,
+            };
+            let mut sha1 = Sha1::new();
+            let mut size = 0;
+            read_entry(archive, index, &mut buffer, declared, &mut sha1, &mut size).context(&name, Phase::Read)?;
+            let digest = sha1.finish();
+            match by_digest.iter_mut().find(|(known, _)| *known == digest) {
+                Some((_, same)) => same.push(index),
+                None => by_digest.push((digest, vec![index])),
+            }
+        }
+        for (sha1, indices) in by_digest.into_iter().filter(|(_, indices)| indices.len() > 1) {
+            let entries: Vec<_> = indices.iter().filter_map(|&index| archive.by_index(index)).collect();
+            let compressed_size = entries.iter().map(|entry| entry.compressed_size).sum::<u64>();
+            let smallest = entries.iter().map(|entry| entry.compressed_size).min().unwrap_or_default();
+            groups.push(DuplicateGroup {
+                size: entries.first().map_or(0, |entry| entry.size),
+                sha1,
+                names: entries.iter().map(|entry| entry.name.clone()).collect(),
+                compressed_size,
+                savings: compressed_size - smallest,
+            });
+        }
+    }
+    groups.sort_by(|a, b| b.savings.cmp(&a.savings).then_with(|| a.names.cmp(&b.names)));
+    Ok(groups)
+}
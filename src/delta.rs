@@ -0,0 +1,127 @@
+use std::fs::{metadata, remove_dir, remove_dir_all, remove_file, symlink_metadata, File};
+use std::io::{BufWriter, ErrorKind};
+use std::path::Path;
+
+use crate::changes::{verify_against_fs, ChangeKind};
+use crate::decode::entry_path;
+use crate::error::{Context, Phase};
+use crate::input::with_permissions;
+use crate::meta::{push_string, Parser};
+use crate::workspace::write_output;
+use crate::{dos_time, time_data, Arena, ArchiveWriter, DecodeOptions, EncodeOptions, EntryOptions, Report, ZipArchive, ZipError, META_NAME};
+
+/*
+ 差分のzipで、消すファイルの一覧を格納するエントリの名前
+ このエントリは常に差分のzipの最初に置く。
+ */
+pub const DELTA_NAME: &str = ".zipper-delta.json";
+
+/*
+ previous_zipを作った後のrootの中の変化を、差分のzipとしてoutput_zipに書き込む。
+ 内容やサイズが変わったファイルと新しいファイルだけを格納し、なくなったもの（ファイルとディレクトリが入れ替わったものを含む）の名前は
+ {"removed": [...]} の形で.zipper-delta.jsonに書く。変化はverify_against_fsで調べるため、空のディレクトリが増えたことは含めない。
+ */
+pub fn delta(previous_zip: &str, root: impl AsRef<Path>, output_zip: &str) -> Result<Report, ZipError> {
+    let root = root.as_ref();
+    let previous = ZipArchive::open(previous_zip)?;
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for change in verify_against_fs(&previous, root)? {
+        match change.kind {
+            ChangeKind::Missing | ChangeKind::TypeChanged => removed.push(change.name),
+            ChangeKind::Modified { .. } | ChangeKind::SizeChanged { .. } | ChangeKind::Added => changed.push(change.name),
+        }
+    }
+
+    let options = EncodeOptions::default();
+    let clock = &*options.clock;
+    write_output(Path::new(output_zip), None, &[root.to_path_buf()], |file| {
+        let mut writer = ArchiveWriter::new(BufWriter::new(file));
+        let mut arena = Arena::new();
+        let json = removal_json(&removed);
+        writer.add_entry(&mut json.as_bytes(), DELTA_NAME, dos_time(clock.now(), clock), &options, &EntryOptions::new(), &mut arena)?;
+        for name in &changed {
+            let path = entry_path(root, name, false)?;
+            let file_metadata = metadata(&path).context(name, Phase::Open)?;
+            let mut input = File::open(&path).context(name, Phase::Open)?;
+            let entry = with_permissions(EntryOptions::new(), &file_metadata);
+            writer.add_entry(&mut input, name, time_data(&path.to_string_lossy(), clock), &options, &entry, &mut arena)?;
+        }
+        Ok(writer.finish()?)
+    })
+}
+
+/*
+ deltaで作った差分のzipを、前のzipを展開したdestに当てはめる。
+ 先に.zipper-delta.jsonに書かれたファイルやディレクトリを消し（既にないものは無視する）、それで空になったディレクトリも消してから、
+ 他のエントリを全て展開する。
+ 消す名前も展開するエントリの名前と同じように調べ、destの外を指すものがあれば何も消さずにUnsafePathにする。
+ */
+pub fn apply_delta(delta_zip: &str, dest: impl AsRef<Path>) -> Result<Report, ZipError> {
+    let dest = dest.as_ref();
+    let mut archive = ZipArchive::open(delta_zip)?;
+    if archive.index_of(DELTA_NAME).is_none() {
+        return Err(ZipError::InvalidArchive(format!("{} is not a delta (no {})", delta_zip, DELTA_NAME)));
+    }
+    let json = String::from_utf8(archive.read_to_vec(DELTA_NAME)?)
+        .map_err(|_| ZipError::InvalidMetadata(format!("{} is not UTF-8", DELTA_NAME)))?;
+    let paths = parse_removal(&json)?
+        .iter()
+        .map(|name| Ok((name.clone(), entry_path(dest, name, false)?)))
+        .collect::<Result<Vec<_>, ZipError>>()?;
+    for (name, path) in paths {
+        let result = match symlink_metadata(&path) {
+            Ok(file_metadata) if file_metadata.is_dir() => remove_dir_all(&path),
+            Ok(_) => remove_file(&path),
+            Err(err) => Err(err),
+        };
+        match result {
+            Err(err) if !matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => return Err(err).context(&name, Phase::Extract),
+            _ => {}
+        }
+
+        /*
+         ディレクトリがファイルに入れ替わった場合に展開できるように、消したことで空になったディレクトリも消す（空でなければ止める）
+         */
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|dir| dir.starts_with(dest) && *dir != dest) {
+            if remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+
+    let selected: Vec<usize> = archive.iter()
+        .zip(0..)
+        .filter(|(entry, _)| entry.name != DELTA_NAME && entry.name != META_NAME)
+        .map(|(_, index)| index)
+        .collect();
+    archive.extract_where(dest, &DecodeOptions::default(), selected)
+}
+
+fn removal_json(removed: &[String]) -> String {
+    let mut json = String::from("{\n  \"removed\": [");
+    for (i, name) in removed.iter().enumerate() {
+        json.push_str(if i == 0 { "\n    " } else { ",\n    " });
+        push_string(&mut json, name);
+    }
+    json.push_str(if removed.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+    json
+}
+
+/*
+ removal_jsonで作ったJSONを読み込む
+ */
+fn parse_removal(json: &str) -> Result<Vec<String>, ZipError> {
+    let mut parser = Parser::new(json);
+    parser.expect('{')?;
+    let key = parser.string()?;
+    if key != "removed" {
+        return Err(ZipError::InvalidMetadata(format!("unknown key: {}", key)));
+    }
+    parser.expect(':')?;
+    let removed = parser.array()?;
+    parser.expect('}')?;
+    Ok(removed)
+}
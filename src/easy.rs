@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use crate::{build, ZipArchive, ZipError};
+
+/*
+ よくある使い方を1行で行うための関数
+ オプションを細かく指定したい場合はbuild()やencode_withを使う。
+
+     zipper::zip_file("report.pdf", "report.zip")?;
+     zipper::zip_dir("assets", "assets.zip")?;
+     zipper::unzip_to("assets.zip", "out")?;
+ */
+
+/// inputのファイルを1つだけ格納したzipをoutputに作る。zipの中での名前はinputのファイル名になる。
+///
+/// ```
+/// # let dir = std::env::temp_dir().join(format!("zipper-doc-zip-file-{}", std::process::id()));
+/// # std::fs::create_dir_all(&dir)?;
+/// # std::env::set_current_dir(&dir)?;
+/// std::fs::write("report.txt", "quarterly numbers")?;
+/// zipper::zip_file("report.txt", "report.zip")?;
+/// assert_eq!(zipper::ZipArchive::open("report.zip")?.entries()[0].name, "report.txt");
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), zipper::ZipError>(())
+/// ```
+pub fn zip_file(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), ZipError> {
+    let input = input.as_ref();
+    let name = input.file_name()
+        .ok_or_else(|| std::io::Error::other(format!("{} has no file name", input.display())))?
+        .to_string_lossy()
+        .into_owned();
+    build().add_file(input, name)?.write_file(output)?;
+    Ok(())
+}
+
+/// dirの中のファイルを全て格納したzipをoutputに作る。zipの中での名前はdirからの相対パスになる。
+///
+/// ```
+/// # let dir = std::env::temp_dir().join(format!("zipper-doc-zip-dir-{}", std::process::id()));
+/// # std::fs::create_dir_all(&dir)?;
+/// # std::env::set_current_dir(&dir)?;
+/// # std::fs::create_dir_all("assets/images")?;
+/// # std::fs::write("assets/images/logo.svg", "<svg/>")?;
+/// std::fs::write("assets/readme.txt", "hello")?;
+/// zipper::zip_dir("assets", "assets.zip")?;
+/// assert!(zipper::ZipArchive::open("assets.zip")?.index_of("images/logo.svg").is_some());
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), zipper::ZipError>(())
+/// ```
+pub fn zip_dir(dir: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), ZipError> {
+    build().add_dir(dir)?.write_file(output)?;
+    Ok(())
+}
+
+/// zipのエントリを全てdestの中に展開する。destの外を指す名前のエントリがある場合はUnsafePathにする。
+///
+/// ```
+/// # let dir = std::env::temp_dir().join(format!("zipper-doc-unzip-to-{}", std::process::id()));
+/// # std::fs::create_dir_all(&dir)?;
+/// # std::env::set_current_dir(&dir)?;
+/// # std::fs::create_dir_all("assets")?;
+/// # std::fs::write("assets/readme.txt", "hello")?;
+/// zipper::zip_dir("assets", "assets.zip")?;
+/// zipper::unzip_to("assets.zip", "out")?;
+/// assert_eq!(std::fs::read_to_string("out/readme.txt")?, "hello");
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), zipper::ZipError>(())
+/// ```
+pub fn unzip_to(zip: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), ZipError> {
+    ZipArchive::open(zip)?.extract_all(dest)?;
+    Ok(())
+}
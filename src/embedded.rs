@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+use std::io::Read;
+use std::sync::OnceLock;
+
+use crate::archive::into_zip_error;
+use crate::{EntryReader, ZipArchive, ZipError};
+
+/*
+ プログラムに埋め込んだzip（include_zip!で作る）
+ 最初に使うときに1度だけセントラルヘッダーを読み、その後はどのスレッドからも同じZipArchiveを使う。
+ エントリは埋め込んだデータから直接読むため、zipの全体をコピーすることはない。
+
+     let data = zipper::include_zip!("assets.zip").read("textures/grass.png")?;
+
+ data:     埋め込んだzipのデータ
+ archive:  dataを開いたもの（開けなかった場合はそのエラーの説明）
+ */
+#[derive(Debug)]
+pub struct EmbeddedZip {
+    data: &'static [u8],
+    archive: OnceLock<Result<ZipArchive<'static>, String>>,
+}
+
+impl EmbeddedZip {
+    /*
+     dataのzipを、まだ開かずに持つ（include_zip!の中でstaticとして作るためにconstにしている）
+     */
+    pub const fn new(data: &'static [u8]) -> Self {
+        EmbeddedZip { data, archive: OnceLock::new() }
+    }
+
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+
+    /*
+     埋め込んだzipを開いたもの。開けない場合は、何度呼んでも同じ内容のInvalidArchiveにする。
+     */
+    pub fn archive(&self) -> Result<&ZipArchive<'static>, ZipError> {
+        self.archive
+            .get_or_init(|| ZipArchive::from_slice(self.data).map_err(|err| err.to_string()))
+            .as_ref()
+            .map_err(|err| ZipError::InvalidArchive(err.clone()))
+    }
+
+    /*
+     nameという名前のエントリを、展開しながら読み出せるようにする（ZipArchive::open_entry_detachedと同じ）
+     */
+    pub fn open(&self, name: &str) -> Result<EntryReader<'static>, ZipError> {
+        let archive = self.archive()?;
+        archive.open_entry_detached(index_of(archive, name)?)
+    }
+
+    /*
+     nameという名前のエントリの中身を返す。
+     無圧縮のエントリは埋め込んだデータの一部をそのまま借りて返し（crc32は確かめない）、
+     それ以外は展開してcrc32とサイズを確かめたものを返す。
+     */
+    pub fn read(&self, name: &str) -> Result<Cow<'static, [u8]>, ZipError> {
+        let archive = self.archive()?;
+        let index = index_of(archive, name)?;
+        if let Some(slice) = archive.stored_slice(index)? {
+            return Ok(Cow::Borrowed(slice));
+        }
+        let mut reader = archive.open_entry_detached(index)?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(into_zip_error)?;
+        Ok(Cow::Owned(data))
+    }
+}
+
+fn index_of(archive: &ZipArchive, name: &str) -> Result<usize, ZipError> {
+    archive.index_of(name).ok_or_else(|| ZipError::InvalidArchive(format!("no entry named {}", name)))
+}
+
+/*
+ include_zip!("assets.zip") で、zipをinclude_bytes!でプログラムに埋め込み、それを読む&'static EmbeddedZipを返す。
+ パスはinclude_bytes!と同じく、呼び出したソースファイルからの相対パスになる。
+ 呼び出した場所ごとに1つのstaticを作るため、何度通っても開くのは最初の1度だけになる。
+ */
+#[macro_export]
+macro_rules! include_zip {
+    ($path:expr) => {{
+        static EMBEDDED: $crate::EmbeddedZip = $crate::EmbeddedZip::new(include_bytes!($path));
+        &EMBEDDED
+    }};
+}
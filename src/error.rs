@@ -0,0 +1,233 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::archive::method_label;
+use crate::{Diagnostic, Violation};
+
+/*
+ このクレートで起こるエラーをまとめたもの
+ Io:             入出力のエラー
+ EntryTooLarge:  エントリのサイズがmax_entry_sizeを超えた
+ EntryTimeout:   エントリの圧縮にentry_timeout以上の時間がかかった
+ FileChanged:    読み込んでいる間にファイルが書き換えられた
+ ExtraFieldTooLarge:  拡張フィールドの長さが65535バイトを超えた
+ ReservedExtraField:  このクレートが使う番号の拡張フィールドを指定した
+ InvalidExtraField:   拡張フィールドの長さが途中で足りなくなっている
+ InvalidMetadata:     .zipper-meta.jsonの内容が読み込めない
+ InsufficientSpace:   一時ファイルを置くか出力先のファイルシステムの空きがzipの最大の大きさより少ない
+ BufferTooSmall:      encode_intoに渡されたバッファにzipが収まらない
+ InvalidArchive:      zipのヘッダーが壊れている
+ InvalidDeflate:      deflateで圧縮されたデータが壊れている
+ Unsupported:         対応していない機能（ブロックの種類や暗号など）が使われている
+ UnsupportedMethod:   エントリが対応していない圧縮方法（bzip2、LZMA、zstdなど）で圧縮されている
+ CrcMismatch:         展開したデータのcrc32がヘッダーの値と食い違う
+ InvalidPlacement:    エントリのデータを指定された位置に置けない
+ UnrepresentableName:  エントリの名前に、指定された文字コードで表せない文字が含まれている
+ InvalidEntries:      書き込む前に調べたエントリに問題があった（見つかった問題を全て持つ）
+ Nonstandard:         仕様から外れている（lenientで読み込めば展開できる）
+ UnsafePath:          エントリの名前が展開先の外を指している（..や絶対パス、ドライブ文字）
+ PasswordRequired:    エントリが暗号化されているのにパスワードが指定されていない
+ WrongPassword:       指定されたパスワードでは暗号化ヘッダーが合わない（ZipCryptoでは、復号したデータが壊れていた場合も）
+ AuthenticationFailed:  AESで暗号化されたデータの認証コードが合わない（データが壊れているか書き換えられている）
+ NotFound:            Vfsのパスに当たるファイルやディレクトリがない
+ NotAFile:            Vfsで開こうとしたパスがディレクトリ
+ NotADirectory:       Vfsで中を見ようとしたパスがファイル
+ EntryStillOpen:      ZipWriterでエントリを開いたまま、次のエントリを開こうとしたかfinishを呼んだ
+ NoEntryOpen:         ZipWriterでエントリを開かずに、データを書き込もうとしたかend_entryを呼んだ
+ AlreadyFinished:     ZipWriterでfinishを呼んだ後に、さらに書き込もうとした
+ Context:             どのエントリ（またはファイル）のどの処理で、どの位置で起きたかを付けたエラー
+ */
+#[derive(Debug)]
+pub enum ZipError {
+    Io(io::Error),
+    EntryTooLarge { name: String, limit: u64 },
+    EntryTimeout { name: String, limit: Duration },
+    FileChanged { name: String },
+    ExtraFieldTooLarge { name: String, size: usize },
+    ReservedExtraField { name: String, id: u16 },
+    InvalidExtraField,
+    InvalidMetadata(String),
+    InsufficientSpace { path: String, required: u64, available: u64 },
+    BufferTooSmall { required: usize, available: usize },
+    InvalidArchive(String),
+    InvalidDeflate(String),
+    Unsupported(String),
+    UnsupportedMethod { name: String, method: u16 },
+    CrcMismatch { name: String, expected: u32, actual: u32 },
+    InvalidPlacement { name: String, reason: String },
+    UnrepresentableName { name: String, encoding: String },
+    InvalidEntries(Vec<Violation>),
+    Nonstandard(Diagnostic),
+    UnsafePath(String),
+    PasswordRequired(String),
+    WrongPassword(String),
+    AuthenticationFailed(String),
+    NotFound(String),
+    NotAFile(String),
+    NotADirectory(String),
+    EntryStillOpen(String),
+    NoEntryOpen,
+    AlreadyFinished,
+    Context { name: String, phase: Phase, offset: Option<u64>, source: Box<ZipError> },
+}
+
+/*
+ エラーが起きたときに行っていた処理
+ Open:        ファイルを開く、またはファイルの情報を読む
+ Read:        ファイルやディレクトリ、zipのヘッダーを読む
+ Compress:    エントリを読み込みながら圧縮する
+ Write:       エントリをzipに書き込む
+ Extract:     エントリを展開してファイルに書き込む
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Open,
+    Read,
+    Compress,
+    Write,
+    Extract,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Open => "opening",
+            Phase::Read => "reading",
+            Phase::Compress => "compressing",
+            Phase::Write => "writing",
+            Phase::Extract => "extracting",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ZipError {
+    /*
+     io::Errorをエントリの名前付きのエラーにする。
+     LimitedReaderで制限を超えた場合のエラーはそれぞれの種類のエラーにする。
+     */
+    pub(crate) fn with_entry(err: io::Error, name: &str) -> Self {
+        match err.get_ref().and_then(|inner| inner.downcast_ref::<LimitExceeded>()) {
+            Some(LimitExceeded::Size(limit)) => ZipError::EntryTooLarge { name: name.to_string(), limit: *limit },
+            Some(LimitExceeded::Time(limit)) => ZipError::EntryTimeout { name: name.to_string(), limit: *limit },
+            None => ZipError::Io(err),
+        }
+    }
+
+    /*
+     エントリの名前や位置を持たないエラー（Io、InvalidDeflate、InvalidExtraField）にそれらを付ける。
+     他のエラーは既に名前を持っていて、種類で見分けて扱う呼び出し元もあるため、そのまま返す。
+     */
+    pub(crate) fn with_context(self, name: &str, phase: Phase, offset: Option<u64>) -> Self {
+        match self {
+            ZipError::Io(_) | ZipError::InvalidDeflate(_) | ZipError::InvalidExtraField => {
+                ZipError::Context { name: name.to_string(), phase, offset, source: Box::new(self) }
+            }
+            err => err,
+        }
+    }
+}
+
+/*
+ Resultのエラーにwith_contextで名前と処理を付けるためのもの
+ ? の前に .context(name, Phase::Read) のように書く。
+ */
+pub(crate) trait Context<T> {
+    fn context(self, name: &str, phase: Phase) -> Result<T, ZipError>;
+    fn context_at(self, name: &str, phase: Phase, offset: u64) -> Result<T, ZipError>;
+}
+
+impl<T, E: Into<ZipError>> Context<T> for Result<T, E> {
+    fn context(self, name: &str, phase: Phase) -> Result<T, ZipError> {
+        self.map_err(|err| err.into().with_context(name, phase, None))
+    }
+
+    fn context_at(self, name: &str, phase: Phase, offset: u64) -> Result<T, ZipError> {
+        self.map_err(|err| err.into().with_context(name, phase, Some(offset)))
+    }
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZipError::Io(err) => write!(f, "IO Error => {}", err),
+            ZipError::EntryTooLarge { name, limit } => write!(f, "{} is larger than the limit of {} bytes", name, limit),
+            ZipError::EntryTimeout { name, limit } => write!(f, "{} took longer than the limit of {:?}", name, limit),
+            ZipError::FileChanged { name } => write!(f, "{} was modified while it was being archived", name),
+            ZipError::ExtraFieldTooLarge { name, size } => write!(f, "extra fields of {} are {} bytes, more than the limit of 65535 bytes", name, size),
+            ZipError::ReservedExtraField { name, id } => write!(f, "extra field 0x{:04x} of {} is reserved", id, name),
+            ZipError::InvalidExtraField => write!(f, "extra field is truncated"),
+            ZipError::InvalidMetadata(message) => write!(f, "invalid metadata entry: {}", message),
+            ZipError::InsufficientSpace { path, required, available } => write!(f, "not enough space to write {}: up to {} bytes are needed but only {} bytes are available", path, required, available),
+            ZipError::InvalidArchive(message) => write!(f, "invalid archive: {}", message),
+            ZipError::InvalidDeflate(message) => write!(f, "invalid deflate data: {}", message),
+            ZipError::Unsupported(message) => write!(f, "unsupported: {}", message),
+            ZipError::UnsupportedMethod { name, method } => match method_label(*method) {
+                Some(label) => write!(f, "{} is compressed with method {} ({}), which is not supported", name, method, label),
+                None => write!(f, "{} is compressed with method {}, which is not supported", name, method),
+            },
+            ZipError::CrcMismatch { name, expected, actual } => write!(f, "crc32 of {} is {:08x} but the header says {:08x}", name, actual, expected),
+            ZipError::Nonstandard(diagnostic) => write!(f, "nonstandard archive: {} (use lenient mode to extract anyway)", diagnostic),
+            ZipError::InvalidPlacement { name, reason } => write!(f, "cannot place {}: {}", name, reason),
+            ZipError::UnrepresentableName { name, encoding } => write!(f, "{} cannot be represented in {}", name, encoding),
+            ZipError::InvalidEntries(violations) => {
+                write!(f, "{} problems found before writing:", violations.len())?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            ZipError::UnsafePath(name) => write!(f, "{} points outside of the output directory", name),
+            ZipError::PasswordRequired(name) => write!(f, "{} is encrypted and needs a password", name),
+            ZipError::WrongPassword(name) => write!(f, "wrong password for {}", name),
+            ZipError::AuthenticationFailed(name) => write!(f, "authentication code of {} does not match", name),
+            ZipError::NotFound(path) => write!(f, "{} is not in the archive", path),
+            ZipError::NotAFile(path) => write!(f, "{} is a directory", path),
+            ZipError::NotADirectory(path) => write!(f, "{} is not a directory", path),
+            ZipError::EntryStillOpen(name) => write!(f, "entry {} is still open", name),
+            ZipError::NoEntryOpen => write!(f, "no entry is open"),
+            ZipError::AlreadyFinished => write!(f, "the archive is already finished"),
+            ZipError::Context { name, phase, offset: Some(offset), source } => write!(f, "{} {} at offset {}: {}", phase, name, offset, source),
+            ZipError::Context { name, phase, offset: None, source } => write!(f, "{} {}: {}", phase, name, source),
+            ZipError::BufferTooSmall { required, available } => write!(f, "output buffer is too small: {} bytes are needed but only {} bytes are available", required, available),
+        }
+    }
+}
+
+impl error::Error for ZipError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ZipError::Io(err) => Some(err),
+            ZipError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ZipError {
+    fn from(err: io::Error) -> Self {
+        ZipError::Io(err)
+    }
+}
+
+/*
+ LimitedReaderで制限を超えた場合に、io::Errorの中に入れておくもの
+ */
+#[derive(Debug)]
+pub(crate) enum LimitExceeded {
+    Size(u64),
+    Time(Duration),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Size(limit) => write!(f, "entry is larger than {} bytes", limit),
+            LimitExceeded::Time(limit) => write!(f, "entry took longer than {:?}", limit),
+        }
+    }
+}
+
+impl error::Error for LimitExceeded {}
@@ -0,0 +1,133 @@
+use crate::aes::AES_EXTRA_ID;
+use crate::ZipError;
+
+/*
+ 拡張フィールドの1つ分
+ id:    拡張フィールドの種類を示す番号
+ data:  拡張フィールドの中身
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraField {
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+/*
+ このクレートが自分で書き込むため、アプリケーションからは指定できない拡張フィールドの番号
+ 0x0001: Zip64の拡張情報
+ 0x9901: WinZipのAES暗号の情報（EntryOptions::passwordで暗号化したときに書き込む）
+ 0xd935: データの位置を合わせるための詰め物（Androidのzipalignと同じ番号）
+ */
+pub(crate) const RESERVED_IDS: [u16; 3] = [0x0001, AES_EXTRA_ID, PADDING_ID];
+
+/*
+ データの位置を合わせるための詰め物の拡張フィールドの番号
+ */
+const PADDING_ID: u16 = 0xd935;
+
+/*
+ 拡張タイムスタンプ（UNIX時間で書かれた日時）の拡張フィールドの番号
+ */
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+/*
+ 詰め物の拡張フィールドの最小の長さ（番号と長さの4バイトと、揃える単位の2バイト）
+ */
+pub(crate) const MIN_PADDING_LEN: usize = 6;
+
+/*
+ 拡張フィールドの長さとして格納できる最大の長さ
+ */
+pub(crate) const MAX_EXTRA_LEN: usize = 0xffff;
+
+impl ExtraField {
+    pub fn new(id: u16, data: impl Into<Vec<u8>>) -> Self {
+        ExtraField { id, data: data.into() }
+    }
+}
+
+/*
+ 拡張フィールドを並べてヘッダーに書き込む形式にする
+ それぞれ 2byte: 番号, 2byte: 長さ, nbyte: 中身 の順になる。
+ 全体の長さが65535バイトを超える場合や、このクレートが使う番号が含まれている場合にはエラーにする。
+ */
+pub(crate) fn encode_extra_fields(name: &str, fields: &[ExtraField]) -> Result<Vec<u8>, ZipError> {
+    let mut buffer = Vec::new();
+    for field in fields {
+        if RESERVED_IDS.contains(&field.id) {
+            return Err(ZipError::ReservedExtraField { name: name.to_string(), id: field.id });
+        }
+        if field.data.len() > MAX_EXTRA_LEN - 4 {
+            return Err(ZipError::ExtraFieldTooLarge { name: name.to_string(), size: field.data.len() + 4 });
+        }
+        buffer.extend_from_slice(&field.id.to_le_bytes());
+        buffer.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(&field.data);
+    }
+    if buffer.len() > MAX_EXTRA_LEN {
+        return Err(ZipError::ExtraFieldTooLarge { name: name.to_string(), size: buffer.len() });
+    }
+    Ok(buffer)
+}
+
+/*
+ ヘッダーに書き込まれている拡張フィールドを読み込んで、1つずつに分ける
+ 長さが途中で足りなくなっている場合にはエラーにする。
+ */
+pub fn parse_extra_fields(data: &[u8]) -> Result<Vec<ExtraField>, ZipError> {
+    let mut fields = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(ZipError::InvalidExtraField);
+        }
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        if rest.len() < 4 + len {
+            return Err(ZipError::InvalidExtraField);
+        }
+        fields.push(ExtraField::new(id, &rest[4..4 + len]));
+        rest = &rest[4 + len..];
+    }
+    Ok(fields)
+}
+
+/*
+ 全体でlenバイトになる詰め物の拡張フィールドを作る
+ 2byte: 番号, 2byte: 長さ, 2byte: 揃える単位（位置を指定した場合は0）, 残り: 0 の順になる。
+ lenはMIN_PADDING_LEN以上でなければならない。
+ */
+pub(crate) fn padding_field(len: usize, alignment: u16) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(len);
+    buffer.extend_from_slice(&PADDING_ID.to_le_bytes());
+    buffer.extend_from_slice(&((len - 4) as u16).to_le_bytes());
+    buffer.extend_from_slice(&alignment.to_le_bytes());
+    buffer.resize(len, 0);
+    buffer
+}
+
+/*
+ ローカルヘッダーの拡張フィールドから詰め物を探し、書かれている揃える単位を返す
+ 位置を指定した詰め物の場合は0を、詰め物がない場合はNoneを返す。
+ */
+pub(crate) fn padding_alignment(extra: &[u8]) -> Option<u16> {
+    parse_extra_fields(extra).ok()?
+        .into_iter()
+        .find(|field| field.id == PADDING_ID)
+        .and_then(|field| field.data.get(..2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])))
+}
+
+/*
+ 拡張タイムスタンプ（0x5455）の拡張フィールドから、最終更新日時（UNIX時間）を読む
+ 1byte: どの日時が入っているかのフラグ（bit0: 最終更新日時）, 4byte: 最終更新日時, ... の順になる。
+ セントラルヘッダーには最終更新日時しか書かれないが、どちらでも先頭の4バイトを読めばよい。
+ */
+pub(crate) fn extended_mtime(extra: &[u8]) -> Option<i64> {
+    let field = parse_extra_fields(extra).ok()?
+        .into_iter()
+        .find(|field| field.id == EXTENDED_TIMESTAMP_ID)?;
+    match field.data.as_slice() {
+        [flags, a, b, c, d, ..] if flags & 0x01 != 0 => Some(i32::from_le_bytes([*a, *b, *c, *d]) as i64),
+        _ => None,
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+use crate::{EntryReader, Vfs, VfsMetadata, ZipArchive, ZipError, ZipVfs};
+
+/*
+ カーネルから来るリクエストの種類（fuse_kernel.hのfuse_opcode）
+ */
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const KERNEL_VERSION: u32 = 7;         // 対応しているプロトコルのメジャーバージョン
+const KERNEL_MINOR_VERSION: u32 = 31;  // 対応しているプロトコルのマイナーバージョン
+const ROOT_ID: u64 = 1;                // 一番上のディレクトリのinode番号
+const IN_HEADER_SIZE: usize = 40;      // fuse_in_headerの大きさ
+const OUT_HEADER_SIZE: usize = 16;     // fuse_out_headerの大きさ
+const BUFFER_SIZE: usize = 1 << 20;    // リクエストを読み込むバッファの大きさ（カーネルが送る最大のリクエストより大きくする）
+const MAX_WRITE: u32 = 4096;           // 書き込みのリクエストの最大の大きさ（読み取り専用のため使わない）
+const ATTR_TTL: u64 = 60;              // カーネルが名前や属性を覚えておく秒数（zipは書き換えないため長めにする）
+const BLOCK_SIZE: u32 = 4096;          // statで返すブロックの大きさ
+const MAX_NAME_LEN: u32 = 255;         // statfsで返す名前の最大の長さ
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;  // 開き直してもページキャッシュを捨てない
+const DT_DIR: u32 = 4;                 // readdirで返す種類: ディレクトリ
+const DT_REG: u32 = 8;                 // readdirで返す種類: 普通のファイル
+const DIRENT_HEADER_SIZE: usize = 24;  // fuse_direntの名前より前の部分の大きさ
+
+/*
+ zipを読み取り専用のファイルシステムとしてmountpointにマウントし、アンマウントされるまでリクエストに答え続ける（fuse featureで使える）。
+ ZipVfsでディレクトリの木を作り、開いたファイルはそれぞれopen_entry_detachedで開き直したEntryReaderで読む。
+ カーネルの読み出しは途中の位置から来ることがあるため、EntryReaderをシークして読む（前に戻る場合は展開し直す）。
+ rootで動かしている場合は/dev/fuseを直接マウントし、そうでない場合はfusermount3（なければfusermount）に頼む。
+ 止めるには、umount mountpoint（rootでない場合はfusermount -u mountpoint）でアンマウントする。
+ */
+pub fn mount(archive: ZipArchive<'_>, mountpoint: &Path) -> Result<(), ZipError> {
+    let mut server = Server::new(ZipVfs::new(archive))?;
+    let mut device = open_device(mountpoint)?;
+    let result = server.run(&mut device);
+    if result.is_err() {
+        if let Ok(target) = CString::new(mountpoint.as_os_str().as_bytes()) {
+            unsafe {
+                libc::umount2(target.as_ptr(), libc::MNT_DETACH);
+            }
+        }
+    }
+    result
+}
+
+/*
+ /dev/fuseを開いてmountpointにマウントする。権限がない場合はfusermountにマウントしてもらい、開いたものを受け取る。
+ */
+fn open_device(mountpoint: &Path) -> Result<File, ZipError> {
+    let device = OpenOptions::new().read(true).write(true).custom_flags(libc::O_CLOEXEC).open("/dev/fuse")?;
+    let target = CString::new(mountpoint.as_os_str().as_bytes())
+        .map_err(|_| ZipError::NotFound(mountpoint.display().to_string()))?;
+    let options = format!("fd={},rootmode=40000,user_id={},group_id={}", device.as_raw_fd(), unsafe { libc::getuid() }, unsafe { libc::getgid() });
+    let options = CString::new(options).unwrap_or_default();
+    let flags = libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV;
+    let mounted = unsafe {
+        libc::mount(b"zipper\0".as_ptr().cast(), target.as_ptr(), b"fuse.zipper\0".as_ptr().cast(), flags, options.as_ptr().cast())
+    };
+    if mounted == 0 {
+        return Ok(device);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::EPERM) {
+        return Err(err.into());
+    }
+    fusermount(mountpoint)
+}
+
+/*
+ fusermountを_FUSE_COMMFDの方法で呼び、マウントした/dev/fuseをUnixソケットで受け取る
+ */
+fn fusermount(mountpoint: &Path) -> Result<File, ZipError> {
+    let (ours, theirs) = UnixStream::pair()?;
+    if unsafe { libc::fcntl(theirs.as_raw_fd(), libc::F_SETFD, 0) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut last_err = None;
+    for program in ["fusermount3", "fusermount"] {
+        let status = Command::new(program)
+            .args(["-o", "ro,nosuid,nodev,fsname=zipper,subtype=zipper", "--"])
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+            .status();
+        match status {
+            Ok(status) if status.success() => return receive_fd(&ours),
+            Ok(status) => return Err(io::Error::other(format!("{} failed with {}", program, status)).into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => last_err = Some(err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let err = last_err.unwrap_or_else(|| ErrorKind::NotFound.into());
+    Err(io::Error::new(err.kind(), "mounting needs root or fusermount3").into())
+}
+
+/*
+ socketからSCM_RIGHTSで送られたファイルディスクリプタを1つ受け取る
+ */
+fn receive_fd(socket: &UnixStream) -> Result<File, ZipError> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec { iov_base: byte.as_mut_ptr().cast(), iov_len: byte.len() };
+    let mut control = [0u8; 64];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = control.len() as _;
+    if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+    if header.is_null() || unsafe { (*header).cmsg_type } != libc::SCM_RIGHTS {
+        return Err(io::Error::other("fusermount did not send /dev/fuse").into());
+    }
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<libc::c_int>()) };
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/*
+ マウントしたzipへのリクエストに答えるもの
+ vfs:      読んでいるzip
+ paths:    inode番号から1を引いた位置に、そのパスを置いたもの（最初は一番上のディレクトリ）
+ inodes:   パスからinode番号を引く表
+ handles:  開いているファイル（ファイルハンドルから引く）
+ next_handle:  次に開いたファイルに付ける番号
+ total_size:   全てのエントリの展開した大きさの合計（statfsで返す）
+ uid, gid:     全てのファイルの持ち主にするユーザーとグループ（マウントしたユーザー）
+ */
+struct Server<'d> {
+    vfs: ZipVfs<'d>,
+    paths: Vec<String>,
+    inodes: HashMap<String, u64>,
+    handles: HashMap<u64, EntryReader<'d>>,
+    next_handle: u64,
+    total_size: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl<'d> Server<'d> {
+    /*
+     ディレクトリの木を辿って、全てのパスにinode番号を付ける（zipの中は変わらないため、最初に1度だけ付ける）
+     */
+    fn new(mut vfs: ZipVfs<'d>) -> Result<Self, ZipError> {
+        let mut paths = vec![String::new()];
+        let mut next = 0;
+        while let Some(dir) = paths.get(next).cloned() {
+            next += 1;
+            if vfs.stat(&dir)?.is_dir {
+                paths.extend(vfs.read_dir(&dir)?.into_iter().map(|entry| join(&dir, &entry.name)));
+            }
+        }
+        let inodes = paths.iter().enumerate().map(|(index, path)| (path.clone(), index as u64 + ROOT_ID)).collect();
+        let total_size = vfs.archive().iter().map(|entry| entry.size).sum();
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok(Server { vfs, paths, inodes, handles: HashMap::new(), next_handle: 1, total_size, uid, gid })
+    }
+
+    /*
+     アンマウントされるまで、deviceからリクエストを読んで答える
+     */
+    fn run(&mut self, device: &mut File) -> Result<(), ZipError> {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        loop {
+            let len = match device.read(&mut buffer) {
+                Ok(len) => len,
+                Err(err) if err.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted || err.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let request = buffer.get(..len).unwrap_or_default();
+            let (opcode, unique, node) = (u32_at(request, 4), u64_at(request, 8), u64_at(request, 16));
+            let body = request.get(IN_HEADER_SIZE..).unwrap_or_default();
+            let reply = match self.handle(opcode, node, body) {
+                Some(reply) => reply,
+                None => continue,
+            };
+            let (error, data) = match reply {
+                Ok(data) => (0, data),
+                Err(errno) => (-errno, Vec::new()),
+            };
+            let mut out = Vec::with_capacity(OUT_HEADER_SIZE + data.len());
+            put32(&mut out, (OUT_HEADER_SIZE + data.len()) as u32);
+            put32(&mut out, error as u32);
+            put64(&mut out, unique);
+            out.extend_from_slice(&data);
+            match device.write(&out) {
+                Err(err) if err.raw_os_error() != Some(libc::ENOENT) => return Err(err.into()),
+                _ => {}
+            }
+            if opcode == FUSE_DESTROY {
+                return Ok(());
+            }
+        }
+    }
+
+    /*
+     1つのリクエストに答える。返事のいらないリクエストではNoneを、失敗した場合はerrnoを返す。
+     */
+    fn handle(&mut self, opcode: u32, node: u64, body: &[u8]) -> Option<Result<Vec<u8>, i32>> {
+        let reply = match opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            FUSE_INIT => Ok(init_reply(body)),
+            FUSE_LOOKUP => self.lookup(node, body),
+            FUSE_GETATTR => self.getattr(node),
+            FUSE_OPEN => self.open(node, body),
+            FUSE_READ => self.read(body),
+            FUSE_RELEASE => {
+                self.handles.remove(&u64_at(body, 0));
+                Ok(Vec::new())
+            }
+            FUSE_OPENDIR => self.opendir(node),
+            FUSE_READDIR => self.readdir(node, body),
+            FUSE_STATFS => Ok(self.statfs()),
+            FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_ACCESS | FUSE_DESTROY => Ok(Vec::new()),
+            _ => Err(libc::ENOSYS),
+        };
+        Some(reply)
+    }
+
+    fn path(&self, node: u64) -> Result<&str, i32> {
+        let index = node.checked_sub(ROOT_ID).ok_or(libc::ENOENT)?;
+        self.paths.get(index as usize).map(String::as_str).ok_or(libc::ENOENT)
+    }
+
+    fn stat(&self, node: u64) -> Result<VfsMetadata, i32> {
+        self.vfs.stat(self.path(node)?).map_err(|err| errno(&err))
+    }
+
+    fn lookup(&self, parent: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let name = body.split(|&byte| byte == 0).next().unwrap_or_default();
+        let name = std::str::from_utf8(name).map_err(|_| libc::ENOENT)?;
+        let node = *self.inodes.get(&join(self.path(parent)?, name)).ok_or(libc::ENOENT)?;
+        let mut out = Vec::new();
+        put64(&mut out, node);
+        put64(&mut out, 0);
+        put64(&mut out, ATTR_TTL);
+        put64(&mut out, ATTR_TTL);
+        put32(&mut out, 0);
+        put32(&mut out, 0);
+        self.put_attr(&mut out, node, &self.stat(node)?);
+        Ok(out)
+    }
+
+    fn getattr(&self, node: u64) -> Result<Vec<u8>, i32> {
+        let mut out = Vec::new();
+        put64(&mut out, ATTR_TTL);
+        put32(&mut out, 0);
+        put32(&mut out, 0);
+        self.put_attr(&mut out, node, &self.stat(node)?);
+        Ok(out)
+    }
+
+    fn open(&mut self, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        if u32_at(body, 0) as i32 & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        let index = self.vfs.index_of(self.path(node)?).map_err(|err| errno(&err))?;
+        let reader = self.vfs.archive().open_entry_detached(index).map_err(|err| errno(&err))?;
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, reader);
+        Ok(open_reply(handle, FOPEN_KEEP_CACHE))
+    }
+
+    fn read(&mut self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let (handle, offset, size) = (u64_at(body, 0), u64_at(body, 8), u32_at(body, 16));
+        let reader = self.handles.get_mut(&handle).ok_or(libc::EBADF)?;
+        let mut data = Vec::with_capacity(size as usize);
+        let result = reader.seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.by_ref().take(size as u64).read_to_end(&mut data));
+        if let Err(err) = result {
+            eprintln!("{}: {}", reader.entry().name, err);
+            return Err(libc::EIO);
+        }
+        Ok(data)
+    }
+
+    fn opendir(&self, node: u64) -> Result<Vec<u8>, i32> {
+        if !self.stat(node)?.is_dir {
+            return Err(libc::ENOTDIR);
+        }
+        Ok(open_reply(0, 0))
+    }
+
+    /*
+     .と..に続けてディレクトリの中のものを、offset番目からsizeバイトに収まるだけ返す
+     */
+    fn readdir(&self, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let (offset, size) = (u64_at(body, 8), u32_at(body, 16) as usize);
+        let path = self.path(node)?;
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let parent = if path.is_empty() { node } else { self.inodes.get(parent).copied().unwrap_or(ROOT_ID) };
+        let mut entries = vec![(".".to_string(), node, DT_DIR), ("..".to_string(), parent, DT_DIR)];
+        for entry in self.vfs.read_dir(path).map_err(|err| errno(&err))? {
+            let child = self.inodes.get(&join(path, &entry.name)).copied().ok_or(libc::ENOENT)?;
+            entries.push((entry.name, child, if entry.metadata.is_dir { DT_DIR } else { DT_REG }));
+        }
+
+        let mut out = Vec::new();
+        for (position, (name, child, kind)) in entries.into_iter().enumerate().skip(offset as usize) {
+            let len = (DIRENT_HEADER_SIZE + name.len() + 7) & !7;
+            if out.len() + len > size {
+                break;
+            }
+            put64(&mut out, child);
+            put64(&mut out, position as u64 + 1);
+            put32(&mut out, name.len() as u32);
+            put32(&mut out, kind);
+            out.extend_from_slice(name.as_bytes());
+            out.resize(out.len() + len - DIRENT_HEADER_SIZE - name.len(), 0);
+        }
+        Ok(out)
+    }
+
+    fn statfs(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put64(&mut out, self.total_size.div_ceil(BLOCK_SIZE as u64));
+        put64(&mut out, 0);
+        put64(&mut out, 0);
+        put64(&mut out, self.paths.len() as u64);
+        put64(&mut out, 0);
+        put32(&mut out, BLOCK_SIZE);
+        put32(&mut out, MAX_NAME_LEN);
+        put32(&mut out, BLOCK_SIZE);
+        out.resize(out.len() + 4 + 6 * 4, 0);
+        out
+    }
+
+    /*
+     fuse_attrを書き込む。属性の分からないものはファイルを0644、ディレクトリを0755にする。
+     */
+    fn put_attr(&self, out: &mut Vec<u8>, node: u64, metadata: &VfsMetadata) {
+        let (kind, default_mode, nlink) = if metadata.is_dir { (libc::S_IFDIR, 0o755, 2) } else { (libc::S_IFREG, 0o644, 1) };
+        let mode = metadata.permissions.map_or(default_mode, |permissions| permissions.unix_mode() & 0o7777);
+        let modified = metadata.modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+        put64(out, node);
+        put64(out, metadata.size);
+        put64(out, metadata.size.div_ceil(512));
+        for _ in 0..3 {
+            put64(out, modified.as_secs());
+        }
+        for _ in 0..3 {
+            put32(out, modified.subsec_nanos());
+        }
+        put32(out, kind | mode);
+        put32(out, nlink);
+        put32(out, self.uid);
+        put32(out, self.gid);
+        put32(out, 0);
+        put32(out, BLOCK_SIZE);
+        put32(out, 0);
+    }
+}
+
+/*
+ INITへの返事（fuse_init_out）。カーネルのほうが古い場合もそのバージョンで答える。
+ */
+fn init_reply(body: &[u8]) -> Vec<u8> {
+    let minor = u32_at(body, 4).min(KERNEL_MINOR_VERSION);
+    let mut out = Vec::new();
+    put32(&mut out, KERNEL_VERSION);
+    put32(&mut out, minor);
+    put32(&mut out, u32_at(body, 8));
+    put32(&mut out, 0);
+    put16(&mut out, 0);
+    put16(&mut out, 0);
+    put32(&mut out, MAX_WRITE);
+    put32(&mut out, 1);
+    out.resize(64, 0);
+    out
+}
+
+/*
+ OPENとOPENDIRへの返事（fuse_open_out）
+ */
+fn open_reply(handle: u64, flags: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    put64(&mut out, handle);
+    put32(&mut out, flags);
+    put32(&mut out, 0);
+    out
+}
+
+/*
+ エラーをカーネルに返すerrnoにする
+ */
+fn errno(err: &ZipError) -> i32 {
+    match err {
+        ZipError::Context { source, .. } => errno(source),
+        ZipError::NotFound(_) => libc::ENOENT,
+        ZipError::NotAFile(_) => libc::EISDIR,
+        ZipError::NotADirectory(_) => libc::ENOTDIR,
+        ZipError::PasswordRequired(_) | ZipError::WrongPassword(_) => libc::EACCES,
+        _ => libc::EIO,
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) }
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    data.get(pos..pos + 4).and_then(|bytes| bytes.try_into().ok()).map_or(0, u32::from_ne_bytes)
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    data.get(pos..pos + 8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_ne_bytes)
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn put64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
@@ -0,0 +1,60 @@
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::tar::{TarKind, TarReader};
+use crate::workspace::write_output;
+use crate::writer::archive_comment;
+use crate::{dos_time, Arena, ArchiveWriter, EncodeOptions, EntryOptions, Permissions, Report, ZipError};
+
+/*
+ gitのリポジトリrepoのrevの時点のファイルをzipにしてoutput_fileに書き込む。
+ git archive --format=tar の出力を読み込んで、そのままzipのエントリにしている。
+ それぞれのエントリの日時には、git archiveが設定するコミットの日時を使う。
+ シンボリックリンクなどの通常のファイルとディレクトリ以外のものは格納しない。
+ 途中で失敗した場合には、書き込み途中のoutput_fileを削除する。
+ gitがエラー出力を多く書いてパイプが一杯になっても止まらないように、エラー出力は別のスレッドで読み続ける。
+ */
+pub fn git_archive(repo: &Path, rev: &str, output_file: &str, options: &EncodeOptions) -> Result<Report, ZipError> {
+    let mut child = Command::new("git")
+        .arg("-C").arg(repo)
+        .arg("archive").arg("--format=tar").arg(rev)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("failed to read the output of git archive"))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| io::Error::other("failed to read the error output of git archive"))?;
+    let stderr_reader = thread::spawn(move || {
+        let mut message = Vec::new();
+        let _ = stderr.read_to_end(&mut message);
+        message
+    });
+    let mut tar = TarReader::new(BufReader::new(stdout));
+    write_output(Path::new(output_file), options.tmpdir.as_deref(), &[repo.to_path_buf()], |file| {
+        let mut writer = ArchiveWriter::new(BufWriter::new(file)).with_comment(archive_comment(options)?);
+        let mut arena = Arena::new();
+        while let Some(entry) = tar.next_entry()? {
+            let time = dos_time(entry.mtime, &*options.clock);
+            let mode = if entry.kind == TarKind::Directory { 0o040000 | entry.mode } else { entry.mode };
+            let entry_options = EntryOptions { permissions: Some(Permissions::from_unix_mode(mode)), ..EntryOptions::default() };
+            match entry.kind {
+                TarKind::File => {
+                    writer.add_entry(&mut tar, &entry.name, time, options, &entry_options, &mut arena)?;
+                }
+                TarKind::Directory => {
+                    writer.add_entry(&mut io::empty(), &entry.name, time, options, &entry_options, &mut arena)?;
+                }
+                TarKind::Other => {}
+            }
+        }
+
+        let status = child.wait()?;
+        let message = stderr_reader.join().unwrap_or_default();
+        if !status.success() {
+            let message = String::from_utf8_lossy(&message);
+            return Err(io::Error::other(format!("git archive failed: {}", message.trim())).into());
+        }
+        Ok(writer.finish()?)
+    })
+}
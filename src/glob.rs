@@ -0,0 +1,87 @@
+/*
+ nameがpatternに当てはまるかどうかを調べる
+ *  は / 以外の0文字以上、** は / を含む0文字以上、? は / 以外の1文字に当てはまる。
+ patternに / が含まれない場合は、nameの最後の部分（ファイル名）だけと比べる。
+ 例えば *.json はどのディレクトリの中の .json にも当てはまり、docs/ の後に ** を続けたものは docs/ の中の全てに当てはまる。
+ */
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let name = if pattern.contains('/') { name } else { file_name(name) };
+    matches(pattern.as_bytes(), name.as_bytes())
+}
+
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=name.len()).filter_map(|skip| name.get(skip..)).any(|tail| matches(rest, tail)),
+        [b'*', rest @ ..] => {
+            let limit = name.iter().position(|&byte| byte == b'/').unwrap_or(name.len());
+            (0..=limit).filter_map(|skip| name.get(skip..)).any(|tail| matches(rest, tail))
+        }
+        [b'?', rest @ ..] => match name {
+            [first, tail @ ..] => *first != b'/' && matches(rest, tail),
+            [] => false,
+        },
+        [byte, rest @ ..] => match name {
+            [first, tail @ ..] => first == byte && matches(rest, tail),
+            [] => false,
+        },
+    }
+}
+
+/*
+ 名前の最後の部分（ディレクトリの場合は最後の / を除いた部分）
+ */
+fn file_name(name: &str) -> &str {
+    name.trim_end_matches('/').rsplit('/').next().unwrap_or(name)
+}
+
+/*
+ patternの最初の * や ? より前の部分（当てはまる名前は全てこれから始まる）
+ */
+fn literal_prefix(pattern: &str) -> &str {
+    pattern.split(['*', '?']).next().unwrap_or_default()
+}
+
+/*
+ エントリの名前を並べ替えておき、パターンに当てはまるものを速く探すための索引
+ パターンの先頭の決まった部分から始まる名前の範囲を二分探索で絞り込み、その中だけをパターンと比べる。
+ paths:  名前の順に並べたエントリの位置（/ を含むパターンに使う）
+ files:  名前の最後の部分の順に並べたエントリの位置（/ を含まないパターンに使う）
+ */
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NameIndex {
+    paths: Vec<usize>,
+    files: Vec<usize>,
+}
+
+impl NameIndex {
+    /*
+     0からlen-1までのエントリの名前をnameで引いて、並べ替える
+     */
+    pub(crate) fn new<'a, F: Fn(usize) -> &'a str>(len: usize, name: F) -> Self {
+        let mut paths: Vec<usize> = (0..len).collect();
+        paths.sort_unstable_by_key(|&index| name(index));
+        let mut files = paths.clone();
+        files.sort_by_cached_key(|&index| file_name(name(index)));
+        NameIndex { paths, files }
+    }
+
+    /*
+     patternに当てはまるエントリの位置を返す（nameはnewに渡したものと同じ名前を返さなければならない）
+     */
+    pub(crate) fn matching<'a, F: Fn(usize) -> &'a str>(&self, pattern: &str, name: F) -> Vec<usize> {
+        let (sorted, key): (&[usize], fn(&str) -> &str) = if pattern.contains('/') {
+            (&self.paths, |name| name)
+        } else {
+            (&self.files, file_name)
+        };
+        let prefix = literal_prefix(pattern);
+        let start = sorted.partition_point(|&index| key(name(index)) < prefix);
+        sorted.get(start..).unwrap_or_default()
+            .iter()
+            .take_while(|&&index| key(name(index)).starts_with(prefix))
+            .filter(|&&index| glob_match(pattern, name(index)))
+            .copied()
+            .collect()
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::inflate::Inflater;
+use crate::{Crc32, ZipError};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];  // gzipの先頭の2バイト
+const METHOD_DEFLATE: u8 = 8;              // gzipで使える唯一の圧縮方法
+const HEADER_SIZE: usize = 10;             // ヘッダーの固定長の部分
+const TRAILER_SIZE: usize = 8;             // 後ろに付くcrc32と展開後のサイズ
+
+/*
+ ヘッダーのフラグ（FTEXTは読むときには使わない）
+ */
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAG_RESERVED: u8 = 0xe0;
+
+/*
+ dataがgzipの先頭かどうか
+ */
+pub(crate) fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/*
+ 1つのメンバーだけのgzipを展開しながら読み出すためのもの
+ 最後まで読み込んだときに、後ろに付いたcrc32と展開後のサイズを確かめる。
+ 続けて別のメンバーがある（gzipをつなげたもの）場合はUnsupportedにする。
+ inflater:  ヘッダーの後ろのdeflateのデータを展開するもの
+ crc:       今までに読み出したデータのcrc32
+ size:      今までに読み出したバイト数（gzipに書かれるのと同じく、2^32で割った余り）
+ name:      ヘッダーに書かれた元のファイル名（ない場合はNone）
+ mtime:     ヘッダーに書かれた最終更新日時（UNIX時間。0の場合は書かれていない）
+ finished:  後ろのcrc32を確かめ終わったかどうか
+ */
+pub(crate) struct GzipReader<R: Read> {
+    inflater: Inflater<R>,
+    crc: Crc32,
+    size: u32,
+    name: Option<String>,
+    mtime: u32,
+    finished: bool,
+}
+
+impl<R: Read> GzipReader<R> {
+    /*
+     inputの先頭のヘッダーを読む。gzipでない場合や、deflate以外の圧縮方法の場合はエラーにする。
+     */
+    pub(crate) fn new(mut input: R) -> Result<Self, ZipError> {
+        let mut header = [0u8; HEADER_SIZE];
+        input.read_exact(&mut header)?;
+        let [id1, id2, method, flags, m0, m1, m2, m3, _, _] = header;
+        if !is_gzip(&[id1, id2]) {
+            return Err(ZipError::InvalidArchive("not a gzip file".to_string()));
+        }
+        if method != METHOD_DEFLATE || flags & FLAG_RESERVED != 0 {
+            return Err(ZipError::Unsupported(format!("gzip with method {} and flags {:#04x}", method, flags)));
+        }
+        if flags & FLAG_EXTRA != 0 {
+            let mut len = [0u8; 2];
+            input.read_exact(&mut len)?;
+            io::copy(&mut (&mut input).take(u16::from_le_bytes(len) as u64), &mut io::sink())?;
+        }
+        let name = if flags & FLAG_NAME != 0 { Some(String::from_utf8_lossy(&read_c_string(&mut input)?).into_owned()) } else { None };
+        if flags & FLAG_COMMENT != 0 {
+            read_c_string(&mut input)?;
+        }
+        if flags & FLAG_HCRC != 0 {
+            input.read_exact(&mut [0u8; 2])?;
+        }
+        Ok(GzipReader {
+            inflater: Inflater::new(input),
+            crc: Crc32::new(),
+            size: 0,
+            name,
+            mtime: u32::from_le_bytes([m0, m1, m2, m3]),
+            finished: false,
+        })
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn mtime(&self) -> Option<u32> {
+        Some(self.mtime).filter(|&mtime| mtime != 0)
+    }
+
+    /*
+     後ろに付いたcrc32と展開後のサイズを確かめ、別のメンバーが続いていないかを調べる
+     */
+    fn finish(&mut self) -> io::Result<()> {
+        let input = self.inflater.get_mut();
+        let mut trailer = [0u8; TRAILER_SIZE];
+        input.read_exact(&mut trailer)?;
+        let [c0, c1, c2, c3, s0, s1, s2, s3] = trailer;
+        let expected = u32::from_le_bytes([c0, c1, c2, c3]);
+        let actual = self.crc.clone().get_crc32();
+        let name = self.name.clone().unwrap_or_default();
+        if expected != actual || u32::from_le_bytes([s0, s1, s2, s3]) != self.size {
+            return Err(io::Error::new(ErrorKind::InvalidData, ZipError::CrcMismatch { name, expected, actual }));
+        }
+        let mut next = Vec::with_capacity(GZIP_MAGIC.len());
+        input.take(GZIP_MAGIC.len() as u64).read_to_end(&mut next)?;
+        if is_gzip(&next) {
+            return Err(io::Error::new(ErrorKind::InvalidData, ZipError::Unsupported("gzip with several members".to_string())));
+        }
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inflater.read(buf)?;
+        for &byte in buf.iter().take(len) {
+            self.crc.push_buf(byte);
+        }
+        self.size = self.size.wrapping_add(len as u32);
+        if len == 0 && !buf.is_empty() && !self.finished {
+            self.finish()?;
+        }
+        Ok(len)
+    }
+}
+
+/*
+ 0で終わる文字列を読む（0は含めない）
+ */
+fn read_c_string<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        input.read_exact(&mut byte)?;
+        match byte {
+            [0] => return Ok(bytes),
+            [value] => bytes.push(value),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+
+use crate::input::walk;
+use crate::{encode_entry, time_data, Arena, ArchiveWriter, EncodeOptions, EntryOptions, Permissions};
+
+/*
+ HTTPで待ち受けて、GET /path でroot以下のファイルをzipにして返す。
+ pathがディレクトリの場合は、中のファイルを全てそのディレクトリからの相対パスの名前で格納する（addと同じくたどる）。
+ zipは出来上がった順にそのままソケットに書き込むため、出力先をシークする必要はない。
+ 圧縮したデータもメモリに溜めずに書き込み、crc32とサイズはデータディスクリプタに書く（EncodeOptions.streaming）。
+ Content-Lengthは付けず、接続を閉じることでレスポンスの終わりを示している。
+ */
+pub fn serve_http(addr: &str, root: &Path) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr)?;
+    let mut arena = Arena::new();
+    for stream in listener.incoming() {
+        if let Err(err) = handle_client(stream?, root, &mut arena) {
+            eprintln!("IO Error => {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handle_client(mut stream: TcpStream, root: &Path, arena: &mut Arena) -> Result<(), Error> {
+    let mut request_line = String::new();
+    BufReader::new(&mut stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("");
+
+    if method != "GET" {
+        return respond_error(&mut stream, "405 Method Not Allowed", "only GET is supported");
+    }
+    let path = match resolve(root, target) {
+        Some(path) => path,
+        None => return respond_error(&mut stream, "400 Bad Request", "invalid path"),
+    };
+    if path.is_dir() {
+        return send_dir(&mut stream, &path, arena);
+    }
+    let mut input = match File::open(&path) {
+        Ok(input) => input,
+        Err(_) => return respond_error(&mut stream, "404 Not Found", "no such file"),
+    };
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("data").to_string();
+    let options = EncodeOptions { streaming: true, ..EncodeOptions::new() };
+    let time = time_data(&path.to_string_lossy(), &*options.clock);
+    let entry = EntryOptions { permissions: Some(Permissions::from_metadata(&input.metadata()?)), ..EntryOptions::default() };
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Disposition: attachment; filename=\"{}.zip\"\r\nConnection: close\r\n\r\n", name)?;
+    encode_entry(&mut input, &mut stream, &name, time, &options, &entry, arena).map_err(Error::other)?;
+    stream.flush()
+}
+
+/*
+ dirの中のファイルを全てzipにして返す。レスポンスを返し始めた後に失敗した場合は、接続を閉じて途中で終わらせる。
+ */
+fn send_dir(stream: &mut TcpStream, dir: &Path, arena: &mut Arena) -> Result<(), Error> {
+    let mut files = Vec::new();
+    if walk(dir, "", &mut files).is_err() {
+        return respond_error(stream, "404 Not Found", "cannot read the directory");
+    }
+    let name = dir.file_name().and_then(|name| name.to_str()).unwrap_or("archive").to_string();
+    let options = EncodeOptions { streaming: true, ..EncodeOptions::new() };
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Disposition: attachment; filename=\"{}.zip\"\r\nConnection: close\r\n\r\n", name)?;
+    let mut writer = ArchiveWriter::new(&mut *stream);
+    for (path, name, entry) in &files {
+        let mut input = File::open(path)?;
+        let time = time_data(&path.to_string_lossy(), &*options.clock);
+        writer.add_entry(&mut input, name, time, &options, entry, arena).map_err(Error::other)?;
+    }
+    writer.finish()?;
+    stream.flush()
+}
+
+/*
+ リクエストのパスをroot以下のパスにする。
+ ..などでrootの外に出ようとしている場合にはNoneを返す。
+ */
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(target.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn respond_error(stream: &mut TcpStream, status: &str, message: &str) -> Result<(), Error> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, message.len(), message)?;
+    stream.flush()
+}
@@ -0,0 +1,438 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::ZipError;
+
+/*
+ 長さの符号（257〜285）ごとの基本の長さと追加ビット数
+ */
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/*
+ 距離の符号（0〜29）ごとの基本の距離と追加ビット数
+ */
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/*
+ 動的ハフマンのブロックで、符号の長さの表の符号の長さが並んでいる順番
+ */
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/*
+ 展開したデータのうち、後ろの距離の符号で参照できる範囲（32KB）
+ */
+const WINDOW_SIZE: usize = 32768;
+
+/*
+ 圧縮されたデータをビット単位で読み込むためのもの
+ deflateでは1バイトの中を下位ビットから順に使う。
+ input:    圧縮されたデータ
+ byte:     今読んでいるバイト
+ bit_pos:  byteの中で次に読むビットの位置（8の場合は次のバイトを読み込む）
+ */
+struct BitReader<R: Read> {
+    input: R,
+    byte: u8,
+    bit_pos: u8,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(input: R) -> Self {
+        BitReader { input, byte: 0, bit_pos: 8 }
+    }
+
+    /*
+     1ビット読み込む
+     */
+    fn bit(&mut self) -> Result<u16, ZipError> {
+        if self.bit_pos == 8 {
+            let mut byte = [0u8];
+            self.read_exact(&mut byte)?;
+            self.byte = byte[0];
+            self.bit_pos = 0;
+        }
+        let bit = (self.byte >> self.bit_pos) & 1;
+        self.bit_pos += 1;
+        Ok(bit as u16)
+    }
+
+    /*
+     countビットを読み込んで、最初に読んだビットを最下位とする数にする（追加ビットなど）
+     */
+    fn bits(&mut self, count: u8) -> Result<u16, ZipError> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    /*
+     今のバイトの残りのビットを捨てて、次のバイトの境界に進む
+     */
+    fn align_to_byte(&mut self) {
+        self.bit_pos = 8;
+    }
+
+    /*
+     バイトの境界からbufの大きさだけそのまま読み込む
+     */
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ZipError> {
+        self.input.read_exact(buf).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => ZipError::InvalidDeflate("unexpected end of data".to_string()),
+            _ => ZipError::Io(err),
+        })
+    }
+}
+
+/*
+ 正規化されたハフマン符号の表
+ 符号の長さの一覧から作り、長さの短い順に符号を割り当てる。
+ counts:   長さごとの符号の数
+ symbols:  符号を小さい順に並べたときの記号
+ */
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            if let Some(count) = counts.get_mut(length as usize) {
+                *count += 1;
+            }
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        let mut offset = 0;
+        for (start, &count) in offsets.iter_mut().zip(&counts).skip(1) {
+            *start = offset;
+            offset += count;
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate().filter(|(_, &length)| length != 0) {
+            if let Some(offset) = offsets.get_mut(length as usize) {
+                if let Some(slot) = symbols.get_mut(*offset as usize) {
+                    *slot = symbol as u16;
+                }
+                *offset += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    /*
+     符号の長さの組み合わせが多すぎて、符号を割り当てられないかどうか
+     */
+    fn is_oversubscribed(&self) -> bool {
+        let mut left: i32 = 1;
+        for &count in self.counts.iter().skip(1) {
+            left <<= 1;
+            left -= count as i32;
+            if left < 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    /*
+     ハフマン符号を1つ読み込んで記号を返す
+     符号は上位ビットから順に格納されているため、1ビットずつ読みながら長さごとの範囲に入るかを調べる。
+     */
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, ZipError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for &count in self.counts.iter().skip(1) {
+            code |= reader.bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| ZipError::InvalidDeflate("invalid huffman code".to_string()));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(ZipError::InvalidDeflate("invalid huffman code".to_string()))
+    }
+}
+
+/*
+ 固定ハフマンの表（リテラルと長さ, 距離）を作る
+ */
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/*
+ 動的ハフマンのブロック（BTYPE=10）の先頭から、リテラルと長さ, 距離の表を読み込む
+ まず符号の長さの表を読み、それを使ってリテラルと長さ, 距離の符号の長さを続けて読み込む。
+ 16は直前の長さを3〜6回、17は0を3〜10回、18は0を11〜138回繰り返す。
+ */
+fn dynamic_tables<R: Read>(reader: &mut BitReader<R>) -> Result<(Huffman, Huffman), ZipError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(ZipError::InvalidDeflate("too many length or distance codes".to_string()));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_count) {
+        let length = reader.bits(3)? as u8;
+        if let Some(slot) = code_lengths.get_mut(index) {
+            *slot = length;
+        }
+    }
+    let code = Huffman::new(&code_lengths);
+    if code.is_oversubscribed() {
+        return Err(ZipError::InvalidDeflate("invalid code length table".to_string()));
+    }
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + reader.bits(2)? as usize),
+                None => return Err(ZipError::InvalidDeflate("repeat with no previous length".to_string())),
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err(ZipError::InvalidDeflate("too many code lengths".to_string()));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths.get(256).copied().unwrap_or(0) == 0 {
+        return Err(ZipError::InvalidDeflate("missing end-of-block code".to_string()));
+    }
+
+    let (literal_lengths, distance_lengths) = lengths.split_at(literal_count);
+    let literal = Huffman::new(literal_lengths);
+    let distance = Huffman::new(distance_lengths);
+    if literal.is_oversubscribed() || distance.is_oversubscribed() {
+        return Err(ZipError::InvalidDeflate("invalid literal or distance lengths".to_string()));
+    }
+    Ok((literal, distance))
+}
+
+/*
+ 展開の途中の状態
+ Header:   次のブロックの先頭を読む
+ Stored:   無圧縮のブロックの残りのバイト数
+ Huffman:  ハフマン符号のブロックの途中（リテラルと長さ, 距離の表）
+ Done:     最後のブロックを読み終えた
+ */
+enum State {
+    Header,
+    Stored(usize),
+    Huffman(Huffman, Huffman),
+    Done,
+}
+
+/*
+ deflateで圧縮されたデータを少しずつ展開するためのもの
+ Readとして読み込むと、必要な分だけ展開して返す。
+ output:    展開したデータ。距離の符号で参照するため、読み出した後も後ろのWINDOW_SIZEバイトは残しておく。
+ read_pos:  outputの中で次に読み出す位置
+ last:      今のブロックが最後のブロックかどうか
+ */
+pub(crate) struct Inflater<R: Read> {
+    reader: BitReader<R>,
+    state: State,
+    output: Vec<u8>,
+    read_pos: usize,
+    last: bool,
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(input: R) -> Self {
+        Inflater {
+            reader: BitReader::new(input),
+            state: State::Header,
+            output: Vec::new(),
+            read_pos: 0,
+            last: false,
+        }
+    }
+
+    /*
+     読み込み元を返す（展開し終わった後に、続くデータを読み切るのに使う）
+     */
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader.input
+    }
+
+    /*
+     読み込み元を返す（エントリを先頭から読み直すときに使う）
+     */
+    pub(crate) fn into_inner(self) -> R {
+        self.reader.input
+    }
+
+    /*
+     読み出していないデータがwantバイト以上になるか、最後まで展開するまで展開を進める
+     */
+    fn fill(&mut self, want: usize) -> Result<(), ZipError> {
+        while self.output.len() - self.read_pos < want {
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::Header if self.last => return Ok(()),
+                State::Header => {
+                    self.last = self.reader.bit()? == 1;
+                    self.state = match self.reader.bits(2)? {
+                        0 => {
+                            self.reader.align_to_byte();
+                            let mut header = [0u8; 4];
+                            self.reader.read_exact(&mut header)?;
+                            let len = u16::from_le_bytes([header[0], header[1]]);
+                            let nlen = u16::from_le_bytes([header[2], header[3]]);
+                            if len != !nlen {
+                                return Err(ZipError::InvalidDeflate(format!("stored block length {} does not match its complement {}", len, nlen)));
+                            }
+                            State::Stored(len as usize)
+                        }
+                        1 => {
+                            let (literal, distance) = fixed_tables();
+                            State::Huffman(literal, distance)
+                        }
+                        2 => {
+                            let (literal, distance) = dynamic_tables(&mut self.reader)?;
+                            State::Huffman(literal, distance)
+                        }
+                        block_type => return Err(ZipError::InvalidDeflate(format!("invalid block type {}", block_type))),
+                    };
+                }
+                State::Stored(remaining) => {
+                    let len = remaining.min(want.max(1));
+                    let start = self.output.len();
+                    self.output.resize(start + len, 0);
+                    self.reader.read_exact(self.output.get_mut(start..).unwrap_or_default())?;
+                    self.state = if remaining == len { State::Header } else { State::Stored(remaining - len) };
+                }
+                State::Huffman(literal, distance) => {
+                    let end = self.symbol(&literal, &distance)?;
+                    self.state = if end { State::Header } else { State::Huffman(literal, distance) };
+                }
+                State::Done => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     ハフマン符号を1つ読み込んで、リテラルか一致した部分をoutputに追加する。ブロックの終わりの場合はtrueを返す。
+     */
+    fn symbol(&mut self, literal: &Huffman, distance: &Huffman) -> Result<bool, ZipError> {
+        let reader = &mut self.reader;
+        let output = &mut self.output;
+        let symbol = literal.decode(reader)?;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(true),
+            257..=285 => {
+                let index = (symbol - 257) as usize;
+                let length = match (LENGTH_BASE.get(index), LENGTH_EXTRA.get(index)) {
+                    (Some(&base), Some(&extra)) => base as usize + reader.bits(extra)? as usize,
+                    _ => return Err(ZipError::InvalidDeflate(format!("invalid length code {}", symbol))),
+                };
+                let index = distance.decode(reader)? as usize;
+                let dist = match (DISTANCE_BASE.get(index), DISTANCE_EXTRA.get(index)) {
+                    (Some(&base), Some(&extra)) => base as usize + reader.bits(extra)? as usize,
+                    _ => return Err(ZipError::InvalidDeflate(format!("invalid distance code {}", index))),
+                };
+                if dist > output.len() {
+                    return Err(ZipError::InvalidDeflate(format!("distance {} is too far back", dist)));
+                }
+                let start = output.len() - dist;
+                for position in start..start + length {
+                    match output.get(position).copied() {
+                        Some(byte) => output.push(byte),
+                        None => return Err(ZipError::InvalidDeflate(format!("distance {} is too far back", dist))),
+                    }
+                }
+            }
+            _ => return Err(ZipError::InvalidDeflate(format!("invalid length code {}", symbol))),
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill(buf.len()).map_err(|err| match err {
+            ZipError::Io(err) => err,
+            err => io::Error::new(ErrorKind::InvalidData, err),
+        })?;
+        let available = self.output.get(self.read_pos..).unwrap_or_default();
+        let len = buf.len().min(available.len());
+        if let (Some(dst), Some(src)) = (buf.get_mut(..len), available.get(..len)) {
+            dst.copy_from_slice(src);
+        }
+        self.read_pos += len;
+
+        /*
+         読み出し済みのデータが溜まったら、距離の符号で参照できる分だけを残して捨てる
+         */
+        if self.read_pos > 2 * WINDOW_SIZE {
+            let drop = self.read_pos - WINDOW_SIZE;
+            self.output.drain(..drop);
+            self.read_pos -= drop;
+        }
+        Ok(len)
+    }
+}
+
+/*
+ deflateで圧縮されたdataを展開して返す。
+ 無圧縮（BTYPE=00）、固定ハフマン（BTYPE=01）、動的ハフマン（BTYPE=10）のブロックに対応している。
+ */
+pub(crate) fn inflate(data: &[u8]) -> Result<Vec<u8>, ZipError> {
+    let mut inflater = Inflater::new(data);
+    inflater.fill(usize::MAX)?;
+    Ok(inflater.output)
+}
+
+/*
+ inflateと同じだが、deflateのデータが何バイトだったかも返す。
+ データディスクリプタを使うエントリのように、圧縮後のサイズが分からない場合にデータの終わりを探すのに使う。
+ BitReaderは1バイトずつ読み込むため、最後のブロックを読み終えた時点で読み込んだ分がちょうどdeflateのデータになる。
+ */
+pub(crate) fn inflate_prefix(data: &[u8]) -> Result<(Vec<u8>, usize), ZipError> {
+    let mut rest = data;
+    let output = {
+        let mut inflater = Inflater::new(&mut rest);
+        inflater.fill(usize::MAX)?;
+        inflater.output
+    };
+    Ok((output, data.len() - rest.len()))
+}
@@ -0,0 +1,116 @@
+use std::fs::{metadata, read_dir, read_to_string, Metadata};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Context, Phase};
+use crate::{EntryOptions, Permissions, ZipError};
+
+/*
+ 格納するファイルのパスと、zipの中での名前、エントリごとのオプション
+ */
+pub(crate) type FileEntry = (PathBuf, String, EntryOptions);
+
+/*
+ zipに格納するものの指定の1つ分
+ ファイル、ディレクトリ、@listfile（1行に1つずつパスを書いたファイル）のどれかを表す。
+ path:    格納するファイルやディレクトリ、またはlistfileのパス
+ list:    trueの場合にはpathをlistfileとして読み込む
+ prefix:  zipの中での名前の前に付ける文字列（空でなければ最後に / を付ける）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub path: PathBuf,
+    pub list: bool,
+    pub prefix: String,
+}
+
+impl Input {
+    /*
+     コマンドラインの引数と同じ形式で指定する。@から始まる場合にはlistfileになる。
+     */
+    pub fn new(arg: &str) -> Self {
+        match arg.strip_prefix('@') {
+            Some(list) => Input { path: PathBuf::from(list), list: true, prefix: String::new() },
+            None => Input { path: PathBuf::from(arg), list: false, prefix: String::new() },
+        }
+    }
+
+    /*
+     zipの中での名前の前に付ける文字列を指定する
+     */
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_matches('/').to_string();
+        if !self.prefix.is_empty() {
+            self.prefix.push('/');
+        }
+        self
+    }
+}
+
+/*
+ inputsをそれぞれ格納するファイルのパスとzipの中での名前に展開してfilesに追加する
+ ファイルはprefixにファイル名を付けた名前に、ディレクトリはprefixにディレクトリからの相対パスを付けた名前になる。
+ listfileの中では空行と#から始まる行を無視し、それぞれの行を同じprefixのファイルかディレクトリとして扱う。
+ たどったディレクトリはdirsに追加する。
+ */
+pub(crate) fn collect_inputs(inputs: &[Input], files: &mut Vec<FileEntry>, dirs: &mut Vec<PathBuf>) -> Result<(), ZipError> {
+    for input in inputs {
+        if input.list {
+            let list = read_to_string(&input.path).context(&input.path.display().to_string(), Phase::Read)?;
+            for line in list.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                collect_path(Path::new(line), &input.prefix, files, dirs)?;
+            }
+        } else {
+            collect_path(&input.path, &input.prefix, files, dirs)?;
+        }
+    }
+    Ok(())
+}
+
+fn collect_path(path: &Path, prefix: &str, files: &mut Vec<FileEntry>, dirs: &mut Vec<PathBuf>) -> Result<(), ZipError> {
+    let file_metadata = metadata(path).context(&path.display().to_string(), Phase::Open)?;
+    if file_metadata.is_dir() {
+        dirs.push(path.to_path_buf());
+        return walk(path, prefix, files);
+    }
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => return Err(std::io::Error::other(format!("{} has no file name", path.display())).into()),
+    };
+    files.push((path.to_path_buf(), format!("{}{}", prefix, file_name), with_permissions(EntryOptions::new(), &file_metadata)));
+    Ok(())
+}
+
+/*
+ dirの中を再帰的にたどって、ファイルのパスとprefixを付けた名前をfilesに追加する
+ 名前は区切り文字を / にして、ディレクトリの中では名前順に並べる。
+ */
+pub(crate) fn walk(dir: &Path, prefix: &str, files: &mut Vec<FileEntry>) -> Result<(), ZipError> {
+    let mut entries = Vec::new();
+    let dir_name = dir.display().to_string();
+    for entry in read_dir(dir).context(&dir_name, Phase::Read)? {
+        entries.push(entry.context(&dir_name, Phase::Read)?);
+    }
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        let file_type = entry.file_type().context(&path.display().to_string(), Phase::Open)?;
+        if file_type.is_dir() {
+            walk(&path, &format!("{}/", name), files)?;
+        } else if file_type.is_file() {
+            let file_metadata = entry.metadata().context(&path.display().to_string(), Phase::Open)?;
+            files.push((path, name, with_permissions(EntryOptions::new(), &file_metadata)));
+        }
+    }
+    Ok(())
+}
+
+/*
+ 属性が指定されていない場合は、ファイルのメタデータから属性を設定する
+ */
+pub(crate) fn with_permissions(mut entry: EntryOptions, metadata: &Metadata) -> EntryOptions {
+    if entry.permissions.is_none() {
+        entry.permissions = Some(Permissions::from_metadata(metadata));
+    }
+    entry
+}
@@ -1,8 +1,142 @@
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{Error};
+use std::convert::TryFrom;
 use std::fs::metadata;
-use chrono::prelude::*;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use clock::civil_time;
+use error::Context;
+use limit::LimitedReader;
+use optimal::optimal_tokens;
+use space::{check_space, worst_case_size};
+use workspace::{temp_dir, write_output};
+use writer::{archive_comment, ArchiveWriter, CentralFields, SliceWriter};
+
+mod aes;
+
+/*
+ 外から受け取ったzipを読み込むモジュール（archive, compare, decode, dedup, delta, glob, gzip, inflate, normalize, permissions, recompress, repair, scan, unpack, vacuum, vfs, volumes）では、
+ 壊れたzipや悪意のあるzipでpanicしないように、添字での参照とunwrapを禁止してエラーとして返すようにしている。
+ */
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod archive;
+mod arena;
+mod build;
+mod cache;
+mod changes;
+mod clock;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod compare;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod decode;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod dedup;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod delta;
+mod easy;
+mod embedded;
+mod error;
+mod extra;
+#[cfg(all(target_os = "linux", feature = "fuse"))]
+mod fuse;
+mod git;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod glob;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod gzip;
+#[cfg(feature = "http")]
+mod http;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod inflate;
+mod input;
+mod limit;
+mod listing;
+mod meta;
+mod names;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod normalize;
+mod optimal;
+mod options;
+mod order;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod permissions;
+mod progress;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod recompress;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod repair;
+mod report;
+mod runtime;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod scan;
+#[cfg(all(unix, feature = "serve"))]
+mod serve;
+mod space;
+mod tar;
+#[cfg(feature = "zipper-test-support")]
+mod testing;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod unpack;
+mod validate;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod vacuum;
+mod vectors;
+mod version;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod vfs;
+#[deny(clippy::indexing_slicing, clippy::unwrap_used)]
+mod volumes;
+mod workspace;
+mod writer;
+mod zipcrypto;
+
+pub use archive::{Damaged, DosDateTime, EntryReader, RawReader, ZipArchive, ZipEntry};
+pub use arena::Arena;
+pub use build::{build, Builder};
+pub use cache::CompressionCache;
+pub use changes::{verify_against_fs, Change, ChangeKind};
+pub use clock::{Clock, FixedClock, SystemClock, UtcClock};
+pub use compare::{compare_archives, Difference, DifferenceKind};
+pub use decode::{decode, decode_with};
+pub use dedup::{dedup_report, DuplicateGroup};
+pub use delta::{apply_delta, delta, DELTA_NAME};
+pub use easy::{unzip_to, zip_dir, zip_file};
+pub use embedded::EmbeddedZip;
+pub use error::{Phase, ZipError};
+pub use extra::{parse_extra_fields, ExtraField};
+#[cfg(all(target_os = "linux", feature = "fuse"))]
+pub use fuse::mount;
+pub use git::git_archive;
+#[cfg(feature = "http")]
+pub use http::serve_http;
+pub use input::Input;
+pub use listing::{human_size, sort_entries, SortKey};
+pub use meta::{Metadata, META_NAME};
+#[cfg(feature = "encoding")]
+pub use names::LegacyNames;
+pub use names::{name_decoder, name_encoder, Cp437Names, NameDecoder, NameEncoder, Utf8Names};
+pub use normalize::normalize;
+pub use options::{ChangePolicy, Compression, DecodeOptions, EncodeOptions, Engine, EntryOptions, FileOptions, Placement, COMPRESSED_EXTENSIONS};
+pub use permissions::Permissions;
+pub use progress::Progress;
+pub use recompress::recompress_stream;
+pub use repair::repair;
+pub use report::{Diagnostic, DiagnosticKind, EntryReport, Features, Group, Profile, Report, Stats};
+pub use runtime::ZipperRuntime;
+pub use scan::{scan, ScanOptions, ScannedEntry};
+#[cfg(all(unix, feature = "serve"))]
+pub use serve::serve;
+#[cfg(feature = "zipper-test-support")]
+pub use testing::TestTree;
+pub use unpack::{detect_format, extract_any, ArchiveFormat};
+pub use vacuum::vacuum;
+pub use validate::{Violation, ViolationKind};
+pub use vectors::{gen_vectors, VectorResult, VectorStatus};
+pub use version::version_info;
+pub use vfs::{Vfs, VfsEntry, VfsMetadata, ZipVfs};
+pub use writer::{Finished, WriterState, ZipWriter};
 
 /*
  デバッグ用に出力を制御するためのもの
@@ -12,29 +146,49 @@ const PRINT_DEBUG: bool = false;
 const MAX_BUFFER_SIZE: usize = 1024;  // 1回の入力で受けつける最大のバイト
 const MAX_MATCH_LEN: usize = 258;     // 最大でどれだけ一致するかのサイズ
 const MIN_MATCH_LEN: usize = 3;       // 少なくとも３は一致しないと圧縮処理が行われない
-const MAX_WINDOW_SIZE: usize = 1024;  // スライドウインドウの最大サイズ 小さめにとっている
+const MAX_CHANGE_RETRIES: u32 = 3;    // 読み込み中にファイルが書き換えられた際にやり直す最大の回数
+const DEFAULT_ENTRY_NAME: &str = "data";  // encode_intoで作るエントリの名前
+const METHOD_STORED: u16 = 0x0000;       // 圧縮方法: 無圧縮
+const METHOD_DEFLATED: u16 = 0x0008;     // 圧縮方法: deflate
+const METHOD_AES: u16 = 0x0063;          // 圧縮方法: WinZipのAES暗号（元の圧縮方法は拡張フィールドに書く）
+const FLAG_ENCRYPTED: u16 = 0x0001;      // 汎用フラグのbit0: 暗号化されている
+const FLAG_DESCRIPTOR: u16 = 0x0008;     // 汎用フラグのbit3: crc32とサイズをデータの後ろのデータディスクリプタに書いた
+const MAX_STORED_LEN: usize = 65535;     // 無圧縮のブロック1つに入れられる最大のバイト
+const MAX_LEVEL: u8 = 10;                // この圧縮レベル以上では、時間をかけて最短経路で一致を選ぶ
+const ZIP64_MARK: u64 = 0xffff_ffff;     // ヘッダーに書けないサイズや位置の代わりに書き、ZIP64の拡張情報に書いたことを示す値
+const ZIP64_COUNT_MARK: u16 = 0xffff;    // エンドセントラルヘッダーに書けないエントリの数の代わりに書く値
+const ZIP64_EXTRA_ID: u16 = 0x0001;      // ZIP64の拡張情報の拡張フィールドの番号
+const ZIP64_END_RECORD_LEN: u64 = 44;    // ZIP64のエンドセントラルヘッダーの、大きさの欄より後ろのバイト数
+
+/*
+ Builtinの圧縮レベル1〜9ごとの、一致を探すスライドウインドウの大きさ
+ 一致を探す時間はウインドウの大きさに比例するため、レベルを上げるほど遅くなるが、遠くにある一致も見つけられる。
+ 既定のレベル6は1024にしている。
+ */
+const WINDOW_SIZES: [usize; 9] = [64, 128, 256, 512, 768, 1024, 2048, 4096, 8192];
 
 /*
  bit単位で出力を行うためのもの
  bit_count:     bufferに何ビット突っ込んだかを保持する
  buffer:        出力用のbuffer
  output_vector: 出力データをこのvectorに溜めて最後に一気に出力する
- output:        出力ファイルデータ
  */
-struct BitWriter<'a, T: Write> {
+struct BitWriter {
     bit_count: u8,
     buffer: u8,
     output_vector: Vec<u8>,
-    output: &'a mut T,
 }
 
-impl<'a, T: Write> BitWriter<'a, T> {
-    pub fn new(output: &'a mut T) -> Self {
+impl BitWriter {
+    /*
+     output_vectorにはすでに確保してあるvectorを渡して使い回せるようにしている
+     */
+    pub fn new(mut output_vector: Vec<u8>) -> Self {
+        output_vector.clear();
         BitWriter {
             bit_count: 0,
             buffer: 0,
-            output_vector: Vec::new(),
-            output,
+            output_vector,
         }
     }
 
@@ -71,6 +225,70 @@ impl<'a, T: Write> BitWriter<'a, T> {
         Ok(())
     }
 
+    /*
+     リテラルの並びを固定ハフマン符号にしてまとめて書き込む
+     途中まで使っているバイトのビットを64ビットの値に移し、LITERAL_CODESで引いた符号を下位ビットから詰めていき、
+     32ビット溜まるごとに4バイトずつ出力する。最後に残ったビットはbufferに戻す。
+     一致が見つからない部分が長く続く場合に、1バイトずつchangerとcode_bitsを呼ぶより速い。
+     */
+    pub fn write_literals(&mut self, literals: impl IntoIterator<Item = u8>) -> Result<(), Error> {
+        let mut acc: u64 = 0;
+        let mut count: u32 = 0;
+        if self.bit_count > 0 {
+            acc = (self.buffer.reverse_bits() >> (8 - self.bit_count)) as u64;
+            count = self.bit_count as u32;
+        }
+        for byte in literals {
+            let (code, len) = LITERAL_CODES[byte as usize];
+            acc |= (code as u64) << count;
+            count += len as u32;
+            if count >= 32 {
+                self.output_vector.extend_from_slice(&(acc as u32).to_le_bytes());
+                acc >>= 32;
+                count -= 32;
+            }
+        }
+        while count >= 8 {
+            self.output_vector.push(acc as u8);
+            acc >>= 8;
+            count -= 8;
+        }
+        self.buffer = if count == 0 { 0 } else { (acc as u8).reverse_bits() >> (8 - count) };
+        self.bit_count = count as u8;
+        Ok(())
+    }
+
+    /*
+     bytesをそのまま出力する
+     バイトの境界に揃っている場合はまとめてコピーし、揃っていない場合は1バイトずつビットとして書き込む。
+     無圧縮のブロックやヘッダーなど、バイト単位のデータを書き込む場合に使う。
+     */
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.bit_count == 8 {
+            self.flush_to_output()?;
+        }
+        if self.bit_count == 0 {
+            self.output_vector.extend_from_slice(bytes);
+            return Ok(());
+        }
+        for &byte in bytes {
+            self.extra_bits(byte as u16, 8)?;
+        }
+        Ok(())
+    }
+
+    /*
+     途中まで使っているバイトの残りを0で埋めて、次の出力をバイトの境界に揃える
+     */
+    pub fn align_to_byte(&mut self) -> Result<(), Error> {
+        if self.bit_count > 0 {
+            self.flush()?;
+            self.buffer = 0;
+            self.bit_count = 0;
+        }
+        Ok(())
+    }
+
     /*
      最後にvecterに入っているものをまとめて出力する
      また、出力がバイト単位になるようにパディングを行う
@@ -84,8 +302,8 @@ impl<'a, T: Write> BitWriter<'a, T> {
                 buffer |= (self.buffer >> i) & 1;
             }
 
-            self.output_vector.push(buffer.clone());
-            if PRINT_DEBUG == true {
+            self.output_vector.push(buffer);
+            if PRINT_DEBUG {
                 println!("push data: {:08b}", self.buffer);
                 for i in 0..(self.output_vector.len()){
                     print!("{:08b}", self.output_vector[i]);
@@ -106,8 +324,8 @@ impl<'a, T: Write> BitWriter<'a, T> {
             buffer <<= 1;
             buffer |= (self.buffer >> i) & 1;
         }
-        self.output_vector.push(buffer.clone());
-        if PRINT_DEBUG == true {
+        self.output_vector.push(buffer);
+        if PRINT_DEBUG {
             println!("push data: {:08b}", buffer);
             for i in 0..(self.output_vector.len()){
                 print!("{:08b}", self.output_vector[i]);
@@ -120,6 +338,43 @@ impl<'a, T: Write> BitWriter<'a, T> {
     }
 }
 
+/*
+ 圧縮したデータをメモリに溜めずに、そのまま書き込む先（EncodeOptions.streamingの場合に使う）
+ output:   書き込み先
+ written:  書き込んだバイト数（エントリの圧縮後のサイズになる）
+ */
+pub(crate) struct Sink<'a> {
+    output: &'a mut dyn Write,
+    written: u64,
+}
+
+impl<'a> Sink<'a> {
+    pub fn new(output: &'a mut dyn Write) -> Self {
+        Sink { output, written: 0 }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /*
+     bufferに溜まった圧縮したデータを書き込んで、bufferを空にする
+     */
+    fn drain(&mut self, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        self.output.write_all(buffer)?;
+        self.written += buffer.len() as u64;
+        buffer.clear();
+        Ok(())
+    }
+}
+
+/*
+ sinkに書き込んだ分と、bufferに残っている分を合わせた圧縮後のバイト数
+ */
+fn compressed_len(sink: &Option<&mut Sink>, buffer: &[u8]) -> u64 {
+    sink.as_ref().map_or(0, |sink| sink.written) + buffer.len() as u64
+}
+
 /*
  読み込みをbyteで保持するもの
  buffer:          データをMAX_BUFFER_SIZE分取り込むための配列。
@@ -127,6 +382,9 @@ impl<'a, T: Write> BitWriter<'a, T> {
  buf_size:        bufferの何番目までデータがあるかを保持する
  flag:            読み込むデータがもうない時に使用する。
  file_size:       入力ファイルのサイズを記録する。
+ crc:             読み込んだデータのcrc32を計算する。
+ read_time:       読み込みにかかった時間を記録する。
+ crc_time:        crc32の計算にかかった時間を記録する。
  input:           入力ファイルの情報を記録する。
  */
 struct ByteReader<'a, T: Read> {
@@ -134,69 +392,89 @@ struct ByteReader<'a, T: Read> {
     buf_count: usize,
     buf_size: usize,
     flag: bool,
-    file_size: u32,
+    file_size: u64,
+    crc: Crc32,
+    read_time: Duration,
+    crc_time: Duration,
     input: &'a mut T,
 }
 
 impl<'a, T: Read> ByteReader<'a, T> {
-    pub fn new(input: &'a mut T) -> Self {
+    pub fn new(input: &'a mut T) -> Result<Self, Error> {
         let mut reader = ByteReader {
             buffer: [0; MAX_BUFFER_SIZE],
             buf_count: 0,
             buf_size: 0,
             flag: true,
             file_size: 0,
+            crc: Crc32::new(),
+            read_time: Duration::default(),
+            crc_time: Duration::default(),
             input,
         };
-        let _ = reader.load_next_byte();
-        reader
+        reader.load_next_byte()?;
+        Ok(reader)
     }
 
     /*
      bufferが最後まで読まれたり、最初の読み込みの際に実行される。
+     読み込んだ分のcrc32もここで計算しておく。
      */
     fn load_next_byte(&mut self) -> Result<(), std::io::Error>{
-        match self.input.read(&mut self.buffer)? {
+        let read_start = Instant::now();
+        let size = self.input.read(&mut self.buffer)?;
+        self.read_time += read_start.elapsed();
+        match size {
             0 => {
                 self.flag = false;
                 self.buf_size = 0;
             },
             n => {
-                self.file_size += n as u32;
+                self.file_size += n as u64;
                 self.buf_size = n;
                 self.flag = true;
+                let crc_start = Instant::now();
+                for byte in &self.buffer[..n] {
+                    self.crc.push_buf(*byte);
+                }
+                self.crc_time += crc_start.elapsed();
             }
         };
         Ok(())
     }
 
     /*
-     buf_countの位置にあるバイトを返す。
+     buf_countの位置にあるバイトを返す。入力の最後まで読んだ後はNoneを返す。
      */
-    pub fn seek_byte(&mut self) -> u8{
-        self.buffer[self.buf_count]
+    pub fn seek_byte(&mut self) -> Option<u8> {
+        if self.buf_count < self.buf_size {
+            self.buffer.get(self.buf_count).copied()
+        } else {
+            None
+        }
     }
 
     /*
      bit_countを進める。bufferの最後まできていた場合には
      load_next_byteで次のブロックを読み込む。
      */
-    pub fn next_byte(&mut self) {
+    pub fn next_byte(&mut self) -> Result<(), Error> {
         if self.buf_count + 1 < self.buf_size {
             self.buf_count += 1;
         } else {
-            let _ = self.load_next_byte();
+            self.load_next_byte()?;
             self.buf_count = 0;
         }
+        Ok(())
     }
 
     /*
      bit_countの位置にあるバイトを返して、next_byteを読みこむ
      */
-    pub fn get_byte(&mut self) -> u8 {
-        let buffer = self.buffer[self.buf_count];
-        self.next_byte();
-        buffer
+    pub fn get_byte(&mut self) -> Result<u8, Error> {
+        let buffer = self.seek_byte().ok_or_else(|| Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        self.next_byte()?;
+        Ok(buffer)
     }
 }
 
@@ -211,6 +489,7 @@ impl<'a, T: Read> ByteReader<'a, T> {
  buf_count:    bufferが何bit処理されたかを保持する
  first_count:  最初の4バイトは反転する必要があるためカウントする
  */
+#[derive(Clone)]
 struct Crc32 {
     divisor: u32,
     non_divisor: u32,
@@ -244,7 +523,7 @@ impl Crc32 {
             self.non_divisor += !buffer as u32;
             self.first_count += 1;
         } else {
-            self.buffer = buffer.clone();
+            self.buffer = buffer;
             self.buf_count = 8;
             self.bit_shift();
         }
@@ -288,47 +567,225 @@ impl Crc32 {
             buffer <<= 1;
             buffer |= (self.non_divisor >> i) & 1;
         }
-        if PRINT_DEBUG == true {
+        if PRINT_DEBUG {
             println!("crc32: {:08x?}", !buffer);
         }
         !buffer
     }
 }
 
+/*
+ メモリ上のデータのcrc32を計算する
+ */
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    for byte in data {
+        crc.push_buf(*byte);
+    }
+    crc.get_crc32()
+}
+
+/*
+ zipのヘッダーに書ける大きさ（4GiB未満）かを確かめてu32にする
+ ZIP64で書き込まない、repairやvacuumで使う。収まらない場合は切り詰めずにエラーにする。
+ */
+pub(crate) fn zip_size(len: u64) -> Result<u32, Error> {
+    u32::try_from(len).map_err(|_| Error::other(format!("{} bytes do not fit in a zip without ZIP64", len)))
+}
+
 /*
  zipのローカルヘッダーやセントラルヘッダー、エンドセントラルヘッダなどを
  保持するための構造体
  buffer:       ヘッダー情報を保持する
  before_size:  圧縮前のサイズを保持する
  after_size:   圧縮後のサイズを保持する
+               どちらかがZIP64_MARK以上の場合は、ヘッダーにはZIP64_MARKを書き、ZIP64の拡張情報に本当のサイズを書く
  filename:     ファイルの名前を保持する
+ name_bytes:   ヘッダーに書き込む名前のバイト列（指定しない場合はfilenameのUTF-8のバイト列）
+ utf8:         name_bytesがUTF-8かどうか（アスキーコード以外の文字を含む場合に汎用フラグのbit11を立てる）
  crc32:        crc32の情報を保持する
  hms:          時間, 分, 秒のデータを保持する
  ymd:          年, 月, 日のデータを保持する
+ extra:        拡張フィールドのデータを保持する
+ method:       圧縮方法（METHOD_DEFLATEDかMETHOD_STORED）
+ padding:      ローカルヘッダーの拡張フィールドだけに入れる、データの位置を合わせるための詰め物
+ external:     セントラルヘッダーに入れる外部属性（Unixのモードと、DOSの属性）
+ encrypted:    WinZipのAES（AE-2）で暗号化したかどうか（ヘッダーには圧縮方法の代わりにMETHOD_AESを書き、crc32は書かない）
+ comment:      セントラルヘッダーに入れるエントリのコメントのバイト列（name_bytesと同じ文字コード）
+ descriptor:   crc32とサイズをローカルヘッダーに書かずに、データの後ろのデータディスクリプタに書くかどうか
+ descriptor_zip64:  データディスクリプタのサイズを8バイトで書くかどうか
+                    （ローカルヘッダーにはZIP64_MARKと、0を書いたZIP64の拡張情報を入れる）
  */
 struct Header{
     buffer: Vec<u8>,
-    before_size: u32,
-    after_size: u32,
+    before_size: u64,
+    after_size: u64,
     filename: String,
+    name_bytes: Vec<u8>,
+    utf8: bool,
     crc32: u32,
     hms: u16,
     ymd: u16,
+    extra: Vec<u8>,
+    method: u16,
+    padding: Vec<u8>,
+    external: u32,
+    encrypted: bool,
+    comment: Vec<u8>,
+    descriptor: bool,
+    descriptor_zip64: bool,
 }
 
 impl Header {
-    pub fn new(before_size: u32, after_size: u32, filename: impl Into<String>, crc32: u32, hms: u16, ymd: u16) -> Self {
+    pub fn new(before_size: u64, after_size: u64, filename: impl Into<String>, crc32: u32, hms: u16, ymd: u16) -> Self {
+        let filename = filename.into();
         Header{
             buffer: Vec::new(),
             before_size,
             after_size,
-            filename: filename.into(),
+            name_bytes: filename.as_bytes().to_vec(),
+            utf8: true,
+            filename,
             crc32,
             hms,
             ymd,
+            extra: Vec::new(),
+            method: METHOD_DEFLATED,
+            padding: Vec::new(),
+            external: 0,
+            encrypted: false,
+            comment: Vec::new(),
+            descriptor: false,
+            descriptor_zip64: false,
         }
     }
 
+    /*
+     拡張フィールドのデータを設定する
+     */
+    pub fn with_extra(mut self, extra: Vec<u8>) -> Self {
+        self.extra = extra;
+        self
+    }
+
+    /*
+     ヘッダーに書き込む名前のバイト列を設定する（UTF-8以外の文字コードで書き込む場合はutf8をfalseにする）
+     */
+    pub fn with_name_bytes(mut self, name_bytes: Vec<u8>, utf8: bool) -> Self {
+        self.name_bytes = name_bytes;
+        self.utf8 = utf8;
+        self
+    }
+
+    /*
+     圧縮方法を設定する
+     */
+    pub fn with_method(mut self, method: u16) -> Self {
+        self.method = method;
+        self
+    }
+
+    /*
+     ローカルヘッダーだけに入れる詰め物を設定する
+     */
+    pub fn with_padding(mut self, padding: Vec<u8>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /*
+     セントラルヘッダーに入れる外部属性を設定する
+     */
+    pub fn with_external_attributes(mut self, external: u32) -> Self {
+        self.external = external;
+        self
+    }
+
+    /*
+     セントラルヘッダーに入れるコメントを設定する
+     */
+    pub fn with_comment(mut self, comment: Vec<u8>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /*
+     AESで暗号化したデータの大きさと、AESの拡張フィールドを設定する（拡張フィールドはwith_extraの後に加える）
+     */
+    pub fn with_encryption(mut self, after_size: u64, aes_extra: &[u8]) -> Self {
+        self.after_size = after_size;
+        self.extra.extend_from_slice(aes_extra);
+        self.encrypted = true;
+        self
+    }
+
+    /*
+     crc32とサイズをデータディスクリプタに書くようにする（データを書き込む前にはサイズが分からない場合に使う）
+     zip64:  データディスクリプタのサイズを8バイトで書くかどうか（4GiB以上になるかもしれない場合に指定する）
+     */
+    pub fn with_descriptor(mut self, zip64: bool) -> Self {
+        self.descriptor = true;
+        self.descriptor_zip64 = zip64;
+        self
+    }
+
+    /*
+     データを書き込んだ後に分かった、圧縮前と圧縮後のサイズとcrc32を設定する
+     */
+    pub fn with_sizes(mut self, before_size: u64, after_size: u64, crc32: u32) -> Self {
+        self.before_size = before_size;
+        self.after_size = after_size;
+        self.crc32 = crc32;
+        self
+    }
+
+    /*
+     ローカルヘッダーの大きさ（データの位置を決めるために使う）
+     */
+    pub fn local_header_len(&self) -> usize {
+        30 + self.name_bytes.len() + self.local_extra_len()
+    }
+
+    /*
+     ローカルヘッダーの拡張フィールドの長さ（ZIP64の拡張情報と詰め物を含む）
+     */
+    pub fn local_extra_len(&self) -> usize {
+        let zip64 = if self.local_zip64() { 4 + 16 } else { 0 };
+        zip64 + self.extra.len() + self.padding.len()
+    }
+
+    /*
+     ローカルヘッダーにZIP64の拡張情報を入れるかどうか
+     データディスクリプタを使う場合は、サイズが分からないためdescriptor_zip64で決める。
+     */
+    fn local_zip64(&self) -> bool {
+        if self.descriptor { self.descriptor_zip64 } else { self.zip64_sizes() }
+    }
+
+    /*
+     ローカルヘッダーの位置がoffsetの場合の、セントラルヘッダーの拡張フィールドの長さ（ZIP64の拡張情報を含む）
+     */
+    pub fn central_extra_len(&self, offset: u64) -> usize {
+        let zip64 = self.central_zip64_values(offset).len();
+        let zip64 = if zip64 == 0 { 0 } else { 4 + 8 * zip64 };
+        zip64 + self.extra.len()
+    }
+
+    /*
+     圧縮前か圧縮後のサイズが4GiB以上で、ヘッダーにZIP64の拡張情報が要るかどうか
+     */
+    pub fn zip64_sizes(&self) -> bool {
+        self.before_size >= ZIP64_MARK || self.after_size >= ZIP64_MARK
+    }
+
+    /*
+     ローカルヘッダーの位置がoffsetの場合に、セントラルヘッダーでZIP64の拡張情報に書く値を返す。
+     APPNOTEの順（圧縮前のサイズ、圧縮後のサイズ、ローカルヘッダーの位置）に、ヘッダーに書けないものだけを並べる。
+     */
+    fn central_zip64_values(&self, offset: u64) -> Vec<u64> {
+        [self.before_size, self.after_size, offset].iter().copied().filter(|&value| value >= ZIP64_MARK).collect()
+    }
+
     /*
      32bitの情報をbufferに追加する
      */
@@ -343,6 +800,14 @@ impl Header {
         self.buffer.push(d as u8);
     }
 
+    /*
+     64bitの情報をbufferに追加する
+     */
+    fn push64(&mut self, num: u64) {
+        self.push32(num as u32);
+        self.push32((num >> 32) as u32);
+    }
+
     /*
     16bitの情報をbufferに追加する
      */
@@ -363,6 +828,36 @@ impl Header {
         self.buffer.push(0x06);
     }
 
+    /*
+     PK0606ヘッダ（ZIP64のエンドセントラルヘッダー）であることを示す情報を追加する
+     */
+    fn push_pk0606(&mut self){
+        self.buffer.push(0x50);
+        self.buffer.push(0x4b);
+        self.buffer.push(0x06);
+        self.buffer.push(0x06);
+    }
+
+    /*
+     PK0607ヘッダ（ZIP64のエンドセントラルヘッダーのロケーター）であることを示す情報を追加する
+     */
+    fn push_pk0607(&mut self){
+        self.buffer.push(0x50);
+        self.buffer.push(0x4b);
+        self.buffer.push(0x06);
+        self.buffer.push(0x07);
+    }
+
+    /*
+     PK0708ヘッダ（データディスクリプタ）であることを示す情報を追加する
+     */
+    fn push_pk0708(&mut self){
+        self.buffer.push(0x50);
+        self.buffer.push(0x4b);
+        self.buffer.push(0x07);
+        self.buffer.push(0x08);
+    }
+
     /*
      PK0304ヘッダであることを示す情報を追加する
      */
@@ -383,115 +878,262 @@ impl Header {
         self.buffer.push(0x02);
     }
 
+    /*
+     汎用フラグを返す
+     UTF-8のファイル名やコメントにアスキーコード以外の文字が含まれている場合には、UTF-8であることを示すbit11を立てる
+     データディスクリプタを使う場合には、bit3を立てる
+     */
+    fn flags(&self) -> u16 {
+        let utf8 = if !self.utf8 || (self.name_bytes.is_ascii() && self.comment.is_ascii()) {
+            0x0000
+        } else {
+            0x0800
+        };
+        let descriptor = if self.descriptor { utf8 | FLAG_DESCRIPTOR } else { utf8 };
+        if self.encrypted { descriptor | FLAG_ENCRYPTED } else { descriptor }
+    }
+
+    /*
+     展開に必要なバージョン（AESで暗号化した場合は5.1、ZIP64を使う場合は4.5、それ以外は2.0）
+     */
+    fn version_needed(&self, zip64: bool) -> u16 {
+        if self.encrypted {
+            0x0033
+        } else if zip64 {
+            0x002d
+        } else {
+            0x0014
+        }
+    }
+
+    /*
+     ヘッダーに書き込む圧縮方法（AESで暗号化した場合は、元の圧縮方法はAESの拡張フィールドに書く）
+     */
+    fn header_method(&self) -> u16 {
+        if self.encrypted { METHOD_AES } else { self.method }
+    }
+
+    /*
+     ヘッダーに書き込むcrc32（AE-2では、暗号化する前のデータが分かってしまわないように0にする）
+     */
+    fn header_crc32(&self) -> u32 {
+        if self.encrypted { 0 } else { self.crc32 }
+    }
+
     /*
      ファイルの名前の情報を追加する
      */
     fn push_filename(&mut self){
-        let bytes: &[u8] = self.filename.as_bytes();
-        for i in 0..bytes.len() {
-            self.buffer.push(bytes[i]);
+        let bytes = std::mem::take(&mut self.name_bytes);
+        self.buffer.extend_from_slice(&bytes);
+        self.name_bytes = bytes;
+    }
+
+    /*
+     valuesを書いたZIP64の拡張情報を追加する（valuesが空の場合は何もしない）
+     */
+    fn push_zip64(&mut self, values: &[u64]) {
+        if values.is_empty() {
+            return;
+        }
+        self.push16(ZIP64_EXTRA_ID);
+        self.push16((values.len() * 8) as u16);
+        for &value in values {
+            self.push64(value);
         }
     }
+
+    /*
+     拡張フィールドの情報を追加する
+     */
+    fn push_extra(&mut self){
+        let extra = std::mem::take(&mut self.extra);
+        self.buffer.extend_from_slice(&extra);
+        self.extra = extra;
+    }
     
     /*
      ローカルヘッダーに必要な情報をもらって、ローカルヘッダーを作成する
      構造
      8byte: PK0304ヘッダを示す情報
-     4byte: 展開に必要なバージョン（2.0）
-     4byte: オプション（今回は設定していない）
-     4byte: 使用圧縮アルゴリズム（deflate圧縮: 0008）
+     4byte: 展開に必要なバージョン（2.0。ZIP64を使う場合は4.5、AESで暗号化した場合は5.1）
+     4byte: オプション（ファイル名がUTF-8の場合にはbit11を、暗号化した場合にはbit0を、
+            データディスクリプタを使う場合にはbit3を立てる）
+     4byte: 使用圧縮アルゴリズム（deflate圧縮: 0008, 無圧縮: 0000, AES: 0063）
      4byte: 時刻
      4byte: 日付
-     8byte: crc32情報
-     8byte: 圧縮後のサイズ（mとする）
+     8byte: crc32情報（AESで暗号化した場合と、データディスクリプタを使う場合は0）
+     8byte: 圧縮後のサイズ（mとする。4GiB以上の場合はどちらのサイズもffffffffにする。
+            データディスクリプタを使う場合は0にし、8バイトで書く場合はffffffffにする）
      8byte: 圧縮前のサイズ
      4byte: ファイル名の長さ（nとする）
-     4byte: コメントがあればその長さ（今回はないものとしている）
+     4byte: 拡張フィールドの長さ（eとする）
      nbyte: ファイル名
+     ebyte: 拡張フィールド（ZIP64の場合は先頭に圧縮前と圧縮後のサイズを書いたZIP64の拡張情報を入れ、
+            位置を合わせる詰め物も含む。データディスクリプタを使う場合は、サイズの代わりに0を書く）
      mbyte: 圧縮したデータ（ここではpushしておらず、ファイルに書き込む際に追加している）
      */
     pub fn local_header(mut self) -> Vec<u8> {
+        let zip64 = self.local_zip64();
+        let (before_size, after_size, crc32) = if self.descriptor {
+            (0, 0, 0)
+        } else {
+            (self.before_size, self.after_size, self.header_crc32())
+        };
+        let sizes = if zip64 { vec![before_size, after_size] } else { Vec::new() };
         self.push_pk0304();
-        self.push16(0x0014);
-        self.push16(0x0000);
-        self.push16(0x0008);
+        self.push16(self.version_needed(zip64));
+        self.push16(self.flags());
+        self.push16(self.header_method());
         self.push16(self.hms);
         self.push16(self.ymd);
-        self.push32(self.crc32);
-        self.push32(self.after_size);
-        self.push32(self.before_size);
-        self.push16((self.filename.len()) as u16);
-        self.push16(0x0000);
+        self.push32(crc32);
+        self.push32(if zip64 { ZIP64_MARK as u32 } else { after_size as u32 });
+        self.push32(if zip64 { ZIP64_MARK as u32 } else { before_size as u32 });
+        self.push16((self.name_bytes.len()) as u16);
+        self.push16(self.local_extra_len() as u16);
         self.push_filename();
+        self.push_zip64(&sizes);
+        self.push_extra();
+        let padding = std::mem::take(&mut self.padding);
+        self.buffer.extend_from_slice(&padding);
         self.buffer
     }
     
     /*
      セントラルヘッダーに必要な情報をもらって、セントラルヘッダーを作成する
      8byte: PK0102ヘッダを示す情報
-     4byte: 作成したバージョン（ここでは2.0としている）
-     4byte: 展開に必要なバージョン（2.0）
-     4byte: オプション（今回は設定していない）
-     4byte: 使用圧縮アルゴリズム（deflate圧縮）
+     4byte: 作成したバージョン（上位8bitは作成したOSでUnix（3）、下位8bitは展開に必要なバージョンと同じ値）
+     4byte: 展開に必要なバージョン（2.0。ZIP64を使う場合は4.5、AESで暗号化した場合は5.1）
+     4byte: オプション（ローカルヘッダーと同じ）
+     4byte: 使用圧縮アルゴリズム（deflate圧縮: 0008, 無圧縮: 0000, AES: 0063）
      4byte: 時刻
      4byte: 日付
-     8byte: crc32情報
-     8byte: 圧縮後のサイズ
-     8byte: 圧縮前のサイズ
+     8byte: crc32情報（AESで暗号化した場合は0。データディスクリプタを使う場合もここには本当の値を書く）
+     8byte: 圧縮後のサイズ（4GiB以上の場合はffffffff）
+     8byte: 圧縮前のサイズ（4GiB以上の場合はffffffff）
      4byte: ファイル名の長さ（nとする）
-     4byte: 拡張フィールドの長さ。（使用していないため0）
-     4byte: コメントがあればその長さ（今回はないものとしている）
+     4byte: 拡張フィールドの長さ（eとする）
+     4byte: コメントの長さ（cとする）
      4byte: 分割されている場合、対応するPK0304ヘッダが格納されたパートの番号
             （分割していないため０）
      4byte: 対応するPK0304に格納したファイルの属性情報（0としている）
-     8byte: OSで保持していた対象ファイルの属性情報（0としている）
-     8byte: 対応するPK0304ヘッダの位置
-            （今回はファイル一つのみの設定であるため0としている）
+     8byte: OSで保持していた対象ファイルの属性情報（externalとして設定する）
+     8byte: 対応するPK0304ヘッダの位置（offsetとしてもらう。4GiB以上の場合はffffffff）
      nbyte: ファイル名
-
+     ebyte: 拡張フィールド（ffffffffにした値がある場合は、先頭にそれを書いたZIP64の拡張情報を入れる）
+     cbyte: コメント
      */
-    pub fn central_header(mut self) -> Vec<u8> {
+    pub fn central_header(mut self, offset: u64) -> Vec<u8> {
+        let zip64 = self.central_zip64_values(offset);
+        let version_needed = self.version_needed(!zip64.is_empty() || self.local_zip64());
+        let extra_len = self.central_extra_len(offset);
         self.push_pk0102();
-        self.push16(0x0314);
-        self.push16(0x0014);
-        self.push16(0x0000);
-        self.push16(0x0008);
+        self.push16(0x0300 | version_needed);
+        self.push16(version_needed);
+        self.push16(self.flags());
+        self.push16(self.header_method());
         self.push16(self.hms);
         self.push16(self.ymd);
-        self.push32(self.crc32);
-        self.push32(self.after_size);
-        self.push32(self.before_size);
-        self.push16((self.filename.len()) as u16);
-        self.push16(0x0000);
+        self.push32(self.header_crc32());
+        self.push32(self.after_size.min(ZIP64_MARK) as u32);
+        self.push32(self.before_size.min(ZIP64_MARK) as u32);
+        self.push16((self.name_bytes.len()) as u16);
+        self.push16(extra_len as u16);
+        self.push16((self.comment.len()) as u16);
         self.push16(0x0000);
         self.push16(0x0000);
-        self.push16(0x0000);
-        self.push32(0x00000000);
-        self.push32(0x00000000);
+        self.push32(self.external);
+        self.push32(offset.min(ZIP64_MARK) as u32);
         self.push_filename();
+        self.push_zip64(&zip64);
+        self.push_extra();
+        let comment = std::mem::take(&mut self.comment);
+        self.buffer.extend_from_slice(&comment);
         self.buffer
     }
     
+    /*
+     圧縮したデータの後ろに書き込むデータディスクリプタを作成する
+     8byte: PK0708ヘッダを示す情報
+     8byte: crc32情報
+     8byte: 圧縮後のサイズ（descriptor_zip64の場合は16byte）
+     8byte: 圧縮前のサイズ（descriptor_zip64の場合は16byte）
+     */
+    pub fn data_descriptor(mut self) -> Vec<u8> {
+        self.push_pk0708();
+        self.push32(self.crc32);
+        if self.descriptor_zip64 {
+            self.push64(self.after_size);
+            self.push64(self.before_size);
+        } else {
+            self.push32(self.after_size as u32);
+            self.push32(self.before_size as u32);
+        }
+        self.buffer
+    }
+
     /*
      エンドセントラルヘッダーに必要な情報をもらって、エンドセントラルヘッダーを作成する
      8byte: PK0506ヘッダを示す情報
      4byte: 分割している場合にはこのパートの番号（分割していないため0）
      4byte: 分割している場合には最初のPK0304が格納されたパートの番号（分割していないため０）
      4byte: 分割時にこのパートに格納されているファイル数（分割していないため下と同じ）
-     4byte: 圧縮したファイルの数（1としている）
+     4byte: 圧縮したファイルの数（countとしてもらう）
      8byte: PK0102ヘッダの合計サイズ
      8byte: PK0102ヘッダの開始位置
-     4byte: コメントの長さ（今回は無し）
+     4byte: コメントの長さ（cとする。with_commentで設定したzip全体のコメント）
+     cbyte: コメント
      */
-    pub fn end_header(mut self, header_size: u32, header_start: u32) -> Vec<u8>{
+    pub fn end_header(mut self, count: u16, header_size: u32, header_start: u32) -> Vec<u8>{
         self.push_pk0506();
         self.push16(0x0000);
         self.push16(0x0000);
-        self.push16(0x0001);
-        self.push16(0x0001);
+        self.push16(count);
+        self.push16(count);
         self.push32(header_size);
         self.push32(header_start);
-        self.push16(0x00);
+        self.push16((self.comment.len()) as u16);
+        let comment = std::mem::take(&mut self.comment);
+        self.buffer.extend_from_slice(&comment);
+        self.buffer
+    }
+
+    /*
+     セントラルヘッダーの位置や大きさが4GiB以上の場合に、エンドセントラルヘッダーの前に置く
+     ZIP64のエンドセントラルヘッダーと、その位置を示すロケーターを作成する
+     ZIP64のエンドセントラルヘッダー
+     8byte: PK0606ヘッダを示す情報
+     16byte: これより後ろの大きさ（44）
+     4byte: 作成したバージョン（4.5）
+     4byte: 展開に必要なバージョン（4.5）
+     8byte: このパートの番号（分割していないため0）
+     8byte: 最初のPK0102が格納されたパートの番号（分割していないため0）
+     16byte: このパートに格納されているファイル数（分割していないため下と同じ）
+     16byte: 圧縮したファイルの数（countとしてもらう）
+     16byte: PK0102ヘッダの合計サイズ
+     16byte: PK0102ヘッダの開始位置
+     ロケーター
+     8byte: PK0607ヘッダを示す情報
+     8byte: ZIP64のエンドセントラルヘッダーが格納されたパートの番号（0）
+     16byte: ZIP64のエンドセントラルヘッダーの位置（PK0102ヘッダの直後）
+     8byte: パートの数（1）
+     */
+    pub fn zip64_end_header(mut self, count: u64, header_size: u64, header_start: u64) -> Vec<u8> {
+        self.push_pk0606();
+        self.push64(ZIP64_END_RECORD_LEN);
+        self.push16(0x032d);
+        self.push16(0x002d);
+        self.push32(0);
+        self.push32(0);
+        self.push64(count);
+        self.push64(count);
+        self.push64(header_size);
+        self.push64(header_start);
+        self.push_pk0607();
+        self.push32(0);
+        self.push64(header_start + header_size);
+        self.push32(1);
         self.buffer
     }
 
@@ -499,44 +1141,63 @@ impl Header {
      cloneの実装を行なっている
      */
     pub fn clone(&self) -> Self {
-        Header::new(self.before_size, self.after_size, self.filename.clone(), self.crc32, self.hms, self.ymd)
+        let header = Header::new(self.before_size, self.after_size, self.filename.clone(), self.crc32, self.hms, self.ymd)
+            .with_name_bytes(self.name_bytes.clone(), self.utf8)
+            .with_extra(self.extra.clone())
+            .with_method(self.method)
+            .with_padding(self.padding.clone())
+            .with_external_attributes(self.external)
+            .with_comment(self.comment.clone());
+        Header { encrypted: self.encrypted, descriptor: self.descriptor, descriptor_zip64: self.descriptor_zip64, ..header }
     }
 }
 
 /*
  ファイルの最終更新日時を取得してそれぞれをzipに必要な形式にして返す。
+ 最終更新日時が取得できない場合にはclockの現在時刻を使う。
  下のurlのヘッダ構造の部分から形式を知った。
  https://hgotoh.jp/wiki/doku.php/documents/other/other-017
  */
-fn time_data(filename: &str)  -> (u16, u16) {
+fn time_data(filename: &str, clock: &dyn Clock)  -> (u16, u16) {
     let times;
     if let Ok(metadata) = metadata(filename) {
         if let Ok(time) = metadata.modified() {
             if let Ok(epoch) = time.duration_since(std::time::SystemTime::UNIX_EPOCH) {
-                times = epoch.as_secs();
+                times = epoch.as_secs() as i64;
             } else {
                 times = 0;
             }
         } else {
-            let now = std::time::SystemTime::now();
-            if let Ok(epoch) = now.duration_since(std::time::SystemTime::UNIX_EPOCH) {
-                times = epoch.as_secs();
-            } else {
-                times = 0;
-            }
+            times = clock.now();
         }
     } else {
         times = 0;
     }
-    let data = Local.timestamp(times as i64, 0);
+    dos_time(times, clock)
+}
+
+/*
+ UNIX時間をzipに必要な形式（時刻, 日付）にして返す。
+ clockのoffsetを足してから変換するため、どの地域の時刻になるかはclockで決まる。
+ zipでは1980年より前の日時を表せないため、その場合は1980年1月1日0時0分0秒にする。
+ 同じく2107年より後の日時は年の7bitに入らないため、その場合は2107年12月31日23時59分58秒にする。
+ */
+fn dos_time(times: i64, clock: &dyn Clock) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = civil_time(times + clock.offset(times));
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
     let mut hms = 0;
-    hms += (data.hour() as u32)<< 11;
-    hms += (data.minute() as u32) << 5;
-    hms += (data.second() as u32) / 2;
+    hms += hour << 11;
+    hms += minute << 5;
+    hms += second / 2;
     let mut ymd = 0;
-    ymd += ((data.year() - 1980) as u32) << 9;
-    ymd += (data.month() as u32) << 5;
-    ymd += data.day() as u32;
+    ymd += ((year - 1980) as u32) << 9;
+    ymd += month << 5;
+    ymd += day;
 
     (hms as u16, ymd as u16)
 }
@@ -555,7 +1216,7 @@ fn match_check<T: Eq>(window: &[T], check: &[T]) -> isize {
                 continue 'outer;
             }
         }
-        if PRINT_DEBUG == true {
+        if PRINT_DEBUG {
             println!("{} {} {}", window.len(), check.len(), i);
         }
         return (window.len() - check.len() - i + 1) as isize;
@@ -569,7 +1230,7 @@ fn match_check<T: Eq>(window: &[T], check: &[T]) -> isize {
 fn changer(num: usize) -> (u8, u16) {
     let (len, re) = match num {
         0   ..= 143 => (8, num + 0x30 ),
-        144 ..= 255 => (9, num + 0x91 ),
+        144 ..= 255 => (9, num + 0x100),
         256 ..= 279 => (7, num - 0x100),
         280 ..= 287 => (8, num - 0x58 ),
         _ => (0, 512),
@@ -577,35 +1238,53 @@ fn changer(num: usize) -> (u8, u16) {
     (len, re as u16)
 }
 
+/*
+ リテラル（0〜255）ごとの固定ハフマン符号と、その長さ
+ 符号はBitWriter::write_literalsで下位ビットから詰められるように、ビットの順番を逆にしておく。
+ */
+const LITERAL_CODES: [(u16, u8); 256] = literal_codes();
+
+const fn literal_codes() -> [(u16, u8); 256] {
+    let mut table = [(0, 0); 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let (len, code) = if byte < 144 { (8, byte + 0x30) } else { (9, byte + 0x100) };
+        table[byte] = ((code as u16).reverse_bits() >> (16 - len), len as u8);
+        byte += 1;
+    }
+    table
+}
+
 /*
  長さから長さ符号と拡張ビットを調べる
  */
 fn length_extra(data: u16) -> (u16, u8, u16){
     let (num, len, extra) = match data {
         3   ..=  10 => (data + 254, 0, 0),
-        11  ..=  12 => (265, 1, ((data - 3)) & 0b1),
-        13  ..=  14 => (266, 1, ((data - 3)) & 0b1),
-        15  ..=  16 => (267, 1, ((data - 3)) & 0b1),
-        17  ..=  18 => (268, 1, ((data - 3)) & 0b1),
-        19  ..=  22 => (269, 2, ((data - 3)) & 0b11),
-        23  ..=  26 => (270, 2, ((data - 3)) & 0b11),
-        27  ..=  30 => (271, 2, ((data - 3)) & 0b11),
-        31  ..=  34 => (272, 2, ((data - 3)) & 0b11),
-        35  ..=  42 => (273, 3, ((data - 3)) & 0b111),
-        43  ..=  50 => (274, 3, ((data - 3)) & 0b111),
-        51  ..=  58 => (275, 3, ((data - 3)) & 0b111),
-        59  ..=  66 => (276, 3, ((data - 3)) & 0b111),
-        67  ..=  82 => (277, 4, ((data - 3)) & 0b1111),
-        83  ..=  98 => (278, 4, ((data - 3)) & 0b1111),
-        99  ..= 114 => (279, 4, ((data - 3)) & 0b1111),
-        115 ..= 130 => (280, 4, ((data - 3)) & 0b1111),
-        131 ..= 162 => (281, 5, ((data - 3)) & 0b11111),
-        163 ..= 194 => (282, 5, ((data - 3)) & 0b11111),
-        195 ..= 226 => (283, 5, ((data - 3)) & 0b11111),
-        227 ..= 257 => (284, 5, ((data - 3)) & 0b11111),
+        11  ..=  12 => (265, 1, (data - 3) & 0b1),
+        13  ..=  14 => (266, 1, (data - 3) & 0b1),
+        15  ..=  16 => (267, 1, (data - 3) & 0b1),
+        17  ..=  18 => (268, 1, (data - 3) & 0b1),
+        19  ..=  22 => (269, 2, (data - 3) & 0b11),
+        23  ..=  26 => (270, 2, (data - 3) & 0b11),
+        27  ..=  30 => (271, 2, (data - 3) & 0b11),
+        31  ..=  34 => (272, 2, (data - 3) & 0b11),
+        35  ..=  42 => (273, 3, (data - 3) & 0b111),
+        43  ..=  50 => (274, 3, (data - 3) & 0b111),
+        51  ..=  58 => (275, 3, (data - 3) & 0b111),
+        59  ..=  66 => (276, 3, (data - 3) & 0b111),
+        67  ..=  82 => (277, 4, (data - 3) & 0b1111),
+        83  ..=  98 => (278, 4, (data - 3) & 0b1111),
+        99  ..= 114 => (279, 4, (data - 3) & 0b1111),
+        115 ..= 130 => (280, 4, (data - 3) & 0b1111),
+        131 ..= 162 => (281, 5, (data - 3) & 0b11111),
+        163 ..= 194 => (282, 5, (data - 3) & 0b11111),
+        195 ..= 226 => (283, 5, (data - 3) & 0b11111),
+        227 ..= 257 => (284, 5, (data - 3) & 0b11111),
+        258         => (285, 0, 0),
         _ => (286, 6, 0)
     };
-    (num as u16 ,len as u8 ,extra as u16)
+    (num, len as u8, extra)
 }
 
 /*
@@ -642,7 +1321,7 @@ fn distance_extra(data: u32) -> (u8, u8, u16){
         24577 ..= 32768 => (29,13, (data - 1) & 0b1111111111111),
         _ => (31, 14, 0)
     };
-    (num as u8 ,dis as u8, extra as u16)
+    (num as u8, dis as u8, extra as u16)
 }
 
 /*
@@ -659,40 +1338,386 @@ fn distance_extra(data: u32) -> (u8, u8, u16){
 
  デバッグは出力を手で解析して行なった。
  */
-pub fn encode(input_file: &str, output_file: &str) -> Result<(), std::io::Error> {
-    let mut input = File::open(input_file)?;
-    let mut output = File::create(output_file)?;
-    let mut input_reader = ByteReader::new(&mut input);
-    let mut output_writer = BitWriter::new(&mut output);
-    let mut crcs = Crc32::new();
+pub fn encode(input_file: &str, output_file: &str) -> Result<(), ZipError> {
+    encode_with(input_file, output_file, &EncodeOptions::default())?;
+    Ok(())
+}
+
+/*
+ 複数のファイルを、それぞれ1つのエントリとして格納したzipを作る。
+ エントリはinput_filesの順に書き込み、zipの中での名前はzip_fileと同じくファイル名になる。
+ ファイル名が重なる場合は、何も書き込まずにInvalidEntriesを返す。
+ */
+pub fn encode_files(input_files: &[&str], output_file: &str) -> Result<(), ZipError> {
+    encode_files_with(input_files, output_file, &EncodeOptions::default())?;
+    Ok(())
+}
 
-    let mut window = Vec::new();
+/*
+ encode_filesにオプションを指定できるようにしたもの（Builder::optionsに渡した場合と同じ）
+ */
+pub fn encode_files_with(input_files: &[&str], output_file: &str, options: &EncodeOptions) -> Result<Report, ZipError> {
+    let mut builder = build().options(options.clone());
+    for input_file in input_files {
+        let path = Path::new(input_file);
+        let name = path.file_name()
+            .ok_or_else(|| Error::other(format!("{} has no file name", input_file)))?
+            .to_string_lossy()
+            .into_owned();
+        builder = builder.add_file(path, name)?;
+    }
+    builder.write_file(output_file)
+}
 
-    output_writer.extra_bits(0b1, 1)?;
+/*
+ LZ77で一致を探した結果を保持する
+ Literal: 一致が見つからなかったバイト
+ Match:   一致した長さと距離
+ */
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(u16, u32),
+}
+
+/*
+ 溜まったトークンを固定ハフマン符号にしてBitWriterに書き込む
+ 続いているリテラルは、BitWriter::write_literalsでまとめて書き込む。
+ */
+fn write_tokens(output_writer: &mut BitWriter, tokens: &[Token]) -> Result<(), Error> {
+    let mut rest = tokens;
+    while let Some(token) = rest.first() {
+        let consumed = match *token {
+            Token::Literal(_) => {
+                let run = rest.iter().position(|token| matches!(token, Token::Match(..))).unwrap_or(rest.len());
+                let literals = rest[..run].iter().filter_map(|token| match *token {
+                    Token::Literal(byte) => Some(byte),
+                    Token::Match(..) => None,
+                });
+                if PRINT_DEBUG {
+                    for byte in literals.clone() {
+                        let (bits, buf) = changer(byte as usize);
+                        println!("{:09b} :{}", buf, bits);
+                    }
+                }
+                output_writer.write_literals(literals)?;
+                run
+            }
+            Token::Match(length, offset) => {
+                let (num , data, extra) = length_extra(length);
+                let (bits, buf) = changer(num as usize);
+                output_writer.code_bits(buf, bits)?;
+                if PRINT_DEBUG {
+                    println!("{:09b} :{}", buf, bits);
+                }
+                output_writer.extra_bits(extra, data)?;
+                if PRINT_DEBUG {
+                    println!("{:05b} :{}", extra, data);
+                }
+                let (num , data, extra) = distance_extra(offset);
+                output_writer.code_bits(num as u16, 5)?;
+                if PRINT_DEBUG {
+                    println!("{:05b} :{}", num, 5);
+                }
+                output_writer.extra_bits(extra , data)?;
+                if PRINT_DEBUG {
+                    println!("{:09b} :{}", extra, data);
+                }
+                1
+            }
+        };
+        rest = &rest[consumed..];
+    }
+    Ok(())
+}
+
+/*
+ encodeにオプションを指定できるようにしたもの。
+ 処理の結果をReportとして返す。
+ options.profileがtrueの場合には、各段階にかかった時間もReportに含める。
+ */
+pub fn encode_with(input_file: &str, output_file: &str, options: &EncodeOptions) -> Result<Report, ZipError> {
+    encode_with_arena(input_file, output_file, options, &mut Arena::new())
+}
+
+/*
+ encode_withと同じだが、作業用のバッファをarenaから借りて使う。
+ 同じarenaを渡し続けることで、何度も圧縮を行う際のアロケーションを減らせる。
+ */
+pub fn encode_with_arena(input_file: &str, output_file: &str, options: &EncodeOptions, arena: &mut Arena) -> Result<Report, ZipError> {
+    let mut retries = 0;
+    loop {
+        let before = FileState::stat(input_file).context(input_file, Phase::Open)?;
+        let input = File::open(input_file).context(input_file, Phase::Open)?;
+        let time = time_data(input_file, &*options.clock);
+        let entry = EntryOptions { permissions: Some(Permissions::from_metadata(&input.metadata()?)), ..EntryOptions::default() };
+        let temp = temp_dir(options.tmpdir.as_deref(), Path::new(output_file), &[])?;
+        check_space(Path::new(output_file), &temp, worst_case_size([(before.size, input_file.len())]))?;
+
+        /*
+         tailの場合には、開いた時点のサイズまでしか読み込まない
+         */
+        let limit = if options.tail { before.size } else { u64::MAX };
+        let mut input = LimitedReader::new(input.take(limit), options.max_entry_size, options.entry_timeout);
+        let mut report = write_output(Path::new(output_file), options.tmpdir.as_deref(), &[], |mut output| {
+            encode_entry(&mut input, &mut output, input_file, time, options, &entry, arena)
+        })?;
+        let after = FileState::stat(input_file)?;
+        if options.tail {
+            report.entries[0].cut_at = Some(before.size);
+        }
+
+        /*
+         読み込んでいる間にファイルが書き換えられていないかを確認する
+         tailの場合には、後ろに追記されただけであれば書き換えられていないものとする
+         */
+        let read_size = report.entries[0].before_size;
+        let unchanged = if options.tail {
+            after.size >= before.size && before.size == read_size
+        } else {
+            before == after && before.size == read_size
+        };
+        if unchanged {
+            report.entries[0].retries = retries;
+            return Ok(report);
+        }
+        match options.on_change {
+            ChangePolicy::Warn => {
+                report.entries[0].changed = true;
+                report.entries[0].retries = retries;
+                return Ok(report);
+            }
+            ChangePolicy::Retry if retries < MAX_CHANGE_RETRIES => {
+                retries += 1;
+            }
+            _ => {
+                return Err(ZipError::FileChanged { name: input_file.to_string() });
+            }
+        }
+    }
+}
+
+/*
+ ファイルが書き換えられたかを調べるためのもの
+ size:      ファイルのサイズ
+ modified:  最終更新日時
+ */
+#[derive(PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl FileState {
+    fn stat(filename: &str) -> Result<Self, Error> {
+        let metadata = metadata(filename)?;
+        Ok(FileState {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/*
+ メモリ上のinputを圧縮し、"data"という名前のエントリ1つだけのzipとしてoutに書き込む。
+ 書き込んだバイト数を返す。zipがoutに収まらない場合にはBufferTooSmallを返す。
+ 組み込みやFFIなどで、呼び出す側がメモリを用意する場合に使う。
+ */
+pub fn encode_into(input: &[u8], out: &mut [u8]) -> Result<usize, ZipError> {
+    encode_into_with(input, DEFAULT_ENTRY_NAME, out, &EncodeOptions::default())
+}
+
+/*
+ encode_intoにエントリの名前とオプションを指定できるようにしたもの。
+ 日時はoptions.clockの現在時刻になる。
+ BufferTooSmallには、zipを書き込むのに必要だったバイト数が入っている。
+ */
+pub fn encode_into_with(mut input: &[u8], name: &str, out: &mut [u8], options: &EncodeOptions) -> Result<usize, ZipError> {
+    let mut writer = ZipWriter::with_options(SliceWriter::new(out), options.clone());
+    writer.write_entry(name, EntryOptions::default(), &mut input)?;
+    let output = writer.finish()?.into_inner();
+    if output.required > output.buffer.len() {
+        return Err(ZipError::BufferTooSmall { required: output.required, available: output.buffer.len() });
+    }
+    Ok(output.required)
+}
+
+/*
+ inputから読み込んだデータを圧縮し、nameという名前のエントリ1つだけのzipとしてoutputに書き込む。
+ time:   zipの形式にした（時刻, 日付）
+ entry:  属性などのエントリごとのオプション
+ */
+pub(crate) fn encode_entry<R: Read, W: Write>(input: &mut R, output: &mut W, name: &str, time: (u16, u16), options: &EncodeOptions, entry: &EntryOptions, arena: &mut Arena) -> Result<Report, ZipError> {
+    let mut writer = ArchiveWriter::new(output).with_comment(archive_comment(options)?);
+    writer.add_entry(input, name, time, options, entry, arena)?;
+    Ok(writer.finish()?)
+}
+
+/*
+ inputを圧縮してarena.compressedに入れる。入力のサイズとcrc32を返す。
+ options.sample_lenが指定されている場合は、それより大きいエントリの先頭だけを先に圧縮してみて、小さくならなければ
+ （既に圧縮された画像やアーカイブなど）残りも含めて圧縮を試さずに無圧縮のブロックにする。
+ 圧縮しても小さくならなかったエントリは、書き込むときに圧縮せずに格納する（writer::choose_method）。
+ sinkが指定された場合は、圧縮したデータをarena.compressedに溜めずに、できたところから順にsinkに書き込む。
+ */
+fn compress<R: Read>(input: &mut R, options: &EncodeOptions, arena: &mut Arena, profile: &mut Profile, mut sink: Option<&mut Sink>) -> Result<(u64, u32), Error> {
+    arena.clear();
+    let progress = options.progress.as_deref();
+    if let Some(progress) = progress {
+        progress.update(0, 0, 0);
+    }
+    let sample_len = options.sample_len.filter(|_| options.level > 0).unwrap_or(0);
+    let mut sample = Vec::new();
+    let read_start = Instant::now();
+    input.by_ref().take(sample_len).read_to_end(&mut sample)?;
+    profile.read += read_start.elapsed();
+    let mut input = sample.as_slice().chain(input);
+    let (file_size, crc32) = if sample_len > 0 && sample.len() as u64 == sample_len && !compresses(&sample, options)? {
+        compress_stored(&mut input, arena, profile, progress, sink.as_deref_mut())?
+    } else {
+        deflate(&mut input, options, arena, profile, progress, sink.as_deref_mut())?
+    };
+    if let Some(sink) = sink.as_deref_mut() {
+        sink.drain(&mut arena.compressed)?;
+    }
+    if let Some(progress) = progress {
+        progress.update(file_size, compressed_len(&sink, &arena.compressed), crc32);
+    }
+    Ok((file_size, crc32))
+}
+
+/*
+ options.engineとoptions.levelに合わせて、inputをdeflateで圧縮してarena.compressedに入れる
+ */
+fn deflate<R: Read>(input: &mut R, options: &EncodeOptions, arena: &mut Arena, profile: &mut Profile, progress: Option<&Progress>, sink: Option<&mut Sink>) -> Result<(u64, u32), Error> {
+    match options.engine {
+        Engine::Builtin if options.level == 0 => compress_stored(input, arena, profile, progress, sink),
+        Engine::Builtin if options.level >= MAX_LEVEL => compress_optimal(input, arena, profile),
+        Engine::Builtin => {
+            let limits = BlockLimits { symbols: options.block_max_symbols, bytes: options.block_max_bytes };
+            compress_builtin(input, arena, profile, progress, &limits, WINDOW_SIZES[usize::from(options.level) - 1], sink)
+        }
+        #[cfg(feature = "miniz")]
+        Engine::Miniz => compress_miniz(input, options.level, arena, profile),
+    }
+}
+
+/*
+ sampleをoptionsと同じ方法で圧縮してみて、小さくなるかどうかを返す
+ */
+fn compresses(sample: &[u8], options: &EncodeOptions) -> Result<bool, Error> {
+    let mut arena = Arena::new();
+    deflate(&mut &sample[..], options, &mut arena, &mut Profile::new(), None, None)?;
+    Ok(arena.compressed.len() < sample.len())
+}
+
+/*
+ inputを圧縮せずにarena.compressedに入れる（Compression::Storeのエントリに使う）。入力のサイズとcrc32を返す。
+ sinkが指定された場合は、arena.compressedに溜めずに読み込んだところから順にsinkに書き込む。
+ */
+fn read_stored<R: Read>(input: &mut R, arena: &mut Arena, profile: &mut Profile, mut sink: Option<&mut Sink>) -> Result<(u64, u32), Error> {
+    arena.clear();
+    let mut crc = Crc32::new();
+    let mut file_size = 0;
+    loop {
+        let read_start = Instant::now();
+        let len = input.by_ref().take(MAX_STORED_LEN as u64).read_to_end(&mut arena.compressed)?;
+        profile.read += read_start.elapsed();
+
+        let crc_start = Instant::now();
+        for &byte in &arena.compressed[arena.compressed.len() - len..] {
+            crc.push_buf(byte);
+        }
+        profile.crc += crc_start.elapsed();
+        file_size += len as u64;
+        if let Some(sink) = sink.as_deref_mut() {
+            sink.drain(&mut arena.compressed)?;
+        }
+        if len == 0 {
+            return Ok((file_size, crc.get_crc32()));
+        }
+    }
+}
+
+/*
+ 1つのdeflateのブロックの大きさの上限
+ symbols:  ブロックに入れるトークンの最大の数
+ bytes:    ブロックに入れる入力の最大のバイト数
+ */
+struct BlockLimits {
+    symbols: Option<usize>,
+    bytes: Option<usize>,
+}
+
+impl BlockLimits {
+    fn is_set(&self) -> bool {
+        self.symbols.is_some() || self.bytes.is_some()
+    }
+
+    /*
+     今のブロックのトークンの数と入力のバイト数が、どちらかの上限に達したかどうか
+     */
+    fn reached(&self, symbols: usize, bytes: usize) -> bool {
+        self.symbols.is_some_and(|limit| symbols >= limit) || self.bytes.is_some_and(|limit| bytes >= limit)
+    }
+}
+
+/*
+ このクレートの固定ハフマン方式でdeflate圧縮を行う。
+ 圧縮したデータはarena.compressedに入れて、入力のサイズとcrc32を返す。
+ progressがある場合には、溜まったトークンを符号化するたびに途中経過を更新する。
+ window_sizeは一致を探すスライドウインドウの大きさ（圧縮レベルからWINDOW_SIZESで決める）。
+ limitsが指定されている場合には、上限に達するたびにブロックを区切る。
+ その場合はどのブロックが最後になるかを前もって決められないため、全てのブロックを最後ではないものにして、
+ 最後に空の固定ハフマンのブロックを付ける。
+ */
+fn compress_builtin<R: Read>(input: &mut R, arena: &mut Arena, profile: &mut Profile, progress: Option<&Progress>, limits: &BlockLimits, window_size: usize, mut sink: Option<&mut Sink>) -> Result<(u64, u32), Error> {
+    let mut input_reader = ByteReader::new(input)?;
+    let mut output_writer = BitWriter::new(std::mem::take(&mut arena.compressed));
+
+    let window = &mut arena.window;
+    let tokens = &mut arena.tokens;
+    let res = &mut arena.matched;
+
+    output_writer.extra_bits(!limits.is_set() as u16, 1)?;
     output_writer.extra_bits(0b01, 2)?;
 
-    let first = input_reader.get_byte();
-    crcs.push_buf(first.clone());
-    let (bit, first_data)= changer(first as usize);
-    output_writer.code_bits(first_data, bit)?;
+    let match_start = Instant::now();
+    let mut block_symbols = 0;
+    let mut block_bytes = 0;
+    /*
+     空のファイルの場合には何も出力しない
+     */
+    if input_reader.flag {
+        let first = input_reader.get_byte()?;
+        tokens.push(Token::Literal(first));
+        block_symbols += 1;
+        block_bytes += 1;
+    }
 
     loop{
-        if input_reader.flag == false { break;}
-        let byte = input_reader.get_byte();
-        if PRINT_DEBUG == true {
+        if !input_reader.flag { break;}
+        let byte = input_reader.get_byte()?;
+        if PRINT_DEBUG {
             println!("{:02x?}", byte);
         }
-        crcs.push_buf(byte.clone());
-        
-        let mut res = vec![byte.clone()];
+
+        res.clear();
+        res.push(byte);
 
         let mut offset: isize = -1;
 
         window.push(res[0]);
+        /*
+         入力の最後まで読んだ後はbufferに前のデータが残っているため、一致の探索を続けない
+         */
         while res.len() < MAX_MATCH_LEN {
-            let v = input_reader.seek_byte().clone();
+            let v = match input_reader.seek_byte() {
+                Some(v) => v,
+                None => break,
+            };
             res.push(v);
-            let new_offset = match_check(&mut window, &mut res);
+            let new_offset = match_check(window, res);
             window.push(v);
             if new_offset == -1 {
                 res.pop();
@@ -700,72 +1725,170 @@ pub fn encode(input_file: &str, output_file: &str) -> Result<(), std::io::Error>
                 break;
             }
             offset = new_offset;
-            crcs.push_buf(v.clone());
-            input_reader.next_byte();
-            if input_reader.flag == false { break };
+            input_reader.next_byte()?;
+            if !input_reader.flag { break };
         }
         if res.len() < MIN_MATCH_LEN {
-            for byte in &res {
-                let (bits, buf) = changer(*byte as usize);
-                output_writer.code_bits(buf, bits)?;
-                if PRINT_DEBUG == true {
-                    println!("{:09b} :{}", buf, bits);
-                }
+            for byte in res.iter() {
+                tokens.push(Token::Literal(*byte));
             }
+            block_symbols += res.len();
         } else {
-            let (num , data, extra) = length_extra(res.len() as u16);
-            let (bits, buf) = changer(num as usize);
-            output_writer.code_bits(buf, bits)?;
-            if PRINT_DEBUG == true {
-                println!("{:09b} :{}", buf, bits);
-            }
-            output_writer.extra_bits(extra, data)?;
-            if PRINT_DEBUG == true {
-                println!("{:05b} :{}", extra, data);
+            tokens.push(Token::Match(res.len() as u16, offset as u32));
+            block_symbols += 1;
+        }
+        block_bytes += res.len();
+        if window.len() > window_size {
+            window.drain(0..(window.len() - window_size));
+        }
+        let end_block = limits.reached(block_symbols, block_bytes) && input_reader.flag;
+        if tokens.len() >= MAX_BUFFER_SIZE || end_block {
+            let coding_start = Instant::now();
+            write_tokens(&mut output_writer, tokens)?;
+            tokens.clear();
+            if end_block {
+                output_writer.code_bits(0b0000000, 7)?;
+                output_writer.extra_bits(0b0, 1)?;
+                output_writer.extra_bits(0b01, 2)?;
+                block_symbols = 0;
+                block_bytes = 0;
             }
-            let (num , data, extra) = distance_extra(offset as u32);
-            output_writer.code_bits(num as u16, 5)?;
-            if PRINT_DEBUG == true {
-                println!("{:05b} :{}", num, 5);
+            profile.entropy_coding += coding_start.elapsed();
+            if let Some(sink) = sink.as_deref_mut() {
+                sink.drain(&mut output_writer.output_vector)?;
             }
-            output_writer.extra_bits(extra , data)?;
-            if PRINT_DEBUG == true {
-                println!("{:09b} :{}", extra, data);
+            if let Some(progress) = progress {
+                let crc32 = input_reader.crc.clone().get_crc32();
+                progress.update(input_reader.file_size, compressed_len(&sink, &output_writer.output_vector), crc32);
             }
         }
-        if window.len() > MAX_WINDOW_SIZE{
-            window.drain(0..(window.len() - MAX_WINDOW_SIZE));
-        }
-
     }
 
+    let coding_start = Instant::now();
+    write_tokens(&mut output_writer, tokens)?;
     output_writer.code_bits(0b0000000, 7)?;
+    if limits.is_set() {
+        output_writer.extra_bits(0b1, 1)?;
+        output_writer.extra_bits(0b01, 2)?;
+        output_writer.code_bits(0b0000000, 7)?;
+    }
     output_writer.flush()?;
+    profile.entropy_coding += coding_start.elapsed();
+
+    /*
+     match_searchには読み込みとcrc32の計算が含まれているため、その分を引いておく
+     */
+    profile.read = input_reader.read_time;
+    profile.crc = input_reader.crc_time;
+    profile.match_search = match_start.elapsed()
+        .checked_sub(profile.entropy_coding + profile.read + profile.crc)
+        .unwrap_or_default();
 
-    let crc32 = crcs.get_crc32();
+    arena.compressed = output_writer.output_vector;
+    Ok((input_reader.file_size, input_reader.crc.get_crc32()))
+}
 
-    let (hms, ymd) = time_data(&input_file);
+/*
+ 圧縮レベルがMAX_LEVEL以上の場合に、入力を全て読み込んでから最短経路で一致を選び、固定ハフマンで符号化する。
+ 入力全体をメモリに置き、1バイトごとに一致を探すため時間がかかるが、配布物などで少しでも小さくしたい場合に使う。
+ */
+fn compress_optimal<R: Read>(input: &mut R, arena: &mut Arena, profile: &mut Profile) -> Result<(u64, u32), Error> {
+    let read_start = Instant::now();
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    profile.read = read_start.elapsed();
 
-    let header = Header::new(input_reader.file_size, (output_writer.output_vector.len()) as u32, input_file, crc32, hms, ymd);
+    let crc_start = Instant::now();
+    let crc32 = crc32(&data);
+    profile.crc = crc_start.elapsed();
 
-    let local_header = header.clone().local_header();
-    let central_header = header.clone().central_header();
-    let end_header = header.clone().end_header((central_header.len()) as u32, (local_header.len() + output_writer.output_vector.len()) as u32);
+    let match_start = Instant::now();
+    optimal_tokens(&data, &mut arena.tokens);
+    profile.match_search = match_start.elapsed();
 
-    if PRINT_DEBUG == true {
-        for i in 0..(output_writer.output_vector.len()){
-            print!("{:08b}", output_writer.output_vector[i]);
+    let coding_start = Instant::now();
+    let mut output_writer = BitWriter::new(std::mem::take(&mut arena.compressed));
+    output_writer.extra_bits(0b1, 1)?;
+    output_writer.extra_bits(0b01, 2)?;
+    write_tokens(&mut output_writer, &arena.tokens)?;
+    output_writer.code_bits(0b0000000, 7)?;
+    output_writer.flush()?;
+    profile.entropy_coding = coding_start.elapsed();
+
+    arena.compressed = output_writer.output_vector;
+    Ok((data.len() as u64, crc32))
+}
+
+/*
+ 圧縮レベルが0の場合に、inputを無圧縮のブロック（BTYPE=00）だけのdeflateにする。
+ ブロックごとに、ヘッダーの3ビットの後をバイトの境界に揃え、LENとNLEN（LENの否定）に続けてデータをそのまま書き込む。
+ 入力がちょうどMAX_STORED_LENの倍数の場合は、最後に空のブロックを書き込む。
+ */
+fn compress_stored<R: Read>(input: &mut R, arena: &mut Arena, profile: &mut Profile, progress: Option<&Progress>, mut sink: Option<&mut Sink>) -> Result<(u64, u32), Error> {
+    let mut output_writer = BitWriter::new(std::mem::take(&mut arena.compressed));
+    let mut block = vec![0u8; MAX_STORED_LEN];
+    let mut crc = Crc32::new();
+    let mut file_size: u64 = 0;
+    loop {
+        let read_start = Instant::now();
+        let mut len = 0;
+        while len < block.len() {
+            match input.read(&mut block[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        profile.read += read_start.elapsed();
+
+        let crc_start = Instant::now();
+        for &byte in &block[..len] {
+            crc.push_buf(byte);
+        }
+        profile.crc += crc_start.elapsed();
+        file_size += len as u64;
+
+        let coding_start = Instant::now();
+        let last = len < block.len();
+        output_writer.extra_bits(last as u16, 1)?;
+        output_writer.extra_bits(0b00, 2)?;
+        output_writer.align_to_byte()?;
+        output_writer.write_bytes(&(len as u16).to_le_bytes())?;
+        output_writer.write_bytes(&(!(len as u16)).to_le_bytes())?;
+        output_writer.write_bytes(&block[..len])?;
+        profile.entropy_coding += coding_start.elapsed();
+        if let Some(sink) = sink.as_deref_mut() {
+            sink.drain(&mut output_writer.output_vector)?;
+        }
+        if let Some(progress) = progress {
+            progress.update(file_size, compressed_len(&sink, &output_writer.output_vector), crc.clone().get_crc32());
+        }
+        if last {
+            break;
         }
-        println!();
     }
+    arena.compressed = output_writer.output_vector;
+    Ok((file_size, crc.get_crc32()))
+}
 
-    /*
-     ここでzipファイルを出力している。
-     */
-    output_writer.output.write_all(&local_header)?;
-    output_writer.output.write_all(&output_writer.output_vector)?;
-    output_writer.output.write_all(&central_header)?;
-    output_writer.output.write_all(&end_header)?;
+/*
+ miniz_oxideを使ってdeflate圧縮を行う。
+ zipのヘッダなどはこのクレートで作成し、deflateの部分だけを任せている。
+ miniz_oxideでは一致の探索と符号化を分けて計測できないため、まとめてmatch_searchに入れている。
+ */
+#[cfg(feature = "miniz")]
+fn compress_miniz<R: Read>(input: &mut R, level: u8, arena: &mut Arena, profile: &mut Profile) -> Result<(u64, u32), Error> {
+    let read_start = Instant::now();
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    profile.read = read_start.elapsed();
 
-    Ok(())
+    let crc_start = Instant::now();
+    let crc = crc32(&data);
+    profile.crc = crc_start.elapsed();
+
+    let match_start = Instant::now();
+    arena.compressed = miniz_oxide::deflate::compress_to_vec(&data, level);
+    profile.match_search = match_start.elapsed();
+
+    Ok((data.len() as u64, crc))
 }
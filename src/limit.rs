@@ -0,0 +1,53 @@
+use std::io::prelude::*;
+use std::io::Error;
+use std::time::{Duration, Instant};
+
+use crate::error::LimitExceeded;
+
+/*
+ 読み込んだサイズと経過時間を見て、制限を超えた場合にエラーを返すReader
+ 時間は読み込みのたびに確認しているため、読み込みが返ってこない場合（書き込み側のいないFIFOなど）には
+ 止めることができない。
+ inner:     元のReader
+ read:      今までに読み込んだバイト数
+ max_size:  読み込める最大のバイト数
+ deadline:  この時刻を過ぎたらエラーにする
+ timeout:   エラーに含めるための制限時間
+ */
+pub(crate) struct LimitedReader<R: Read> {
+    inner: R,
+    read: u64,
+    max_size: Option<u64>,
+    deadline: Option<Instant>,
+    timeout: Duration,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, max_size: Option<u64>, timeout: Option<Duration>) -> Self {
+        LimitedReader {
+            inner,
+            read: 0,
+            max_size,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            timeout: timeout.unwrap_or_default(),
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() > deadline {
+                return Err(Error::other(LimitExceeded::Time(self.timeout)));
+            }
+        }
+        let size = self.inner.read(buf)?;
+        self.read += size as u64;
+        if let Some(max_size) = self.max_size {
+            if self.read > max_size {
+                return Err(Error::other(LimitExceeded::Size(max_size)));
+            }
+        }
+        Ok(size)
+    }
+}
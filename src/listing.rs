@@ -0,0 +1,109 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::ZipEntry;
+
+/*
+ human_sizeで使う単位（1024倍ごと）
+ */
+const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+/*
+ エントリの一覧を並べ替えるときに比べるもの
+ Name:   名前（大文字と小文字を区別せず、同じ場合は区別して比べる）
+ Size:   展開後のサイズ
+ Ratio:  圧縮率（圧縮で減った割合）
+ Mtime:  最終更新日時（ZipEntry::modified_time）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Ratio,
+    Mtime,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "ratio" => Ok(SortKey::Ratio),
+            "mtime" => Ok(SortKey::Mtime),
+            _ => Err(format!("unknown sort key: {}", s)),
+        }
+    }
+}
+
+impl SortKey {
+    /*
+     2つのエントリをこのキーで比べる（小さいものが先になる）
+     */
+    pub fn compare(&self, a: &ZipEntry, b: &ZipEntry) -> Ordering {
+        match self {
+            SortKey::Name => compare_names(&a.name, &b.name),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Ratio => compare_ratios(a, b),
+            SortKey::Mtime => a.modified_time().cmp(&b.modified_time()),
+        }
+    }
+}
+
+/*
+ entriesをkeyで並べ替える（reverseの場合は大きいものから）。
+ 同じ値のエントリはセントラルヘッダーの順のままにする。
+ */
+pub fn sort_entries(entries: &mut [&ZipEntry], key: SortKey, reverse: bool) {
+    entries.sort_by(|a, b| {
+        let ordering = key.compare(a, b);
+        if reverse { ordering.reverse() } else { ordering }
+    });
+}
+
+/*
+ ls -h のように、サイズを1024倍ごとの単位を付けて短く書く（1023、1.5K、15K、2.0Mなど）。
+ 単位を付ける場合は、10未満なら小数点以下1桁まで、それ以上なら整数で書く。
+ */
+pub fn human_size(size: u64) -> String {
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64;
+    let mut unit = "";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    if value < 10.0 {
+        format!("{:.1}{}", value, unit)
+    } else {
+        format!("{:.0}{}", value, unit)
+    }
+}
+
+/*
+ 名前を大文字と小文字を区別せずに比べ、同じ場合は区別して比べる（README.mdとreadme.txtが並ぶようにする）
+ */
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let lower = |name: &str| name.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+    lower(a).cmp(&lower(b)).then_with(|| a.cmp(b))
+}
+
+/*
+ 圧縮率（(size - compressed_size) / size）を、割り算をせずに掛け合わせて比べる。
+ サイズが0のエントリは0%とする。
+ */
+fn compare_ratios(a: &ZipEntry, b: &ZipEntry) -> Ordering {
+    let fraction = |entry: &ZipEntry| match entry.size {
+        0 => (0, 1),
+        size => (size as i128 - entry.compressed_size as i128, size as i128),
+    };
+    let (a_saved, a_size) = fraction(a);
+    let (b_saved, b_size) = fraction(b);
+    (a_saved * b_size).cmp(&(b_saved * a_size))
+}
@@ -1,26 +1,1084 @@
 extern crate zipper;
 
 use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
 
-use zipper::encode;
+use zipper::{apply_delta, build, compare_archives, decode_with, dedup_report, delta, encode_files_with, encode_with, extract_any, gen_vectors, git_archive, human_size, name_decoder, name_encoder, normalize, recompress_stream, repair, scan, sort_entries, vacuum, verify_against_fs, version_info, Compression, CompressionCache, DecodeOptions, EncodeOptions, Input, Report, ScanOptions, SortKey, VectorStatus, ZipArchive, ZipEntry, ZipError};
+
+/*
+ gen-vectors dir で、テスト用のzipをdirの中に作成する。
+ */
+fn run_gen_vectors(dir: &str) {
+    match gen_vectors(Path::new(dir)) {
+        Ok(results) => {
+            for result in results {
+                match result.status {
+                    VectorStatus::Written => println!("written  {}: {}", result.file_name, result.description),
+                    VectorStatus::Skipped(reason) => println!("skipped  {}: {} ({})", result.file_name, result.description, reason),
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ serve --socket path で、Unixソケットを使ったJSON-RPCのサーバーとして動かす。
+ */
+#[cfg(all(unix, feature = "serve"))]
+fn run_serve() {
+    let args: Vec<String> = env::args().collect();
+    let socket = match args.iter().position(|arg| arg == "--socket") {
+        Some(i) if i + 1 < args.len() => args[i + 1].clone(),
+        _ => panic!("No socket path"),
+    };
+    if let Err(err) = zipper::serve(Path::new(&socket)) {
+        eprintln!("IO Error => {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(all(unix, feature = "serve")))]
+fn run_serve() {
+    panic!("serve is not enabled (build with --features serve on a unix platform)");
+}
+
+/*
+ serve-http --addr 127.0.0.1:8080 --root dir で、dirの中のファイルをzipにして返すHTTPサーバーとして動かす。
+ */
+#[cfg(feature = "http")]
+fn run_serve_http() {
+    let args: Vec<String> = env::args().collect();
+    let value = |name: &str, default: &str| match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => args[i + 1].clone(),
+        _ => default.to_string(),
+    };
+    let addr = value("--addr", "127.0.0.1:8080");
+    let root = value("--root", ".");
+    if let Err(err) = zipper::serve_http(&addr, Path::new(&root)) {
+        eprintln!("IO Error => {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn run_serve_http() {
+    panic!("serve-http is not enabled (build with --features http)");
+}
+
+/*
+ mount [--password pw] [--name-encoding cp932] archive.zip mountpoint で、zipを読み取り専用のファイルシステムとしてマウントする。
+ アンマウントされるまで終わらない。
+ */
+#[cfg(all(target_os = "linux", feature = "fuse"))]
+fn run_mount() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let mut options = DecodeOptions::new();
+    options.password = take_password(&mut args);
+    take_name_encoding(&mut args, &mut options);
+    if args.len() < 2 {
+        panic!("No zip file name or mount point");
+    }
+    let result = ZipArchive::open_with(&args[0], &options).and_then(|archive| zipper::mount(archive, Path::new(&args[1])));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "fuse")))]
+fn run_mount() {
+    panic!("mount is not enabled (build with --features fuse on linux)");
+}
+
+/*
+ argsから --password pw を取り除き、pwを返す（decode、extract、test、catで暗号化されたエントリを読むのに使う）
+ */
+fn take_password(args: &mut Vec<String>) -> Option<String> {
+    let index = args.iter().position(|arg| arg == "--password")?;
+    args.remove(index);
+    if index >= args.len() {
+        panic!("No password after --password");
+    }
+    Some(args.remove(index))
+}
+
+/*
+ argsから --name-encoding label を取り除き、optionsのname_decoderにする
+ （decode、extract、listで、UTF-8のフラグが立っていない名前をCP437以外の文字コードで読むのに使う）
+ */
+fn take_name_encoding(args: &mut Vec<String>, options: &mut DecodeOptions) {
+    let index = match args.iter().position(|arg| arg == "--name-encoding") {
+        Some(index) => index,
+        None => return,
+    };
+    args.remove(index);
+    if index >= args.len() {
+        panic!("No encoding after --name-encoding");
+    }
+    options.name_decoder = name_decoder(&args.remove(index)).unwrap_or_else(|err| panic!("{}", err));
+}
+
+/*
+ argsから --jobs n を取り除き、optionsのjobsにする（extractで複数のエントリを同時に展開するのに使う）
+ */
+fn take_jobs(args: &mut Vec<String>, options: &mut DecodeOptions) {
+    let index = match args.iter().position(|arg| arg == "--jobs") {
+        Some(index) => index,
+        None => return,
+    };
+    args.remove(index);
+    if index >= args.len() {
+        panic!("No number of jobs after --jobs");
+    }
+    let n = args.remove(index);
+    options.jobs = n.parse().unwrap_or_else(|_| panic!("invalid number of jobs: {}", n));
+}
+
+/*
+ decode [--lenient] input.zip output_dir で、zipのエントリを全てoutput_dirの中に展開する。
+ --lenient を付けると、よくある仕様違反を警告にして展開を続ける。
+ --allow-unsafe-paths を付けると、output_dirの外を指す名前のエントリもそのまま展開する。
+ --special-bits を付けると、属性のsetuid、setgid、スティッキーのビットも展開したファイルに設定する（付けない場合は外す）。
+ --password pw を付けると、ZipCryptoかWinZipのAESで暗号化されたエントリをpwで復号する。
+ --name-encoding cp932 のように付けると、UTF-8のフラグが立っていない名前をCP437の代わりにその文字コードで読む。
+ */
+fn run_decode() {
+    let mut options = DecodeOptions::new();
+    let mut rest: Vec<String> = env::args().skip(2).collect();
+    options.password = take_password(&mut rest);
+    take_name_encoding(&mut rest, &mut options);
+    let mut args = Vec::new();
+    for arg in rest {
+        match arg.as_str() {
+            "--lenient" => options.lenient = true,
+            "--allow-unsafe-paths" => options.allow_unsafe_paths = true,
+            "--special-bits" => options.special_bits = true,
+            _ => args.push(arg),
+        }
+    }
+    if args.len() < 2 {
+        panic!("No zip file or directory name");
+    }
+    match decode_with(&args[0], &args[1], &options) {
+        Ok(report) => {
+            for diagnostic in &report.diagnostics {
+                eprintln!("warning: {}", diagnostic);
+            }
+            println!("{} entries extracted to {}", report.entries.len(), args[1]);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ extract input.zip output_dir [pattern...] で、名前がpatternのどれかに当てはまるエントリだけを展開する。
+ patternを指定しない場合は全てのエントリを展開する。シェルに展開されないように、パターンは '*.json' のように囲んで渡す。
+ --password pw を付けると、ZipCryptoかWinZipのAESで暗号化されたエントリをpwで復号する（test、catでも同じ）。
+ --name-encoding と --special-bits はdecodeと同じ。
+ --jobs n で、n個のスレッドで複数のエントリを同時に展開する。
+ input.zipの代わりにgzip、tar、tar.gzも渡せる（拡張子ではなく先頭のバイト列で種類を調べる）。
+ */
+fn run_extract() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let mut options = DecodeOptions::new();
+    options.password = take_password(&mut args);
+    take_name_encoding(&mut args, &mut options);
+    take_jobs(&mut args, &mut options);
+    if let Some(index) = args.iter().position(|arg| arg == "--special-bits") {
+        args.remove(index);
+        options.special_bits = true;
+    }
+    if args.len() < 2 {
+        panic!("No archive or directory name");
+    }
+    match extract_any(&args[0], &args[1], &args[2..], &options) {
+        Ok(count) => println!("{} entries extracted to {}", count, args[1]),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ list [--sort name|size|ratio|mtime] [--reverse] [--human] input.zip で、zipのエントリの一覧をセントラルヘッダーから表示する。
+ unzip -v のように、展開後のサイズ、圧縮方法、圧縮後のサイズ、圧縮率、日時、crc32、名前を1行ずつ書き、最後に合計を書く。
+ --sortを付けない場合はセントラルヘッダーの順にする。--human を付けるとサイズを1.5Kのように単位を付けて書く。
+ zip全体のコメントは一覧の前に、エントリのコメントはそのエントリの次の行に字下げして書く。
+ --name-encoding はdecodeと同じ。
+ */
+fn run_list() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let mut options = DecodeOptions::new();
+    take_name_encoding(&mut args, &mut options);
+    let mut argv = args.into_iter();
+    let mut path = None;
+    let mut sort = None;
+    let mut reverse = false;
+    let mut human = false;
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--sort" => {
+                let key = argv.next().unwrap_or_default();
+                sort = Some(key.parse::<SortKey>().unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--reverse" => reverse = true,
+            "--human" | "-h" => human = true,
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| panic!("No zip file name"));
+    let size = |size: u64| if human { human_size(size) } else { size.to_string() };
+
+    match ZipArchive::open_with(&path, &options) {
+        Ok(archive) => {
+            let mut entries: Vec<&ZipEntry> = archive.entries().iter().collect();
+            if let Some(key) = sort {
+                sort_entries(&mut entries, key, reverse);
+            } else if reverse {
+                entries.reverse();
+            }
+            if !archive.comment().is_empty() {
+                println!("{}", archive.comment());
+            }
+            println!("{:>10}  {:<7} {:>10} {:>5}  {:<19} {:<8}  Name", "Length", "Method", "Size", "Cmpr", "Date Time", "CRC-32");
+            let mut total_size = 0u64;
+            let mut total_compressed = 0u64;
+            for entry in entries {
+                let time = entry.modified;
+                println!(
+                    "{:>10}  {:<7} {:>10} {:>5}  {:04}-{:02}-{:02} {:02}:{:02}:{:02} {:08x}  {}",
+                    size(entry.size), entry.method_name(), size(entry.compressed_size), ratio(entry.size, entry.compressed_size),
+                    time.year, time.month, time.day, time.hour, time.minute, time.second, entry.crc32, entry.name
+                );
+                for line in entry.comment.lines() {
+                    println!("{:>12}{}", "", line);
+                }
+                total_size += entry.size;
+                total_compressed += entry.compressed_size;
+            }
+            println!(
+                "{:>10}  {:<7} {:>10} {:>5}  {} files",
+                size(total_size), "", size(total_compressed), ratio(total_size, total_compressed), archive.len()
+            );
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ listで表示する圧縮率（圧縮で減った割合）
+ */
+fn ratio(size: u64, compressed_size: u64) -> String {
+    if size == 0 {
+        return "0%".to_string();
+    }
+    format!("{}%", (size as i64 - compressed_size as i64) * 100 / size as i64)
+}
+
+/*
+ stats input.zip で、エントリを拡張子ごとと一番上のディレクトリごとに集計し、数とサイズと圧縮率を表にして表示する。
+ --json を付けるとJSONで表示する。
+ */
+fn run_stats(path: &str, json: bool) {
+    match ZipArchive::open(path) {
+        Ok(archive) if json => print!("{}", archive.stats().to_json()),
+        Ok(archive) => print!("{}", archive.stats()),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ test input.zip で、全てのエントリを展開せずに確かめ、unzip -t のようにエントリごとの結果を表示する。
+ 壊れたエントリがある場合は終了コードを1にする。
+ */
+fn run_test(path: &str, password: Option<&str>) {
+    let mut archive = match ZipArchive::open(path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(password) = password {
+        archive.set_password(password);
+    }
+    let damaged = archive.verify();
+    for entry in archive.entries() {
+        match damaged.iter().find(|damaged| damaged.name == entry.name) {
+            Some(damaged) => println!("    testing: {}  FAILED ({})", entry.name, damaged.error),
+            None => println!("    testing: {}  OK", entry.name),
+        }
+    }
+    if damaged.is_empty() {
+        println!("No errors detected in {}", path);
+    } else {
+        println!("{} of {} entries are damaged in {}", damaged.len(), archive.len(), path);
+        process::exit(1);
+    }
+}
+
+/*
+ scan input.zip で、全てのエントリを一定の大きさのバッファに展開してcrc32を確かめ、エントリごとにSHA-1を表示する（sha1sumと同じ形）。
+ --buffer-size、--max-entry-size、--max-total-size で使うメモリと展開する量を制限する。
+ 失敗したエントリがある場合は終了コードを1にする。
+ */
+fn run_scan() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let password = take_password(&mut args);
+    let mut options = ScanOptions::new();
+    let mut paths = Vec::new();
+    let mut argv = args.into_iter();
+    while let Some(arg) = argv.next() {
+        let mut size = || parse_size(&argv.next().unwrap_or_default()).unwrap_or_else(|err| panic!("{}", err));
+        match arg.as_str() {
+            "--buffer-size" => options.buffer_size = size() as usize,
+            "--max-entry-size" => options.max_entry_size = Some(size()),
+            "--max-total-size" => options.max_total_size = Some(size()),
+            _ => paths.push(arg),
+        }
+    }
+    let path = paths.first().unwrap_or_else(|| panic!("No zip file name"));
+    let mut archive = match ZipArchive::open(path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(password) = password {
+        archive.set_password(&password);
+    }
+    let scanned = scan(&mut archive, &options);
+    let mut failed = 0;
+    for entry in &scanned {
+        match (entry.sha1_hex(), &entry.error) {
+            (_, Some(err)) => {
+                println!("FAILED  {}  ({})", entry.name, err);
+                failed += 1;
+            }
+            (Some(sha1), None) => println!("{}  {}", sha1, entry.name),
+            (None, None) => {}
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} entries failed in {}", failed, scanned.len(), path);
+        process::exit(1);
+    }
+}
+
+/*
+ cmp [--contents] [--password pw] a.zip b.zip [c.zip...] で、2つ目以降のzipをそれぞれ1つ目と比べて、違いを1行ずつ表示する。
+ エントリの有無、サイズ、crc32、圧縮方法、日時、属性、コメントを比べ、--contents を付けると中身も展開して比べる。
+ 3つ以上渡した場合は、比べる組ごとに見出しを付ける。
+ cmpコマンドと同じく、全て同じ場合は終了コードを0、違いがある場合は1、読めないzipがある場合は2にする。
+ */
+fn run_cmp() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let password = take_password(&mut args);
+    let contents = args.iter().any(|arg| arg == "--contents");
+    args.retain(|arg| arg != "--contents");
+    if args.len() < 2 {
+        panic!("No zip files to compare");
+    }
+    let open = |path: &str| {
+        let mut archive = ZipArchive::open(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(2);
+        });
+        if let Some(password) = &password {
+            archive.set_password(password);
+        }
+        archive
+    };
+    let mut first = open(&args[0]);
+    let mut differ = false;
+    for path in &args[1..] {
+        let mut second = open(path);
+        let differences = compare_archives(&mut first, &mut second, contents).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(2);
+        });
+        if args.len() > 2 {
+            println!("--- {} vs {}", args[0], path);
+        }
+        for difference in &differences {
+            println!("{}", difference);
+        }
+        if differences.is_empty() {
+            println!("{} and {} are the same", args[0], path);
+        }
+        differ |= !differences.is_empty();
+    }
+    if differ {
+        process::exit(1);
+    }
+}
+
+/*
+ dedup-report [--password pw] input.zip で、展開した内容が同じエントリの組を、減らせるサイズの大きい順に表示する。
+ 組ごとに、エントリの数と展開後のサイズ、1つだけ残した場合に減る圧縮後のサイズを書き、その下に名前を字下げして書く。
+ 最後に、全ての組で減らせるサイズの合計を書く。
+ */
+fn run_dedup_report() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let password = take_password(&mut args);
+    let path = args.first().unwrap_or_else(|| panic!("No zip file name"));
+    let mut archive = match ZipArchive::open(path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(password) = password {
+        archive.set_password(&password);
+    }
+    let groups = match dedup_report(&mut archive) {
+        Ok(groups) => groups,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    for group in &groups {
+        println!("{} entries with the same {} bytes (saves {} compressed bytes)", group.names.len(), group.size, group.savings);
+        for name in &group.names {
+            println!("  {}", name);
+        }
+    }
+    let entries: usize = groups.iter().map(|group| group.names.len() - 1).sum();
+    let savings: u64 = groups.iter().map(|group| group.savings).sum();
+    println!("{} duplicate groups, {} redundant entries: {} bytes ({}) could be saved by storing each content once",
+        groups.len(), entries, savings, human_size(savings));
+}
+
+/*
+ verify-fs input.zip dir で、zipを作った後にdirの中のファイルが変わっていないかを、エントリを展開せずにcrc32で確かめる。
+ 変わったファイルがある場合は1つずつ表示して、終了コードを1にする。
+ */
+fn run_verify_fs(path: &str, root: &str) {
+    let result = ZipArchive::open(path).and_then(|archive| verify_against_fs(&archive, root));
+    match result {
+        Ok(changes) if changes.is_empty() => println!("No changes in {} since {} was created", root, path),
+        Ok(changes) => {
+            for change in &changes {
+                println!("{}", change);
+            }
+            println!("{} files changed in {} since {} was created", changes.len(), root, path);
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ repair input.zip output.zip で、セントラルヘッダーが壊れたり途中で切れたりしたzipから、
+ ローカルヘッダーを探し直して取り出せたエントリだけのzipを作る。
+ */
+fn run_repair(input: &str, output: &str) {
+    match repair(input, output) {
+        Ok(report) => {
+            for diagnostic in &report.diagnostics {
+                eprintln!("warning: {}", diagnostic);
+            }
+            println!("{} entries recovered into {} ({} dropped)", report.entries.len(), output, report.diagnostics.len());
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ normalize input.zip output.zip で、圧縮したデータはそのままに、ヘッダーを揃えて常に同じバイト列になるzipに書き直す。
+ */
+fn run_normalize(input: &str, output: &str) {
+    match normalize(input, output) {
+        Ok(report) => println!("{} entries normalized into {}", report.entries.len(), output),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ delta previous.zip dir output.zip で、previous.zipを作った後にdirの中で変わったファイルと新しいファイル、なくなったファイルの一覧を差分のzipにする。
+ */
+fn run_delta(previous: &str, dir: &str, output: &str) {
+    match delta(previous, dir, output) {
+        Ok(report) => println!("{} changed entries written to {}", report.entries.len().saturating_sub(1), output),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ apply delta.zip dir で、前のzipを展開したdirに差分のzipを当てはめ、差分を作ったときのファイルの並びに戻す。
+ */
+fn run_apply(delta_zip: &str, dir: &str) {
+    match apply_delta(delta_zip, dir) {
+        Ok(report) => println!("{} entries applied to {}", report.entries.len(), dir),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ recompress [--store] [--level 0-10] [pattern...] で、標準入力から読み込んだzipのうちpatternに当てはまるエントリ（指定しない場合は全て）を
+ 圧縮し直し、シークせずに標準出力に書き出す（curl ... | zipper recompress '*.json' > out.zip のようにつなげて使う）。
+ */
+fn run_recompress() {
+    let mut compression = Compression::Deflate;
+    let mut options = EncodeOptions::new();
+    let mut patterns = Vec::new();
+    let mut args = env::args().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--store" => compression = Compression::Store,
+            "--level" => {
+                let level = args.next().unwrap_or_default();
+                options.level = level.parse().unwrap_or_else(|_| panic!("invalid level: {}", level));
+            }
+            _ => patterns.push(arg),
+        }
+    }
+    let output = io::BufWriter::new(io::stdout().lock());
+    if let Err(err) = recompress_stream(io::stdin().lock(), output, &patterns, compression, &options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/*
+ vacuum archive.zip で、zipの中のどのエントリにも使われていない部分を取り除いて、ファイルを直接切り詰める。
+ */
+fn run_vacuum(path: &str) {
+    match vacuum(path) {
+        Ok(removed) => println!("{} bytes reclaimed from {}", removed, path),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ cat input.zip name（または -p name input.zip）で、zipのエントリを展開しながら標準出力に書き出す。
+ headなどに渡して読み出し側が先に終わった場合（書き込みがBrokenPipeになった場合）は、エラーにせずに終了する。
+ エラーの場合は終了コードを1にする。
+ */
+fn run_cat(path: &str, name: &str, password: Option<&str>) {
+    let result = ZipArchive::open(path).and_then(|mut archive| {
+        if let Some(password) = password {
+            archive.set_password(password);
+        }
+        let mut entry = archive.open_by_name(name)?;
+        match io::copy(&mut entry, &mut io::stdout().lock()) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result.map(|_| ()).map_err(ZipError::from),
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/*
+ git-archive rev -o output [-C repo] [--tmpdir dir] で、gitのリポジトリのrevの時点のファイルをzipにする。
+ */
+fn run_git_archive() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    let mut rev = None;
+    let mut output = None;
+    let mut repo = ".".to_string();
+    let mut options = EncodeOptions::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = iter.next(),
+            "--tmpdir" => options.tmpdir = Some(iter.next().unwrap_or_else(|| panic!("No temporary directory")).into()),
+            "-C" => repo = iter.next().unwrap_or_else(|| panic!("No repository path")),
+            _ => rev = Some(arg),
+        }
+    }
+    let rev = rev.unwrap_or_else(|| panic!("No revision"));
+    let output = output.unwrap_or_else(|| panic!("No output file name"));
+    match git_archive(Path::new(&repo), &rev, &output, &options) {
+        Ok(report) => println!("{} entries written to {}", report.entries.len(), output),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ add output [--prefix p] file dir @listfile ... で、ファイルやディレクトリをまとめて1つのzipにする。
+ --prefix はそれより後の引数に対して、zipの中での名前の前に付ける文字列を指定する。
+ --reproducible を付けると、何度作成しても同じzipになる。
+ --jobs n で、n個のスレッドで並列に圧縮する。出力は1つのスレッドの場合と同じになる。
+ --cache dir で、圧縮したデータをdirに保存しておき、内容が変わっていないファイルの圧縮を省く。
+ --nest-dirs を付けると、一番上のディレクトリごとに別のzipにして、無圧縮で格納する。
+ --order file で、fileに書かれたエントリを先頭に並べ、データの位置を合わせる。
+ --explain-compat を付けると、zipで使った機能（ZIP64やUTF-8の名前など）を表示する。
+ --tmpdir dir で、書き込み途中のzipをdirに置く。
+ --name-encoding shift_jis で、名前をUTF-8の代わりにその文字コードで書き込む（encodingのfeatureが必要）。
+ --password pw を付けると、エントリをpwでWinZipのAES-256で暗号化する。
+ --encrypt pattern を付けた場合は、名前がpatternのどれかに当てはまるエントリだけを暗号化する（何度でも指定できる）。
+ --store pattern を付けると、名前がpatternに当てはまるエントリを圧縮せずに格納する（何度でも指定できる）。
+ --store-ext png,jpg で、圧縮せずに格納する拡張子を既定のもの（COMPRESSED_EXTENSIONS）の代わりにする（空にすると全て圧縮する）。
+ -z comment で、zip全体のコメントを書き込む。
+ */
+fn run_add() {
+    let mut args: Vec<String> = env::args().skip(2).collect();
+    let password = take_password(&mut args);
+    let mut output = None;
+    let mut prefix = String::new();
+    let mut reproducible = false;
+    let mut jobs = 1;
+    let mut cache = None;
+    let mut nest_dirs = false;
+    let mut order = None;
+    let mut explain_compat = false;
+    let mut tmpdir = None;
+    let mut name_encoding = None;
+    let mut encrypt = Vec::new();
+    let mut store = Vec::new();
+    let mut store_ext = None;
+    let mut comment = None;
+    let mut inputs = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--prefix" => prefix = iter.next().unwrap_or_else(|| panic!("No prefix")),
+            "--reproducible" => reproducible = true,
+            "--nest-dirs" => nest_dirs = true,
+            "--explain-compat" => explain_compat = true,
+            "--order" => order = Some(iter.next().unwrap_or_else(|| panic!("No order file"))),
+            "--cache" => cache = Some(CompressionCache::new(iter.next().unwrap_or_else(|| panic!("No cache directory")))),
+            "--tmpdir" => tmpdir = Some(iter.next().unwrap_or_else(|| panic!("No temporary directory"))),
+            "--encrypt" => encrypt.push(iter.next().unwrap_or_else(|| panic!("No pattern to encrypt"))),
+            "--store" => store.push(iter.next().unwrap_or_else(|| panic!("No pattern to store"))),
+            "--store-ext" => store_ext = Some(split_extensions(&iter.next().unwrap_or_default())),
+            "-z" => comment = Some(iter.next().unwrap_or_else(|| panic!("No comment"))),
+            "--name-encoding" => {
+                let label = iter.next().unwrap_or_default();
+                name_encoding = Some(name_encoder(&label).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--jobs" => {
+                let n = iter.next().unwrap_or_default();
+                jobs = n.parse().unwrap_or_else(|_| panic!("invalid number of jobs: {}", n));
+            }
+            _ if output.is_none() => output = Some(arg),
+            _ => inputs.push(Input::new(&arg).with_prefix(&prefix)),
+        }
+    }
+    let output = output.unwrap_or_else(|| panic!("No output file name"));
+    let mut builder = build().reproducible(reproducible).jobs(jobs).nest_dirs(nest_dirs);
+    if let Some(cache) = cache {
+        builder = builder.cache(cache);
+    }
+    if let Some(tmpdir) = tmpdir {
+        builder = builder.tmpdir(tmpdir);
+    }
+    if let Some(encoder) = name_encoding {
+        builder = builder.name_encoder(encoder);
+    }
+    for pattern in store {
+        builder = builder.store(pattern);
+    }
+    if let Some(extensions) = store_ext {
+        builder = builder.stored_extensions(extensions);
+    }
+    if let Some(comment) = comment {
+        builder = builder.comment(comment);
+    }
+    match password {
+        Some(password) if encrypt.is_empty() => builder = builder.encrypt("**", password),
+        Some(password) => {
+            for pattern in encrypt {
+                builder = builder.encrypt(pattern, password.clone());
+            }
+        }
+        None if !encrypt.is_empty() => panic!("No password for --encrypt"),
+        None => {}
+    }
+    if let Some(order) = order {
+        builder = match builder.order_file(&order) {
+            Ok(builder) => builder,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        };
+    }
+    match builder.add_inputs(&inputs).and_then(|builder| builder.write_file(&output)) {
+        Ok(report) => {
+            let cached = report.entries.iter().filter(|entry| entry.cached).count();
+            println!("{} entries written to {} ({} from cache)", report.entries.len(), output, cached);
+            if explain_compat {
+                print_compat(&report);
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ --explain-compat が指定された場合に、zipで使った機能と展開するソフトで気を付けることを表示する
+ */
+fn print_compat(report: &Report) {
+    println!("compat: {}", report.features);
+    for note in report.features.notes() {
+        println!("    {}", note);
+    }
+}
+
+/*
+ 2G や 512K のようなサイズをバイト数にする
+ */
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid size: {}", s))?;
+    let unit: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size: {}", s)),
+    };
+    number.checked_mul(unit).ok_or(format!("invalid size: {}", s))
+}
+
+/*
+ png,jpg,.mp4 のようにカンマで区切った拡張子を分ける（先頭の.は取り除く）
+ */
+fn split_extensions(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|extension| extension.trim().trim_start_matches('.'))
+        .filter(|extension| !extension.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/*
+ 60s や 5m のような時間をDurationにする
+ */
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration: {}", s))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        _ => return Err(format!("invalid duration: {}", s)),
+    };
+    Ok(Duration::from_secs(seconds))
+}
 
 /*
  コマンドライン引数で入力を受け付けている。
+ --profile を付けると各段階にかかった時間を表示する。
+ --engine builtin|miniz でdeflate圧縮に使うものを選ぶ。
+ --on-change warn|retry|error で、読み込み中にファイルが書き換えられた場合にどうするかを選ぶ。
+ --tail を付けると、ファイルを開いた時点のサイズまでだけを格納する。
+ --max-entry-size 2G や --entry-timeout 60s で、1つのエントリのサイズや時間を制限する。
+ --sample 64K で、先頭の64Kを圧縮して小さくならないファイルは、残りを圧縮せずに格納する。
+ --store-ext png,jpg で、圧縮せずに格納する拡張子を選ぶ（既定はpngやzipなどの既に圧縮された形式、空にすると全て圧縮する）。
+ --stream を付けると、圧縮したデータをメモリに溜めずに書き込み、crc32とサイズはデータディスクリプタに書く。
+ -z comment で、zip全体のコメントを書き込む。
+ --tmpdir dir で、書き込み途中のzipをdirに置く。
+ --name-encoding shift_jis で、名前をUTF-8の代わりにその文字コードで書き込む。
  */
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    if env::args().nth(1).as_deref() == Some("--version") {
+        if env::args().nth(2).as_deref() == Some("--verbose") {
+            print!("{}", version_info());
+        } else {
+            println!("zipper {}", env!("CARGO_PKG_VERSION"));
+        }
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("serve") {
+        run_serve();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("serve-http") {
+        run_serve_http();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("mount") {
+        run_mount();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("add") {
+        run_add();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("decode") {
+        run_decode();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("extract") {
+        run_extract();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("list") {
+        run_list();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("stats") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        let path = args.iter().find(|arg| !arg.starts_with("--")).unwrap_or_else(|| panic!("No zip file name"));
+        run_stats(path, args.iter().any(|arg| arg == "--json"));
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("test") {
+        let mut args: Vec<String> = env::args().skip(2).collect();
+        let password = take_password(&mut args);
+        let path = args.first().unwrap_or_else(|| panic!("No zip file name"));
+        run_test(path, password.as_deref());
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("scan") {
+        run_scan();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("cmp") {
+        run_cmp();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("dedup-report") {
+        run_dedup_report();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("verify-fs") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if args.len() < 2 {
+            panic!("No zip file name or directory");
+        }
+        run_verify_fs(&args[0], &args[1]);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("repair") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if args.len() < 2 {
+            panic!("No input or output file name");
+        }
+        run_repair(&args[0], &args[1]);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("normalize") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if args.len() < 2 {
+            panic!("No input or output file name");
+        }
+        run_normalize(&args[0], &args[1]);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("delta") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if args.len() < 3 {
+            panic!("No previous zip, directory or output file name");
+        }
+        run_delta(&args[0], &args[1], &args[2]);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("apply") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if args.len() < 2 {
+            panic!("No delta zip or directory name");
+        }
+        run_apply(&args[0], &args[1]);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("recompress") {
+        run_recompress();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("vacuum") {
+        let path = env::args().nth(2).unwrap_or_else(|| panic!("No zip file name"));
+        run_vacuum(&path);
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("cat") {
+        let mut args: Vec<String> = env::args().skip(2).collect();
+        let password = take_password(&mut args);
+        if args.len() < 2 {
+            panic!("No zip file or entry name");
+        }
+        run_cat(&args[0], &args[1], password.as_deref());
+        return;
+    }
+    if matches!(env::args().nth(1).as_deref(), Some("-p") | Some("--pipe")) {
+        let mut args: Vec<String> = env::args().skip(2).collect();
+        let password = take_password(&mut args);
+        if args.len() < 2 {
+            panic!("No entry or zip file name");
+        }
+        run_cat(&args[1], &args[0], password.as_deref());
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("git-archive") {
+        run_git_archive();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("gen-vectors") {
+        let dir = env::args().nth(2).unwrap_or_else(|| panic!("No directory name"));
+        run_gen_vectors(&dir);
+        return;
+    }
+
+    let mut options = EncodeOptions::new();
+    let mut explain_compat = false;
+    let mut args: Vec<String> = Vec::new();
+    let mut argv = env::args();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--profile" => options.profile = true,
+            "--tail" => options.tail = true,
+            "--stream" => options.streaming = true,
+            "-z" => options.comment = Some(argv.next().unwrap_or_else(|| panic!("No comment"))),
+            "--explain-compat" => explain_compat = true,
+            "--engine" => {
+                let name = argv.next().unwrap_or_default();
+                options.engine = name.parse().unwrap_or_else(|err| panic!("{}", err));
+            }
+            "--level" => {
+                let level = argv.next().unwrap_or_default();
+                options.level = level.parse().unwrap_or_else(|_| panic!("invalid level: {}", level));
+            }
+            "--block-max-symbols" => {
+                let count = argv.next().unwrap_or_default();
+                options.block_max_symbols = Some(count.parse().unwrap_or_else(|_| panic!("invalid symbol count: {}", count)));
+            }
+            "--block-max-bytes" => {
+                let size = argv.next().unwrap_or_default();
+                options.block_max_bytes = Some(parse_size(&size).unwrap_or_else(|err| panic!("{}", err)) as usize);
+            }
+            "--store-ext" => options.stored_extensions = split_extensions(&argv.next().unwrap_or_default()),
+            "--sample" => {
+                let size = argv.next().unwrap_or_default();
+                options.sample_len = Some(parse_size(&size).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--max-entry-size" => {
+                let size = argv.next().unwrap_or_default();
+                options.max_entry_size = Some(parse_size(&size).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--entry-timeout" => {
+                let time = argv.next().unwrap_or_default();
+                options.entry_timeout = Some(parse_duration(&time).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--on-change" => {
+                let name = argv.next().unwrap_or_default();
+                options.on_change = name.parse().unwrap_or_else(|err| panic!("{}", err));
+            }
+            "--tmpdir" => options.tmpdir = Some(argv.next().unwrap_or_else(|| panic!("No temporary directory")).into()),
+            "--name-encoding" => {
+                let label = argv.next().unwrap_or_default();
+                options.name_encoder = name_encoder(&label).unwrap_or_else(|err| panic!("{}", err));
+            }
+            _ => args.push(arg),
+        }
+    }
 
     if args.len() < 3 {
         let usage = r#"
-        compress input -> output
+        compress [--profile] [--engine builtin|miniz] [--level 0-10] [--on-change warn|retry|error] [--tail]
+                 [--max-entry-size 2G] [--entry-timeout 60s] [--sample 64K] [--store-ext png,jpg]
+                 [--block-max-symbols n] [--block-max-bytes 64K] [--stream] [--explain-compat] [--tmpdir dir]
+                 [--name-encoding shift_jis] [-z comment] input... -> output
+        add [--reproducible] [--jobs n] [--cache dir] [--nest-dirs] [--order file] [--explain-compat] [--tmpdir dir]
+            [--name-encoding shift_jis] [--password pw [--encrypt pattern]...] [--store pattern]... [--store-ext png,jpg]
+            [-z comment] output [--prefix p] file dir @listfile ...
+        decode [--lenient] [--allow-unsafe-paths] [--special-bits] [--password pw] [--name-encoding cp932] input.zip output_dir
+        extract [--password pw] [--name-encoding cp932] [--jobs n] [--special-bits] input.zip|.tar|.tar.gz|.gz output_dir [pattern...]
+        list [--sort name|size|ratio|mtime] [--reverse] [--human] [--name-encoding cp932] input.zip
+        stats [--json] input.zip
+        test [--password pw] input.zip
+        scan [--password pw] [--buffer-size 64K] [--max-entry-size 1G] [--max-total-size 4G] input.zip
+        dedup-report [--password pw] input.zip
+        cmp [--contents] [--password pw] a.zip b.zip [c.zip...]
+        verify-fs input.zip dir
+        repair input.zip output.zip
+        normalize input.zip output.zip
+        recompress [--store] [--level 0-10] [pattern...] < input.zip > output.zip
+        delta previous.zip dir output.zip
+        apply delta.zip dir
+        vacuum archive.zip
+        cat [--password pw] input.zip name
+        mount [--password pw] [--name-encoding cp932] archive.zip mountpoint
+        -p|--pipe name [--password pw] input.zip
+        gen-vectors dir
+        --version [--verbose]
+        git-archive rev -o output [-C repo] [--tmpdir dir]
+        serve --socket path
+        serve-http [--addr 127.0.0.1:8080] [--root dir]
     "#;
 
         println!("{}", usage);
         panic!("No file names");
     }
-    let input_file = &args[1];
-    let output_file = &args[2];
-
-    encode(&input_file, &output_file)
-        .unwrap_or_else(|err| eprintln!("IO Error => {}", err));
+    /*
+     入力が複数ある場合は、最後の引数を出力にしてそれぞれを1つのエントリにする
+     */
+    let (output_file, input_files) = args[1..].split_last().unwrap_or_else(|| panic!("No file names"));
+    let result = match input_files {
+        [input_file] => encode_with(input_file, output_file, &options),
+        _ => encode_files_with(&input_files.iter().map(String::as_str).collect::<Vec<_>>(), output_file, &options),
+    };
+    match result {
+        Ok(report) => {
+            if options.profile {
+                print!("{}", report);
+            }
+            if explain_compat {
+                print_compat(&report);
+            }
+            for entry in report.entries.iter().filter(|entry| entry.changed) {
+                eprintln!("warning: {} was modified while it was being archived", entry.name);
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
 }
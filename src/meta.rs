@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::ZipError;
+
+/*
+ アーカイブの情報を格納するエントリの名前
+ このエントリは常にアーカイブの最初に置く。
+ */
+pub const META_NAME: &str = ".zipper-meta.json";
+
+/*
+ アーカイブがどのように作られたかを記録するためのもの
+ creator:  作成したクレートとそのバージョン
+ options:  作成したときのオプション（圧縮レベルなど）
+ user:     アプリケーションが自由に付ける情報
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub creator: String,
+    pub options: BTreeMap<String, String>,
+    pub user: BTreeMap<String, String>,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            creator: format!("zipper {}", env!("CARGO_PKG_VERSION")),
+            options: BTreeMap::new(),
+            user: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Metadata::default()
+    }
+
+    /*
+     アプリケーションの情報を追加する
+     */
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.user.insert(key.into(), value.into());
+    }
+
+    /*
+     JSONにする。キーの順番は常に同じになる。
+     */
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\n  \"creator\": ");
+        push_string(&mut json, &self.creator);
+        json.push_str(",\n  \"options\": ");
+        push_object(&mut json, &self.options);
+        json.push_str(",\n  \"user\": ");
+        push_object(&mut json, &self.user);
+        json.push_str("\n}\n");
+        json
+    }
+
+    /*
+     to_jsonで作ったJSONを読み込む
+     */
+    pub fn from_json(json: &str) -> Result<Self, ZipError> {
+        let mut parser = Parser::new(json);
+        let mut metadata = Metadata { creator: String::new(), options: BTreeMap::new(), user: BTreeMap::new() };
+        parser.expect('{')?;
+        loop {
+            if parser.peek() == Some('}') {
+                parser.pos += 1;
+                break;
+            }
+            let key = parser.string()?;
+            parser.expect(':')?;
+            match key.as_str() {
+                "creator" => metadata.creator = parser.string()?,
+                "options" => metadata.options = parser.object()?,
+                "user" => metadata.user = parser.object()?,
+                _ => return Err(ZipError::InvalidMetadata(format!("unknown key: {}", key))),
+            }
+            if parser.peek() == Some(',') {
+                parser.pos += 1;
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+pub(crate) fn push_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn push_object(json: &mut String, map: &BTreeMap<String, String>) {
+    if map.is_empty() {
+        json.push_str("{}");
+        return;
+    }
+    json.push('{');
+    for (i, (key, value)) in map.iter().enumerate() {
+        json.push_str(if i == 0 { "\n    " } else { ",\n    " });
+        push_string(json, key);
+        json.push_str(": ");
+        push_string(json, value);
+    }
+    json.push_str("\n  }");
+}
+
+/*
+ 文字列と、文字列だけを値に持つオブジェクトや配列だけを読み込めるJSONのパーサー
+ */
+pub(crate) struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(json: &str) -> Self {
+        Parser { chars: json.chars().collect(), pos: 0 }
+    }
+
+    pub(crate) fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    pub(crate) fn expect(&mut self, c: char) -> Result<(), ZipError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(ZipError::InvalidMetadata(format!("expected '{}' at {}", c, self.pos)))
+        }
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, ZipError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = *self.chars.get(self.pos).ok_or_else(|| ZipError::InvalidMetadata("unterminated string".to_string()))?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = *self.chars.get(self.pos).ok_or_else(|| ZipError::InvalidMetadata("unterminated string".to_string()))?;
+                    self.pos += 1;
+                    match escaped {
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'u' => {
+                            let hex: String = self.chars.iter().skip(self.pos).take(4).collect();
+                            self.pos += 4;
+                            let code = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                                .ok_or_else(|| ZipError::InvalidMetadata(format!("invalid escape: \\u{}", hex)))?;
+                            s.push(code);
+                        }
+                        c => s.push(c),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    pub(crate) fn object(&mut self) -> Result<BTreeMap<String, String>, ZipError> {
+        let mut map = BTreeMap::new();
+        self.expect('{')?;
+        loop {
+            if self.peek() == Some('}') {
+                self.pos += 1;
+                return Ok(map);
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = self.string()?;
+            map.insert(key, value);
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            }
+        }
+    }
+
+    pub(crate) fn array(&mut self) -> Result<Vec<String>, ZipError> {
+        let mut items = Vec::new();
+        self.expect('[')?;
+        loop {
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(items);
+            }
+            items.push(self.string()?);
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::{EncodeOptions, ZipError};
+
+pub(crate) const UTF8_FLAG: u16 = 0x0800;  // 汎用フラグのbit11: 名前がUTF-8で書かれている
+
+/*
+ CP437の0x80から0xffまでの文字（0x7f以下はアスキーコードと同じとして読む）
+ */
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/*
+ エントリの名前をzipに書き込むバイト列にするためのもの
+ 古いツールで開く相手のために、UTF-8以外の文字コード（Shift_JISなど）で書き込めるように差し替えられるようにしている。
+ encode:  nameを書き込むバイト列にする。この文字コードで表せない文字がある場合はNoneを返す
+ utf8:    UTF-8で書き込むかどうか（trueの場合は、アスキーコード以外の文字を含む名前に汎用フラグのbit11を立てる）
+ label:   エラーに表示する文字コードの名前
+ */
+pub trait NameEncoder: Debug + Send + Sync {
+    fn encode(&self, name: &str) -> Option<Vec<u8>>;
+
+    fn utf8(&self) -> bool {
+        false
+    }
+
+    fn label(&self) -> &str;
+}
+
+/*
+ zipに書かれた名前のバイト列を文字列にするためのもの
+ UTF-8のフラグ（汎用フラグのbit11）が立っていないエントリの名前にだけ使い、立っている場合は常にUTF-8として読む。
+ decode:  名前のバイト列を文字列にする。この文字コードで読めないバイトは置き換える
+ */
+pub trait NameDecoder: Debug + Send + Sync {
+    fn decode(&self, bytes: &[u8]) -> String;
+}
+
+/*
+ 名前をそのままUTF-8で書き込む（書き込むときに指定しない場合はこれを使う）
+ 読む場合は、フラグを付け忘れたツールで作られたUTF-8の名前をそのまま読むのに使う。
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8Names;
+
+impl NameEncoder for Utf8Names {
+    fn encode(&self, name: &str) -> Option<Vec<u8>> {
+        Some(name.as_bytes().to_vec())
+    }
+
+    fn utf8(&self) -> bool {
+        true
+    }
+
+    fn label(&self) -> &str {
+        "UTF-8"
+    }
+}
+
+impl NameDecoder for Utf8Names {
+    fn decode(&self, bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/*
+ CP437（IBM PCの文字コード）で読み書きする。APPNOTEでは、UTF-8のフラグが立っていない名前はこの文字コードで書かれているとしているため、
+ 読むときに指定しない場合はこれを使う。
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cp437Names;
+
+impl NameEncoder for Cp437Names {
+    fn encode(&self, name: &str) -> Option<Vec<u8>> {
+        name.chars()
+            .map(|c| match c {
+                c if c.is_ascii() => Some(c as u8),
+                c => CP437_HIGH.iter().position(|&high| high == c).map(|index| 0x80 + index as u8),
+            })
+            .collect()
+    }
+
+    fn label(&self) -> &str {
+        "IBM437"
+    }
+}
+
+impl NameDecoder for Cp437Names {
+    fn decode(&self, bytes: &[u8]) -> String {
+        bytes.iter()
+            .map(|&byte| match CP437_HIGH.get(usize::from(byte).wrapping_sub(0x80)) {
+                Some(&c) if byte >= 0x80 => c,
+                _ => char::from(byte),
+            })
+            .collect()
+    }
+}
+
+/*
+ encoding_rsの文字コードで書き込む。UTF-8のフラグは立てない。
+ 日本語版の古いWindowsのツールなど、UTF-8の名前を正しく扱えない相手に渡すzipを作る場合に使う。
+ */
+#[cfg(feature = "encoding")]
+#[derive(Debug, Clone, Copy)]
+pub struct LegacyNames(&'static encoding_rs::Encoding);
+
+#[cfg(feature = "encoding")]
+impl LegacyNames {
+    /*
+     Shift_JIS（日本語版のWindowsのCP932として読まれる）
+     */
+    pub fn shift_jis() -> Self {
+        LegacyNames(encoding_rs::SHIFT_JIS)
+    }
+
+    /*
+     shift_jis、euc-jpなどのWHATWGの名前から作る。Windowsでの呼び方のcp932もShift_JISとして受け付ける。
+     書き込みに使えない文字コード（UTF-16など、encoding_rsが別の文字コードで書き込むもの）の場合はNoneを返す。
+     */
+    pub fn for_label(label: &str) -> Option<Self> {
+        if label.eq_ignore_ascii_case("cp932") {
+            return Some(LegacyNames::shift_jis());
+        }
+        let encoding = encoding_rs::Encoding::for_label(label.as_bytes())?;
+        if encoding.output_encoding() != encoding {
+            return None;
+        }
+        Some(LegacyNames(encoding))
+    }
+}
+
+#[cfg(feature = "encoding")]
+impl NameEncoder for LegacyNames {
+    fn encode(&self, name: &str) -> Option<Vec<u8>> {
+        let (bytes, _, had_errors) = self.0.encode(name);
+        if had_errors {
+            return None;
+        }
+        Some(bytes.into_owned())
+    }
+
+    fn utf8(&self) -> bool {
+        self.0 == encoding_rs::UTF_8
+    }
+
+    fn label(&self) -> &str {
+        self.0.name()
+    }
+}
+
+#[cfg(feature = "encoding")]
+impl NameDecoder for LegacyNames {
+    fn decode(&self, bytes: &[u8]) -> String {
+        self.0.decode_without_bom_handling(bytes).0.into_owned()
+    }
+}
+
+/*
+ CLIの--name-encodingに渡された名前から、書き込みに使うものを作る。
+ utf-8とcp437以外はencodingのfeatureが有効な場合だけ使える。
+ */
+pub fn name_encoder(label: &str) -> Result<Arc<dyn NameEncoder>, String> {
+    if is_utf8(label) {
+        return Ok(Arc::new(Utf8Names));
+    }
+    if is_cp437(label) {
+        return Ok(Arc::new(Cp437Names));
+    }
+    legacy_encoder(label)
+}
+
+/*
+ CLIの--name-encodingに渡された名前から、読み込みに使うものを作る（name_encoderと同じ名前を受け付ける）
+ */
+pub fn name_decoder(label: &str) -> Result<Arc<dyn NameDecoder>, String> {
+    if is_utf8(label) {
+        return Ok(Arc::new(Utf8Names));
+    }
+    if is_cp437(label) {
+        return Ok(Arc::new(Cp437Names));
+    }
+    legacy_decoder(label)
+}
+
+fn is_utf8(label: &str) -> bool {
+    label.eq_ignore_ascii_case("utf-8") || label.eq_ignore_ascii_case("utf8")
+}
+
+fn is_cp437(label: &str) -> bool {
+    ["cp437", "ibm437", "437"].iter().any(|name| label.eq_ignore_ascii_case(name))
+}
+
+#[cfg(feature = "encoding")]
+fn legacy_encoder(label: &str) -> Result<Arc<dyn NameEncoder>, String> {
+    match LegacyNames::for_label(label) {
+        Some(encoder) => Ok(Arc::new(encoder)),
+        None => Err(format!("unknown name encoding: {}", label)),
+    }
+}
+
+#[cfg(not(feature = "encoding"))]
+fn legacy_encoder(label: &str) -> Result<Arc<dyn NameEncoder>, String> {
+    Err(format!("name encoding {} is not enabled (build with --features encoding)", label))
+}
+
+#[cfg(feature = "encoding")]
+fn legacy_decoder(label: &str) -> Result<Arc<dyn NameDecoder>, String> {
+    match LegacyNames::for_label(label) {
+        Some(decoder) => Ok(Arc::new(decoder)),
+        None => Err(format!("unknown name encoding: {}", label)),
+    }
+}
+
+#[cfg(not(feature = "encoding"))]
+fn legacy_decoder(label: &str) -> Result<Arc<dyn NameDecoder>, String> {
+    Err(format!("name encoding {} is not enabled (build with --features encoding)", label))
+}
+
+/*
+ options.name_encoderでnameを書き込むバイト列にする。表せない文字がある場合はUnrepresentableNameにする。
+ */
+pub(crate) fn encode_name(name: &str, options: &EncodeOptions) -> Result<Vec<u8>, ZipError> {
+    let encoder = &options.name_encoder;
+    encoder.encode(name).ok_or_else(|| ZipError::UnrepresentableName { name: name.to_string(), encoding: encoder.label().to_string() })
+}
+
+/*
+ エントリの名前のバイト列を文字列にする。UTF-8のフラグが立っている場合はUTF-8として読み（読めない部分は置き換える）、
+ 立っていない場合はdecoderで読む。
+ */
+pub(crate) fn decode_name(bytes: &[u8], flags: u16, decoder: &dyn NameDecoder) -> String {
+    if flags & UTF8_FLAG != 0 {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    decoder.decode(bytes)
+}
@@ -0,0 +1,92 @@
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::aes::{AesInfo, AES_EXTRA_ID};
+use crate::extra::{parse_extra_fields, ExtraField};
+use crate::workspace::write_output;
+use crate::{ArchiveWriter, Header, Report, ZipArchive, ZipEntry, ZipError};
+
+/*
+ 書き込むたびに変わりうるため、正規化したzipには残さない拡張フィールドの番号
+ 0x0001: Zip64の拡張情報（必要な場合は書き込むときに作り直す）
+ 0x000a: NTFSの日時
+ 0x000d: PKWAREのUnixの日時と所有者
+ 0x5455: 拡張タイムスタンプ
+ 0x5855: Info-ZIPのUnixの日時と所有者（古い形式）
+ 0x6375: Info-ZIPのUnicodeのコメント（コメントはUTF-8で書き直す）
+ 0x7075: Info-ZIPのUnicodeのパス（名前はUTF-8で書き直す）
+ 0x7855: Info-ZIPのUnixの所有者
+ 0x7875: Info-ZIPのUnixの所有者（新しい形式）
+ 0xd935: データの位置を合わせるための詰め物
+ */
+const VOLATILE_IDS: [u16; 10] = [0x0001, 0x000a, 0x000d, 0x5455, 0x5855, 0x6375, 0x7075, 0x7855, 0x7875, 0xd935];
+
+/*
+ input_zipを、同じ内容なら常に同じバイト列になる形に書き直してoutput_zipに書き込む（キャッシュのキーや署名に使う）。
+ 圧縮したデータは展開せずにopen_rawでそのまま写し、ヘッダーだけを次のように揃える。
+ ・エントリは名前の順に並べる（同じ名前のものは元の順）
+ ・作成したバージョンと展開に必要なバージョンは、このクレートが書き込む値にする
+ ・名前とコメントはUTF-8で書き、crc32とサイズはローカルヘッダーに書く（データディスクリプタは使わない）
+ ・外部属性は、読み込んだ属性からUnixのモードとDOSの属性を作り直す
+ ・日時や所有者などの書き込むたびに変わる拡張フィールド（VOLATILE_IDS）を取り除き、他の拡張フィールドは元の順に残す
+ 日時はセントラルヘッダーのもの（2秒単位）だけを残す。AE-2で暗号化されたエントリは暗号化したまま写し、
+ crc32を書くAE-1や、従来のzip暗号で暗号化されたエントリはUnsupportedにする。
+ */
+pub fn normalize(input_zip: &str, output_zip: &str) -> Result<Report, ZipError> {
+    let mut archive = ZipArchive::open(input_zip)?;
+    let mut order: Vec<(&ZipEntry, usize)> = archive.iter().zip(0..).collect();
+    order.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    let headers = order.into_iter()
+        .map(|(entry, index)| Ok((canonical_header(entry)?, index)))
+        .collect::<Result<Vec<_>, ZipError>>()?;
+    let comment = archive.comment().as_bytes().to_vec();
+
+    write_output(Path::new(output_zip), None, &[], |file| {
+        let mut writer = ArchiveWriter::new(BufWriter::new(file)).with_comment(comment);
+        for (header, index) in headers {
+            let mut raw = archive.open_raw(index)?;
+            writer.add_raw(header, &mut raw)?;
+        }
+        Ok(writer.finish()?)
+    })
+}
+
+/*
+ entryを正規化したヘッダーを作る（サイズとcrc32は元のエントリのまま）
+ */
+fn canonical_header(entry: &ZipEntry) -> Result<Header, ZipError> {
+    let mut fields = parse_extra_fields(&entry.extra)?;
+    fields.retain(|field| !VOLATILE_IDS.contains(&field.id));
+    let aes = match AesInfo::from_extra(&entry.extra, &entry.name)? {
+        Some(info) if entry.is_encrypted() && info.version == 2 => Some(info),
+        None if !entry.is_encrypted() => None,
+        _ => return Err(ZipError::Unsupported(format!("normalizing {}, which is encrypted with ZipCrypto or AE-1", entry.name))),
+    };
+    let (hms, ymd) = entry.modified.to_dos();
+    let header = Header::new(entry.size, entry.compressed_size, entry.name.as_str(), entry.crc32, hms, ymd)
+        .with_comment(entry.comment.as_bytes().to_vec())
+        .with_external_attributes(entry.permissions.external_attributes());
+    Ok(match aes {
+        Some(info) => {
+            let (aes_field, others): (Vec<ExtraField>, Vec<ExtraField>) = fields.into_iter().partition(|field| field.id == AES_EXTRA_ID);
+            header.with_extra(encode_fields(&others))
+                .with_method(info.method)
+                .with_encryption(entry.compressed_size, &encode_fields(&aes_field))
+        }
+        None => header.with_extra(encode_fields(&fields)).with_method(entry.method),
+    })
+}
+
+/*
+ 読み込んだ拡張フィールドを、ヘッダーに書き込む形式に戻す
+ （読み込んだ長さは65535バイトに収まっているため、長さの確認はArchiveWriterに任せる）
+ */
+fn encode_fields(fields: &[ExtraField]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for field in fields {
+        buffer.extend_from_slice(&field.id.to_le_bytes());
+        buffer.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(&field.data);
+    }
+    buffer
+}
@@ -0,0 +1,139 @@
+use crate::{distance_extra, length_extra, Token, MAX_MATCH_LEN, MIN_MATCH_LEN};
+
+/*
+ 一致を探す範囲（deflateの距離の上限）と、1つの位置で調べる候補の最大の数
+ */
+const WINDOW_SIZE: usize = 32768;
+const MAX_CHAIN: usize = 4096;
+
+/*
+ 先頭の3バイトから求めるハッシュの大きさ（ビット数）
+ */
+const HASH_BITS: u32 = 15;
+
+/*
+ dataを、固定ハフマンで符号化したときのビット数が最も少なくなるトークンの並びにしてtokensに入れる。
+ 1回目に全ての位置で長さごとの最も近い一致を探し、2回目に先頭からの最短経路として
+ リテラルと一致のどちらを選ぶかを決める（それぞれの選び方の重みは今の符号の長さ）。
+ 固定ハフマンでは符号の長さが変わらないため、重みを更新しながら繰り返す必要はなく、1回の最短経路で決まる。
+ */
+pub(crate) fn optimal_tokens(data: &[u8], tokens: &mut Vec<Token>) {
+    let len = data.len();
+    let mut cost = vec![u32::MAX; len + 1];
+    let mut choice = vec![(0u16, 0u32); len + 1];
+    let mut distances = vec![0u32; MAX_MATCH_LEN + 1];
+    let mut finder = MatchFinder::new(len);
+    cost[0] = 0;
+
+    for pos in 0..len {
+        let literal = cost[pos] + literal_cost(data[pos]);
+        if literal < cost[pos + 1] {
+            cost[pos + 1] = literal;
+            choice[pos + 1] = (1, 0);
+        }
+        let longest = finder.find(data, pos, &mut distances);
+        for length in MIN_MATCH_LEN..=longest {
+            let distance = distances[length];
+            let total = cost[pos] + match_cost(length as u16, distance);
+            if total < cost[pos + length] {
+                cost[pos + length] = total;
+                choice[pos + length] = (length as u16, distance);
+            }
+        }
+    }
+
+    /*
+     後ろから選んだものをたどって、先頭からの順に並べ直す
+     */
+    let start = tokens.len();
+    let mut pos = len;
+    while pos > 0 {
+        let (length, distance) = choice[pos];
+        if distance == 0 {
+            pos -= 1;
+            tokens.push(Token::Literal(data[pos]));
+        } else {
+            pos -= length as usize;
+            tokens.push(Token::Match(length, distance));
+        }
+    }
+    tokens[start..].reverse();
+}
+
+/*
+ 固定ハフマンでリテラルを符号化したときのビット数
+ */
+fn literal_cost(byte: u8) -> u32 {
+    if byte < 144 { 8 } else { 9 }
+}
+
+/*
+ 固定ハフマンで一致を符号化したときのビット数（長さ符号と距離符号、それぞれの拡張ビット）
+ */
+fn match_cost(length: u16, distance: u32) -> u32 {
+    let (code, length_bits, _) = length_extra(length);
+    let (_, distance_bits, _) = distance_extra(distance);
+    let code_bits = if code < 280 { 7 } else { 8 };
+    code_bits + length_bits as u32 + 5 + distance_bits as u32
+}
+
+/*
+ 先頭の3バイトが同じ位置をつないだ鎖で一致を探すためのもの
+ head:  ハッシュごとに最も新しい位置（+1、0は無し）
+ prev:  位置ごとに、同じハッシュの1つ前の位置（+1、0は無し）
+ */
+struct MatchFinder {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl MatchFinder {
+    fn new(len: usize) -> Self {
+        MatchFinder {
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; len],
+        }
+    }
+
+    /*
+     posから始まる一致を近い順に調べて、長さごとの最も近い距離をdistancesに入れ、最も長い一致の長さを返す。
+     調べた後にposを鎖に加える。一致がない場合は0を返す。
+     */
+    fn find(&mut self, data: &[u8], pos: usize, distances: &mut [u32]) -> usize {
+        if pos + MIN_MATCH_LEN > data.len() {
+            return 0;
+        }
+        let hash = hash(&data[pos..pos + MIN_MATCH_LEN]);
+        let max_len = MAX_MATCH_LEN.min(data.len() - pos);
+        let mut longest = 0;
+        let mut candidate = self.head[hash];
+        let mut chain = 0;
+        while candidate != 0 && chain < MAX_CHAIN && longest < max_len {
+            let start = candidate as usize - 1;
+            let distance = pos - start;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+            let length = data[start..start + max_len].iter()
+                .zip(&data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length >= MIN_MATCH_LEN && length > longest {
+                for entry in &mut distances[longest.max(MIN_MATCH_LEN - 1) + 1..=length] {
+                    *entry = distance as u32;
+                }
+                longest = length;
+            }
+            candidate = self.prev[start];
+            chain += 1;
+        }
+        self.prev[pos] = self.head[hash];
+        self.head[hash] = pos as u32 + 1;
+        longest
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
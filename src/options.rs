@@ -0,0 +1,324 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Clock, Cp437Names, ExtraField, NameDecoder, NameEncoder, Permissions, Progress, SystemClock, Utf8Names};
+
+/*
+ 圧縮レベルを指定しなかった場合の値
+ */
+pub const DEFAULT_LEVEL: u8 = 6;
+
+/*
+ 既に圧縮されている形式の拡張子（EncodeOptions.stored_extensionsの初期値）
+ 画像、音声、動画、アーカイブと、中身がzipになっているOfficeの文書などで、deflateしてもほとんど小さくならない。
+ */
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avif", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg", "lz4", "m4a",
+    "mkv", "mov", "mp3", "mp4", "odt", "ogg", "opus", "png", "pptx", "rar", "tgz", "webm", "webp", "woff2", "xlsx",
+    "xz", "zip", "zst",
+];
+
+/*
+ deflate圧縮を行う部分をどれにするかを選ぶためのもの
+ Builtin:  このクレートの固定ハフマン方式
+ Miniz:    miniz_oxideを使う（minizフィーチャーが有効な場合のみ）
+ zipのヘッダなどはどちらの場合もこのクレートで作成する。
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    #[default]
+    Builtin,
+    #[cfg(feature = "miniz")]
+    Miniz,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "builtin" => Ok(Engine::Builtin),
+            #[cfg(feature = "miniz")]
+            "miniz" => Ok(Engine::Miniz),
+            #[cfg(not(feature = "miniz"))]
+            "miniz" => Err("miniz engine is not enabled (build with --features miniz)".to_string()),
+            _ => Err(format!("unknown engine: {}", s)),
+        }
+    }
+}
+
+/*
+ 読み込んでいる間にファイルのサイズや最終更新日時が変わった場合にどうするか
+ Warn:   そのまま格納して、Reportに変わったことを記録する
+ Retry:  最初から圧縮をやり直す。何度やり直しても変わる場合にはエラーにする
+ Error:  エラーにする
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangePolicy {
+    #[default]
+    Warn,
+    Retry,
+    Error,
+}
+
+impl FromStr for ChangePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(ChangePolicy::Warn),
+            "retry" => Ok(ChangePolicy::Retry),
+            "error" => Ok(ChangePolicy::Error),
+            _ => Err(format!("unknown policy: {}", s)),
+        }
+    }
+}
+
+/*
+ encode_withに渡すオプションをまとめたもの
+ profile:   trueの場合には、読み込み、一致の探索、ハフマン符号化、crc32、書き込みの
+            それぞれにかかった時間を計測してReportに含める。
+ engine:    deflate圧縮に使うもの
+ clock:     最終更新日時が取れない場合の現在時刻や、タイムゾーンを決めるための時計
+ on_change: 読み込んでいる間にファイルが書き換えられた場合にどうするか
+ tail:      trueの場合には、ファイルを開いた時点のサイズまでだけを格納する。
+            ログファイルのように追記され続けるファイルでも、crc32やサイズが食い違わないようにする。
+ level:     圧縮レベル（0〜10）。Builtinでは0の場合に無圧縮のブロックだけにし、それ以外は固定ハフマンにする。
+            1〜9では、レベルが高いほど一致を探すウインドウを大きくする（遅くなるが小さくなる）。
+            （無圧縮のブロックのように、圧縮して小さくならなかったエントリは圧縮せずに格納する）
+            10の場合は、入力を全て読み込んでから最短経路で一致を選ぶ（遅いがよく圧縮する）。
+ max_entry_size:  1つのエントリとして読み込める最大のバイト数。超えた場合はEntryTooLargeになる。
+ entry_timeout:   1つのエントリの圧縮にかけられる最大の時間。超えた場合はEntryTimeoutになる。
+ progress:        指定された場合には、圧縮中のエントリの読み込んだバイト数などを更新していく。
+ block_max_symbols:  1つのdeflateのブロックに入れるトークンの最大の数
+ block_max_bytes:    1つのdeflateのブロックに入れる入力の最大のバイト数
+                     どちらかが指定された場合、Builtinではその数に達したところでブロックを区切る。
+                     ブロックを小さくすると早く区切りがつくが、ブロックごとに数ビットずつ大きくなる。
+ tmpdir:          書き込み途中のzipなどの一時ファイルを置くディレクトリ。
+                  指定しない場合は環境変数TMPDIR、出力先のディレクトリの順に使う。
+ name_encoder:    エントリの名前をヘッダーに書き込むバイト列にするもの（指定しない場合はUTF-8）
+ sample_len:      指定された場合には、これより大きいエントリの先頭のこのバイト数を先に圧縮してみて、
+                  小さくならなければ残りは圧縮を試さずに格納する（画像や動画など、既に圧縮されたファイルが多い場合に速くなる）。
+                  先頭だけが圧縮しにくいエントリは、残りが圧縮できても格納することになる。
+                  指定しない場合も、全体を圧縮して小さくならなかったエントリは圧縮せずに格納する。
+ stored_extensions:  名前の拡張子がこれに含まれるエントリは、圧縮を試さずに格納する（大文字と小文字は区別しない）。
+                     初期値はCOMPRESSED_EXTENSIONSで、空にすると全てのエントリを圧縮する。
+ streaming:       trueの場合には、圧縮したデータをメモリに溜めずにそのまま書き込み、crc32とサイズはデータの後ろの
+                  データディスクリプタに書く（汎用フラグのbit3）。パイプやソケットなど、書き戻せない出力先に大きなエントリを
+                  書き込む場合に使う。データを書いた後では圧縮方法を変えられないため、圧縮して小さくならなかったエントリも
+                  deflateのまま格納する。max_entry_sizeが4GiBより十分小さくなければ、データディスクリプタのサイズは8バイトで書く。
+                  パスワードを指定したエントリは暗号化する前に全体が要るため、これまでと同じく溜めてから書き込む。
+ comment:         指定された場合には、zip全体のコメントとしてエンドセントラルヘッダーの後ろに書き込む
+                  （name_encoderの文字コードで65535バイトまで）。
+ */
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    pub profile: bool,
+    pub engine: Engine,
+    pub clock: Arc<dyn Clock>,
+    pub on_change: ChangePolicy,
+    pub tail: bool,
+    pub level: u8,
+    pub max_entry_size: Option<u64>,
+    pub entry_timeout: Option<Duration>,
+    pub progress: Option<Arc<Progress>>,
+    pub block_max_symbols: Option<usize>,
+    pub block_max_bytes: Option<usize>,
+    pub tmpdir: Option<PathBuf>,
+    pub name_encoder: Arc<dyn NameEncoder>,
+    pub sample_len: Option<u64>,
+    pub stored_extensions: Vec<String>,
+    pub streaming: bool,
+    pub comment: Option<String>,
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        EncodeOptions::default()
+    }
+
+    /*
+     nameの拡張子がstored_extensionsに含まれていて、圧縮せずに格納するかどうかを返す。
+     拡張子は最後の/より後ろの、最後の.より後ろとする（.gitignoreのように先頭の.だけの場合は拡張子なし）。
+     */
+    pub(crate) fn stores_extension(&self, name: &str) -> bool {
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        match file_name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                self.stored_extensions.iter().any(|stored| stored.eq_ignore_ascii_case(extension))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            profile: false,
+            engine: Engine::default(),
+            clock: Arc::new(SystemClock),
+            on_change: ChangePolicy::default(),
+            tail: false,
+            level: DEFAULT_LEVEL,
+            max_entry_size: None,
+            entry_timeout: None,
+            progress: None,
+            block_max_symbols: None,
+            block_max_bytes: None,
+            tmpdir: None,
+            name_encoder: Arc::new(Utf8Names),
+            sample_len: None,
+            stored_extensions: COMPRESSED_EXTENSIONS.iter().map(|extension| extension.to_string()).collect(),
+            streaming: false,
+            comment: None,
+        }
+    }
+}
+
+/*
+ エントリのデータをzipの中のどこに置くか
+ 決まった位置からデータを読み込むローダーのために、ローカルヘッダーの拡張フィールドに詰め物を入れて位置を合わせる。
+ Offset:  データがちょうどこの位置から始まるようにする
+ Align:   データの位置がこの数の倍数になるようにする
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    Offset(u64),
+    Align(u64),
+}
+
+/*
+ エントリのデータをどう格納するか
+ Deflate:  deflateで圧縮する（圧縮しても小さくならない場合は、圧縮せずにStoreと同じく格納する）
+ Store:    圧縮せずにそのまま格納する（method 0）。既に圧縮されたデータや、EPUBのmimetypeやAPKのように
+           無圧縮が決められているものに使う
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    Deflate,
+    Store,
+}
+
+/*
+ エントリごとに指定するオプション
+ extra_fields:  アプリケーションが独自に付ける拡張フィールド
+ placement:     指定された場合には、データの位置をそれに合わせる
+ permissions:   指定された場合には、セントラルヘッダーの外部属性に書き込む
+ password:      指定された場合には、このパスワードでWinZipのAES-256（AE-2）で暗号化する
+                （暗号化したエントリと、しないエントリを1つのzipに混ぜられる）
+ compression:   圧縮するか、圧縮せずに格納するか
+ level:         指定された場合には、EncodeOptions.levelの代わりにこのエントリの圧縮レベルにする
+ modified:      指定された場合には、ファイルの日時などの代わりにこれ（UNIX時間）をエントリの最終更新日時にする
+ comment:       指定された場合には、セントラルヘッダーにエントリのコメントとして書き込む（名前と同じ文字コードにする）
+ EntryOptions::new().level(9).comment("generated") のようにつなげても指定できる。
+ */
+#[derive(Debug, Clone, Default)]
+pub struct EntryOptions {
+    pub extra_fields: Vec<ExtraField>,
+    pub placement: Option<Placement>,
+    pub permissions: Option<Permissions>,
+    pub password: Option<String>,
+    pub compression: Compression,
+    pub level: Option<u8>,
+    pub modified: Option<i64>,
+    pub comment: Option<String>,
+}
+
+/*
+ 他のzipのクレートと同じ名前でも使えるようにしたEntryOptionsの別名
+ */
+pub type FileOptions = EntryOptions;
+
+impl EntryOptions {
+    pub fn new() -> Self {
+        EntryOptions::default()
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn modified(mut self, modified: i64) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /*
+     levelが指定されている場合は、optionsのlevelをそれに変えたものを返す。
+     compressionがStoreの場合は、別のスレッドやキャッシュで圧縮するときにも手間をかけないよう、levelを0にする。
+     */
+    pub(crate) fn encode_options<'a>(&self, options: &'a EncodeOptions) -> Cow<'a, EncodeOptions> {
+        let level = match self.compression {
+            Compression::Store => Some(0),
+            Compression::Deflate => self.level,
+        };
+        match level {
+            Some(level) if level != options.level => Cow::Owned(EncodeOptions { level, ..options.clone() }),
+            _ => Cow::Borrowed(options),
+        }
+    }
+}
+
+/*
+ decode_withやZipArchive::extract_all_withに渡すオプション
+ lenient:  trueの場合には、よくある仕様違反（バージョンの値、ヘッダー間のcrc32の食い違い、UTF-8のフラグの付け忘れなど）を
+           エラーにせず、Report.diagnosticsに記録して展開を続ける。（decode_withだけで使う）
+ allow_unsafe_paths:  trueの場合には、..や絶対パス、ドライブ文字を含む名前もそのまま展開先につなげる。
+                      展開先の外に書き込まれるおそれがあるため、信頼できるzipにだけ使う。
+ special_bits:  trueの場合には、Unixの属性の特別なビット（setuid、setgid、スティッキー）も展開したファイルに設定する。
+                falseの場合には、信頼できないzipから他の人の権限で実行されるファイルができないように、これらのビットを外す。
+ password:  ZipCrypto（PKWAREの従来の暗号）で暗号化されたエントリを復号するパスワード
+ name_decoder:  UTF-8のフラグが立っていない名前を読む文字コード（指定しない場合はAPPNOTEに従いCP437）
+                ZipArchiveではopen_withに渡したときに使う。
+ jobs:  展開に使うスレッドの数。2以上の場合は、その数のエントリを同時に展開する（0と1の場合は呼び出したスレッドだけで展開する）
+        ZipArchive::extract_all_withとextract_matching_withで使う（decode_withは先頭から順に読むため使わない）。
+ */
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    pub lenient: bool,
+    pub allow_unsafe_paths: bool,
+    pub special_bits: bool,
+    pub password: Option<String>,
+    pub name_decoder: Arc<dyn NameDecoder>,
+    pub jobs: usize,
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        DecodeOptions::default()
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            lenient: false,
+            allow_unsafe_paths: false,
+            special_bits: false,
+            password: None,
+            name_decoder: Arc::new(Cp437Names),
+            jobs: 0,
+        }
+    }
+}
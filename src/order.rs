@@ -0,0 +1,66 @@
+use crate::input::FileEntry;
+use crate::{Placement, ZipError};
+
+/*
+ エントリの並び順を指定するファイルを読み込む
+ 1行に1つずつエントリの名前を書き、書いた順にzipの先頭に並べる。
+ 名前の後に offset=4096 や align=4096 を付けると、データの位置もそれに合わせる（0xから始まる16進数も使える）。
+ 空行と#から始まる行は無視する。
+ */
+pub(crate) fn parse_order(text: &str) -> Result<Vec<(String, Option<Placement>)>, ZipError> {
+    let mut order = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default().to_string();
+        let placement = match words.next() {
+            None => None,
+            Some(word) => Some(parse_placement(word).ok_or_else(|| invalid_line(number, line))?),
+        };
+        if words.next().is_some() {
+            return Err(invalid_line(number, line));
+        }
+        order.push((name, placement));
+    }
+    Ok(order)
+}
+
+fn parse_placement(word: &str) -> Option<Placement> {
+    let (key, value) = word.split_once('=')?;
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => value.parse().ok()?,
+    };
+    match key {
+        "offset" => Some(Placement::Offset(value)),
+        "align" => Some(Placement::Align(value)),
+        _ => None,
+    }
+}
+
+fn invalid_line(number: usize, line: &str) -> ZipError {
+    ZipError::InvalidPlacement { name: line.to_string(), reason: format!("line {} of the order file is invalid", number + 1) }
+}
+
+/*
+ orderに書かれたエントリをその順番でfilesの先頭に移し、位置の指定をエントリのオプションに入れる。
+ それ以外のエントリは元の順番のまま後ろに並べる。
+ orderに書かれた名前のエントリがない場合にはエラーにする。
+ */
+pub(crate) fn apply_order(files: Vec<FileEntry>, order: &[(String, Option<Placement>)]) -> Result<Vec<FileEntry>, ZipError> {
+    let mut rest: Vec<Option<FileEntry>> = files.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(rest.len());
+    for (name, placement) in order {
+        let index = rest.iter().position(|file| file.as_ref().map(|file| &file.1) == Some(name))
+            .ok_or_else(|| ZipError::InvalidPlacement { name: name.clone(), reason: "no such entry".to_string() })?;
+        if let Some(mut file) = rest[index].take() {
+            file.2.placement = *placement;
+            ordered.push(file);
+        }
+    }
+    ordered.extend(rest.into_iter().flatten());
+    Ok(ordered)
+}
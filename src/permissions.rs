@@ -0,0 +1,227 @@
+use std::fs::{self, Metadata};
+use std::io::Error;
+use std::path::Path;
+
+/*
+ Unixのファイルの種類のビット（S_IFMT）と、その中のディレクトリ（S_IFDIR）と通常のファイル（S_IFREG）
+ */
+const TYPE_MASK: u32 = 0o170000;
+const TYPE_DIR: u32 = 0o040000;
+const TYPE_FILE: u32 = 0o100000;
+
+/*
+ Unixのパーミッションのうち、持ち主の書き込みの許可と、全員の書き込みの許可
+ */
+const OWNER_WRITE: u32 = 0o200;
+const ALL_WRITE: u32 = 0o222;
+
+/*
+ Unixのパーミッションのうち、特別なビット（setuid、setgid、スティッキー）を除いた部分と、それを含めた部分
+ */
+const PERMISSION_MASK: u32 = 0o777;
+const SPECIAL_MASK: u32 = 0o7777;
+
+/*
+ DOSの属性のうち、読み取り専用（0x01）とディレクトリ（0x10）
+ */
+const DOS_READONLY: u8 = 0x01;
+const DOS_DIRECTORY: u8 = 0x10;
+
+/*
+ セントラルヘッダーの「作成したOS」のうち、Unixを表す値（上位8bit）
+ */
+const HOST_UNIX: u16 = 3;
+
+/*
+ エントリの属性
+ Unixのモード（種類のビットを含む）として持ち、DOSの属性とはこの値から相互に変換する。
+ DOSの属性には読み取り専用とディレクトリしかないため、DOSの属性から作った場合は
+ ファイルは0o644、ディレクトリは0o755（読み取り専用の場合は書き込みの許可を全て外す）とする。
+ mode:  Unixのモード
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    mode: u32,
+}
+
+impl Permissions {
+    /*
+     Unixのモードから作る。種類のビットがない場合は通常のファイルとする。
+     */
+    pub fn from_unix_mode(mode: u32) -> Self {
+        let mode = if mode & TYPE_MASK == 0 { mode | TYPE_FILE } else { mode };
+        Permissions { mode: mode & 0o177777 }
+    }
+
+    /*
+     DOSの属性から作る
+     */
+    pub fn from_dos_attributes(attributes: u8) -> Self {
+        let mut mode = if attributes & DOS_DIRECTORY != 0 { TYPE_DIR | 0o755 } else { TYPE_FILE | 0o644 };
+        if attributes & DOS_READONLY != 0 {
+            mode &= !ALL_WRITE;
+        }
+        Permissions { mode }
+    }
+
+    /*
+     セントラルヘッダーの「作成したOS」と外部属性から作る。
+     Unixで作られていて上位16bitにモードが入っている場合はそれを使い、それ以外はDOSの属性（下位8bit）を使う。
+     */
+    pub fn from_external_attributes(version_made_by: u16, external: u32) -> Self {
+        let mode = external >> 16;
+        if version_made_by >> 8 == HOST_UNIX && mode != 0 {
+            Permissions::from_unix_mode(mode)
+        } else {
+            Permissions::from_dos_attributes(external as u8)
+        }
+    }
+
+    /*
+     ファイルシステムのメタデータから作る。Unix以外では読み取り専用かどうかだけを見る。
+     */
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Permissions::from_unix_mode(metadata.mode())
+        }
+        #[cfg(not(unix))]
+        {
+            let mut attributes = if metadata.permissions().readonly() { DOS_READONLY } else { 0 };
+            if metadata.is_dir() {
+                attributes |= DOS_DIRECTORY;
+            }
+            Permissions::from_dos_attributes(attributes)
+        }
+    }
+
+    /*
+     Unixのモード（種類のビットを含む）
+     */
+    pub fn unix_mode(&self) -> u32 {
+        self.mode
+    }
+
+    /*
+     DOSの属性。持ち主が書き込めない場合は読み取り専用にする。
+     */
+    pub fn dos_attributes(&self) -> u8 {
+        let mut attributes = 0;
+        if self.mode & OWNER_WRITE == 0 {
+            attributes |= DOS_READONLY;
+        }
+        if self.is_dir() {
+            attributes |= DOS_DIRECTORY;
+        }
+        attributes
+    }
+
+    /*
+     セントラルヘッダーの外部属性（上位16bitにUnixのモード、下位8bitにDOSの属性）
+     */
+    pub fn external_attributes(&self) -> u32 {
+        (self.mode << 16) | self.dos_attributes() as u32
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_DIR
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.mode & OWNER_WRITE == 0
+    }
+
+    /*
+     pathのファイルにこの属性を設定する。Unix以外では読み取り専用かどうかだけを設定する。
+     信頼できないzipから展開したファイルが他の人の権限で実行されないように、特別なビット（setuid、setgid、スティッキー）は設定しない。
+     */
+    pub fn apply(&self, path: &Path) -> Result<(), Error> {
+        self.restore(path, false)
+    }
+
+    /*
+     applyと同じだが、特別なビットも設定する。信頼できるzipにだけ使う。
+     */
+    pub fn apply_with_special_bits(&self, path: &Path) -> Result<(), Error> {
+        self.restore(path, true)
+    }
+
+    /*
+     pathのファイルにこの属性を設定する。special_bitsがtrueの場合だけ特別なビットも設定する。
+     */
+    pub(crate) fn restore(&self, path: &Path, special_bits: bool) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mask = if special_bits { SPECIAL_MASK } else { PERMISSION_MASK };
+            fs::set_permissions(path, fs::Permissions::from_mode(self.mode & mask))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = special_bits;
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(self.readonly());
+            fs::set_permissions(path, permissions)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dos_attributes_from_unix_mode() {
+        assert_eq!(Permissions::from_unix_mode(0o100644).dos_attributes(), 0);
+        assert_eq!(Permissions::from_unix_mode(0o100444).dos_attributes(), DOS_READONLY);
+        assert_eq!(Permissions::from_unix_mode(0o040755).dos_attributes(), DOS_DIRECTORY);
+        assert_eq!(Permissions::from_unix_mode(0o040555).dos_attributes(), DOS_DIRECTORY | DOS_READONLY);
+
+        /*
+         種類のビットがないモードは通常のファイルとして扱う
+         */
+        assert_eq!(Permissions::from_unix_mode(0o644).unix_mode(), 0o100644);
+    }
+
+    #[test]
+    fn unix_mode_from_dos_attributes() {
+        assert_eq!(Permissions::from_dos_attributes(0).unix_mode(), 0o100644);
+        assert_eq!(Permissions::from_dos_attributes(DOS_READONLY).unix_mode(), 0o100444);
+        assert_eq!(Permissions::from_dos_attributes(DOS_DIRECTORY).unix_mode(), 0o040755);
+        assert_eq!(Permissions::from_dos_attributes(DOS_DIRECTORY | DOS_READONLY).unix_mode(), 0o040555);
+    }
+
+    #[test]
+    fn external_attributes_round_trip() {
+        for mode in [0o100644, 0o100755, 0o100444, 0o104755, 0o040755, 0o041777] {
+            let permissions = Permissions::from_unix_mode(mode);
+            let external = permissions.external_attributes();
+            assert_eq!(Permissions::from_external_attributes(HOST_UNIX << 8 | 20, external), permissions);
+
+            /*
+             Unix以外で作られたzipでは、DOSの属性（下位8bit）だけを使う
+             */
+            let dos = Permissions::from_external_attributes(20, external);
+            assert_eq!(dos.is_dir(), permissions.is_dir());
+            assert_eq!(dos.readonly(), permissions.readonly());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_strips_special_bits() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("zipper-permissions-{}", std::process::id()));
+        fs::write(&path, b"")?;
+        let permissions = Permissions::from_unix_mode(0o104755);
+        permissions.apply(&path)?;
+        let stripped = fs::metadata(&path)?.permissions().mode() & SPECIAL_MASK;
+        permissions.apply_with_special_bits(&path)?;
+        let kept = fs::metadata(&path)?.permissions().mode() & SPECIAL_MASK;
+        fs::remove_file(&path)?;
+        assert_eq!(stripped, 0o755);
+        assert_eq!(kept, 0o4755);
+        Ok(())
+    }
+}
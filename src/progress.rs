@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/*
+ 圧縮中のエントリの途中経過
+ EncodeOptions.progressに入れておくと、圧縮している間に別のスレッドから読み取れる。
+ データベースのダンプのような大きいエントリで、進み具合を表示したり、異常に早く気付いたりするために使う。
+ 値はエントリごとに0からになり、ある程度のバイト数を処理するたびに更新される。
+ bytes_in:   今までに読み込んだバイト数
+ bytes_out:  今までに圧縮して出力したバイト数
+ crc:        今までに読み込んだデータのcrc32
+ */
+#[derive(Debug, Default)]
+pub struct Progress {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    crc: AtomicU32,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress::default()
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn current_crc(&self) -> u32 {
+        self.crc.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn update(&self, bytes_in: u64, bytes_out: u64, crc: u32) {
+        self.bytes_in.store(bytes_in, Ordering::Relaxed);
+        self.bytes_out.store(bytes_out, Ordering::Relaxed);
+        self.crc.store(crc, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+
+use crate::archive::{end_comment, find_end, has_zip64, parse_central, read16, read32, read_descriptor, read_zip64, DataDescriptor, CENTRAL_SIGNATURE, END_SIGNATURE, LOCAL_HEADER_SIZE, LOCAL_SIGNATURE};
+use crate::error::{Context, Phase};
+use crate::extra::{encode_extra_fields, parse_extra_fields, ExtraField, RESERVED_IDS};
+use crate::glob::glob_match;
+use crate::inflate::Inflater;
+use crate::names::{decode_name, encode_name, Cp437Names, UTF8_FLAG};
+use crate::zipcrypto::FLAG_ENCRYPTED;
+use crate::{Arena, ArchiveWriter, CentralFields, Compression, Crc32, EncodeOptions, EntryOptions, Header, Report, ZipEntry, ZipError, FLAG_DESCRIPTOR, METHOD_DEFLATED, METHOD_STORED};
+
+const CENTRAL_HEADER_SIZE: usize = 46;  // セントラルヘッダーの固定長の部分
+const BUFFER_SIZE: usize = 64 * 1024;   // 圧縮したまま写すエントリを、終わりを探すために1度に展開する大きさ
+
+/*
+ ストリームから読み込んだローカルヘッダーの情報
+ name:             エントリの名前（UTF-8のフラグが立っていない場合はCP437として読む）
+ name_bytes:       ヘッダーに書かれた名前のバイト列（そのまま写すエントリに使う）
+ flags:            汎用フラグ
+ method:           圧縮方法
+ time:             ヘッダーに書かれた（時刻, 日付）
+ crc32:            展開した後のデータのcrc32（データディスクリプタを使う場合は0）
+ compressed_size:  圧縮した後のサイズ（データディスクリプタを使う場合は0）
+ size:             展開した後のサイズ（データディスクリプタを使う場合は0）
+ extra:            このクレートが書き込むもの（ZIP64の拡張情報など）を除いた拡張フィールド
+ zip64:            ZIP64の拡張情報があるかどうか（データディスクリプタのサイズが8byteずつになる）
+ */
+struct LocalEntry {
+    name: String,
+    name_bytes: Vec<u8>,
+    flags: u16,
+    method: u16,
+    time: (u16, u16),
+    crc32: u32,
+    compressed_size: u64,
+    size: u64,
+    extra: Vec<ExtraField>,
+    zip64: bool,
+}
+
+impl LocalEntry {
+    fn descriptor(&self) -> bool {
+        self.flags & FLAG_DESCRIPTOR != 0
+    }
+}
+
+/*
+ inputから読み込んだzipを、patternsに当てはまるエントリだけcompressionとoptionsで圧縮し直して、outputに書き込む。
+ どちらも先頭から順に読み書きするだけで、シークもエントリ全体をメモリに溜めることもしないため、
+ ネットワークのストリームをそのまま流すプロキシやゲートウェイに使える（patternsが空の場合は全てのエントリ）。
+ 圧縮し直すエントリは展開してcrc32を確かめながら、options.streamingと同じくデータディスクリプタを使って書き込む。
+ それ以外のエントリとディレクトリは、圧縮したままのデータをそのまま写す。
+ 外部属性とエントリのコメント、zip全体のコメントは最後のセントラルヘッダーから読んで引き継ぐ。
+ 展開できない圧縮方法（zstdなど）のエントリを圧縮し直そうとした場合はUnsupportedMethodに、
+ 暗号化されたエントリや、データディスクリプタを使う無圧縮のエントリ（データの終わりが分からない）はUnsupportedにする。
+ */
+pub fn recompress_stream<R: Read, W: Write>(input: R, output: W, patterns: &[String], compression: Compression, options: &EncodeOptions) -> Result<Report, ZipError> {
+    let mut input = BufReader::new(input);
+    let options = EncodeOptions { streaming: true, ..options.clone() };
+    let mut writer = ArchiveWriter::new(output);
+    let mut arena = Arena::new();
+    let mut names = Vec::new();
+    let mut pending = None;
+    let signature = loop {
+        let signature = match pending.take() {
+            Some(signature) => signature,
+            None => read_signature(&mut input)?,
+        };
+        if signature != LOCAL_SIGNATURE {
+            break signature;
+        }
+        let entry = read_local(&mut input)?;
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(ZipError::Unsupported(format!("recompressing {}, which is encrypted", entry.name)));
+        }
+        let recompressed = !entry.name.ends_with('/') && matches(patterns, &entry.name);
+        pending = if recompressed {
+            recompress_entry(&mut input, &mut writer, &entry, compression, &options, &mut arena)?
+        } else {
+            copy_entry(&mut input, &mut writer, &entry)?
+        };
+        names.push((entry.name, recompressed));
+    };
+    if signature != CENTRAL_SIGNATURE && signature != END_SIGNATURE {
+        return Err(ZipError::InvalidArchive(format!("unexpected signature {:08x} after {} entries", signature, names.len())));
+    }
+
+    /*
+     セントラルヘッダーとエンドセントラルヘッダーは小さいため、最後まで読み込んでから属性とコメントを取り出す
+     */
+    let mut tail = signature.to_le_bytes().to_vec();
+    input.read_to_end(&mut tail).context("central directory", Phase::Read)?;
+    let comments = central_comments(&tail);
+    let central = parse_central(&tail, comments.len(), 0, None, &Cp437Names)?;
+    writer.set_central_fields(&central_fields(&names, &central, &comments, &options)?);
+    let comment = find_end(&tail).map(|end| end_comment(&tail, end).to_vec()).unwrap_or_default();
+    Ok(writer.with_comment(comment).finish()?)
+}
+
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, name))
+}
+
+fn read_signature<R: Read>(input: &mut R) -> Result<u32, ZipError> {
+    let mut signature = [0u8; 4];
+    input.read_exact(&mut signature).context("stream", Phase::Read)?;
+    Ok(u32::from_le_bytes(signature))
+}
+
+/*
+ ローカルヘッダーの署名より後ろ（名前と拡張フィールドまで）を読む
+ */
+fn read_local<R: Read>(input: &mut R) -> Result<LocalEntry, ZipError> {
+    let mut header = [0u8; LOCAL_HEADER_SIZE];
+    if let Some(rest) = header.get_mut(4..) {
+        input.read_exact(rest).context("local header", Phase::Read)?;
+    }
+    let flags = read16(&header, 6);
+    let mut name_bytes = vec![0; read16(&header, 26) as usize];
+    let mut extra = vec![0; read16(&header, 28) as usize];
+    input.read_exact(&mut name_bytes).and_then(|_| input.read_exact(&mut extra)).context("local header", Phase::Read)?;
+    let name = decode_name(&name_bytes, flags, &Cp437Names);
+    let mut compressed_size = read32(&header, 18) as u64;
+    let mut size = read32(&header, 22) as u64;
+    if flags & FLAG_DESCRIPTOR == 0 {
+        read_zip64(&name, &extra, &mut [&mut size, &mut compressed_size])?;
+    }
+    let mut fields = parse_extra_fields(&extra).context(&name, Phase::Read)?;
+    fields.retain(|field| !RESERVED_IDS.contains(&field.id));
+    Ok(LocalEntry {
+        name_bytes,
+        flags,
+        method: read16(&header, 8),
+        time: (read16(&header, 10), read16(&header, 12)),
+        crc32: read32(&header, 14),
+        compressed_size,
+        size,
+        extra: fields,
+        zip64: has_zip64(&extra),
+        name,
+    })
+}
+
+/*
+ entryを展開し、compressionで圧縮し直して書き込む。
+ 読み終えた後に、データディスクリプタの後ろで読みすぎた次の署名があれば返す。
+ */
+fn recompress_entry<R: Read, W: Write>(input: &mut BufReader<R>, writer: &mut ArchiveWriter<W>, entry: &LocalEntry, compression: Compression, options: &EncodeOptions, arena: &mut Arena) -> Result<Option<u32>, ZipError> {
+    let entry_options = EntryOptions { compression, extra_fields: entry.extra.clone(), ..EntryOptions::default() };
+    let limit = if entry.descriptor() { u64::MAX } else { entry.compressed_size };
+    let data = (&mut *input).take(limit);
+    let (sums, mut data) = match entry.method {
+        METHOD_STORED if entry.descriptor() => {
+            return Err(ZipError::Unsupported(format!("recompressing {}, which is stored with a data descriptor", entry.name)));
+        }
+        METHOD_STORED => {
+            let mut data = Checked::new(data);
+            writer.add_entry(&mut data, &entry.name, entry.time, options, &entry_options, arena)?;
+            (data.sums(), data.inner)
+        }
+        METHOD_DEFLATED => {
+            let mut data = Checked::new(Inflater::new(data));
+            writer.add_entry(&mut data, &entry.name, entry.time, options, &entry_options, arena)?;
+            (data.sums(), data.inner.into_inner())
+        }
+        method => return Err(ZipError::UnsupportedMethod { name: entry.name.clone(), method }),
+    };
+    if !entry.descriptor() {
+        io::copy(&mut data, &mut io::sink()).context(&entry.name, Phase::Read)?;
+    }
+    let compressed_size = limit - data.limit();
+    check_sums(input, entry, sums, compressed_size)
+}
+
+/*
+ entryの圧縮したままのデータを、展開せずに書き込む。
+ データディスクリプタを使うdeflateのエントリは、展開しながらdeflateのデータの終わりを探して写す。
+ 読み終えた後に、データディスクリプタの後ろで読みすぎた次の署名があれば返す。
+ */
+fn copy_entry<R: Read, W: Write>(input: &mut BufReader<R>, writer: &mut ArchiveWriter<W>, entry: &LocalEntry) -> Result<Option<u32>, ZipError> {
+    let header = Header::new(entry.size, entry.compressed_size, entry.name.as_str(), entry.crc32, entry.time.0, entry.time.1)
+        .with_name_bytes(entry.name_bytes.clone(), entry.flags & UTF8_FLAG != 0)
+        .with_extra(encode_extra_fields(&entry.name, &entry.extra)?)
+        .with_method(entry.method);
+    if !entry.descriptor() {
+        writer.add_raw(header, input)?;
+        return Ok(None);
+    }
+    if entry.method != METHOD_DEFLATED {
+        return Err(ZipError::Unsupported(format!("copying {}, which uses a data descriptor with method {}", entry.name, entry.method)));
+    }
+    let mut data = RawDeflate::new(&mut *input);
+    writer.stream_raw(header, &mut data, entry.zip64, |data| data.sums())?;
+    let (sums, compressed_size) = (data.sums(), data.inflater.get_mut().len);
+    check_sums(input, entry, sums, compressed_size)
+}
+
+/*
+ 展開したデータのサイズとcrc32を、ローカルヘッダーかデータディスクリプタの値と比べる。
+ データディスクリプタの後ろで読みすぎた次の署名があれば返す。
+ */
+fn check_sums<R: Read>(input: &mut R, entry: &LocalEntry, (size, crc32): (u64, u32), compressed_size: u64) -> Result<Option<u32>, ZipError> {
+    let (expected_size, expected_crc32, pending) = if entry.descriptor() {
+        let (descriptor, pending) = read_stream_descriptor(input, compressed_size, entry.zip64).context(&entry.name, Phase::Read)?;
+        (descriptor.size, descriptor.crc32, pending)
+    } else {
+        (entry.size, entry.crc32, None)
+    };
+    if expected_size != size || expected_crc32 != crc32 {
+        return Err(ZipError::CrcMismatch { name: entry.name.clone(), expected: expected_crc32, actual: crc32 });
+    }
+    Ok(pending)
+}
+
+/*
+ データの後ろのデータディスクリプタを読む。
+ 先頭の印があるかどうかは読んでみるまで分からないため、印がある場合の長さだけ読み、
+ 印がなかった場合に読みすぎた4バイト（次のヘッダーの署名）を一緒に返す。
+ */
+fn read_stream_descriptor<R: Read>(input: &mut R, compressed_size: u64, zip64: bool) -> Result<(DataDescriptor, Option<u32>), ZipError> {
+    let mut buffer = vec![0u8; if zip64 { 24 } else { 16 }];
+    input.read_exact(&mut buffer)?;
+    let descriptor = read_descriptor(&buffer, 0, Some(compressed_size), zip64)
+        .ok_or_else(|| ZipError::InvalidArchive("data descriptor is truncated".to_string()))?;
+    let pending = buffer.get(descriptor.len..).filter(|rest| rest.len() == 4).map(|rest| read32(rest, 0));
+    Ok((descriptor, pending))
+}
+
+/*
+ 先頭から続くセントラルヘッダーのエントリのコメントを、デコードせずにバイト列のまま順に返す
+ */
+fn central_comments(tail: &[u8]) -> Vec<&[u8]> {
+    let mut comments = Vec::new();
+    let mut pos = 0;
+    while read32(tail, pos) == CENTRAL_SIGNATURE {
+        let start = pos + CENTRAL_HEADER_SIZE + read16(tail, pos + 28) as usize + read16(tail, pos + 30) as usize;
+        pos = start + read16(tail, pos + 32) as usize;
+        comments.push(tail.get(start..pos).unwrap_or_default());
+    }
+    comments
+}
+
+/*
+ 書き込んだ順の（エントリの名前, 圧縮し直したか）ごとに、セントラルヘッダーの同じ名前のエントリの外部属性とコメントを返す
+ （同じ名前は前から順に対応させる）。commentsはcentralと同じ順に並べたエントリのコメントのバイト列
+ そのまま写したエントリは名前のバイト列と同じ文字コードのコメントを写し、圧縮し直したエントリは名前と同じくoptions.name_encoderで書き込む。
+ */
+fn central_fields(names: &[(String, bool)], central: &[ZipEntry], comments: &[&[u8]], options: &EncodeOptions) -> Result<Vec<Option<CentralFields>>, ZipError> {
+    let mut by_name: HashMap<&str, Vec<(&ZipEntry, &[u8])>> = HashMap::new();
+    for (entry, comment) in central.iter().zip(comments).rev() {
+        by_name.entry(entry.name.as_str()).or_default().push((entry, comment));
+    }
+    names.iter()
+        .map(|(name, recompressed)| {
+            let (entry, comment) = match by_name.get_mut(name.as_str()).and_then(Vec::pop) {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            let (comment, utf8) = if !recompressed {
+                (comment.to_vec(), entry.flags & UTF8_FLAG != 0)
+            } else if options.name_encoder.utf8() {
+                (entry.comment.as_bytes().to_vec(), true)
+            } else {
+                (encode_name(&entry.comment, options)?, false)
+            };
+            Ok(Some(CentralFields { external_attributes: entry.permissions.external_attributes(), comment, utf8 }))
+        })
+        .collect()
+}
+
+/*
+ 読み出したデータのcrc32とサイズを数えるためのもの
+ */
+struct Checked<R: Read> {
+    inner: R,
+    crc: Crc32,
+    size: u64,
+}
+
+impl<R: Read> Checked<R> {
+    fn new(inner: R) -> Self {
+        Checked { inner, crc: Crc32::new(), size: 0 }
+    }
+
+    fn sums(&self) -> (u64, u32) {
+        (self.size, self.crc.clone().get_crc32())
+    }
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        for &byte in buf.iter().take(len) {
+            self.crc.push_buf(byte);
+        }
+        self.size += len as u64;
+        Ok(len)
+    }
+}
+
+/*
+ 読み込んだバイトを、読み出されるまで溜めておくためのもの
+ recorded:  まだ読み出していない、読み込んだバイト
+ len:       今までに読み込んだバイト数
+ */
+struct Recorder<R: Read> {
+    input: R,
+    recorded: Vec<u8>,
+    len: u64,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.input.read(buf)?;
+        self.recorded.extend_from_slice(buf.get(..len).unwrap_or_default());
+        self.len += len as u64;
+        Ok(len)
+    }
+}
+
+/*
+ データディスクリプタを使うdeflateのエントリの、圧縮したままのデータを読み出すためのもの
+ 圧縮後のサイズが分からないため、読み込みながら展開してdeflateのデータの終わりを見つける
+ （Inflaterは1バイトずつ読み込むため、展開し終えた時点で読み込んだ分がちょうどdeflateのデータになる）。
+ inflater:  読み込んだバイトをRecorderに溜めながら展開するもの
+ output:    展開したデータを受け取るバッファ（crc32とサイズを数えるだけで、中身は使わない）
+ crc:       展開したデータのcrc32
+ size:      展開したデータのサイズ
+ done:      deflateのデータを最後まで読んだかどうか
+ */
+struct RawDeflate<R: Read> {
+    inflater: Inflater<Recorder<R>>,
+    output: Vec<u8>,
+    crc: Crc32,
+    size: u64,
+    done: bool,
+}
+
+impl<R: Read> RawDeflate<R> {
+    fn new(input: R) -> Self {
+        RawDeflate {
+            inflater: Inflater::new(Recorder { input, recorded: Vec::new(), len: 0 }),
+            output: vec![0; BUFFER_SIZE],
+            crc: Crc32::new(),
+            size: 0,
+            done: false,
+        }
+    }
+
+    fn sums(&self) -> (u64, u32) {
+        (self.size, self.crc.clone().get_crc32())
+    }
+}
+
+impl<R: Read> Read for RawDeflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.inflater.get_mut().recorded.is_empty() && !self.done {
+            let len = self.inflater.read(&mut self.output)?;
+            for &byte in self.output.iter().take(len) {
+                self.crc.push_buf(byte);
+            }
+            self.size += len as u64;
+            self.done = len == 0;
+        }
+        let recorded = &mut self.inflater.get_mut().recorded;
+        let len = buf.len().min(recorded.len());
+        if let (Some(dst), Some(src)) = (buf.get_mut(..len), recorded.get(..len)) {
+            dst.copy_from_slice(src);
+        }
+        recorded.drain(..len);
+        Ok(len)
+    }
+}
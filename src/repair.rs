@@ -0,0 +1,200 @@
+use std::convert::TryFrom;
+use std::fs::read;
+use std::io::{Error, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::archive::{read16, read32, read_descriptor, CENTRAL_SIGNATURE, LOCAL_HEADER_SIZE, LOCAL_SIGNATURE};
+use crate::error::{Context, Phase};
+use crate::inflate::{inflate, inflate_prefix};
+use crate::workspace::write_output;
+use crate::{crc32, zip_size, Diagnostic, DiagnosticKind, EntryReport, Header, Report, ZipError, METHOD_DEFLATED, METHOD_STORED};
+
+const DOS_DIRECTORY: u32 = 0x10;  // 外部属性: ディレクトリ
+
+/*
+ ローカルヘッダーから取り出せた1つのエントリ
+ name:             エントリの名前
+ header:           ローカルヘッダーの固定長の部分（セントラルヘッダーを作るのに使う）
+ crc32:            データのcrc32（データディスクリプタを使う場合はそちらの値）
+ compressed_size:  圧縮した後のサイズ
+ size:             展開した後のサイズ
+ extra:            ローカルヘッダーの拡張フィールド
+ range:            ローカルヘッダーからデータ（とデータディスクリプタ）の終わりまでの範囲
+ */
+struct Salvaged {
+    name: Vec<u8>,
+    header: [u8; LOCAL_HEADER_SIZE],
+    crc32: u32,
+    compressed_size: u32,
+    size: u32,
+    extra: Vec<u8>,
+    range: Range<usize>,
+}
+
+/*
+ input_zipの中からローカルヘッダーを先頭から探し直し、セントラルヘッダーを作り直したzipをoutput_zipに書き込む。
+ エンドセントラルヘッダーやセントラルヘッダーが途中で切れている（ダウンロードが途中で止まったなど）zipから、
+ 読めるエントリだけを取り出すのに使う。
+ ローカルヘッダーとデータはそのまま写す。データが途中で切れているものや、展開してcrc32が合わないものは入れずに
+ Report.diagnosticsにDroppedEntryとして記録する。
+ */
+pub fn repair(input_zip: &str, output_zip: &str) -> Result<Report, ZipError> {
+    let data = read(input_zip).context(input_zip, Phase::Read)?;
+    let mut report = Report::new();
+    let mut salvaged = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = find_local(&data, pos) {
+        match salvage(&data, start) {
+            Ok(entry) => {
+                pos = entry.range.end;
+                report.entries.push(EntryReport {
+                    name: String::from_utf8_lossy(&entry.name).into_owned(),
+                    before_size: entry.size as u64,
+                    after_size: entry.compressed_size as u64,
+                    crc32: entry.crc32,
+                    profile: None,
+                    changed: false,
+                    retries: 0,
+                    cut_at: None,
+                    cached: false,
+                });
+                salvaged.push(entry);
+            }
+            Err(reason) => {
+                let name = local_name(&data, start);
+                report.diagnostics.push(Diagnostic { name, kind: DiagnosticKind::DroppedEntry(reason) });
+                pos = start + 4;
+            }
+        }
+    }
+
+    write_output(Path::new(output_zip), None, &[], |mut file| {
+        write_repaired(&mut file, &data, &salvaged).context(output_zip, Phase::Write)?;
+        Ok(report)
+    })
+}
+
+/*
+ 取り出せたエントリのローカルヘッダーとデータを写し、続けてセントラルヘッダーとエンドセントラルヘッダーを書き込む
+ */
+fn write_repaired<W: Write>(output: &mut W, data: &[u8], salvaged: &[Salvaged]) -> Result<(), Error> {
+    let mut central = Vec::new();
+    let mut offset: u32 = 0;
+    for entry in salvaged {
+        let local = data.get(entry.range.clone()).unwrap_or_default();
+        output.write_all(local)?;
+        central.extend_from_slice(&central_header(entry, offset));
+        offset = zip_size(offset as u64 + local.len() as u64)?;
+    }
+    let count = u16::try_from(salvaged.len())
+        .map_err(|_| Error::other(format!("{} entries do not fit in a zip without ZIP64", salvaged.len())))?;
+    let end_header = Header::new(0, 0, "", 0, 0, 0).end_header(count, zip_size(central.len() as u64)?, offset);
+    output.write_all(&central)?;
+    output.write_all(&end_header)?;
+    output.flush()
+}
+
+/*
+ ローカルヘッダーの値からセントラルヘッダーを作る
+ 作成したOSや属性はローカルヘッダーに書かれていないため、MS-DOSとしてディレクトリの属性だけを付ける。
+ データディスクリプタのビットは、値をセントラルヘッダーに書くためそのまま残してよい。
+ */
+fn central_header(entry: &Salvaged, offset: u32) -> Vec<u8> {
+    let header = &entry.header;
+    let external = if entry.name.ends_with(b"/") { DOS_DIRECTORY } else { 0 };
+    let mut buffer = Vec::with_capacity(46 + entry.name.len() + entry.extra.len());
+    buffer.extend_from_slice(&CENTRAL_SIGNATURE.to_le_bytes());
+    buffer.extend_from_slice(&(read16(header, 4) & 0x00ff).to_le_bytes());
+    buffer.extend_from_slice(&read16(header, 4).to_le_bytes());
+    buffer.extend_from_slice(&read16(header, 6).to_le_bytes());
+    buffer.extend_from_slice(&read16(header, 8).to_le_bytes());
+    buffer.extend_from_slice(&read16(header, 10).to_le_bytes());
+    buffer.extend_from_slice(&read16(header, 12).to_le_bytes());
+    buffer.extend_from_slice(&entry.crc32.to_le_bytes());
+    buffer.extend_from_slice(&entry.compressed_size.to_le_bytes());
+    buffer.extend_from_slice(&entry.size.to_le_bytes());
+    buffer.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(&(entry.extra.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    buffer.extend_from_slice(&external.to_le_bytes());
+    buffer.extend_from_slice(&offset.to_le_bytes());
+    buffer.extend_from_slice(&entry.name);
+    buffer.extend_from_slice(&entry.extra);
+    buffer
+}
+
+/*
+ posから後ろで最初のローカルヘッダーの署名の位置を返す
+ */
+fn find_local(data: &[u8], pos: usize) -> Option<usize> {
+    (pos..data.len().saturating_sub(3)).find(|&start| read32(data, start) == LOCAL_SIGNATURE)
+}
+
+/*
+ startにあるローカルヘッダーの名前を読めるだけ読む（取り出せなかったエントリの報告に使う）
+ */
+fn local_name(data: &[u8], start: usize) -> String {
+    let name_start = start + LOCAL_HEADER_SIZE;
+    let name_end = (name_start + read16(data, start + 26) as usize).min(data.len());
+    String::from_utf8_lossy(data.get(name_start..name_end).unwrap_or_default()).into_owned()
+}
+
+/*
+ startにあるローカルヘッダーのエントリを取り出す。取り出せない場合はその理由を返す。
+ データディスクリプタを使うエントリは、圧縮後のサイズがローカルヘッダーに書かれていないため、
+ deflateのデータを展開してその終わりを探す（無圧縮の場合は終わりが分からないため取り出せない）。
+ */
+fn salvage(data: &[u8], start: usize) -> Result<Salvaged, String> {
+    let header: [u8; LOCAL_HEADER_SIZE] = data.get(start..start + LOCAL_HEADER_SIZE)
+        .and_then(|bytes| <[u8; LOCAL_HEADER_SIZE]>::try_from(bytes).ok())
+        .ok_or_else(|| "local header is truncated".to_string())?;
+    let flags = read16(&header, 6);
+    let method = read16(&header, 8);
+    let name_start = start + LOCAL_HEADER_SIZE;
+    let extra_start = name_start + read16(&header, 26) as usize;
+    let data_start = extra_start + read16(&header, 28) as usize;
+    let name = data.get(name_start..extra_start).ok_or_else(|| "name is truncated".to_string())?.to_vec();
+    let extra = data.get(extra_start..data_start).ok_or_else(|| "extra field is truncated".to_string())?.to_vec();
+    let rest = data.get(data_start..).unwrap_or_default();
+
+    let (contents, compressed_size, crc, size, end) = if flags & 0x0008 != 0 {
+        if method != METHOD_DEFLATED {
+            return Err(format!("size of method {} with a data descriptor is unknown", method));
+        }
+        let (contents, compressed_size) = inflate_prefix(rest).map_err(|err| err.to_string())?;
+        let descriptor_start = data_start + compressed_size;
+        let descriptor = read_descriptor(data, descriptor_start, Some(compressed_size as u64), false)
+            .ok_or_else(|| "data descriptor is truncated".to_string())?;
+        let size = zip_size(descriptor.size).map_err(|err| err.to_string())?;
+        (contents, compressed_size, descriptor.crc32, size, descriptor_start + descriptor.len)
+    } else {
+        let compressed_size = read32(&header, 18) as usize;
+        let compressed = rest.get(..compressed_size).ok_or_else(|| "data is truncated".to_string())?;
+        let contents = match method {
+            METHOD_STORED => compressed.to_vec(),
+            METHOD_DEFLATED => inflate(compressed).map_err(|err| err.to_string())?,
+            method => return Err(format!("compression method {} is not supported", method)),
+        };
+        (contents, compressed_size, read32(&header, 14), read32(&header, 22), data_start + compressed_size)
+    };
+
+    let actual = crc32(&contents);
+    if actual != crc {
+        return Err(format!("crc32 is {:08x} but the header says {:08x}", actual, crc));
+    }
+    if contents.len() as u64 != size as u64 {
+        return Err(format!("size is {} but the header says {}", contents.len(), size));
+    }
+    Ok(Salvaged {
+        name,
+        header,
+        crc32: crc,
+        compressed_size: zip_size(compressed_size as u64).map_err(|err| err.to_string())?,
+        size,
+        extra,
+        range: start..end,
+    })
+}
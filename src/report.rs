@@ -0,0 +1,382 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::meta::push_string;
+
+/*
+ 1つのエントリの圧縮にかかった時間をそれぞれの段階ごとに保持する
+ read:            入力ファイルの読み込み
+ match_search:    LZ77での一致の探索
+ entropy_coding:  固定ハフマンでの符号化
+ crc:             crc32の計算
+ write:           ヘッダの作成とzipファイルへの書き込み
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Profile {
+    pub read: Duration,
+    pub match_search: Duration,
+    pub entropy_coding: Duration,
+    pub crc: Duration,
+    pub write: Duration,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    /*
+     全ての段階の合計時間を返す
+     */
+    pub fn total(&self) -> Duration {
+        self.read + self.match_search + self.entropy_coding + self.crc + self.write
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total().as_secs_f64();
+        let phases = [
+            ("read", self.read),
+            ("match-search", self.match_search),
+            ("entropy-coding", self.entropy_coding),
+            ("crc", self.crc),
+            ("write", self.write),
+        ];
+        for (name, time) in phases.iter() {
+            let ratio = if total > 0.0 { time.as_secs_f64() / total * 100.0 } else { 0.0 };
+            writeln!(f, "    {:<15}{:>10.3} ms ({:>5.1}%)", name, time.as_secs_f64() * 1000.0, ratio)?;
+        }
+        write!(f, "    {:<15}{:>10.3} ms", "total", total * 1000.0)
+    }
+}
+
+/*
+ 1つのエントリの圧縮結果を保持する
+ name:         zipに格納した名前
+ before_size:  圧縮前のサイズ
+ after_size:   圧縮後のサイズ
+ crc32:        crc32の値
+ profile:      計測を行なった場合には各段階の時間
+ changed:      読み込んでいる間にファイルが書き換えられたかどうか
+ retries:      ファイルが書き換えられたために圧縮をやり直した回数
+ cut_at:       tailを指定した場合に、どこまでを格納したか（ファイルを開いた時点のサイズ）
+ cached:       圧縮せずにCompressionCacheから取り出したかどうか
+ */
+#[derive(Debug, Clone)]
+pub struct EntryReport {
+    pub name: String,
+    pub before_size: u64,
+    pub after_size: u64,
+    pub crc32: u32,
+    pub profile: Option<Profile>,
+    pub changed: bool,
+    pub retries: u32,
+    pub cut_at: Option<u64>,
+    pub cached: bool,
+}
+
+/*
+ zipを読み込んだときに見つかった、仕様から外れている点
+ VersionNeeded:      展開に必要なバージョンが知られているどのバージョンよりも大きい
+ CrcHeaderMismatch:  ローカルヘッダーとセントラルヘッダーのcrc32が食い違う（データと合う方を使う）
+ MissingUtf8Flag:    名前がUTF-8なのに、UTF-8であることを示すbit11が立っていない
+ MissingCentralDirectory:  エンドセントラルヘッダーが見つからない
+ DroppedEntry:       repairで取り出せなかったエントリと、その理由（データが途中で切れている、crc32が合わないなど）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    VersionNeeded(u16),
+    CrcHeaderMismatch { local: u32, central: u32 },
+    MissingUtf8Flag,
+    MissingCentralDirectory,
+    DroppedEntry(String),
+}
+
+/*
+ 仕様から外れている点と、それが見つかったエントリ
+ name:  エントリの名前（アーカイブ全体のことであれば空）
+ kind:  どのように外れているか
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub name: String,
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.name.is_empty() {
+            write!(f, "{}: ", self.name)?;
+        }
+        match &self.kind {
+            DiagnosticKind::VersionNeeded(version) => write!(f, "needs version {}.{} to extract", version / 10, version % 10),
+            DiagnosticKind::CrcHeaderMismatch { local, central } => write!(f, "crc32 is {:08x} in the local header but {:08x} in the central header", local, central),
+            DiagnosticKind::MissingUtf8Flag => write!(f, "name is UTF-8 but the UTF-8 flag is not set"),
+            DiagnosticKind::MissingCentralDirectory => write!(f, "end of central directory record is missing"),
+            DiagnosticKind::DroppedEntry(reason) => write!(f, "dropped: {}", reason),
+        }
+    }
+}
+
+/*
+ 書き込んだzipで使った機能のうち、展開するソフトによって対応が分かれるもの
+ zip64:             4GBを超えるサイズや位置のためのZIP64（そうしたエントリやzipだけで使う）
+ utf8_names:        UTF-8の名前（bit11を立てる）
+ data_descriptors:  データの後ろにサイズとcrc32を書くデータディスクリプタ（EncodeOptions.streamingの場合に使う）
+ stored:            無圧縮のエントリ
+ deflated:          deflateで圧縮したエントリ
+ extra_fields:      拡張フィールド（位置を合わせるための詰め物を含む）
+ unix_permissions:  外部属性に書いたUnixのモード
+ encrypted:         WinZipのAESで暗号化したエントリ
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    pub zip64: bool,
+    pub utf8_names: bool,
+    pub data_descriptors: bool,
+    pub stored: bool,
+    pub deflated: bool,
+    pub extra_fields: bool,
+    pub unix_permissions: bool,
+    pub encrypted: bool,
+}
+
+impl Features {
+    /*
+     使った機能ごとに、展開するソフトで気を付けることを返す
+     */
+    pub fn notes(&self) -> Vec<&'static str> {
+        let mut notes = Vec::new();
+        if self.zip64 {
+            notes.push("ZIP64 needs an extractor that supports PKZIP 4.5 or later");
+        }
+        if self.utf8_names {
+            notes.push("extractors that ignore the UTF-8 flag show non-ASCII names garbled");
+        }
+        if self.data_descriptors {
+            notes.push("some streaming extractors cannot read entries with data descriptors");
+        }
+        if self.extra_fields {
+            notes.push("extractors skip unknown extra fields, but tools that rewrite the archive may drop them");
+        }
+        if self.unix_permissions {
+            notes.push("Unix permissions are restored only by extractors that read the external attributes");
+        }
+        if self.encrypted {
+            notes.push("encrypted entries need an extractor that supports WinZip AES (AE-2)");
+        }
+        if !self.zip64 && !self.data_descriptors && !self.encrypted {
+            notes.push("no ZIP64 or data descriptors, so any PKZIP 2.0 extractor can read the archive");
+        }
+        notes
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |used: bool| if used { "yes" } else { "no" };
+        let methods: Vec<&str> = [(self.deflated, "deflate"), (self.stored, "stored")]
+            .iter()
+            .filter(|(used, _)| *used)
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "uses ZIP64: {}, UTF-8 names: {}, data descriptors: {}, extra fields: {}, Unix permissions: {}, encryption: {}, methods: {}",
+               yes_no(self.zip64), yes_no(self.utf8_names), yes_no(self.data_descriptors), yes_no(self.extra_fields),
+               yes_no(self.unix_permissions), yes_no(self.encrypted), if methods.is_empty() { "none".to_string() } else { methods.join(", ") })
+    }
+}
+
+/*
+ encode_withやdecodeの最終的な結果をまとめたもの
+ entries:      それぞれのエントリの結果
+ diagnostics:  decode_withをlenientで呼んだ場合に、見逃した仕様から外れている点
+ features:     zipを書き込んだ場合に、その中で使った機能（decodeでは全てfalse）
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub entries: Vec<EntryReport>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub features: Features,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report::default()
+    }
+
+    /*
+     書き込んだエントリを拡張子ごとと一番上のディレクトリごとに集計する
+     */
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        for entry in &self.entries {
+            stats.add(&entry.name, entry.before_size, entry.after_size);
+        }
+        stats
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            let ratio = if entry.before_size > 0 {
+                entry.after_size as f64 / entry.before_size as f64 * 100.0
+            } else {
+                0.0
+            };
+            writeln!(f, "{}: {} -> {} bytes ({:.1}%) crc32: {:08x}",
+                     entry.name, entry.before_size, entry.after_size, ratio, entry.crc32)?;
+            if entry.changed {
+                writeln!(f, "    warning: modified while being archived")?;
+            }
+            if let Some(cut_at) = entry.cut_at {
+                writeln!(f, "    cut at {} bytes (size when opened)", cut_at)?;
+            }
+            if entry.cached {
+                writeln!(f, "    taken from the compression cache")?;
+            }
+            if entry.retries > 0 {
+                writeln!(f, "    retried {} time(s) because the file was modified", entry.retries)?;
+            }
+            if let Some(profile) = &entry.profile {
+                writeln!(f, "{}", profile)?;
+            }
+        }
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "warning: {}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ 同じ拡張子や同じディレクトリのエントリをまとめたもの
+ count:            エントリの数
+ size:             展開した後のサイズの合計
+ compressed_size:  圧縮した後のサイズの合計
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Group {
+    pub count: u64,
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+impl Group {
+    pub fn new() -> Self {
+        Group::default()
+    }
+
+    /*
+     圧縮した後のサイズの、展開した後のサイズに対する割合（展開した後のサイズが0の場合は0）
+     */
+    pub fn ratio(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        self.compressed_size as f64 / self.size as f64
+    }
+
+    fn add(&mut self, size: u64, compressed_size: u64) {
+        self.count += 1;
+        self.size += size;
+        self.compressed_size += compressed_size;
+    }
+}
+
+/*
+ zipのサイズがどのエントリから来ているかを見るための集計
+ total:        全てのエントリ
+ extensions:   拡張子（小文字にしたもの）ごと。拡張子がないものは (none) にまとめる
+ directories:  一番上のディレクトリごと。ディレクトリに入っていないものは (root) にまとめる
+ ディレクトリのエントリはサイズがないため数えない。
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub total: Group,
+    pub extensions: BTreeMap<String, Group>,
+    pub directories: BTreeMap<String, Group>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /*
+     nameのエントリを集計に加える
+     */
+    pub fn add(&mut self, name: &str, size: u64, compressed_size: u64) {
+        if name.ends_with('/') {
+            return;
+        }
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        let extension = match file_name.rfind('.') {
+            Some(dot) if dot > 0 => file_name[dot..].to_lowercase(),
+            _ => "(none)".to_string(),
+        };
+        let directory = match name.split_once('/') {
+            Some((directory, _)) => format!("{}/", directory),
+            None => "(root)".to_string(),
+        };
+        self.total.add(size, compressed_size);
+        self.extensions.entry(extension).or_default().add(size, compressed_size);
+        self.directories.entry(directory).or_default().add(size, compressed_size);
+    }
+
+    /*
+     JSONにする。拡張子とディレクトリは圧縮した後のサイズが大きい順に並べる。
+     */
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\n  \"total\": {");
+        push_group(&mut json, &self.total);
+        json.push('}');
+        for (key, groups) in [("extensions", &self.extensions), ("directories", &self.directories)].iter() {
+            json.push_str(&format!(",\n  \"{}\": [", key));
+            for (i, (name, group)) in largest_first(groups).into_iter().enumerate() {
+                json.push_str(if i == 0 { "\n    {\"name\": " } else { ",\n    {\"name\": " });
+                push_string(&mut json, name);
+                json.push_str(", ");
+                push_group(&mut json, group);
+                json.push('}');
+            }
+            json.push_str(if groups.is_empty() { "]" } else { "\n  ]" });
+        }
+        json.push_str("\n}\n");
+        json
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (title, groups) in [("Extension", &self.extensions), ("Directory", &self.directories)].iter() {
+            writeln!(f, "{:<20} {:>8} {:>12} {:>12} {:>7}", title, "Count", "Length", "Size", "Ratio")?;
+            for (name, group) in largest_first(groups) {
+                write_group(f, name, group)?;
+            }
+            writeln!(f)?;
+        }
+        write_group(f, "Total", &self.total)
+    }
+}
+
+fn write_group(f: &mut fmt::Formatter, name: &str, group: &Group) -> fmt::Result {
+    writeln!(f, "{:<20} {:>8} {:>12} {:>12} {:>6.1}%", name, group.count, group.size, group.compressed_size, group.ratio() * 100.0)
+}
+
+fn push_group(json: &mut String, group: &Group) {
+    json.push_str(&format!("\"count\": {}, \"size\": {}, \"compressed_size\": {}, \"ratio\": {:.4}",
+                           group.count, group.size, group.compressed_size, group.ratio()));
+}
+
+/*
+ 圧縮した後のサイズが大きい順に並べる（同じ場合は名前の順）
+ */
+fn largest_first(groups: &BTreeMap<String, Group>) -> Vec<(&String, &Group)> {
+    let mut sorted: Vec<(&String, &Group)> = groups.iter().collect();
+    sorted.sort_by(|a, b| b.1.compressed_size.cmp(&a.1.compressed_size).then_with(|| a.0.cmp(b.0)));
+    sorted
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{encode_with_arena, Arena, EncodeOptions, Report, ZipError};
+
+/*
+ ワーカーのスレッドで行う仕事（そのスレッドのArenaを受け取る）
+ */
+pub(crate) type Job = Box<dyn FnOnce(&mut Arena) + Send>;
+
+/*
+ 圧縮に使うスレッドとバッファを持ち、複数のzipの作成で使い回すためのもの
+ 1秒に何度もzipを作るサービスなどで、zipを作るたびにスレッドとArenaを作り直さずに済む。
+ このクレートの圧縮は辞書やハッシュテーブルを別に持たず、作り直すのはArenaのバッファだけのため、それを使い回す。
+ Builder::runtimeに渡すと、jobsの代わりにこのスレッドで並列に圧縮する。dropした場合にはスレッドを全て止める。
+ sender:   ワーカーのスレッドに仕事を渡す（dropするとスレッドが終わる）
+ workers:  ワーカーのスレッド（それぞれが自分のArenaを持ち、仕事をまたいで使い回す）
+ arenas:   呼び出したスレッドで圧縮するときに貸し出すArena（返されたものを次に貸し出す）
+ */
+pub struct ZipperRuntime {
+    sender: Option<Mutex<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+    arenas: Mutex<Vec<Arena>>,
+}
+
+impl ZipperRuntime {
+    /*
+     threads個のワーカーのスレッドを作る。0の場合はスレッドを作らず、Arenaだけを使い回す。
+     */
+    pub fn new(threads: usize) -> Result<Self, io::Error> {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(threads);
+        for number in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            let worker = thread::Builder::new()
+                .name(format!("zipper-worker-{}", number))
+                .spawn(move || work(&receiver))?;
+            workers.push(worker);
+        }
+        Ok(ZipperRuntime { sender: Some(Mutex::new(sender)), workers, arenas: Mutex::new(Vec::new()) })
+    }
+
+    /*
+     ワーカーのスレッドの数
+     */
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /*
+     encode_withと同じように1つのファイルを圧縮する。Arenaはこのruntimeのものを使い回す。
+     */
+    pub fn encode(&self, input_file: &str, output_file: &str, options: &EncodeOptions) -> Result<Report, ZipError> {
+        encode_with_arena(input_file, output_file, options, &mut lend_arena(Some(self)))
+    }
+
+    /*
+     jobをワーカーのスレッドのどれかで行う。スレッドがない場合はfalseを返す。
+     */
+    pub(crate) fn execute(&self, job: Job) -> bool {
+        if self.workers.is_empty() {
+            return false;
+        }
+        match self.sender.as_ref().map(|sender| sender.lock()) {
+            Some(Ok(sender)) => sender.send(job).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for ZipperRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZipperRuntime").field("threads", &self.threads()).finish()
+    }
+}
+
+impl Drop for ZipperRuntime {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/*
+ ワーカーのスレッドで、仕事がなくなる（senderがdropされる）まで仕事を受け取って行う。
+ 仕事がpanicした場合は、途中の状態のArenaを作り直してから次の仕事を受け取る。
+ */
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    let mut arena = Arena::new();
+    loop {
+        let job = match receiver.lock().map(|receiver| receiver.recv()) {
+            Ok(Ok(job)) => job,
+            _ => return,
+        };
+        if catch_unwind(AssertUnwindSafe(|| job(&mut arena))).is_err() {
+            arena = Arena::new();
+        }
+    }
+}
+
+/*
+ 呼び出したスレッドで使うArena。dropしたときに貸し出したruntimeへ返す。
+ runtime:  返す先（Noneの場合は新しく作ったArenaで、返さずに捨てる）
+ */
+pub(crate) struct LentArena<'a> {
+    runtime: Option<&'a ZipperRuntime>,
+    arena: Arena,
+}
+
+/*
+ runtimeが返したArenaがあればそれを、なければ新しいArenaを貸し出す
+ */
+pub(crate) fn lend_arena(runtime: Option<&ZipperRuntime>) -> LentArena<'_> {
+    let arena = runtime
+        .and_then(|runtime| runtime.arenas.lock().ok().and_then(|mut arenas| arenas.pop()))
+        .unwrap_or_default();
+    LentArena { runtime, arena }
+}
+
+impl Deref for LentArena<'_> {
+    type Target = Arena;
+
+    fn deref(&self) -> &Arena {
+        &self.arena
+    }
+}
+
+impl DerefMut for LentArena<'_> {
+    fn deref_mut(&mut self) -> &mut Arena {
+        &mut self.arena
+    }
+}
+
+impl Drop for LentArena<'_> {
+    fn drop(&mut self) {
+        if let Some(Ok(mut arenas)) = self.runtime.map(|runtime| runtime.arenas.lock()) {
+            arenas.push(std::mem::take(&mut self.arena));
+        }
+    }
+}
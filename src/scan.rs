@@ -0,0 +1,124 @@
+use std::fmt::Write as _;
+use std::io::Read;
+
+use crate::aes::Sha1;
+use crate::archive::into_zip_error;
+use crate::{ZipArchive, ZipError};
+
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;  // 展開したデータを受け取るバッファの大きさ
+
+/*
+ scanのオプション
+ buffer_size:     展開したデータを受け取るバッファの大きさ（全てのエントリで同じバッファを上書きしながら使う）
+ max_entry_size:  1つのエントリの展開後のサイズの上限。ヘッダーに書かれたサイズが超える場合は展開せずにEntryTooLargeにする
+ max_total_size:  全てのエントリで展開するバイト数の合計の上限。超える分のエントリは展開せずにEntryTooLargeにする
+ */
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub buffer_size: usize,
+    pub max_entry_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+}
+
+impl ScanOptions {
+    pub fn new() -> Self {
+        ScanOptions::default()
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_entry_size: None,
+            max_total_size: None,
+        }
+    }
+}
+
+/*
+ scanで調べたエントリの結果
+ name:   エントリの名前
+ size:   展開したバイト数（途中で失敗した場合はそこまで）
+ crc32:  ヘッダーに書かれたcrc32（errorがNoneの場合は展開したデータと合っている）
+ sha1:   展開したデータのSHA-1（最後まで展開できた場合だけSome）
+ error:  展開や確認に失敗した場合のエラー
+ */
+#[derive(Debug)]
+pub struct ScannedEntry {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub error: Option<ZipError>,
+}
+
+impl ScannedEntry {
+    /*
+     sha1を16進数の文字列にする
+     */
+    pub fn sha1_hex(&self) -> Option<String> {
+        let digest = self.sha1?;
+        let mut hex = String::with_capacity(digest.len() * 2);
+        for byte in digest {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        Some(hex)
+    }
+}
+
+/*
+ archiveの全てのファイルのエントリを展開して、crc32（AESでは認証コード）を確かめ、SHA-1を計算する。
+ ウイルス対策などで、外から受け取った信用できないzipを大量に調べるためのもので、ファイルには何も書き込まない。
+ 展開したデータはoptions.buffer_sizeのバッファを上書きしながら受け取るため、エントリの大きさによらず使うメモリは一定になる。
+ ヘッダーに書かれたサイズより多く展開される（zip爆弾などの）エントリは、そこで展開をやめてエラーにする。
+ 壊れたエントリがあっても残りのエントリを調べ続け、ディレクトリを除いた全てのエントリの結果を順に返す。
+ */
+pub fn scan(archive: &mut ZipArchive, options: &ScanOptions) -> Vec<ScannedEntry> {
+    let mut buffer = vec![0u8; options.buffer_size.max(1)];
+    let mut total: u64 = 0;
+    let mut scanned = Vec::new();
+    for index in 0..archive.len() {
+        let (name, declared, crc32) = match archive.by_index(index) {
+            Some(entry) if !entry.is_dir() => (entry.name.clone(), entry.size, entry.crc32),
+            _ => continue,
+        };
+        let remaining = options.max_total_size.map(|max| max.saturating_sub(total));
+        let limit = options.max_entry_size.into_iter().chain(remaining).min();
+        let mut sha1 = Sha1::new();
+        let mut size = 0;
+        let result = match limit {
+            Some(limit) if declared > limit => Err(ZipError::EntryTooLarge { name: name.clone(), limit }),
+            _ => read_entry(archive, index, &mut buffer, declared, &mut sha1, &mut size),
+        };
+        total = total.saturating_add(size);
+        scanned.push(ScannedEntry {
+            name,
+            size,
+            crc32,
+            sha1: result.as_ref().ok().map(|_| sha1.finish()),
+            error: result.err(),
+        });
+    }
+    scanned
+}
+
+/*
+ index番目のエントリを最後まで展開してsha1に入力する。展開したバイト数はsizeに足していく。
+ declared（ヘッダーに書かれたサイズ）より多く展開された時点でエラーにする。
+ */
+pub(crate) fn read_entry(archive: &mut ZipArchive, index: usize, buffer: &mut [u8], declared: u64, sha1: &mut Sha1, size: &mut u64) -> Result<(), ZipError> {
+    let mut reader = archive.open_entry(index)?;
+    loop {
+        let len = reader.read(buffer).map_err(into_zip_error)?;
+        if len == 0 {
+            return Ok(());
+        }
+        *size += len as u64;
+        if *size > declared {
+            let name = reader.entry().name.clone();
+            return Err(ZipError::InvalidArchive(format!("{} expands to more than the {} bytes in its header", name, declared)));
+        }
+        sha1.update(buffer.get(..len).unwrap_or_default());
+    }
+}
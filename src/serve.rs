@@ -0,0 +1,173 @@
+use std::fs::remove_file;
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::{encode_files_with, encode_with_arena, Arena, DecodeOptions, EncodeOptions, Report, ZipArchive};
+
+/*
+ JSON-RPCのエラーコード
+ */
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/*
+ Unixソケットで待ち受けて、1行に1つのJSON-RPC 2.0のリクエストを処理し続ける。
+ プロセスを起動し直さずに済むように、Arenaは全てのリクエストで使い回している。
+
+ 対応しているメソッド
+ create:   {"paths": ["a.txt", "b.txt"], "output": "out.zip", "profile": false}
+           pathsのファイルを、ファイル名をエントリの名前にして1つのzipに圧縮する。
+ list:     {"archive": "in.zip"}
+           エントリの名前、サイズ、圧縮後のサイズ、crc32、圧縮方法、日時、ディレクトリかどうかを返す。
+ extract:  {"archive": "in.zip", "output": "dir", "password": "pw"}
+           全てのエントリをoutputに展開する（passwordは暗号化されたエントリがある場合だけ指定する）。
+ */
+pub fn serve(socket: &Path) -> Result<(), Error> {
+    if socket.exists() {
+        remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    let mut arena = Arena::new();
+    for stream in listener.incoming() {
+        if let Err(err) = handle_client(stream?, &mut arena) {
+            eprintln!("IO Error => {}", err);
+        }
+    }
+    Ok(())
+}
+
+/*
+ 1つの接続から来るリクエストを、接続が切れるまで処理する
+ */
+fn handle_client(stream: UnixStream, arena: &mut Arena) -> Result<(), Error> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_request(&line, arena);
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+fn handle_request(line: &str, arena: &mut Arena) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return error_response(Value::Null, PARSE_ERROR, &err.to_string()),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => return error_response(id, INVALID_REQUEST, "missing method"),
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = match method {
+        "create" => create(&params, arena),
+        "list" => list(&params),
+        "extract" => extract(&params),
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+    };
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => error_response(id, code, &message),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+/*
+ createメソッド。pathsのファイルを圧縮してoutputに書き込む。
+ ファイルが1つの場合は、Arenaを使い回せるencode_with_arenaで圧縮する。
+ */
+fn create(params: &Value, arena: &mut Arena) -> Result<Value, (i64, String)> {
+    let paths: Vec<&str> = params.get("paths")
+        .and_then(Value::as_array)
+        .map(|paths| paths.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let output = params.get("output").and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, "missing output".to_string()))?;
+    let mut options = EncodeOptions::new();
+    options.profile = params.get("profile").and_then(Value::as_bool).unwrap_or(false);
+
+    let report = match paths.as_slice() {
+        [] => return Err((INVALID_PARAMS, "missing paths".to_string())),
+        [path] => encode_with_arena(path, output, &options, arena),
+        paths => encode_files_with(paths, output, &options),
+    };
+    Ok(report_json(&report.map_err(|err| (SERVER_ERROR, err.to_string()))?))
+}
+
+/*
+ listメソッド。archiveのエントリをセントラルヘッダーの順に返す。
+ */
+fn list(params: &Value) -> Result<Value, (i64, String)> {
+    let archive = params.get("archive").and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, "missing archive".to_string()))?;
+    let archive = ZipArchive::open(archive).map_err(|err| (SERVER_ERROR, err.to_string()))?;
+    let entries: Vec<Value> = archive.iter().map(|entry| {
+        let modified = entry.modified;
+        json!({
+            "name": entry.name,
+            "size": entry.size,
+            "compressed_size": entry.compressed_size,
+            "crc32": entry.crc32,
+            "method": entry.method,
+            "modified": format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", modified.year, modified.month, modified.day, modified.hour, modified.minute, modified.second),
+            "is_dir": entry.is_dir(),
+        })
+    }).collect();
+    Ok(json!({"entries": entries}))
+}
+
+/*
+ extractメソッド。archiveの全てのエントリをoutputに展開する。
+ */
+fn extract(params: &Value) -> Result<Value, (i64, String)> {
+    let archive = params.get("archive").and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, "missing archive".to_string()))?;
+    let output = params.get("output").and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, "missing output".to_string()))?;
+    let options = DecodeOptions {
+        password: params.get("password").and_then(Value::as_str).map(str::to_string),
+        ..DecodeOptions::default()
+    };
+    let report = ZipArchive::open(archive)
+        .and_then(|mut archive| archive.extract_all_with(output, &options))
+        .map_err(|err| (SERVER_ERROR, err.to_string()))?;
+    Ok(report_json(&report))
+}
+
+fn report_json(report: &Report) -> Value {
+    let entries: Vec<Value> = report.entries.iter().map(|entry| {
+        let mut value = json!({
+            "name": entry.name,
+            "before_size": entry.before_size,
+            "after_size": entry.after_size,
+            "crc32": entry.crc32,
+        });
+        if let Some(profile) = &entry.profile {
+            value["profile"] = json!({
+                "read": profile.read.as_secs_f64(),
+                "match_search": profile.match_search.as_secs_f64(),
+                "entropy_coding": profile.entropy_coding.as_secs_f64(),
+                "crc": profile.crc.as_secs_f64(),
+                "write": profile.write.as_secs_f64(),
+            });
+        }
+        value
+    }).collect();
+    json!({"entries": entries})
+}
@@ -0,0 +1,75 @@
+use std::io;
+use std::path::Path;
+
+use crate::workspace::parent_dir;
+use crate::ZipError;
+
+/*
+ 1つのエントリのヘッダーの大きさ（名前と拡張フィールドを除く）
+ ローカルヘッダー30バイトとセントラルヘッダー46バイトに、それぞれのZIP64の拡張情報（20バイトと28バイト）を足したもの
+ */
+const ENTRY_HEADER_SIZE: u64 = 30 + 46 + 20 + 28;
+
+/*
+ エンドセントラルヘッダーの大きさ（ZIP64のエンドセントラルヘッダー56バイトとロケーター20バイトを含む）
+ */
+const END_HEADER_SIZE: u64 = 22 + 56 + 20;
+
+/*
+ (入力のサイズ, 名前の長さ) の並びから、zipが最大でどれだけの大きさになるかを見積もる。
+ 固定ハフマンでは1バイトが最大9ビットになるため、入力のサイズの9/8にブロックの終わりの分を足している。
+ */
+pub(crate) fn worst_case_size<I: IntoIterator<Item = (u64, usize)>>(entries: I) -> u64 {
+    entries.into_iter().fold(END_HEADER_SIZE, |total, (size, name_len)| {
+        total
+            .saturating_add(size.saturating_add(size / 8 + 2))
+            .saturating_add(ENTRY_HEADER_SIZE + 2 * name_len as u64)
+    })
+}
+
+/*
+ 一時ファイルを置くtempのディレクトリのファイルシステムにrequiredバイト以上の空きがあるかを確認する。
+ tempがoutputのあるディレクトリと違う場合は、置き換えるときに別のファイルシステムならコピーになるため、そちらも確認する。
+ 書き込みは一時ファイルに行ってから置き換えるため、outputが既にあっても上書きで空く分は空きに含めない。
+ 空き容量が取得できない場合には確認せずに進める。
+ */
+pub(crate) fn check_space(output: &Path, temp: &Path, required: u64) -> Result<(), ZipError> {
+    let dir = parent_dir(output);
+    check_dir(temp, required)?;
+    if dir != temp {
+        check_dir(&dir, required)?;
+    }
+    Ok(())
+}
+
+/*
+ dirのあるファイルシステムにrequiredバイト以上の空きがあるかを確認する
+ */
+fn check_dir(dir: &Path, required: u64) -> Result<(), ZipError> {
+    let available = match available_space(dir)? {
+        Some(available) => available,
+        None => return Ok(()),
+    };
+    if available < required {
+        return Err(ZipError::InsufficientSpace { path: dir.display().to_string(), required, available });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn available_space(dir: &Path) -> Result<Option<u64>, io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_dir: &Path) -> Result<Option<u64>, io::Error> {
+    Ok(None)
+}
@@ -0,0 +1,205 @@
+use std::io::prelude::*;
+use std::io::{self, Error};
+
+/*
+ tarのブロックのサイズ
+ */
+const BLOCK_SIZE: usize = 512;
+
+/*
+ tarのエントリの種類
+ File:       通常のファイル
+ Directory:  ディレクトリ
+ Other:      シンボリックリンクなど、zipに格納しないもの
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TarKind {
+    File,
+    Directory,
+    Other,
+}
+
+/*
+ tarのヘッダから読み取った情報
+ name:   ファイルの名前
+ mtime:  最終更新日時（UNIX時間）
+ mode:   Unixのパーミッション
+ kind:   エントリの種類
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct TarEntry {
+    pub name: String,
+    pub mtime: i64,
+    pub mode: u32,
+    pub kind: TarKind,
+}
+
+/*
+ tarを先頭から順番に読み込むためのもの
+ next_entryで次のエントリのヘッダを読み込み、その後はこの構造体からエントリのデータをReadで読み込む。
+ input:      tarのデータ
+ remaining:  現在のエントリのデータの残りのバイト数
+ padding:    現在のエントリのデータの後ろにある、512バイトに揃えるための詰め物のバイト数
+ */
+pub(crate) struct TarReader<R: Read> {
+    input: R,
+    remaining: u64,
+    padding: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(input: R) -> Self {
+        TarReader {
+            input,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /*
+     次のエントリのヘッダを読み込む。tarの終わりに来た場合にはNoneを返す。
+     pax拡張ヘッダ（x）やGNUの長い名前（L）は次のエントリの情報として使い、
+     グローバルヘッダ（g）は読み飛ばす。
+     */
+    pub fn next_entry(&mut self) -> Result<Option<TarEntry>, Error> {
+        let mut long_name: Option<String> = None;
+        let mut pax_mtime: Option<i64> = None;
+        let mut pax_size: Option<u64> = None;
+        loop {
+            self.skip_rest()?;
+            let mut header = [0u8; BLOCK_SIZE];
+            if !read_block(&mut self.input, &mut header)? || header.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+            let size = pax_size.take().unwrap_or(parse_octal(&header[124..136])?);
+            self.remaining = size;
+            self.padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+
+            match header[156] {
+                b'x' => {
+                    for (key, value) in self.read_pax()? {
+                        match key.as_str() {
+                            "path" => long_name = Some(value),
+                            "mtime" => pax_mtime = value.split('.').next().and_then(|secs| secs.parse().ok()),
+                            "size" => pax_size = value.parse().ok(),
+                            _ => {}
+                        }
+                    }
+                }
+                b'g' => {}
+                b'L' => {
+                    let mut name = Vec::new();
+                    self.read_to_end(&mut name)?;
+                    long_name = Some(c_string(&name));
+                }
+                flag => {
+                    let name = match long_name.take() {
+                        Some(name) => name,
+                        None => header_name(&header),
+                    };
+                    let kind = match flag {
+                        b'0' | 0 | b'7' => TarKind::File,
+                        b'5' => TarKind::Directory,
+                        _ => TarKind::Other,
+                    };
+                    let mtime = match pax_mtime.take() {
+                        Some(mtime) => mtime,
+                        None => parse_octal(&header[136..148])? as i64,
+                    };
+                    let mode = parse_octal(&header[100..108])? as u32;
+                    return Ok(Some(TarEntry { name, mtime, mode, kind }));
+                }
+            }
+        }
+    }
+
+    /*
+     pax拡張ヘッダのデータを「長さ キー=値\n」の組として読み込む
+     */
+    fn read_pax(&mut self) -> Result<Vec<(String, String)>, Error> {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data)?;
+        let text = String::from_utf8_lossy(&data);
+        let mut records = Vec::new();
+        for line in text.split('\n') {
+            if let Some((_, record)) = line.split_once(' ') {
+                if let Some((key, value)) = record.split_once('=') {
+                    records.push((key.to_string(), value.to_string()));
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /*
+     現在のエントリの読まれていないデータと詰め物を読み飛ばす
+     */
+    fn skip_rest(&mut self) -> Result<(), Error> {
+        let rest = self.remaining + self.padding;
+        io::copy(&mut (&mut self.input).take(rest), &mut io::sink())?;
+        self.remaining = 0;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let size = self.input.read(&mut buf[..max])?;
+        self.remaining -= size as u64;
+        Ok(size)
+    }
+}
+
+/*
+ 512バイトを読み込む。最初から何も読み込めなかった場合にはfalseを返す。
+ */
+fn read_block<R: Read>(input: &mut R, block: &mut [u8; BLOCK_SIZE]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < BLOCK_SIZE {
+        match input.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(Error::new(io::ErrorKind::UnexpectedEof, "truncated tar header")),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+/*
+ ustarの場合にはprefixとnameをつなげて名前にする
+ */
+fn header_name(header: &[u8; BLOCK_SIZE]) -> String {
+    let name = c_string(&header[0..100]);
+    if &header[257..262] == b"ustar" {
+        let prefix = c_string(&header[345..500]);
+        if !prefix.is_empty() {
+            return format!("{}/{}", prefix, name);
+        }
+    }
+    name
+}
+
+/*
+ 0で終わる文字列を読み込む
+ */
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/*
+ 8進数の文字列を読み込む
+ */
+fn parse_octal(bytes: &[u8]) -> Result<u64, Error> {
+    let text = c_string(bytes);
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| Error::new(io::ErrorKind::InvalidData, format!("invalid octal number in tar header: {}", text)))
+}
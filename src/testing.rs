@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{create_dir, create_dir_all, metadata, read, read_dir, remove_dir_all, remove_file, symlink_metadata, write, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::archive::set_modified;
+use crate::{build, unzip_to, Arena, ArchiveWriter, Builder, EncodeOptions, EntryOptions, Header, Permissions, ZipError, METHOD_STORED};
+
+/*
+ 一時ディレクトリの名前が他と重ならないように付ける番号
+ */
+static TREE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/*
+ Zerosで写す0の並びと、SparseFileで書き込むデータが0だけかどうかを比べるためのもの
+ */
+static ZEROS: [u8; 8192] = [0; 8192];
+
+const MTIME_TOLERANCE: u64 = 2;  // zipの日時は2秒単位のため、展開した日時はこれだけずれてもよい
+const TREE_DIR: &str = "tree";   // 一時ディレクトリの中で、ファイルを作るディレクトリの名前
+const DOS_DIRECTORY: u8 = 0x10;  // DOSの属性: ディレクトリ
+const GAP_NAME: &str = "gap";    // sparse_zipで、0だけを格納するエントリの名前
+
+/*
+ TestTreeに作ったエントリ
+ data:   ファイルの中身（ディレクトリの場合はNone）
+ mode:   設定したUnixのモード（設定していない場合は調べない）
+ mtime:  設定した最終更新日時（UNIX時間。設定していない場合は調べない）
+ */
+#[derive(Debug, Clone, Default)]
+struct Expected {
+    data: Option<Vec<u8>>,
+    mode: Option<u32>,
+    mtime: Option<i64>,
+}
+
+/*
+ 結合テスト用に、名前、属性、日時を決めたファイルを一時ディレクトリに作り、
+ zipにして展開した結果が元と同じかどうかを調べるためのもの（zipper-test-support featureで使える）。
+ このクレートを包むクレートのテストからも使える。dropした場合には一時ディレクトリを全て削除する。
+
+     let mut tree = zipper::TestTree::new()?;
+     tree.file("docs/readme.txt", "hello")?.mode("docs/readme.txt", 0o600)?.mtime("docs/readme.txt", 1_600_000_000)?;
+     let zip = tree.zip()?;
+     let out = tree.extract(&zip)?;
+     tree.assert_extracted(&out);
+
+ base:     一時ディレクトリ（treeと、scratchで渡すパスはこの中に置く）
+ root:     ファイルを作るディレクトリ
+ entries:  作ったエントリ（名前順）
+ pending:  まだファイルに設定していない属性や日時があるかどうか
+ */
+#[derive(Debug)]
+pub struct TestTree {
+    base: PathBuf,
+    root: PathBuf,
+    entries: BTreeMap<String, Expected>,
+    pending: bool,
+}
+
+impl TestTree {
+    /*
+     OSの一時ディレクトリの中に、他と重ならない名前（zipper-test-プロセス番号-番号）の空のディレクトリを作る
+     */
+    pub fn new() -> Result<Self, io::Error> {
+        loop {
+            let id = TREE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let base = env::temp_dir().join(format!("zipper-test-{}-{}", process::id(), id));
+            match create_dir(&base) {
+                Ok(()) => {
+                    let root = base.join(TREE_DIR);
+                    create_dir(&root)?;
+                    return Ok(TestTree { base, root, entries: BTreeMap::new(), pending: false });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /*
+     ファイルを作ったディレクトリ
+     */
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /*
+     treeの中のnameのパス
+     */
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /*
+     treeの外にある、作業用のパス（出力するzipや展開先に使う。一時ディレクトリと一緒に削除する）
+     */
+    pub fn scratch(&self, name: &str) -> PathBuf {
+        self.base.join(name)
+    }
+
+    /*
+     nameのファイルをdataの中身で作る。途中のディレクトリも作る。
+     */
+    pub fn file(&mut self, name: &str, data: impl AsRef<[u8]>) -> Result<&mut Self, io::Error> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(&path, data.as_ref())?;
+        self.entries.insert(name.to_string(), Expected { data: Some(data.as_ref().to_vec()), ..Expected::default() });
+        Ok(self)
+    }
+
+    /*
+     nameのディレクトリを作る。途中のディレクトリも作る。
+     zipにはディレクトリのエントリを書き込まないため、展開した結果では空のディレクトリは残らない。
+     */
+    pub fn dir(&mut self, name: &str) -> Result<&mut Self, io::Error> {
+        create_dir_all(self.path(name))?;
+        self.entries.insert(name.trim_end_matches('/').to_string(), Expected::default());
+        Ok(self)
+    }
+
+    /*
+     nameのファイルかディレクトリを、中のものも含めて消す
+     */
+    pub fn remove(&mut self, name: &str) -> Result<&mut Self, io::Error> {
+        let name = name.trim_end_matches('/');
+        let path = self.path(name);
+        if path.is_dir() {
+            remove_dir_all(&path)?;
+        } else {
+            remove_file(&path)?;
+        }
+        let prefix = format!("{}/", name);
+        self.entries.retain(|entry, _| entry != name && !entry.starts_with(&prefix));
+        Ok(self)
+    }
+
+    /*
+     nameの属性をUnixのモード（0o644など）にする。Unix以外では読み取り専用かどうかだけを使う。
+     特別なビット（setuid、setgid、スティッキー）は展開するときに外れるため、展開した結果ではそれ以外のビットだけを調べる。
+     中にファイルを作れなくならないように、設定するのはapplyを呼んだとき（zipにする前）にする。
+     */
+    pub fn mode(&mut self, name: &str, mode: u32) -> Result<&mut Self, io::Error> {
+        self.expected(name)?.mode = Some(mode & 0o7777);
+        self.pending = true;
+        Ok(self)
+    }
+
+    /*
+     nameの最終更新日時をUNIX時間にする。中にファイルを作ると変わるため、設定するのはapplyを呼んだときにする。
+     */
+    pub fn mtime(&mut self, name: &str, time: i64) -> Result<&mut Self, io::Error> {
+        self.expected(name)?.mtime = Some(time);
+        self.pending = true;
+        Ok(self)
+    }
+
+    /*
+     modeとmtimeで指定した属性と日時をファイルに設定する。
+     ディレクトリの中のものから先に設定し、ファイルは日時を設定してから属性を設定する。
+     */
+    pub fn apply(&mut self) -> Result<(), io::Error> {
+        if !self.pending {
+            return Ok(());
+        }
+        for (name, expected) in self.entries.iter().rev() {
+            let path = self.root.join(name);
+            if let Some(time) = expected.mtime {
+                set_modified(&path, SystemTime::UNIX_EPOCH + Duration::from_secs(time.max(0) as u64))?;
+            }
+            if let Some(mode) = expected.mode {
+                Permissions::from_unix_mode(mode).apply(&path)?;
+            }
+        }
+        self.pending = false;
+        Ok(())
+    }
+
+    /*
+     treeの中を全て格納したzipをscratchのtree.zipに作り、そのパスを返す
+     */
+    pub fn zip(&mut self) -> Result<PathBuf, ZipError> {
+        self.zip_with(build())
+    }
+
+    /*
+     オプションを設定したbuilderにtreeの中を全て加えてzipを作る
+     */
+    pub fn zip_with(&mut self, builder: Builder) -> Result<PathBuf, ZipError> {
+        self.apply()?;
+        let output = self.scratch("tree.zip");
+        builder.add_dir(&self.root)?.write_file(&output)?;
+        Ok(output)
+    }
+
+    /*
+     gapバイトの0だけを無圧縮で格納したエントリ（名前はgap）の後に、nameのエントリをdataの中身で書き込んだzipを
+     scratchのsparse.zipに作り、そのパスを返す。4GiBを超える位置のエントリを試すために使う。
+     0の部分はファイルに書かずに穴にする（スパースファイル）ため、時間もディスクもほとんど使わない。
+     gapのエントリのcrc32は計算せずに0にしておくため、そのエントリの中身は読めない。
+     */
+    pub fn sparse_zip(&self, gap: u64, name: &str, data: &[u8]) -> Result<PathBuf, ZipError> {
+        let output = self.scratch("sparse.zip");
+        let mut writer = ArchiveWriter::new(SparseFile { file: File::create(&output)?, pos: 0 });
+        writer.add_raw(Header::new(gap, gap, GAP_NAME, 0, 0, 0x21).with_method(METHOD_STORED), &mut Zeros { left: gap })?;
+        writer.add_entry(&mut &*data, name, (0, 0x21), &EncodeOptions::new(), &EntryOptions::new(), &mut Arena::new())?;
+        let (sparse, _) = writer.finish_into_inner()?;
+        sparse.file.set_len(sparse.pos)?;
+        Ok(output)
+    }
+
+    /*
+     zipをscratchの中の新しいディレクトリ（out-番号）に展開し、そのパスを返す
+     */
+    pub fn extract(&self, zip: &Path) -> Result<PathBuf, ZipError> {
+        let dest = (0..)
+            .map(|number| self.scratch(&format!("out-{}", number)))
+            .find(|dest| !dest.exists())
+            .unwrap_or_else(|| self.scratch("out"));
+        unzip_to(zip, &dest)?;
+        Ok(dest)
+    }
+
+    /*
+     destに展開した結果を、treeに作ったものと比べて違いを返す（空なら同じ）。
+     ファイルの中身と、modeとmtimeで指定した属性と日時を調べ、treeにないファイルがあればそれも違いとする。
+     ディレクトリは、中にエントリがあるものだけが展開されるため、あるかどうかだけを調べる。
+     */
+    pub fn differences(&self, dest: &Path) -> Vec<String> {
+        let mut differences = Vec::new();
+        for (name, expected) in &self.entries {
+            let path = dest.join(name);
+            let data = match &expected.data {
+                Some(data) => data,
+                None => {
+                    if self.has_children(name) && !path.is_dir() {
+                        differences.push(format!("{}/: directory is missing", name));
+                    }
+                    continue;
+                }
+            };
+            let actual = match read(&path) {
+                Ok(actual) => actual,
+                Err(err) => {
+                    differences.push(format!("{}: cannot be read ({})", name, err));
+                    continue;
+                }
+            };
+            if &actual != data {
+                differences.push(format!("{}: contents differ ({} bytes, expected {} bytes)", name, actual.len(), data.len()));
+            }
+            let file_metadata = match metadata(&path) {
+                Ok(file_metadata) => file_metadata,
+                Err(err) => {
+                    differences.push(format!("{}: cannot be read ({})", name, err));
+                    continue;
+                }
+            };
+            if let Some(mode) = expected.mode {
+                let actual = Permissions::from_metadata(&file_metadata);
+                let same = if cfg!(unix) { actual.unix_mode() & 0o777 == mode & 0o777 } else { actual.readonly() == Permissions::from_unix_mode(mode).readonly() };
+                if !same {
+                    differences.push(format!("{}: mode is {:o}, expected {:o}", name, actual.unix_mode() & 0o777, mode & 0o777));
+                }
+            }
+            if let Some(time) = expected.mtime {
+                let actual = file_metadata.modified().ok()
+                    .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map_or(0, |epoch| epoch.as_secs() as i64);
+                if actual.abs_diff(time) > MTIME_TOLERANCE {
+                    differences.push(format!("{}: modified at {}, expected {}", name, actual, time));
+                }
+            }
+        }
+
+        let mut files = Vec::new();
+        list_files(dest, "", &mut files);
+        for name in files.into_iter().filter(|name| !self.entries.contains_key(name)) {
+            differences.push(format!("{}: unexpected file", name));
+        }
+        differences
+    }
+
+    /*
+     destに展開した結果がtreeと同じであることを確かめる。違う場合は違いを全て書いてpanicする。
+     */
+    pub fn assert_extracted(&self, dest: &Path) {
+        let differences = self.differences(dest);
+        if !differences.is_empty() {
+            panic!("{} differs from the test tree:\n  {}", dest.display(), differences.join("\n  "));
+        }
+    }
+
+    /*
+     zipにして展開し、結果がtreeと同じであることを確かめる
+     */
+    pub fn assert_round_trip(&mut self) -> Result<(), ZipError> {
+        let zip = self.zip()?;
+        let dest = self.extract(&zip)?;
+        self.assert_extracted(&dest);
+        Ok(())
+    }
+
+    fn expected(&mut self, name: &str) -> Result<&mut Expected, io::Error> {
+        self.entries.get_mut(name.trim_end_matches('/'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the test tree", name)))
+    }
+
+    fn has_children(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        self.entries.iter().any(|(name, expected)| name.starts_with(&prefix) && expected.data.is_some())
+    }
+}
+
+impl Drop for TestTree {
+    fn drop(&mut self) {
+        make_writable(&self.base);
+        let _ = remove_dir_all(&self.base);
+    }
+}
+
+/*
+ dirの中のファイルの名前を、dirからの相対パス（区切り文字は /）でfilesに追加する
+ */
+fn list_files(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            list_files(&entry.path(), &format!("{}/", name), files);
+        } else {
+            files.push(name);
+        }
+    }
+}
+
+/*
+ 0だけのデータは書き込まずに位置だけを進め、穴にするファイル（sparse_zipで使う）
+ pos:  次に書き込む位置
+ */
+struct SparseFile {
+    file: File,
+    pos: u64,
+}
+
+/*
+ leftバイトの0を読み出すもの（io::repeatより速く、ZEROSからまとめて写す）
+ */
+struct Zeros {
+    left: u64,
+}
+
+impl Read for Zeros {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left.min(ZEROS.len() as u64) as usize);
+        buf[..len].copy_from_slice(&ZEROS[..len]);
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for SparseFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        /*
+         4GiBの穴でも時間がかからないように、1バイトずつではなく0の並びとまとめて比べる
+         */
+        if !buf.chunks(ZEROS.len()).all(|chunk| *chunk == ZEROS[..chunk.len()]) {
+            self.file.seek(SeekFrom::Start(self.pos))?;
+            self.file.write_all(buf)?;
+        }
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/*
+ 削除できるように、path以下の全てのファイルとディレクトリを書き込めるようにする（失敗しても続ける）。
+ 読めないディレクトリもあるため、ディレクトリは中を見る前に設定する。
+ */
+fn make_writable(path: &Path) {
+    let is_dir = match symlink_metadata(path) {
+        Ok(path_metadata) if path_metadata.file_type().is_symlink() => return,
+        Ok(path_metadata) => path_metadata.is_dir(),
+        Err(_) => return,
+    };
+    let attributes = if is_dir { DOS_DIRECTORY } else { 0 };
+    let _ = Permissions::from_dos_attributes(attributes).apply(path);
+    if !is_dir {
+        return;
+    }
+    if let Ok(entries) = read_dir(path) {
+        for entry in entries.flatten() {
+            make_writable(&entry.path());
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::archive::{into_zip_error, restore_dir, set_modified};
+use crate::decode::entry_path;
+use crate::error::{Context, Phase};
+use crate::glob::glob_match;
+use crate::gzip::{is_gzip, GzipReader};
+use crate::tar::{TarKind, TarReader};
+use crate::{DecodeOptions, Permissions, ZipArchive, ZipError};
+
+const SNIFF_LEN: usize = 512;              // 種類を調べるために先頭から読む長さ（tarのヘッダー1つ分）
+const ZIP_MAGIC: &[u8] = b"PK";            // zipのヘッダーのシグネチャの先頭
+const TAR_MAGIC: &[u8] = b"ustar";         // tar（ustar、GNU、pax）のヘッダーのmagic
+const TAR_MAGIC_OFFSET: usize = 257;       // tarのヘッダーの中のmagicの位置
+
+/*
+ extract_anyで展開できるファイルの種類
+ Zip:    zip（先頭が分からないもの、例えば自己解凍形式のものもzipとして開く）
+ Gzip:   1つのファイルを圧縮したgzip
+ Tar:    圧縮していないtar
+ TarGz:  gzipで圧縮したtar（.tar.gzや.tgz）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Gzip,
+    Tar,
+    TarGz,
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Gzip => "gzip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/*
+ pathの先頭のバイト列から種類を調べる（拡張子は見ない）。
+ gzipの場合は、展開した先頭がtarのヘッダーになっているかどうかでTarGzかGzipかを決める。
+ */
+pub fn detect_format(path: impl AsRef<Path>) -> Result<ArchiveFormat, ZipError> {
+    let path = path.as_ref();
+    let path_name = path.display().to_string();
+    let head = read_head(File::open(path).context(&path_name, Phase::Open)?).context(&path_name, Phase::Read)?;
+    if head.starts_with(ZIP_MAGIC) {
+        return Ok(ArchiveFormat::Zip);
+    }
+    if is_gzip(&head) {
+        let gzip = GzipReader::new(BufReader::new(File::open(path).context(&path_name, Phase::Open)?))?;
+        let inner = read_head(gzip).context(&path_name, Phase::Read)?;
+        return Ok(if is_tar(&inner) { ArchiveFormat::TarGz } else { ArchiveFormat::Gzip });
+    }
+    if is_tar(&head) {
+        return Ok(ArchiveFormat::Tar);
+    }
+    Ok(ArchiveFormat::Zip)
+}
+
+/*
+ pathの種類をdetect_formatで調べ、destに展開する。展開したエントリの数を返す。
+ patternsを指定した場合は、extract_matchingと同じく名前が当てはまるものだけを展開する（gzipでは元のファイル名と比べる）。
+ zipではoptionsを全て使い、tarとgzipではallow_unsafe_pathsだけを使う。
+ tarのシンボリックリンクなど、ファイルとディレクトリ以外のエントリは展開しない。
+ gzipはヘッダーに書かれた元のファイル名で、ない場合はpathから .gz を除いた名前で展開する。
+ */
+pub fn extract_any(path: impl AsRef<Path>, dest: impl AsRef<Path>, patterns: &[String], options: &DecodeOptions) -> Result<usize, ZipError> {
+    let (path, dest) = (path.as_ref(), dest.as_ref());
+    let path_name = path.display().to_string();
+    let open = || File::open(path).map(BufReader::new).context(&path_name, Phase::Open);
+    match detect_format(path)? {
+        ArchiveFormat::Zip => {
+            let mut archive = ZipArchive::open_with(path, options)?;
+            let report = if patterns.is_empty() { archive.extract_all_with(dest, options)? } else { archive.extract_matching_with(dest, patterns, options)? };
+            Ok(report.entries.len())
+        }
+        ArchiveFormat::Gzip => extract_gzip(GzipReader::new(open()?)?, path, dest, patterns, options),
+        ArchiveFormat::Tar => extract_tar(TarReader::new(open()?), dest, patterns, options),
+        ArchiveFormat::TarGz => extract_tar(TarReader::new(GzipReader::new(open()?)?), dest, patterns, options),
+    }
+}
+
+/*
+ tarのファイルとディレクトリを展開し、最終更新日時と属性を設定する。
+ ディレクトリは中に書き込み終わってから、深いものから順に日時と属性を設定する。
+ */
+fn extract_tar<R: Read>(mut tar: TarReader<R>, dest: &Path, patterns: &[String], options: &DecodeOptions) -> Result<usize, ZipError> {
+    let mut count = 0;
+    let mut dirs: Vec<(PathBuf, Permissions, SystemTime)> = Vec::new();
+    while let Some(entry) = tar.next_entry().map_err(into_zip_error)? {
+        let name = entry.name.trim_end_matches('/');
+        if name.is_empty() || entry.kind == TarKind::Other || !matches(patterns, name) {
+            continue;
+        }
+        let path = entry_path(dest, name, options.allow_unsafe_paths)?;
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.mtime.max(0) as u64);
+        let permissions = Permissions::from_unix_mode(entry.mode & 0o7777);
+        if entry.kind == TarKind::Directory {
+            create_dir_all(&path).context(name, Phase::Extract)?;
+            dirs.push((path, permissions, modified));
+        } else {
+            write_file(&mut tar, &path).context(name, Phase::Extract)?;
+            set_modified(&path, modified).and_then(|_| permissions.restore(&path, options.special_bits)).context(name, Phase::Extract)?;
+        }
+        count += 1;
+    }
+    dirs.sort_by(|(a, ..), (b, ..)| b.cmp(a));
+    for (path, permissions, modified) in dirs {
+        restore_dir(&path, Some(permissions), Some(modified), options.special_bits).context(&path.display().to_string(), Phase::Extract)?;
+    }
+    Ok(count)
+}
+
+/*
+ gzipを展開して1つのファイルにする
+ */
+fn extract_gzip<R: Read>(mut gzip: GzipReader<R>, path: &Path, dest: &Path, patterns: &[String], options: &DecodeOptions) -> Result<usize, ZipError> {
+    let name = match gzip.name().and_then(|name| name.rsplit(['/', '\\']).next()).filter(|name| !name.is_empty()) {
+        Some(name) => name.to_string(),
+        None => {
+            let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            file_name.strip_suffix(".gz").unwrap_or(&file_name).to_string()
+        }
+    };
+    if !matches(patterns, &name) {
+        return Ok(0);
+    }
+    let output = entry_path(dest, &name, options.allow_unsafe_paths)?;
+    write_file(&mut gzip, &output).context(&name, Phase::Extract)?;
+    if let Some(mtime) = gzip.mtime() {
+        set_modified(&output, SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64)).context(&name, Phase::Extract)?;
+    }
+    Ok(1)
+}
+
+/*
+ readerの残りを全てpathに書き込む。途中のディレクトリも作る。
+ readerが返したエラーの中にZipError（crc32の食い違いなど）がある場合は、それを取り出して返す。
+ */
+fn write_file<R: Read>(reader: &mut R, path: &Path) -> Result<(), ZipError> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    io::copy(reader, &mut File::create(path)?).map_err(into_zip_error)?;
+    Ok(())
+}
+
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, name))
+}
+
+fn is_tar(head: &[u8]) -> bool {
+    head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+}
+
+/*
+ readerの先頭からSNIFF_LENバイトまでを読む（短いファイルではその長さだけ）
+ */
+fn read_head<R: Read>(reader: R) -> Result<Vec<u8>, io::Error> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
@@ -0,0 +1,177 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read, Seek, SeekFrom, Write};
+
+use crate::archive::{central_directory, find_end, read16, read32, read_descriptor, CENTRAL_SIGNATURE, LOCAL_HEADER_SIZE, LOCAL_SIGNATURE};
+use crate::error::{Context, Phase};
+use crate::extra::padding_alignment;
+use crate::{zip_size, ZipError};
+
+const CENTRAL_HEADER_SIZE: usize = 46;      // セントラルヘッダーの固定長の部分
+const END_HEADER_SIZE: usize = 22;          // エンドセントラルヘッダーの固定長の部分（コメントを除く）
+const MAX_TAIL_LEN: u64 = 22 + 0xffff;      // エンドセントラルヘッダーとコメントが入りうる最大の長さ
+const COPY_BUFFER_SIZE: usize = 64 * 1024;  // データを前に詰めるときに1度に写す大きさ
+const ZIP64_MARK: u64 = 0xffff_ffff;        // 値がZIP64の拡張情報に書かれていることを示す値
+
+/*
+ セントラルヘッダーに書かれた1つのエントリの、詰めるのに必要な情報
+ central_pos:      セントラルヘッダーの中でのこのエントリの位置（新しい位置を書き込む）
+ offset:           ローカルヘッダーの位置
+ compressed_size:  圧縮した後のサイズ
+ descriptor:       データディスクリプタを使っているかどうか（汎用フラグのbit3）
+ */
+struct Slot {
+    central_pos: usize,
+    offset: u64,
+    compressed_size: u64,
+    descriptor: bool,
+}
+
+/*
+ pathのzipの中で、どのエントリにも使われていない部分を取り除いて、ファイルを切り詰める。
+ エントリのローカルヘッダーとデータは圧縮し直さずにそのまま前に詰め、セントラルヘッダーの位置を書き換える。
+ 位置を揃える詰め物（Placement::Align）を持つエントリは、揃える単位の倍数だけ動かして揃え方を保つ。
+ 位置を指定した詰め物（Placement::Offset）を持つエントリは動かさない。
+ 先頭に付けられたデータ（自己解凍形式のプログラムなど）もエントリに使われていない部分として取り除く。
+ ファイルを直接書き換えるため、途中で止まった場合にはzipが壊れることがある。取り除いたバイト数を返す。
+ ZIP64のzipは書き換えられないためUnsupportedにする。
+ */
+pub fn vacuum(path: &str) -> Result<u64, ZipError> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path).context(path, Phase::Open)?;
+    let len = file.seek(SeekFrom::End(0)).context(path, Phase::Read)?;
+    let tail_start = len.saturating_sub(MAX_TAIL_LEN);
+    let mut tail = vec![0; (len - tail_start) as usize];
+    read_at(&mut file, tail_start, &mut tail).context_at(path, Phase::Read, tail_start)?;
+    let end = find_end(&tail).ok_or_else(|| ZipError::InvalidArchive("end of central directory record is missing".to_string()))?;
+    let mut end_header = tail.get(end..).unwrap_or_default().to_vec();
+    if end_header.len() < END_HEADER_SIZE {
+        return Err(ZipError::InvalidArchive("end of central directory record is truncated".to_string()));
+    }
+
+    let directory = central_directory(&tail, end, tail_start + end as u64, len, |pos, buf| read_at(&mut file, pos, buf).context_at(path, Phase::Read, pos))?;
+    if directory.zip64 {
+        return Err(ZipError::Unsupported(format!("vacuum of zip64 archive {}", path)));
+    }
+    if directory.disk > 0 {
+        return Err(ZipError::Unsupported(format!("vacuum of split archive {}", path)));
+    }
+    let mut central = vec![0; directory.size];
+    read_at(&mut file, directory.start, &mut central).context_at(path, Phase::Read, directory.start)?;
+    let mut slots = central_slots(&central, directory.count, directory.base)?;
+    slots.sort_by_key(|slot| slot.offset);
+
+    /*
+     壊れたエントリが途中で見つかったときにファイルを書き換え始めていないように、先に全てのエントリの移動先を決めてから写す
+     */
+    let mut moves = Vec::with_capacity(slots.len());
+    let mut write_pos = 0;
+    for slot in &slots {
+        let (new_offset, record_len) = plan(&mut file, slot, write_pos).context_at(path, Phase::Read, slot.offset)?;
+        if let Some(field) = central.get_mut(slot.central_pos + 42..slot.central_pos + 46) {
+            field.copy_from_slice(&zip_size(new_offset).context(path, Phase::Write)?.to_le_bytes());
+        }
+        moves.push((slot.offset, new_offset, record_len));
+        write_pos = new_offset + record_len;
+    }
+    for &(offset, new_offset, record_len) in &moves {
+        if new_offset != offset {
+            copy_within(&mut file, offset, new_offset, record_len).context_at(path, Phase::Write, offset)?;
+        }
+    }
+
+    if let Some(field) = end_header.get_mut(16..20) {
+        field.copy_from_slice(&zip_size(write_pos).context(path, Phase::Write)?.to_le_bytes());
+    }
+    let new_len = write_pos + central.len() as u64 + end_header.len() as u64;
+    file.seek(SeekFrom::Start(write_pos))
+        .and_then(|_| file.write_all(&central))
+        .and_then(|_| file.write_all(&end_header))
+        .and_then(|_| file.set_len(new_len))
+        .and_then(|_| file.sync_all())
+        .context_at(path, Phase::Write, write_pos)?;
+    Ok(len - new_len)
+}
+
+/*
+ セントラルヘッダーを順に読み、それぞれのエントリのローカルヘッダーの位置などを返す
+ 位置にはbaseを足し、ファイルの先頭からの位置にする。
+ */
+fn central_slots(central: &[u8], count: usize, base: u64) -> Result<Vec<Slot>, ZipError> {
+    let mut slots = Vec::with_capacity(count);
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + CENTRAL_HEADER_SIZE > central.len() || read32(central, pos) != CENTRAL_SIGNATURE {
+            return Err(ZipError::InvalidArchive(format!("invalid central header at {}", pos)));
+        }
+        let offset = read32(central, pos + 42) as u64;
+        let compressed_size = read32(central, pos + 20) as u64;
+        if offset == ZIP64_MARK || compressed_size == ZIP64_MARK {
+            return Err(ZipError::Unsupported("vacuum of entries with zip64 extended information".to_string()));
+        }
+        slots.push(Slot {
+            central_pos: pos,
+            offset: offset + base,
+            compressed_size,
+            descriptor: read16(central, pos + 8) & 0x0008 != 0,
+        });
+        pos += CENTRAL_HEADER_SIZE + read16(central, pos + 28) as usize + read16(central, pos + 30) as usize + read16(central, pos + 32) as usize;
+    }
+    Ok(slots)
+}
+
+/*
+ slotのエントリ（ローカルヘッダー、データ、データディスクリプタ）を、write_pos以降のどこまで前に詰められるかを決める。
+ 新しいローカルヘッダーの位置と、エントリ全体の長さを返す。
+ */
+fn plan(file: &mut File, slot: &Slot, write_pos: u64) -> Result<(u64, u64), Error> {
+    if slot.offset < write_pos {
+        return Err(Error::other(format!("entry at {} overlaps the previous entry", slot.offset)));
+    }
+    let mut local = [0u8; LOCAL_HEADER_SIZE];
+    read_at(file, slot.offset, &mut local)?;
+    if read32(&local, 0) != LOCAL_SIGNATURE {
+        return Err(Error::other(format!("invalid local header at {}", slot.offset)));
+    }
+    let name_len = read16(&local, 26) as u64;
+    let mut extra = vec![0; read16(&local, 28) as usize];
+    read_at(file, slot.offset + LOCAL_HEADER_SIZE as u64 + name_len, &mut extra)?;
+    let data_end = slot.offset + LOCAL_HEADER_SIZE as u64 + name_len + extra.len() as u64 + slot.compressed_size;
+    let descriptor_len = if slot.descriptor {
+        let mut descriptor = [0u8; 16];
+        read_at(file, data_end, &mut descriptor)?;
+        read_descriptor(&descriptor, 0, Some(slot.compressed_size), false).map_or(0, |descriptor| descriptor.len as u64)
+    } else {
+        0
+    };
+    let record_len = data_end + descriptor_len - slot.offset;
+
+    /*
+     位置を揃える詰め物がある場合は揃える単位の倍数だけ動かし、位置を指定した詰め物がある場合は動かさない
+     */
+    let new_offset = match padding_alignment(&extra) {
+        Some(0) => slot.offset,
+        Some(alignment) => write_pos + (slot.offset - write_pos) % alignment as u64,
+        None => write_pos,
+    };
+    Ok((new_offset, record_len))
+}
+
+/*
+ fileのfromからlenバイトをtoに写す。toはfromより前なので、前から順に写せば写す前のデータを上書きしない。
+ */
+fn copy_within(file: &mut File, from: u64, to: u64, len: u64) -> Result<(), Error> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut done = 0;
+    while done < len {
+        let chunk = buffer.get_mut(..(len - done).min(COPY_BUFFER_SIZE as u64) as usize).unwrap_or_default();
+        read_at(file, from + done, chunk)?;
+        file.seek(SeekFrom::Start(to + done))?;
+        file.write_all(chunk)?;
+        done += chunk.len() as u64;
+    }
+    Ok(())
+}
+
+fn read_at(file: &mut File, pos: u64, buf: &mut [u8]) -> Result<(), Error> {
+    file.seek(SeekFrom::Start(pos))?;
+    file.read_exact(buf)
+}
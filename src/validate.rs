@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::metadata;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::clock::civil_time;
+use crate::decode::entry_path;
+use crate::error::{Context, Phase};
+use crate::extra::encode_extra_fields;
+use crate::input::FileEntry;
+use crate::{Clock, EncodeOptions, ZipError};
+
+const MAX_NAME_LEN: usize = u16::MAX as usize;  // ヘッダーに書ける名前の最大のバイト数
+const MAX_DOS_YEAR: i64 = 2107;                 // zipの日時で表せる最後の年（1980年から127年）
+
+/*
+ 書き込む前に見つかった、エントリの問題
+ name:  エントリの名前（エントリの数のようにzip全体の問題の場合は空）
+ kind:  問題の種類
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub name: String,
+    pub kind: ViolationKind,
+}
+
+/*
+ エントリの問題の種類
+ EmptyName:            名前が空
+ UnsafeName:           展開先の外を指す名前（..や絶対パス、ドライブ文字）
+ DuplicateName:        同じ名前のエントリが既にある
+ NameTooLong:          名前が65535バイトを超える
+ UnrepresentableName:  名前に、name_encoderの文字コード（中身の名前）で表せない文字がある
+ TooLarge:             ファイルがmax_entry_sizeを超える
+ TimestampOutOfRange:  最終更新日時がzipの日時で表せる2107年より後（中身はその年）
+ InvalidExtraField:    拡張フィールドが書き込めない（中身はその理由）
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    EmptyName,
+    UnsafeName,
+    DuplicateName,
+    NameTooLong(usize),
+    UnrepresentableName(String),
+    TooLarge { size: u64, limit: u64 },
+    TimestampOutOfRange(i64),
+    InvalidExtraField(String),
+}
+
+impl Violation {
+    /*
+     問題を直すための方法
+     */
+    pub fn suggestion(&self) -> &'static str {
+        match self.kind {
+            ViolationKind::EmptyName => "give the entry a name",
+            ViolationKind::UnsafeName => "use a relative name without '..' or a drive letter",
+            ViolationKind::DuplicateName => "remove one of the entries or change its prefix",
+            ViolationKind::NameTooLong(_) => "shorten the name or its directories",
+            ViolationKind::UnrepresentableName(_) => "rename the file or write names as UTF-8",
+            ViolationKind::TooLarge { .. } => "split the file or raise max_entry_size",
+            ViolationKind::TimestampOutOfRange(_) => "fix the modification time of the file or use reproducible mode",
+            ViolationKind::InvalidExtraField(_) => "remove or shrink the extra fields of the entry",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.name.is_empty() {
+            write!(f, "{}: ", self.name)?;
+        }
+        match &self.kind {
+            ViolationKind::EmptyName => write!(f, "name is empty")?,
+            ViolationKind::UnsafeName => write!(f, "name points outside of the output directory")?,
+            ViolationKind::DuplicateName => write!(f, "name is used by another entry")?,
+            ViolationKind::NameTooLong(len) => write!(f, "name is {} bytes, more than the limit of 65535 bytes", len)?,
+            ViolationKind::UnrepresentableName(encoding) => write!(f, "name cannot be represented in {}", encoding)?,
+            ViolationKind::TooLarge { size, limit } => write!(f, "{} bytes is larger than the limit of {} bytes", size, limit)?,
+            ViolationKind::TimestampOutOfRange(year) => write!(f, "modification time in {} cannot be stored after 2107", year)?,
+            ViolationKind::InvalidExtraField(reason) => write!(f, "{}", reason)?,
+        }
+        write!(f, " (fix: {})", self.suggestion())
+    }
+}
+
+/*
+ filesを書き込む前に全て調べ、見つかった問題をまとめて返す（空なら問題はない）。
+ データは読まず、名前とファイルのメタデータだけを調べる。
+ fixed_time:  指定された場合には全てのエントリの日時をこれにする（ファイルの日時は調べない）
+ エントリの数は、65535個を超える場合もZIP64で書き込めるため調べない。
+ */
+pub(crate) fn validate_files(files: &[FileEntry], options: &EncodeOptions, fixed_time: Option<i64>) -> Result<Vec<Violation>, ZipError> {
+    let mut violations = Vec::new();
+    let mut violate = |name: &str, kind: ViolationKind| violations.push(Violation { name: name.to_string(), kind });
+    let mut names = HashSet::with_capacity(files.len());
+    for (path, name, entry) in files {
+        if name.is_empty() {
+            violate(name, ViolationKind::EmptyName);
+        } else if entry_path(Path::new(""), name, false).is_err() {
+            violate(name, ViolationKind::UnsafeName);
+        }
+        if !names.insert(name.as_str()) {
+            violate(name, ViolationKind::DuplicateName);
+        }
+        match options.name_encoder.encode(name) {
+            Some(bytes) if bytes.len() > MAX_NAME_LEN => violate(name, ViolationKind::NameTooLong(bytes.len())),
+            Some(_) => {}
+            None => violate(name, ViolationKind::UnrepresentableName(options.name_encoder.label().to_string())),
+        }
+        if let Err(err) = encode_extra_fields(name, &entry.extra_fields) {
+            violate(name, ViolationKind::InvalidExtraField(err.to_string()));
+        }
+
+        let file_metadata = metadata(path).context(&path.display().to_string(), Phase::Open)?;
+        if let Some(limit) = options.max_entry_size.filter(|&limit| file_metadata.len() > limit) {
+            violate(name, ViolationKind::TooLarge { size: file_metadata.len(), limit });
+        }
+        let times = fixed_time.unwrap_or_else(|| modified_time(file_metadata.modified().ok(), &*options.clock));
+        let (year, ..) = civil_time(times + options.clock.offset(times));
+        if year > MAX_DOS_YEAR {
+            violate(name, ViolationKind::TimestampOutOfRange(year));
+        }
+    }
+
+    Ok(violations)
+}
+
+/*
+ ファイルの最終更新日時（UNIX時間）。取れない場合はclockの現在時刻にする（time_dataと同じ）。
+ */
+fn modified_time(modified: Option<SystemTime>, clock: &dyn Clock) -> i64 {
+    match modified {
+        Some(time) => time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |epoch| epoch.as_secs() as i64),
+        None => clock.now(),
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::writer::ArchiveWriter;
+use crate::{dos_time, encode_entry, Arena, EncodeOptions, EntryOptions, UtcClock, ZipError};
+
+/*
+ テスト用のzipを作成した結果
+ Written:  作成できた
+ Skipped:  まだこのクレートが対応していないため作成しなかった（理由を保持する）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorStatus {
+    Written,
+    Skipped(&'static str),
+}
+
+/*
+ file_name:    作成したzipのファイル名
+ description:  どういうzipなのかの説明
+ status:       作成できたかどうか
+ */
+#[derive(Debug, Clone)]
+pub struct VectorResult {
+    pub file_name: &'static str,
+    pub description: &'static str,
+    pub status: VectorStatus,
+}
+
+/*
+ 作成するzipの内容
+ file_name:    作成するzipのファイル名
+ description:  どういうzipなのかの説明
+ name:         zipの中でのエントリの名前
+ data:         エントリの中身
+ time:         最終更新日時（UNIX時間）
+ */
+struct Vector {
+    file_name: &'static str,
+    description: &'static str,
+    name: String,
+    data: &'static [u8],
+    time: i64,
+}
+
+/*
+ 結果が変わらないように固定した日時（2000-01-01 00:00:00 UTC）
+ タイムゾーンによって結果が変わらないように、UtcClockで変換している。
+ */
+const FIXED_TIME: i64 = 946_684_800;
+
+/*
+ ファイル名の長さとして格納できる最大の長さ
+ */
+const MAX_NAME_LEN: usize = 0xffff;
+
+/*
+ エンドセントラルヘッダーに書けるエントリの数の上限（この数からZIP64のエンドセントラルヘッダーを書く）
+ */
+const MAX_ENTRIES: usize = 0xffff;
+
+fn vectors() -> Vec<Vector> {
+    vec![
+        Vector {
+            file_name: "empty_file.zip",
+            description: "0-byte entry",
+            name: "empty.txt".to_string(),
+            data: b"",
+            time: FIXED_TIME,
+        },
+        Vector {
+            file_name: "empty_name.zip",
+            description: "entry with a 0-byte name",
+            name: String::new(),
+            data: b"entry without a name\n",
+            time: FIXED_TIME,
+        },
+        Vector {
+            file_name: "long_name.zip",
+            description: "entry name of 65535 bytes (the u16 limit)",
+            name: "a".repeat(MAX_NAME_LEN),
+            data: b"entry with a very long name\n",
+            time: FIXED_TIME,
+        },
+        Vector {
+            file_name: "utf8_name.zip",
+            description: "UTF-8 entry name with general purpose bit 11 set",
+            name: "日本語のファイル名.txt".to_string(),
+            data: b"utf-8 name\n",
+            time: FIXED_TIME,
+        },
+        Vector {
+            file_name: "pre_1980.zip",
+            description: "mtime before 1980 (clamped to 1980-01-01 00:00:00)",
+            name: "old.txt".to_string(),
+            data: b"older than the DOS epoch\n",
+            time: 0,
+        },
+    ]
+}
+
+/*
+ まだ作成できないもの
+ */
+const SKIPPED: [(&str, &str, &str); 1] = [
+    ("max_size.zip", "entry of exactly 4 GiB - 1 bytes",
+     "the builtin encoder is too slow for 4 GiB inputs"),
+];
+
+/*
+ 境界となるような条件のzipをdirの中に作成する。
+ このクレートや、zipを読み込む他のツールの確認に使う。
+ */
+pub fn gen_vectors(dir: &Path) -> Result<Vec<VectorResult>, ZipError> {
+    create_dir_all(dir)?;
+    let options = EncodeOptions::new();
+    let mut arena = Arena::new();
+    let mut results = Vec::new();
+
+    for vector in vectors() {
+        let mut output = File::create(dir.join(vector.file_name))?;
+        let mut data = vector.data;
+        encode_entry(&mut data, &mut output, &vector.name, dos_time(vector.time, &UtcClock), &options, &EntryOptions::default(), &mut arena)?;
+        results.push(VectorResult {
+            file_name: vector.file_name,
+            description: vector.description,
+            status: VectorStatus::Written,
+        });
+    }
+    write_max_entries(&dir.join("max_entries.zip"), &options, &mut arena)?;
+    results.push(VectorResult {
+        file_name: "max_entries.zip",
+        description: "archive with 65535 entries (count in the zip64 end of central directory record)",
+        status: VectorStatus::Written,
+    });
+    for (file_name, description, reason) in SKIPPED.iter() {
+        results.push(VectorResult {
+            file_name,
+            description,
+            status: VectorStatus::Skipped(reason),
+        });
+    }
+    Ok(results)
+}
+
+/*
+ 空のエントリをMAX_ENTRIES個格納したzipをpathに作成する
+ */
+fn write_max_entries(path: &Path, options: &EncodeOptions, arena: &mut Arena) -> Result<(), ZipError> {
+    let mut writer = ArchiveWriter::new(BufWriter::new(File::create(path)?));
+    let time = dos_time(FIXED_TIME, &UtcClock);
+    for index in 0..MAX_ENTRIES {
+        writer.add_entry(&mut &b""[..], &format!("{:05}.txt", index), time, options, &EntryOptions::default(), arena)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
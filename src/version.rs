@@ -0,0 +1,22 @@
+/*
+ ビルドしたときの情報
+ アーカイブが展開できないといった報告を受けたときに、どのビルドで作ったものかを分かるようにする。
+ */
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const GIT_HASH: &str = env!("ZIPPER_GIT_HASH");
+const FEATURES: &str = env!("ZIPPER_FEATURES");
+const TARGET: &str = env!("ZIPPER_TARGET");
+
+/*
+ --version --verbose で表示する内容を返す
+ */
+pub fn version_info() -> String {
+    format!(
+        "zipper {}\ncommit:   {}\nfeatures: {}\ntarget:   {}\nprofile:  {}\n",
+        VERSION,
+        GIT_HASH,
+        if FEATURES.is_empty() { "(none)" } else { FEATURES },
+        TARGET,
+        if cfg!(debug_assertions) { "debug" } else { "release" },
+    )
+}
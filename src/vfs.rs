@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::SystemTime;
+
+use crate::{Permissions, ZipArchive, ZipError};
+
+/*
+ ゲームエンジンや静的サイトのサーバーなどが、ファイルを読むのに使う簡単な仮想ファイルシステム
+ パスは / 区切りで、先頭と末尾の / は無視する（空のパスは一番上のディレクトリ）。
+ open:      pathのファイルの中身を読み出すReadを返す
+ read:      pathのファイルの中身を全て読み込む
+ read_dir:  pathのディレクトリの中にあるものを名前順に返す
+ stat:      pathがディレクトリかどうかと、サイズ、最終更新日時、属性を返す
+ 見つからない場合はNotFound、ファイルとディレクトリを取り違えた場合はNotAFileかNotADirectoryにする。
+ */
+pub trait Vfs {
+    fn open(&mut self, path: &str) -> Result<Box<dyn Read + '_>, ZipError>;
+
+    fn read(&mut self, path: &str) -> Result<Vec<u8>, ZipError> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<VfsEntry>, ZipError>;
+
+    fn stat(&self, path: &str) -> Result<VfsMetadata, ZipError>;
+}
+
+/*
+ Vfs::statで返す情報
+ is_dir:       ディレクトリかどうか
+ size:         ファイルのサイズ（ディレクトリは0）
+ modified:     最終更新日時（分からない場合はNone）
+ permissions:  属性（zipにディレクトリのエントリがなく、名前から分かっただけのディレクトリではNone）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsMetadata {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub permissions: Option<Permissions>,
+}
+
+/*
+ Vfs::read_dirで返す、ディレクトリの中の1つ
+ name:      ディレクトリの中での名前（/ を含まない）
+ metadata:  statで返すものと同じ情報
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsEntry {
+    pub name: String,
+    pub metadata: VfsMetadata,
+}
+
+/*
+ ZipVfsの中の1つのパス
+ File:  index番目のエントリ
+ Dir:   ディレクトリ（ディレクトリのエントリがあればその位置。ファイルの名前から分かっただけの場合はNone）
+ */
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    File(usize),
+    Dir(Option<usize>),
+}
+
+/*
+ 開いたZipArchiveを、展開せずにVfsとして読むためのもの
+ 作るときにセントラルヘッダーの名前からディレクトリの木を作り、ファイルを開いたときだけそのデータを読む。
+ zipにはディレクトリのエントリがないことも多いため、ファイルの名前の途中にあるディレクトリも作る。
+ 同じ名前のエントリが複数ある場合は、ZipArchive::by_nameと同じく最初のものを使う。
+
+     let mut vfs = zipper::ZipVfs::new(zipper::ZipArchive::open("assets.zip")?);
+     for entry in vfs.read_dir("textures")? { ... }
+     let data = vfs.read("textures/grass.png")?;
+
+ from_sliceで開いたZipArchiveも使え、その場合は'dがデータを借りている間になる。
+
+ archive:  読んでいるzip
+ nodes:    パス（先頭と末尾の / を除いたもの）から、ファイルかディレクトリかを引く表（名前順）
+ */
+#[derive(Debug)]
+pub struct ZipVfs<'d> {
+    archive: ZipArchive<'d>,
+    nodes: BTreeMap<String, Node>,
+}
+
+impl<'d> ZipVfs<'d> {
+    pub fn new(archive: ZipArchive<'d>) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(String::new(), Node::Dir(None));
+        for (index, entry) in archive.iter().enumerate() {
+            let path = normalize(&entry.name);
+            if path.is_empty() {
+                continue;
+            }
+            for (end, _) in path.match_indices('/') {
+                nodes.entry(path.get(..end).unwrap_or_default().to_string()).or_insert(Node::Dir(None));
+            }
+            let node = if entry.is_dir() { Node::Dir(Some(index)) } else { Node::File(index) };
+            match nodes.get_mut(path) {
+                Some(existing @ Node::Dir(None)) if entry.is_dir() => *existing = node,
+                Some(_) => {}
+                None => {
+                    nodes.insert(path.to_string(), node);
+                }
+            }
+        }
+        ZipVfs { archive, nodes }
+    }
+
+    /*
+     読んでいるzip（パスワードを設定する場合などに使う）
+     */
+    pub fn archive(&mut self) -> &mut ZipArchive<'d> {
+        &mut self.archive
+    }
+
+    pub fn into_archive(self) -> ZipArchive<'d> {
+        self.archive
+    }
+
+    /*
+     pathのファイルがzipの何番目のエントリかを返す
+     */
+    pub fn index_of(&self, path: &str) -> Result<usize, ZipError> {
+        match self.node(path)? {
+            Node::File(index) => Ok(index),
+            Node::Dir(_) => Err(ZipError::NotAFile(path.to_string())),
+        }
+    }
+
+    fn node(&self, path: &str) -> Result<Node, ZipError> {
+        self.nodes.get(normalize(path)).copied().ok_or_else(|| ZipError::NotFound(path.to_string()))
+    }
+
+    fn metadata(&self, node: Node) -> VfsMetadata {
+        let (is_dir, index) = match node {
+            Node::File(index) => (false, Some(index)),
+            Node::Dir(index) => (true, index),
+        };
+        let entry = index.and_then(|index| self.archive.by_index(index));
+        VfsMetadata {
+            is_dir,
+            size: if is_dir { 0 } else { entry.map_or(0, |entry| entry.size) },
+            modified: entry.and_then(|entry| entry.modified_time()),
+            permissions: entry.map(|entry| entry.permissions),
+        }
+    }
+}
+
+impl Vfs for ZipVfs<'_> {
+    fn open(&mut self, path: &str) -> Result<Box<dyn Read + '_>, ZipError> {
+        let index = self.index_of(path)?;
+        Ok(Box::new(self.archive.open_entry(index)?))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<VfsEntry>, ZipError> {
+        if let Node::File(_) = self.node(path)? {
+            return Err(ZipError::NotADirectory(path.to_string()));
+        }
+        let dir = normalize(path);
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let entries = self.nodes.range(prefix.clone()..)
+            .take_while(|(child, _)| child.starts_with(&prefix))
+            .filter_map(|(child, node)| {
+                let name = child.get(prefix.len()..).unwrap_or_default();
+                if name.is_empty() || name.contains('/') {
+                    return None;
+                }
+                Some(VfsEntry { name: name.to_string(), metadata: self.metadata(*node) })
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> Result<VfsMetadata, ZipError> {
+        Ok(self.metadata(self.node(path)?))
+    }
+}
+
+/*
+ パスの先頭と末尾の / を取り除く
+ */
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
@@ -0,0 +1,105 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Context, Phase};
+use crate::ZipError;
+
+/*
+ 分割されたzip（archive.z01, archive.z02, …, archive.zip）を、順につなげた1つのファイルとして読むためのもの
+ 分割されていないzipでは、そのファイルだけを持つ。
+ ヘッダーに書かれた位置はそれぞれのファイルの先頭からのため、disk_startで足す位置を求める。
+ files:   ディスクの番号の順に並べたファイル（最後がエンドセントラルヘッダーのある.zip）
+ starts:  それぞれのファイルがつなげたものの中で始まる位置
+ len:     全体の長さ
+ pos:     次に読む位置
+ */
+#[derive(Debug)]
+pub(crate) struct Volumes {
+    files: Vec<File>,
+    starts: Vec<u64>,
+    len: u64,
+    pos: u64,
+}
+
+impl Volumes {
+    /*
+     pathのzipを開く。拡張子が.zipで、同じ名前の.z01があれば、.z02、.z03と続くものを全て前に付ける。
+     */
+    pub(crate) fn open(path: &Path) -> Result<Self, ZipError> {
+        let mut paths = volume_paths(path);
+        paths.push(path.to_path_buf());
+        let mut files = Vec::with_capacity(paths.len());
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut len: u64 = 0;
+        for path in paths {
+            let path_name = path.display().to_string();
+            let file = File::open(&path).context(&path_name, Phase::Open)?;
+            let file_len = file.metadata().context(&path_name, Phase::Open)?.len();
+            starts.push(len);
+            files.push(file);
+            len = len.saturating_add(file_len);
+        }
+        Ok(Volumes { files, starts, len, pos: 0 })
+    }
+
+    /*
+     ファイルの数（分割されていない場合は1）
+     */
+    pub(crate) fn count(&self) -> usize {
+        self.files.len()
+    }
+
+    /*
+     disk番目のファイルがつなげたものの中で始まる位置（そのファイルがない場合はNone）
+     */
+    pub(crate) fn disk_start(&self, disk: u32) -> Option<u64> {
+        self.starts.get(disk as usize).copied()
+    }
+}
+
+impl Read for Volumes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let disk = self.starts.partition_point(|&start| start <= self.pos).saturating_sub(1);
+        let start = self.disk_start(disk as u32).unwrap_or_default();
+        let end = self.disk_start(disk as u32 + 1).unwrap_or(self.len);
+        let want = buf.len().min(usize::try_from(end - self.pos).unwrap_or(usize::MAX));
+        let file = self.files.get_mut(disk).ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        file.seek(SeekFrom::Start(self.pos - start))?;
+        let len = file.read(buf.get_mut(..want).unwrap_or_default())?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Volumes {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(self.pos)
+    }
+}
+
+/*
+ pathの前に付く分割されたファイル（.z01から、続いているところまで）のパス
+ 拡張子が.ZIPの場合は.Z01のように大文字で探す。
+ */
+fn volume_paths(path: &Path) -> Vec<PathBuf> {
+    let prefix = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("zip") => "z",
+        Some("ZIP") => "Z",
+        _ => return Vec::new(),
+    };
+    (1..)
+        .map(|number| path.with_extension(format!("{}{:02}", prefix, number)))
+        .take_while(|volume| volume.is_file())
+        .collect()
+}
@@ -0,0 +1,208 @@
+use std::env;
+use std::fs::{copy, remove_file, rename, symlink_metadata, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ZipError;
+
+/*
+ 一時ファイルの名前が他と重ならないように付ける番号
+ */
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/*
+ 作業用の一時ファイル
+ persistせずにdropした場合（エラーやpanicの場合）には削除する。
+ シグナル（SIGINT、SIGTERM、SIGHUP）で止められた場合も、作成中の一時ファイルは削除してから終了する。
+ path:       一時ファイルのパス
+ id:         シグナルで止められたときに削除するファイルの一覧での番号
+ persisted:  persistで名前を変えたかどうか
+ */
+pub(crate) struct TempFile {
+    path: PathBuf,
+    id: usize,
+    persisted: bool,
+}
+
+impl TempFile {
+    /*
+     dirの中に、nameを元にした他と重ならない名前（.name.tmpプロセス番号-番号）の一時ファイルを作成する
+     */
+    pub(crate) fn create_in(dir: &Path, name: &str) -> Result<(TempFile, File), io::Error> {
+        install_handlers();
+        loop {
+            let id = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!(".{}.tmp{}-{}", name, process::id(), id));
+
+            /*
+             作成した直後に止められても残らないように、作成する前に一覧に入れておく
+             */
+            register(id, &path);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((TempFile { path, id, persisted: false }, file)),
+                Err(err) => {
+                    unregister(id);
+                    if err.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+
+    /*
+     一時ファイルの名前をdestに変える。destが既にある場合は置き換える。
+     destが別のファイルシステムにあって名前を変えられない場合は、destと同じディレクトリに写してから名前を変える。
+     どちらの場合も、destが書き込み途中の状態になることはない。
+     */
+    pub(crate) fn persist(mut self, dest: &Path) -> Result<(), io::Error> {
+        if rename(&self.path, dest).is_ok() {
+            self.persisted = true;
+            return Ok(());
+        }
+        let name = dest.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let (mut near, _) = TempFile::create_in(&parent_dir(dest), &name)?;
+        copy(&self.path, &near.path)?;
+        rename(&near.path, dest)?;
+        near.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = remove_file(&self.path);
+        }
+        unregister(self.id);
+    }
+}
+
+/*
+ 一時ファイルを置くディレクトリを決める。
+ tmpdir（--tmpdir）、環境変数TMPDIR、nearのあるディレクトリ、OSの一時ディレクトリの順に見て、
+ archived（格納しているディレクトリ）の中にない最初のものを選ぶ。
+ */
+pub(crate) fn temp_dir(tmpdir: Option<&Path>, near: &Path, archived: &[PathBuf]) -> Result<PathBuf, io::Error> {
+    let candidates = tmpdir
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(env::var_os("TMPDIR").filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .chain(Some(parent_dir(near)))
+        .chain(Some(env::temp_dir()));
+    for dir in candidates {
+        if !is_inside(&dir, archived) {
+            return Ok(dir);
+        }
+    }
+    Err(io::Error::other("no temporary directory outside of the archived directories"))
+}
+
+/*
+ outputを書き込むための一時ファイルを作成してwriteに渡し、成功した場合には名前を変えてoutputにする。
+ 途中で失敗したり止められたりしても書き込み途中の壊れたzipは残らず、既にあったoutputもそのまま残る。
+ 一時ファイルはtemp_dirで選んだディレクトリに置く。
+ outputが通常のファイルでない場合（/dev/nullなど）は、置き換えずに直接書き込む。
+ */
+pub(crate) fn write_output<T, F>(output: &Path, tmpdir: Option<&Path>, archived: &[PathBuf], write: F) -> Result<T, ZipError>
+where
+    F: FnOnce(File) -> Result<T, ZipError>,
+{
+    let output = match symlink_metadata(output) {
+        Ok(metadata) if metadata.file_type().is_symlink() => output.canonicalize().unwrap_or_else(|_| output.to_path_buf()),
+        Ok(metadata) if !metadata.is_file() => return write(File::create(output)?),
+        _ => output.to_path_buf(),
+    };
+    let name = output.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let (temp, file) = TempFile::create_in(&temp_dir(tmpdir, &output, archived)?, &name)?;
+    let result = write(file)?;
+    temp.persist(&output)?;
+    Ok(result)
+}
+
+pub(crate) fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/*
+ dirがarchivedのどれかの中にあるかどうか（まだないディレクトリは、どれの中にもないものとする）
+ */
+fn is_inside(dir: &Path, archived: &[PathBuf]) -> bool {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    archived.iter().filter_map(|archived| archived.canonicalize().ok()).any(|archived| dir.starts_with(archived))
+}
+
+#[cfg(unix)]
+static LIVE: std::sync::Mutex<Vec<(usize, std::ffi::CString)>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(unix)]
+fn register(id: usize, path: &Path) {
+    use std::os::unix::ffi::OsStrExt;
+
+    if let (Ok(path), Ok(mut live)) = (std::ffi::CString::new(path.as_os_str().as_bytes()), LIVE.lock()) {
+        live.push((id, path));
+    }
+}
+
+#[cfg(unix)]
+fn unregister(id: usize) {
+    if let Ok(mut live) = LIVE.lock() {
+        live.retain(|(live_id, _)| *live_id != id);
+    }
+}
+
+/*
+ SIGINT、SIGTERM、SIGHUPで止められたときに一時ファイルを削除するハンドラーを、最初の一時ファイルを作るときに1度だけ設定する。
+ 既に他のハンドラーが設定されている（または無視するように設定されている）シグナルはそのままにする。
+ */
+#[cfg(unix)]
+fn install_handlers() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            unsafe {
+                let previous = libc::signal(signal, handler);
+                if previous != libc::SIG_DFL {
+                    libc::signal(signal, previous);
+                }
+            }
+        }
+    });
+}
+
+/*
+ シグナルハンドラーの中ではロックを待たず、メモリも確保しない。
+ 一時ファイルを削除した後は、元の動作に戻して同じシグナルで終了する。
+ */
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if let Ok(live) = LIVE.try_lock() {
+        for (_, path) in live.iter() {
+            unsafe {
+                libc::unlink(path.as_ptr());
+            }
+        }
+    }
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(not(unix))]
+fn register(_id: usize, _path: &Path) {}
+
+#[cfg(not(unix))]
+fn unregister(_id: usize) {}
+
+#[cfg(not(unix))]
+fn install_handlers() {}
@@ -0,0 +1,846 @@
+use std::borrow::Cow;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::Instant;
+
+use crate::aes;
+use crate::archive::{read16, END_SIGNATURE};
+use crate::error::{Context, Phase};
+use crate::extra::{encode_extra_fields, padding_field, MAX_EXTRA_LEN, MIN_PADDING_LEN};
+use crate::inflate::Inflater;
+use crate::limit::LimitedReader;
+use crate::names::encode_name;
+use crate::{compress, crc32, dos_time, read_stored, Arena, Compression, EncodeOptions, EntryOptions, EntryReport, Header, Profile, Placement, Report, Sink, ZipError, METHOD_DEFLATED, METHOD_STORED, PRINT_DEBUG, ZIP64_COUNT_MARK, ZIP64_MARK};
+
+/*
+ 別のスレッドで圧縮しておいたエントリ
+ file_size:  圧縮する前のサイズ
+ crc32:      圧縮する前のデータのcrc32
+ data:       圧縮したデータ
+ profile:    圧縮にかかった時間
+ cached:     圧縮せずにキャッシュから取り出したかどうか
+ */
+pub(crate) struct Compressed {
+    pub file_size: u64,
+    pub crc32: u32,
+    pub data: Vec<u8>,
+    pub profile: Profile,
+    pub cached: bool,
+}
+
+/*
+ エントリを書き込んだ後に、セントラルヘッダーへ書き込む値
+ external_attributes:  外部属性
+ comment:              エントリのコメントのバイト列
+ utf8:                 commentがUTF-8かどうか
+ */
+pub(crate) struct CentralFields {
+    pub external_attributes: u32,
+    pub comment: Vec<u8>,
+    pub utf8: bool,
+}
+
+/*
+ inputから読み込んだデータを圧縮して、書き込む前の状態で返す。
+ 圧縮したデータはarenaから取り出すため、arenaは次のエントリでもう一度確保し直すことになる。
+ */
+pub(crate) fn compress_entry<R: Read>(input: &mut R, name: &str, options: &EncodeOptions, arena: &mut Arena) -> Result<Compressed, ZipError> {
+    let mut profile = Profile::new();
+    let (file_size, crc32) = compress(input, options, arena, &mut profile, None)
+        .map_err(|err| ZipError::with_entry(err, name).with_context(name, Phase::Compress, None))?;
+    Ok(Compressed { file_size, crc32, data: std::mem::take(&mut arena.compressed), profile, cached: false })
+}
+
+/*
+ 複数のエントリを1つのzipとして書き込むためのもの
+ エントリごとにローカルヘッダーと圧縮したデータを書き込んでいき、
+ finishで最後にセントラルヘッダーとエンドセントラルヘッダーを書き込む。
+ finishを呼ばずにdropした場合も、それまでのエントリでセントラルヘッダーを書き込む（書き込めなかった場合のエラーは捨てる）。
+ ただし、エントリの書き込みの途中で失敗した場合やpanicした場合は、壊れたエントリを含むzipを正しく見せないように書き込まない。
+ output:    出力先（finishで取り出した後はNoneになり、それからは書き込めない）
+ offset:    今までに書き込んだバイト数（次のローカルヘッダーの位置）
+ central:   セントラルヘッダーを溜めておく
+ report:    それぞれのエントリの結果
+ poisoned:  エントリの書き込みの途中で失敗したかどうか
+ comment:   エンドセントラルヘッダーに書き込むzip全体のコメント
+ */
+pub(crate) struct ArchiveWriter<W: Write> {
+    output: Option<W>,
+    offset: u64,
+    central: Vec<u8>,
+    report: Report,
+    poisoned: bool,
+    comment: Vec<u8>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(output: W) -> Self {
+        ArchiveWriter {
+            output: Some(output),
+            offset: 0,
+            central: Vec::new(),
+            report: Report::new(),
+            poisoned: false,
+            comment: Vec::new(),
+        }
+    }
+
+    /*
+     zip全体のコメントを設定する（archive_commentで作ったもの）
+     */
+    pub fn with_comment(mut self, comment: Vec<u8>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /*
+     inputから読み込んだデータを圧縮し、nameという名前のエントリとして書き込む。
+     time:   zipの形式にした（時刻, 日付）。entry.modifiedが指定されている場合はそちらを使う。
+     entry:  エントリごとのオプション。拡張フィールドはデータを読み込む前に確認する。
+     options.streamingの場合は、暗号化しないエントリをstream_entryで書き込む。
+     */
+    pub fn add_entry<R: Read>(&mut self, input: &mut R, name: &str, time: (u16, u16), options: &EncodeOptions, entry: &EntryOptions, arena: &mut Arena) -> Result<(), ZipError> {
+        let entry = &*resolve_compression(entry, options, name);
+        let options = &*entry.encode_options(options);
+        let extra = encode_extra_fields(name, &entry.extra_fields)?;
+        let name_bytes = encode_name(name, options)?;
+        if options.streaming && entry.password.is_none() {
+            let (hms, ymd) = entry_time(time, entry, options);
+            let header = Header::new(0, 0, name, 0, hms, ymd)
+                .with_name_bytes(name_bytes, options.name_encoder.utf8())
+                .with_extra(extra)
+                .with_comment(encode_comment(entry, options, name)?);
+            return self.stream_entry(input, header, options, entry, arena);
+        }
+        let mut profile = Profile::new();
+        let compressed = match entry.compression {
+            Compression::Deflate => compress(input, options, arena, &mut profile, None),
+            Compression::Store => read_stored(input, arena, &mut profile, None),
+        };
+        let (file_size, crc32) = compressed
+            .map_err(|err| ZipError::with_entry(err, name).with_context(name, Phase::Compress, Some(self.offset)))?;
+        let (method, data) = match entry.compression {
+            Compression::Deflate => choose_method(&arena.compressed, file_size, entry, name)?,
+            Compression::Store => (METHOD_STORED, Cow::Borrowed(arena.compressed.as_slice())),
+        };
+        let (hms, ymd) = entry_time(time, entry, options);
+        let header = Header::new(file_size, data.len() as u64, name, crc32, hms, ymd)
+            .with_name_bytes(name_bytes, options.name_encoder.utf8())
+            .with_extra(extra)
+            .with_comment(encode_comment(entry, options, name)?)
+            .with_method(method);
+        let (header, data) = seal(header, &data, entry, name)?;
+        let header = self.place(header.with_external_attributes(external_attributes(entry)), entry.placement)?;
+        let offset = self.offset;
+        self.write_entry(header, &data, options.profile.then_some(profile)).context_at(name, Phase::Write, offset)?;
+        Ok(())
+    }
+
+    /*
+     inputから読み込んだデータを圧縮しながら、メモリに溜めずにそのまま書き込む（options.streamingの場合）。
+     ローカルヘッダーにはcrc32とサイズを書かずに、圧縮したデータの後ろにデータディスクリプタを書く。
+     header:  サイズとcrc32以外を設定したこのエントリのヘッダー
+     ローカルヘッダーを書き込んだ後で失敗した場合は、壊れたエントリが残るため、それからは書き込めないようにする。
+     */
+    fn stream_entry<R: Read>(&mut self, input: &mut R, header: Header, options: &EncodeOptions, entry: &EntryOptions, arena: &mut Arena) -> Result<(), ZipError> {
+        let method = match entry.compression {
+            Compression::Deflate => METHOD_DEFLATED,
+            Compression::Store => METHOD_STORED,
+        };
+        let header = header.with_method(method).with_descriptor(streams_zip64(options));
+        let header = self.place(header.with_external_attributes(external_attributes(entry)), entry.placement)?;
+        let offset = self.offset;
+        if self.poisoned {
+            return Err(Error::other("a previous entry failed to be written")).context_at(&header.filename, Phase::Write, offset);
+        }
+        let output = self.output.as_mut().ok_or_else(|| Error::other("the archive is already finished")).context_at(&header.filename, Phase::Write, offset)?;
+        let mut profile = Profile::new();
+        let (header, len) = match stream_data(output, input, header, offset, options, arena, &mut profile) {
+            Ok(streamed) => streamed,
+            Err(err) => {
+                self.poisoned = true;
+                return Err(err);
+            }
+        };
+        self.offset = offset + len;
+        self.record(header, offset, options.profile.then_some(profile));
+        Ok(())
+    }
+
+    /*
+     compress_entryで圧縮しておいたエントリを書き込む。
+     add_entryで書き込んだ場合と同じ内容になる。
+     */
+    pub fn add_compressed(&mut self, compressed: Compressed, name: &str, time: (u16, u16), options: &EncodeOptions, entry: &EntryOptions) -> Result<(), ZipError> {
+        let entry = &*resolve_compression(entry, options, name);
+        let extra = encode_extra_fields(name, &entry.extra_fields)?;
+        let name_bytes = encode_name(name, options)?;
+        let (method, data) = choose_method(&compressed.data, compressed.file_size, entry, name)?;
+        let (hms, ymd) = entry_time(time, entry, options);
+        let header = Header::new(compressed.file_size, data.len() as u64, name, compressed.crc32, hms, ymd)
+            .with_name_bytes(name_bytes, options.name_encoder.utf8())
+            .with_extra(extra)
+            .with_comment(encode_comment(entry, options, name)?)
+            .with_method(method);
+        let (header, data) = seal(header, &data, entry, name)?;
+        let header = self.place(header.with_external_attributes(external_attributes(entry)), entry.placement)?;
+        let offset = self.offset;
+        self.write_entry(header, &data, options.profile.then_some(compressed.profile)).context_at(name, Phase::Write, offset)?;
+        if let Some(report) = self.report.entries.last_mut() {
+            report.cached = compressed.cached;
+        }
+        Ok(())
+    }
+
+    /*
+     dataを圧縮せずに（STORED）、nameという名前のエントリとして書き込む。
+     既に圧縮されているzipなどを、さらに圧縮せずに格納する場合に使う。
+     */
+    pub fn add_stored(&mut self, data: &[u8], name: &str, time: (u16, u16), options: &EncodeOptions, entry: &EntryOptions) -> Result<(), ZipError> {
+        let extra = encode_extra_fields(name, &entry.extra_fields)?;
+        let name_bytes = encode_name(name, options)?;
+        let mut profile = Profile::new();
+        let crc_start = Instant::now();
+        let crc32 = crc32(data);
+        profile.crc = crc_start.elapsed();
+        let (hms, ymd) = entry_time(time, entry, options);
+        let size = data.len() as u64;
+        let header = Header::new(size, size, name, crc32, hms, ymd)
+            .with_name_bytes(name_bytes, options.name_encoder.utf8())
+            .with_extra(extra)
+            .with_comment(encode_comment(entry, options, name)?)
+            .with_method(METHOD_STORED);
+        let (header, data) = seal(header, data, entry, name)?;
+        let header = self.place(header.with_external_attributes(external_attributes(entry)), entry.placement)?;
+        let offset = self.offset;
+        self.write_entry(header, &data, options.profile.then_some(profile)).context_at(name, Phase::Write, offset)?;
+        Ok(())
+    }
+
+    /*
+     別のzipから取り出した圧縮済みの（暗号化されている場合は暗号化済みの）データを、展開せずにそのまま書き込む。
+     header:  crc32、サイズ、圧縮方法を元のエントリと同じにしたヘッダー（dataからは圧縮後のサイズだけ読む）
+     dataが圧縮後のサイズより先に終わった場合は、壊れたエントリが残るため、それからは書き込めないようにする。
+     */
+    pub fn add_raw<R: Read>(&mut self, header: Header, data: &mut R) -> Result<(), ZipError> {
+        let header = self.place(header, None)?;
+        let offset = self.offset;
+        let name = header.filename.clone();
+        if self.poisoned {
+            return Err(Error::other("a previous entry failed to be written")).context_at(&name, Phase::Write, offset);
+        }
+        let output = self.output.as_mut().ok_or_else(|| Error::other("the archive is already finished")).context_at(&name, Phase::Write, offset)?;
+        let local_header = header.clone().local_header();
+        let copied = output.write_all(&local_header).and_then(|_| std::io::copy(&mut data.take(header.after_size), output));
+        match copied {
+            Ok(len) if len == header.after_size => {}
+            Ok(len) => {
+                self.poisoned = true;
+                return Err(ZipError::InvalidArchive(format!("compressed data of {} is truncated at {} bytes", name, len)));
+            }
+            Err(err) => {
+                self.poisoned = true;
+                return Err(err).context_at(&name, Phase::Write, offset);
+            }
+        }
+        self.offset = offset + local_header.len() as u64 + header.after_size;
+        self.record(header, offset, None);
+        Ok(())
+    }
+
+    /*
+     add_rawと同じだが、圧縮後のサイズが前もって分からないデータを、dataの終わりまでそのまま写す。
+     ローカルヘッダーにはcrc32とサイズを書かずに、写し終えた後にデータディスクリプタを書く。
+     header:  サイズとcrc32以外を元のエントリと同じにしたヘッダー
+     zip64:   データディスクリプタのサイズを8バイトで書くかどうか
+     sizes:   dataを最後まで読んだ後に、展開後のサイズとcrc32を返す
+     */
+    pub fn stream_raw<R: Read>(&mut self, header: Header, data: &mut R, zip64: bool, sizes: impl FnOnce(&R) -> (u64, u32)) -> Result<(), ZipError> {
+        let header = self.place(header.with_descriptor(zip64), None)?;
+        let offset = self.offset;
+        let name = header.filename.clone();
+        if self.poisoned {
+            return Err(Error::other("a previous entry failed to be written")).context_at(&name, Phase::Write, offset);
+        }
+        let output = self.output.as_mut().ok_or_else(|| Error::other("the archive is already finished")).context_at(&name, Phase::Write, offset)?;
+        let local_header = header.clone().local_header();
+        let written = match output.write_all(&local_header).and_then(|_| std::io::copy(data, output)) {
+            Ok(written) => written,
+            Err(err) => {
+                self.poisoned = true;
+                return Err(err).context_at(&name, Phase::Write, offset);
+            }
+        };
+        let (size, crc32) = sizes(data);
+        let header = header.with_sizes(size, written, crc32);
+        let descriptor = header.clone().data_descriptor();
+        let finished = if header.zip64_sizes() && !header.descriptor_zip64 {
+            Err(ZipError::Unsupported(format!("{} is 4 GiB or more, but its data descriptor was started without ZIP64", name)))
+        } else if header.central_extra_len(offset) > MAX_EXTRA_LEN {
+            Err(ZipError::ExtraFieldTooLarge { name: name.clone(), size: header.central_extra_len(offset) })
+        } else {
+            output.write_all(&descriptor).context_at(&name, Phase::Write, offset)
+        };
+        if let Err(err) = finished {
+            self.poisoned = true;
+            return Err(err);
+        }
+        self.offset = offset + local_header.len() as u64 + written + descriptor.len() as u64;
+        self.record(header, offset, None);
+        Ok(())
+    }
+
+    /*
+     書き込んだエントリのセントラルヘッダーの外部属性とコメントを、書き込んだ順に並べたfieldsのうちSomeのものに書き換える。
+     ストリームから読み込んだzipを写す場合のように、属性やコメントがエントリを書き込んだ後にしか分からない場合に使う。
+     UTF-8のコメントにアスキーコード以外の文字が含まれている場合は、セントラルヘッダーの汎用フラグのbit11も立てる。
+     65535バイトより長いコメントは書き込めないため、元のコメントのままにする。
+     */
+    pub fn set_central_fields(&mut self, fields: &[Option<CentralFields>]) {
+        let mut central = Vec::with_capacity(self.central.len());
+        let mut pos = 0;
+        for field in fields {
+            let len = |at: usize| read16(&self.central, pos + at) as usize;
+            let comment_start = (pos + 46 + len(28) + len(30)).min(self.central.len());
+            let next = (comment_start + len(32)).min(self.central.len());
+            let start = central.len();
+            central.extend_from_slice(&self.central[pos..comment_start]);
+            match field {
+                Some(CentralFields { external_attributes: external, comment, utf8 }) if comment.len() <= u16::MAX as usize && comment_start - pos >= 46 => {
+                    if *utf8 && !comment.is_ascii() {
+                        let flags = read16(&central, start + 8) | 0x0800;
+                        central[start + 8..start + 10].copy_from_slice(&flags.to_le_bytes());
+                    }
+                    central[start + 38..start + 42].copy_from_slice(&external.to_le_bytes());
+                    central[start + 32..start + 34].copy_from_slice(&(comment.len() as u16).to_le_bytes());
+                    central.extend_from_slice(comment);
+                    self.report.features.unix_permissions |= external >> 16 != 0;
+                }
+                _ => central.extend_from_slice(&self.central[comment_start..next]),
+            }
+            pos = next;
+        }
+        central.extend_from_slice(&self.central[pos..]);
+        self.central = central;
+    }
+
+    /*
+     placementに合わせて、ローカルヘッダーに詰め物を入れる
+     詰め物は拡張フィールドとして入れるため、0バイトかMIN_PADDING_LENバイト以上でなければならない。
+     指定された位置を既に過ぎている場合や、詰め物が拡張フィールドに収まらない場合にはエラーにする。
+     ZIP64の拡張情報を加えると拡張フィールドが収まらなくなる場合は、ExtraFieldTooLargeにする。
+     */
+    fn place(&self, header: Header, placement: Option<Placement>) -> Result<Header, ZipError> {
+        let extra_len = header.local_extra_len().max(header.central_extra_len(self.offset));
+        if extra_len > MAX_EXTRA_LEN {
+            return Err(ZipError::ExtraFieldTooLarge { name: header.filename, size: extra_len });
+        }
+        let start = self.offset + header.local_header_len() as u64;
+        let (padding, alignment) = match placement {
+            None => return Ok(header),
+            Some(Placement::Offset(offset)) => {
+                if offset < start {
+                    return Err(ZipError::InvalidPlacement { name: header.filename, reason: format!("data would start at {}, after offset {}", start, offset) });
+                }
+                (offset - start, 0)
+            }
+            Some(Placement::Align(alignment)) => {
+                if alignment == 0 || alignment > u16::MAX as u64 {
+                    return Err(ZipError::InvalidPlacement { name: header.filename, reason: format!("alignment {} is out of range", alignment) });
+                }
+                let mut padding = (alignment - start % alignment) % alignment;
+                while padding != 0 && padding < MIN_PADDING_LEN as u64 {
+                    padding += alignment;
+                }
+                (padding, alignment as u16)
+            }
+        };
+        if padding == 0 {
+            return Ok(header);
+        }
+        if padding < MIN_PADDING_LEN as u64 {
+            return Err(ZipError::InvalidPlacement { name: header.filename, reason: format!("a gap of {} bytes is too small to fill", padding) });
+        }
+        if padding as usize + header.local_extra_len() > MAX_EXTRA_LEN {
+            return Err(ZipError::InvalidPlacement { name: header.filename, reason: format!("a gap of {} bytes does not fit in the extra field", padding) });
+        }
+        Ok(header.with_padding(padding_field(padding as usize, alignment)))
+    }
+
+    /*
+     ローカルヘッダーと圧縮したデータを書き込み、セントラルヘッダーと結果を溜めておく
+     header:   このエントリのヘッダー（圧縮後のサイズはdataの長さと同じにしておく）
+     profile:  --profileが指定されている場合だけSomeになる。書き込みにかかった時間はここで追加する。
+     */
+    fn write_entry(&mut self, header: Header, data: &[u8], mut profile: Option<Profile>) -> Result<(), Error> {
+        let write_start = Instant::now();
+        let local_header = header.clone().local_header();
+        let next_offset = self.offset + (local_header.len() + data.len()) as u64;
+
+        if PRINT_DEBUG {
+            for byte in data {
+                print!("{:08b}", byte);
+            }
+            println!();
+        }
+
+        if self.poisoned {
+            return Err(Error::other("a previous entry failed to be written"));
+        }
+        let output = self.output.as_mut().ok_or_else(|| Error::other("the archive is already finished"))?;
+        if let Err(err) = output.write_all(&local_header).and_then(|_| output.write_all(data)) {
+            self.poisoned = true;
+            return Err(err);
+        }
+        let offset = self.offset;
+        self.offset = next_offset;
+        if let Some(profile) = profile.as_mut() {
+            profile.write = write_start.elapsed();
+        }
+        self.record(header, offset, profile);
+        Ok(())
+    }
+
+    /*
+     書き込んだエントリのセントラルヘッダーと結果を溜めておく
+     offset:  このエントリのローカルヘッダーの位置
+     */
+    fn record(&mut self, header: Header, offset: u64, profile: Option<Profile>) {
+        self.central.extend_from_slice(&header.clone().central_header(offset));
+        let features = &mut self.report.features;
+        features.utf8_names |= header.flags() & 0x0800 != 0;
+        features.stored |= header.method == METHOD_STORED;
+        features.deflated |= header.method == METHOD_DEFLATED;
+        features.extra_fields |= !header.extra.is_empty() || !header.padding.is_empty();
+        features.unix_permissions |= header.external >> 16 != 0;
+        features.encrypted |= header.encrypted;
+        features.zip64 |= header.zip64_sizes() || header.descriptor_zip64 || offset >= ZIP64_MARK;
+        features.data_descriptors |= header.descriptor;
+
+        self.report.entries.push(EntryReport {
+            name: header.filename,
+            before_size: header.before_size,
+            after_size: header.after_size,
+            crc32: header.crc32,
+            profile,
+            changed: false,
+            retries: 0,
+            cut_at: None,
+            cached: false,
+        });
+    }
+
+    /*
+     セントラルヘッダーとエンドセントラルヘッダーを書き込んで、それぞれのエントリの結果を返す。
+     */
+    pub fn finish(self) -> Result<Report, Error> {
+        let (_, report) = self.finish_into_inner()?;
+        Ok(report)
+    }
+
+    /*
+     finishと同じだが、出力先も返す。メモリ上に作ったzipを取り出す場合に使う。
+     */
+    pub fn finish_into_inner(mut self) -> Result<(W, Report), Error> {
+        if self.poisoned {
+            return Err(Error::other("a previous entry failed to be written"));
+        }
+        let mut output = self.output.take().ok_or_else(|| Error::other("the archive is already finished"))?;
+        self.report.features.zip64 |= self.zip64_end();
+        self.write_central(&mut output)?;
+        Ok((output, std::mem::take(&mut self.report)))
+    }
+
+    /*
+     エントリの数が65535個以上か、セントラルヘッダーの位置か大きさが4GiB以上で、ZIP64のエンドセントラルヘッダーが要るかどうか
+     */
+    fn zip64_end(&self) -> bool {
+        self.report.entries.len() >= ZIP64_COUNT_MARK as usize || self.offset >= ZIP64_MARK || self.central.len() as u64 >= ZIP64_MARK
+    }
+
+    /*
+     溜めておいたセントラルヘッダーとエンドセントラルヘッダーをoutputに書き込む
+     ZIP64のエンドセントラルヘッダーが要る場合は、エンドセントラルヘッダーの前に書き込み、
+     エンドセントラルヘッダーの書けない値はffffやffffffffにする。
+     */
+    fn write_central(&self, output: &mut W) -> Result<(), Error> {
+        let count = self.report.entries.len() as u64;
+        let size = self.central.len() as u64;
+        output.write_all(&self.central)?;
+        if self.zip64_end() {
+            output.write_all(&Header::new(0, 0, "", 0, 0, 0).zip64_end_header(count, size, self.offset))?;
+        }
+        let end_header = Header::new(0, 0, "", 0, 0, 0)
+            .with_comment(self.comment.clone())
+            .end_header(count.min(ZIP64_COUNT_MARK as u64) as u16, size.min(ZIP64_MARK) as u32, self.offset.min(ZIP64_MARK) as u32);
+        output.write_all(&end_header)?;
+        output.flush()
+    }
+}
+
+impl<W: Write> Drop for ArchiveWriter<W> {
+    fn drop(&mut self) {
+        if self.poisoned || thread::panicking() {
+            return;
+        }
+        if let Some(mut output) = self.output.take() {
+            let _ = self.write_central(&mut output);
+        }
+    }
+}
+
+/*
+ ZipWriterの状態
+ NoEntryOpen:  エントリを開いていない（start_entryかfinishを呼べる）
+ EntryOpen:    エントリを開いていて、Writeでデータを書き込める（end_entryで閉じる）
+ Finished:     finishでセントラルヘッダーまで書き込み終わった（それからは何もできない）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriterState {
+    NoEntryOpen,
+    EntryOpen,
+    Finished,
+}
+
+/*
+ エントリを1つずつ開き、Writeでデータを書き込んでzipを作るためのもの
+ start_entryでエントリを開き、write_allなどでデータを書き込み、end_entryで圧縮してzipに書き込む。
+ 全てのエントリを書き込んだらfinishを呼ぶ。
+ エントリを開いたままstart_entryやfinishを呼んだ場合はEntryStillOpen、
+ エントリを開かずにend_entryや書き込みを行った場合はNoEntryOpen、finishの後に呼んだ場合はAlreadyFinishedを返す。
+ エラーを返した場合は状態を変えないため、state()で確かめてから続けられる。
+ エントリのデータはend_entryまでメモリ上に溜めておく。大きなデータはwrite_entryでReadから直接圧縮する。
+ finishを呼ばずにdropした場合は、閉じたエントリまででセントラルヘッダーを書き込む（開いたままのエントリは捨てる）。
+ archive:  書き込み先（finishで取り出した後はNone）
+ options:  全てのエントリで使うオプション
+ entry:    開いているエントリ
+ arena:    圧縮に使う作業用のバッファ
+ */
+pub struct ZipWriter<W: Write> {
+    archive: Option<ArchiveWriter<W>>,
+    options: EncodeOptions,
+    entry: Option<OpenEntry>,
+    arena: Arena,
+}
+
+/*
+ ZipWriterで開いているエントリ
+ name:     エントリの名前
+ time:     zipの形式にした（時刻, 日付）
+ options:  エントリごとのオプション
+ data:     今までに書き込まれたデータ
+ */
+struct OpenEntry {
+    name: String,
+    time: (u16, u16),
+    options: EntryOptions,
+    data: Vec<u8>,
+}
+
+/*
+ ZipWriter::finishが返すもの
+ 出力先とそれぞれのエントリの結果を持つ。捨てるとメモリ上に作ったzipなども捨てることになるため、must_useにしている。
+ output:  書き込み終わった出力先
+ report:  それぞれのエントリの結果
+ */
+#[must_use = "the finished archive is in the output held by Finished"]
+#[derive(Debug)]
+pub struct Finished<W> {
+    output: W,
+    report: Report,
+}
+
+impl<W> Finished<W> {
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    pub fn into_parts(self) -> (W, Report) {
+        (self.output, self.report)
+    }
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(output: W) -> Self {
+        ZipWriter::with_options(output, EncodeOptions::default())
+    }
+
+    /*
+     options.commentは最初からArchiveWriterに渡しておき、finishを呼ばずに捨てた場合にもエンドセントラルヘッダーに書かれるようにする。
+     書き込めないコメント（長すぎるものなど）は、finishでエラーにする。
+     */
+    pub fn with_options(output: W, options: EncodeOptions) -> Self {
+        let comment = archive_comment(&options).unwrap_or_default();
+        ZipWriter {
+            archive: Some(ArchiveWriter::new(output).with_comment(comment)),
+            options,
+            entry: None,
+            arena: Arena::new(),
+        }
+    }
+
+    pub fn state(&self) -> WriterState {
+        match (&self.archive, &self.entry) {
+            (None, _) => WriterState::Finished,
+            (Some(_), Some(_)) => WriterState::EntryOpen,
+            (Some(_), None) => WriterState::NoEntryOpen,
+        }
+    }
+
+    /*
+     nameという名前のエントリを開く。日時はoptions.clockの現在時刻になる。
+     */
+    pub fn start_entry(&mut self, name: &str, options: EntryOptions) -> Result<(), ZipError> {
+        self.check_no_entry()?;
+        let time = dos_time(self.options.clock.now(), &*self.options.clock);
+        self.entry = Some(OpenEntry { name: name.to_string(), time, options, data: Vec::new() });
+        Ok(())
+    }
+
+    /*
+     開いているエントリを圧縮してzipに書き込み、閉じる。
+     圧縮や書き込みに失敗した場合もエントリは閉じる。
+     */
+    pub fn end_entry(&mut self) -> Result<(), ZipError> {
+        let archive = self.archive.as_mut().ok_or(ZipError::AlreadyFinished)?;
+        let entry = self.entry.take().ok_or(ZipError::NoEntryOpen)?;
+        let mut input = LimitedReader::new(entry.data.as_slice(), self.options.max_entry_size, self.options.entry_timeout);
+        archive.add_entry(&mut input, &entry.name, entry.time, &self.options, &entry.options, &mut self.arena)
+    }
+
+    /*
+     inputから読み込んだデータを、nameという名前のエントリとして書き込む（start_entry、write、end_entryをまとめたもの）。
+     データをメモリ上に溜めずに読み込みながら圧縮するため、ファイルやソケットなど大きなデータを書き込む場合に使う。
+     */
+    pub fn write_entry<R: Read>(&mut self, name: &str, options: EntryOptions, input: &mut R) -> Result<(), ZipError> {
+        self.check_no_entry()?;
+        let archive = self.archive.as_mut().ok_or(ZipError::AlreadyFinished)?;
+        let time = dos_time(self.options.clock.now(), &*self.options.clock);
+        let mut input = LimitedReader::new(input, self.options.max_entry_size, self.options.entry_timeout);
+        archive.add_entry(&mut input, name, time, &self.options, &options, &mut self.arena)
+    }
+
+    /*
+     メモリ上のdataを、nameという名前のエントリとして書き込む。プログラムで作ったデータをファイルにせずに入れる場合に使う。
+     エントリごとのオプションを指定する場合はwrite_entryを使う。
+     */
+    pub fn add_bytes(&mut self, name: &str, mut data: &[u8]) -> Result<(), ZipError> {
+        self.write_entry(name, EntryOptions::default(), &mut data)
+    }
+
+    /*
+     readerから最後まで読み込んだデータを、nameという名前のエントリとして書き込む。
+     ソケットや別のプロセスの出力など、長さが前もって分からないものでも、crc32とサイズは読み込みながら計算する。
+     &mut dyn Readもそのまま渡せる。エントリごとのオプションを指定する場合はwrite_entryを使う。
+     */
+    pub fn add_from_reader<R: Read + ?Sized>(&mut self, name: &str, mut reader: &mut R) -> Result<(), ZipError> {
+        self.write_entry(name, EntryOptions::default(), &mut reader)
+    }
+
+    /*
+     セントラルヘッダーとエンドセントラルヘッダーを書き込み、出力先と結果を返す
+     */
+    pub fn finish(&mut self) -> Result<Finished<W>, ZipError> {
+        self.check_no_entry()?;
+        let comment = archive_comment(&self.options)?;
+        let archive = self.archive.take().ok_or(ZipError::AlreadyFinished)?;
+        let (output, report) = archive.with_comment(comment).finish_into_inner()?;
+        Ok(Finished { output, report })
+    }
+
+    fn check_no_entry(&self) -> Result<(), ZipError> {
+        if self.archive.is_none() {
+            return Err(ZipError::AlreadyFinished);
+        }
+        match &self.entry {
+            Some(entry) => Err(ZipError::EntryStillOpen(entry.name.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for ZipWriter<W> {
+    /*
+     開いているエントリにデータを追加する。エントリを開いていない場合はエラーの中にZipErrorを入れて返す。
+     */
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let err = match (&self.archive, self.entry.as_mut()) {
+            (Some(_), Some(entry)) => {
+                entry.data.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+            (None, _) => ZipError::AlreadyFinished,
+            (Some(_), None) => ZipError::NoEntryOpen,
+        };
+        Err(Error::new(ErrorKind::InvalidInput, err))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/*
+ エントリの日時を返す。entry.modifiedが指定されている場合はそれを、なければtimeを使う。
+ */
+fn entry_time(time: (u16, u16), entry: &EntryOptions, options: &EncodeOptions) -> (u16, u16) {
+    entry.modified.map_or(time, |modified| dos_time(modified, &*options.clock))
+}
+
+/*
+ stream_entryで、ローカルヘッダー、圧縮したデータ、データディスクリプタをoutputに書き込む。
+ サイズとcrc32を設定したヘッダーと、書き込んだバイト数を返す。
+ offset:  このエントリのローカルヘッダーの位置（エラーに含める）
+ headerの圧縮方法がMETHOD_STOREDの場合は、読み込んだデータをそのまま書き込む。
+ */
+fn stream_data<W: Write, R: Read>(output: &mut W, input: &mut R, header: Header, offset: u64, options: &EncodeOptions, arena: &mut Arena, profile: &mut Profile) -> Result<(Header, u64), ZipError> {
+    let name = header.filename.clone();
+    let local_header = header.clone().local_header();
+    output.write_all(&local_header).context_at(&name, Phase::Write, offset)?;
+    let mut sink = Sink::new(output);
+    let compressed = if header.method == METHOD_STORED {
+        read_stored(input, arena, profile, Some(&mut sink))
+    } else {
+        compress(input, options, arena, profile, Some(&mut sink))
+    };
+    let (file_size, crc32) = compressed
+        .map_err(|err| ZipError::with_entry(err, &name).with_context(&name, Phase::Compress, Some(offset)))?;
+    let written = sink.written();
+    let header = header.with_sizes(file_size, written, crc32);
+    if header.zip64_sizes() && !header.descriptor_zip64 {
+        return Err(ZipError::Unsupported(format!("{} grew to 4 GiB or more, but its data descriptor was started without ZIP64", name)));
+    }
+    let extra_len = header.central_extra_len(offset);
+    if extra_len > MAX_EXTRA_LEN {
+        return Err(ZipError::ExtraFieldTooLarge { name, size: extra_len });
+    }
+    let write_start = Instant::now();
+    let descriptor = header.clone().data_descriptor();
+    output.write_all(&descriptor).context_at(&name, Phase::Write, offset)?;
+    profile.write = write_start.elapsed();
+    Ok((header, local_header.len() as u64 + written + descriptor.len() as u64))
+}
+
+/*
+ データディスクリプタのサイズを8バイトで書くかどうか
+ max_entry_sizeが指定されていない場合や、圧縮して少し大きくなった場合に4GiBに届くかもしれない場合は8バイトにする。
+ */
+fn streams_zip64(options: &EncodeOptions) -> bool {
+    options.max_entry_size.is_none_or(|max| max.saturating_add(max / 8 + 1024) >= ZIP64_MARK)
+}
+
+/*
+ entry.commentをセントラルヘッダーに書き込むバイト列にする（指定されていない場合は空）
+ */
+fn encode_comment(entry: &EntryOptions, options: &EncodeOptions, name: &str) -> Result<Vec<u8>, ZipError> {
+    let comment = match &entry.comment {
+        Some(comment) => encode_name(comment, options)?,
+        None => return Ok(Vec::new()),
+    };
+    if comment.len() > u16::MAX as usize {
+        return Err(ZipError::Unsupported(format!("comment of {} is {} bytes, more than the limit of 65535 bytes", name, comment.len())));
+    }
+    Ok(comment)
+}
+
+/*
+ options.commentをエンドセントラルヘッダーに書き込むバイト列にする（指定されていない場合は空）
+ 展開するソフトはエンドセントラルヘッダーを後ろから探すため、その印（PK0506）を含むコメントはエラーにする。
+ */
+pub(crate) fn archive_comment(options: &EncodeOptions) -> Result<Vec<u8>, ZipError> {
+    let comment = match &options.comment {
+        Some(comment) => encode_name(comment, options)?,
+        None => return Ok(Vec::new()),
+    };
+    if comment.len() > u16::MAX as usize {
+        return Err(ZipError::Unsupported(format!("the archive comment is {} bytes, more than the limit of 65535 bytes", comment.len())));
+    }
+    if comment.windows(4).any(|window| window == END_SIGNATURE.to_le_bytes()) {
+        return Err(ZipError::Unsupported("the archive comment contains the end of central directory signature".to_string()));
+    }
+    Ok(comment)
+}
+
+/*
+ nameの拡張子がoptions.stored_extensionsに含まれる場合は、entryのcompressionをStoreにしたものを返す
+ */
+fn resolve_compression<'a>(entry: &'a EntryOptions, options: &EncodeOptions, name: &str) -> Cow<'a, EntryOptions> {
+    if entry.compression == Compression::Deflate && options.stores_extension(name) {
+        return Cow::Owned(entry.clone().compression(Compression::Store));
+    }
+    Cow::Borrowed(entry)
+}
+
+/*
+ deflateで圧縮したdataから、エントリをどの方法で格納するかを決め、格納するデータを返す。
+ entry.compressionがStoreの場合や、圧縮しても元のfile_sizeより小さくならなかった場合は、
+ 展開し直した元のデータをMETHOD_STOREDで格納する（dataより小さいため、展開してもメモリは増えない）。
+ */
+fn choose_method<'a>(data: &'a [u8], file_size: u64, entry: &EntryOptions, name: &str) -> Result<(u16, Cow<'a, [u8]>), ZipError> {
+    if entry.compression == Compression::Deflate && (data.len() as u64) < file_size {
+        return Ok((METHOD_DEFLATED, Cow::Borrowed(data)));
+    }
+    let mut raw = Vec::with_capacity(file_size as usize);
+    Inflater::new(data).read_to_end(&mut raw).context(name, Phase::Compress)?;
+    Ok((METHOD_STORED, Cow::Owned(raw)))
+}
+
+/*
+ entry.passwordが指定されている場合は、dataをAESで暗号化し、headerを暗号化したエントリのものにする。
+ 指定されていない場合は、headerとdataをそのまま返す。
+ */
+fn seal<'a>(header: Header, data: &'a [u8], entry: &EntryOptions, name: &str) -> Result<(Header, Cow<'a, [u8]>), ZipError> {
+    let password = match &entry.password {
+        Some(password) => password,
+        None => return Ok((header, Cow::Borrowed(data))),
+    };
+    let (encrypted, aes_extra) = aes::encrypt(password.as_bytes(), header.method, data);
+    if header.extra.len() + aes_extra.len() > MAX_EXTRA_LEN {
+        return Err(ZipError::ExtraFieldTooLarge { name: name.to_string(), size: header.extra.len() + aes_extra.len() });
+    }
+    Ok((header.with_encryption(encrypted.len() as u64, &aes_extra), Cow::Owned(encrypted)))
+}
+
+/*
+ エントリごとのオプションからセントラルヘッダーの外部属性を決める。属性が指定されていない場合は0にする。
+ */
+fn external_attributes(entry: &EntryOptions) -> u32 {
+    entry.permissions.map_or(0, |permissions| permissions.external_attributes())
+}
+
+/*
+ 呼び出す側が用意したバッファに書き込むためのもの
+ バッファに収まらない分は捨てるが、必要なバイト数は数え続ける。
+ buffer:    書き込み先
+ required:  今までに書き込もうとしたバイト数
+ */
+pub(crate) struct SliceWriter<'a> {
+    pub buffer: &'a mut [u8],
+    pub required: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        SliceWriter { buffer, required: 0 }
+    }
+}
+
+impl Write for SliceWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.required < self.buffer.len() {
+            let len = buf.len().min(self.buffer.len() - self.required);
+            self.buffer[self.required..self.required + len].copy_from_slice(&buf[..len]);
+        }
+        self.required += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
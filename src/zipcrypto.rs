@@ -0,0 +1,286 @@
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind, Read};
+
+use crate::aes::{AesInfo, AesStream, AUTH_CODE_SIZE, VERIFIER_SIZE};
+use crate::archive::into_zip_error;
+use crate::ZipError;
+
+/*
+ 暗号化されたデータの先頭に付いている暗号化ヘッダーの大きさ
+ */
+const ENCRYPTION_HEADER_SIZE: usize = 12;
+
+/*
+ 汎用フラグのうち暗号化に関するもの（bit0: 暗号化されている, bit6: 強力な暗号化）と、
+ WinZipのAES暗号化で使われる圧縮方法の番号（実際の圧縮方法はAESの拡張フィールドに書かれている）
+ */
+pub(crate) const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_STRONG_ENCRYPTION: u16 = 0x0040;
+const METHOD_AES: u16 = 99;
+
+/*
+ 鍵の更新に使うcrc32の表（crc32関数とは違い、途中の値から1byteずつ進める必要があるため別に持つ）
+ */
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+}
+
+/*
+ PKWAREの従来の暗号（ZipCrypto）の3つの鍵
+ パスワードで初期化し、復号した1byteごとに更新する。
+ */
+#[derive(Clone)]
+struct Keys {
+    keys: [u32; 3],
+}
+
+impl Keys {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = Keys { keys: [0x1234_5678, 0x2345_6789, 0x3456_7890] };
+        for &byte in password {
+            keys.update(byte);
+        }
+        keys
+    }
+
+    fn update(&mut self, byte: u8) {
+        let [key0, key1, key2] = &mut self.keys;
+        *key0 = crc32_update(*key0, byte);
+        *key1 = key1.wrapping_add(*key0 & 0xff).wrapping_mul(134_775_813).wrapping_add(1);
+        *key2 = crc32_update(*key2, (*key1 >> 24) as u8);
+    }
+
+    fn decrypt(&mut self, byte: u8) -> u8 {
+        let temp = (self.keys[2] | 2) as u16;
+        let plain = byte ^ (temp.wrapping_mul(temp ^ 1) >> 8) as u8;
+        self.update(plain);
+        plain
+    }
+
+    /*
+     暗号化ヘッダーを復号し、最後の1byteがcheckと合うかを確かめる
+     checkはcrc32の上位8bit（データディスクリプタを使うエントリでは更新時刻の上位8bit）。
+     */
+    fn check_header(&mut self, header: &[u8; ENCRYPTION_HEADER_SIZE], check: u8, name: &str) -> Result<(), ZipError> {
+        let mut last = 0;
+        for &byte in header {
+            last = self.decrypt(byte);
+        }
+        if last != check {
+            return Err(ZipError::WrongPassword(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/*
+ エントリの暗号化の方法
+ ZipCrypto:  PKWAREの従来の暗号。暗号化ヘッダーの最後の1byteと比べる値を持つ
+ Aes:        WinZipのAES暗号（AE-1、AE-2）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encryption {
+    ZipCrypto(u8),
+    Aes(AesInfo),
+}
+
+impl Encryption {
+    /*
+     暗号化する前の実際の圧縮方法（AESでは、ヘッダーのmethodは99になっている）
+     */
+    pub(crate) fn method(&self, method: u16) -> u16 {
+        match self {
+            Encryption::ZipCrypto(_) => method,
+            Encryption::Aes(info) => info.method,
+        }
+    }
+
+    /*
+     展開したデータのcrc32を確かめるかどうか（AE-2ではcrc32が書かれておらず、認証コードだけで確かめる）
+     */
+    pub(crate) fn checks_crc(&self) -> bool {
+        !matches!(self, Encryption::Aes(AesInfo { version: 2, .. }))
+    }
+
+    /*
+     復号した後に起きたerrを、パスワードが違うためとみなすかどうか。
+     ZipCryptoでは暗号化ヘッダーの1byteしか確かめられず、違うパスワードでも256回に1回は通ってしまうため、
+     その後でdeflateのデータが壊れていたりcrc32が合わなかったりした場合はパスワードが違うとみなす。
+     AESでは2byteの値と認証コードで確かめられるため、そのままにする。
+     */
+    pub(crate) fn blames_password(&self, err: &ZipError) -> bool {
+        matches!((self, err), (Encryption::ZipCrypto(_), ZipError::InvalidDeflate(_) | ZipError::CrcMismatch { .. }))
+    }
+}
+
+/*
+ 復号に使う鍵
+ ZipCrypto:  従来の暗号の3つの鍵
+ Aes:        AESの鍵と、認証コードを除いた残りのデータのバイト数、認証コードを確かめたかどうか
+ */
+enum Cipher {
+    ZipCrypto(Keys),
+    Aes { stream: Box<AesStream>, remaining: u64, verified: bool, name: String },
+}
+
+/*
+ 暗号化されたデータを復号しながら読み出すためのもの
+ inner:   暗号化されたデータ
+ cipher:  復号に使う鍵（暗号化されていないエントリではNoneにして、そのまま読み出す）
+ */
+pub(crate) struct Decrypter<R: Read> {
+    inner: R,
+    cipher: Option<Cipher>,
+}
+
+impl<R: Read> Decrypter<R> {
+    /*
+     暗号化されていないデータをそのまま読み出す
+     */
+    pub(crate) fn plain(inner: R) -> Self {
+        Decrypter { inner, cipher: None }
+    }
+
+    /*
+     innerの先頭（ZipCryptoでは暗号化ヘッダー、AESではソルトとパスワードを確かめる値）を読んでpasswordが合っているかを確かめ、
+     その後ろのデータを復号しながら読み出す。lenは暗号化されたデータ全体の長さ。
+     パスワードが違う場合はWrongPasswordにする。
+     AESでは最後まで読み出したときに認証コードを確かめ、合わない場合はAuthenticationFailedにする。
+     */
+    pub(crate) fn new(mut inner: R, password: &[u8], encryption: &Encryption, len: u64, name: &str) -> Result<Self, ZipError> {
+        let cipher = match encryption {
+            Encryption::ZipCrypto(check) => {
+                let mut header = [0u8; ENCRYPTION_HEADER_SIZE];
+                inner.read_exact(&mut header)?;
+                let mut keys = Keys::new(password);
+                keys.check_header(&header, *check, name)?;
+                Cipher::ZipCrypto(keys)
+            }
+            Encryption::Aes(info) => {
+                let remaining = len.checked_sub(info.overhead())
+                    .ok_or_else(|| ZipError::InvalidArchive(format!("encrypted data of {} is too short", name)))?;
+                let mut salt = vec![0; info.salt_len()];
+                let mut verifier = [0u8; VERIFIER_SIZE];
+                inner.read_exact(&mut salt)?;
+                inner.read_exact(&mut verifier)?;
+                let stream = Box::new(AesStream::new(password, info, &salt, verifier, name)?);
+                Cipher::Aes { stream, remaining, verified: false, name: name.to_string() }
+            }
+        };
+        Ok(Decrypter { inner, cipher: Some(cipher) })
+    }
+
+    /*
+     読み込み元を返す（エントリを先頭から読み直すときに使う）
+     */
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Decrypter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.cipher {
+            None => self.inner.read(buf),
+            Some(Cipher::ZipCrypto(keys)) => {
+                let len = self.inner.read(buf)?;
+                for byte in buf.iter_mut().take(len) {
+                    *byte = keys.decrypt(*byte);
+                }
+                Ok(len)
+            }
+            Some(Cipher::Aes { stream, remaining, verified, name }) => {
+                let limit = buf.len().min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                if limit == 0 {
+                    /*
+                     データを読み終えたら、続く認証コードを読んで確かめる
+                     */
+                    if !buf.is_empty() && !*verified {
+                        let mut code = [0u8; AUTH_CODE_SIZE];
+                        self.inner.read_exact(&mut code)?;
+                        stream.verify(&code, name).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                        *verified = true;
+                    }
+                    return Ok(0);
+                }
+                let len = self.inner.read(&mut buf[..limit])?;
+                if len == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                stream.decrypt(&mut buf[..len]);
+                *remaining -= len as u64;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/*
+ メモリ上の暗号化されたデータ（暗号化ヘッダーなどを含む）を復号する
+ */
+pub(crate) fn decrypt(data: &[u8], password: &[u8], encryption: &Encryption, name: &str) -> Result<Vec<u8>, ZipError> {
+    let mut decrypter = Decrypter::new(data, password, encryption, data.len() as u64, name)?;
+    let mut plain = Vec::with_capacity(data.len());
+    decrypter.read_to_end(&mut plain).map_err(into_zip_error)?;
+    Ok(plain)
+}
+
+/*
+ メモリ上の暗号化されたデータの先頭だけを読んで、passwordが合っているかを確かめる（何も復号しない）。
+ 展開先に何か書き込む前に、全てのエントリのパスワードを確かめるのに使う。
+ */
+pub(crate) fn check_password(data: &[u8], password: &[u8], encryption: &Encryption, name: &str) -> Result<(), ZipError> {
+    Decrypter::new(data, password, encryption, data.len() as u64, name).map(|_| ())
+}
+
+/*
+ flagsのエントリを読むのに使うパスワードを返す。暗号化されていないエントリではNoneを返す。
+ 暗号化されているのにpasswordがない場合はPasswordRequired、強力な暗号化（PKWAREのもの）の場合はUnsupportedにする。
+ */
+pub(crate) fn password_for<'a>(flags: u16, password: Option<&'a [u8]>, name: &str) -> Result<Option<&'a [u8]>, ZipError> {
+    if flags & FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    if flags & FLAG_STRONG_ENCRYPTION != 0 {
+        return Err(ZipError::Unsupported(format!("strong encryption of {}", name)));
+    }
+    password.map(Some).ok_or_else(|| ZipError::PasswordRequired(name.to_string()))
+}
+
+/*
+ エントリの暗号化の方法を調べる。暗号化されていないエントリではNoneを返す。
+ methodが99の場合はextraのAESの拡張フィールドを読み、それ以外はZipCryptoとする。
+ ZipCryptoで暗号化ヘッダーの最後の1byteと比べる値は、crc32の上位8bitにする。
+ データディスクリプタを使うエントリは、書き込む時点でcrc32が分からないため更新時刻の上位8bitを使う。
+ */
+pub(crate) fn encryption(flags: u16, method: u16, crc32: u32, dos_time: u16, extra: &[u8], name: &str) -> Result<Option<Encryption>, ZipError> {
+    if flags & FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    if method == METHOD_AES {
+        return match AesInfo::from_extra(extra, name)? {
+            Some(info) => Ok(Some(Encryption::Aes(info))),
+            None => Err(ZipError::InvalidArchive(format!("AES extra field of {} is missing", name))),
+        };
+    }
+    let check = if flags & 0x0008 != 0 { (dos_time >> 8) as u8 } else { (crc32 >> 24) as u8 };
+    Ok(Some(Encryption::ZipCrypto(check)))
+}
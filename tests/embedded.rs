@@ -0,0 +1,24 @@
+/*
+ include_zip!で埋め込んだzipを読む結合テスト
+ */
+use std::fs::read;
+
+use zipper::EmbeddedZip;
+
+static TEST: &EmbeddedZip = zipper::include_zip!("../test_data/test.zip");
+
+#[test]
+fn reads_embedded_entries() {
+    let expected = read("test_data/test.txt").unwrap();
+    assert_eq!(TEST.read("test.txt").unwrap(), expected);
+    assert_eq!(TEST.archive().unwrap().len(), 1);
+    assert!(TEST.read("missing.txt").is_err());
+}
+
+#[test]
+fn opens_at_the_call_site() {
+    let embedded = zipper::include_zip!("../test_data/test1.zip");
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut embedded.open("test1.txt").unwrap(), &mut data).unwrap();
+    assert_eq!(data, read("test_data/test1.txt").unwrap());
+}
@@ -0,0 +1,303 @@
+/*
+ TestTreeでファイルを作ってzipにし、展開した結果が元と同じになるかを調べる結合テスト
+ （cargo test --features zipper-test-support で実行する）
+ */
+use std::fs::{read, write};
+
+use zipper::{apply_delta, build, delta, normalize, parse_extra_fields, recompress_stream, Compression, DecodeOptions, EncodeOptions, EntryOptions, TestTree, ZipArchive, ZipError, ZipWriter};
+
+#[test]
+fn files_and_directories() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("readme.txt", "hello")?
+        .file("docs/guide/intro.md", "# intro\n".repeat(100))?
+        .file("empty", "")?
+        .dir("docs/images")?
+        .file("docs/images/logo.svg", "<svg/>")?;
+    tree.assert_round_trip()
+}
+
+#[test]
+fn modes_and_times() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("bin/run.sh", "#!/bin/sh\n")?
+        .mode("bin/run.sh", 0o755)?
+        .mtime("bin/run.sh", 1_600_000_000)?
+        .file("config/secret", "key")?
+        .mode("config/secret", 0o600)?
+        .file("config/readonly", "fixed")?
+        .mode("config/readonly", 0o444)?
+        .mtime("config/readonly", 315_532_800)?;
+    tree.assert_round_trip()
+}
+
+#[test]
+fn non_ascii_names() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("日本語/ファイル.txt", "内容")?
+        .file("with space/a b.txt", "spaces")?
+        .file("émoji 🎉.txt", "party")?;
+    tree.assert_round_trip()
+}
+
+#[test]
+fn reproducible_archives_are_identical() -> Result<(), ZipError> {
+    let mut first = TestTree::new()?;
+    let mut second = TestTree::new()?;
+    for tree in [&mut first, &mut second] {
+        tree.file("a.txt", "same")?.file("dir/b.txt", "contents")?;
+    }
+    first.mtime("a.txt", 1_000_000_000)?;
+    second.mtime("a.txt", 1_500_000_000)?;
+    let first_zip = first.zip_with(build().reproducible(true))?;
+    let second_zip = second.zip_with(build().reproducible(true))?;
+    assert_eq!(read(first_zip)?, read(second_zip)?);
+    Ok(())
+}
+
+#[test]
+fn entries_match_the_tree() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("b.txt", "b")?.file("a/c.txt", "c")?;
+    let zip = tree.zip()?;
+    let archive = ZipArchive::open(&zip)?;
+    let mut names: Vec<&str> = archive.iter().map(|entry| entry.name.as_str()).filter(|name| !name.ends_with('/')).collect();
+    names.sort_unstable();
+    assert_eq!(names, ["a/c.txt", "b.txt"]);
+    Ok(())
+}
+
+#[test]
+fn differences_are_reported() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("a.txt", "original")?;
+    let zip = tree.zip()?;
+    let dest = tree.extract(&zip)?;
+    write(dest.join("a.txt"), "changed")?;
+    write(dest.join("extra.txt"), "")?;
+    let differences = tree.differences(&dest);
+    assert_eq!(differences.len(), 2, "{:?}", differences);
+    assert!(differences[0].starts_with("a.txt: contents differ"));
+    assert!(differences[1].starts_with("extra.txt: unexpected file"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn special_bits_are_not_extracted() -> Result<(), ZipError> {
+    use std::fs::metadata;
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut tree = TestTree::new()?;
+    tree.file("tool", "#!/bin/sh\n")?.mode("tool", 0o4755)?;
+    let zip = tree.zip()?;
+    let dest = tree.extract(&zip)?;
+    assert_eq!(metadata(dest.join("tool"))?.permissions().mode() & 0o7777, 0o755);
+    tree.assert_extracted(&dest);
+    Ok(())
+}
+
+#[test]
+fn archive_in_memory() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("stored.txt", "kept as is")?.file("deflated.txt", "compressed ".repeat(100))?;
+    let data = read(tree.zip_with(build().store("stored.txt"))?)?;
+    let mut archive = ZipArchive::from_slice(&data)?;
+    assert!(archive.verify().is_empty());
+    assert_eq!(archive.read_to_vec("deflated.txt")?, "compressed ".repeat(100).as_bytes());
+
+    /*
+     無圧縮のエントリは、渡したデータの一部がそのまま返る
+     */
+    let stored = archive.index_of("stored.txt").unwrap();
+    let slice = archive.stored_slice(stored)?.unwrap();
+    assert_eq!(slice, b"kept as is");
+    assert!(data.as_ptr_range().contains(&slice.as_ptr()));
+    assert_eq!(archive.stored_slice(archive.index_of("deflated.txt").unwrap())?, None);
+
+    let dest = tree.scratch("memory");
+    archive.extract_all_with(&dest, &DecodeOptions { jobs: 2, ..DecodeOptions::new() })?;
+    tree.assert_extracted(&dest);
+    Ok(())
+}
+
+#[test]
+fn dropped_writer_keeps_comment() -> Result<(), ZipError> {
+    let mut data = Vec::new();
+    {
+        let options = EncodeOptions { comment: Some("hello".to_string()), ..EncodeOptions::new() };
+        let mut writer = ZipWriter::with_options(&mut data, options);
+        writer.add_bytes("a.txt", b"a")?;
+    }
+    let mut archive = ZipArchive::from_slice(&data)?;
+    assert_eq!(archive.comment(), "hello");
+    assert_eq!(archive.read_to_vec("a.txt")?, b"a");
+    Ok(())
+}
+
+#[test]
+fn normalized_archives_are_identical() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("b.txt", "b ".repeat(100))?.file("a/c.txt", "c")?.mtime("a/c.txt", 1_600_000_000)?;
+    let zip = tree.zip()?;
+    let first = tree.scratch("first.zip");
+    let second = tree.scratch("second.zip");
+    normalize(zip.to_str().unwrap(), first.to_str().unwrap())?;
+    normalize(first.to_str().unwrap(), second.to_str().unwrap())?;
+    assert_eq!(read(&first)?, read(&second)?);
+
+    /*
+     エントリは名前の順に並び、展開した結果は元と同じになる
+     */
+    let archive = ZipArchive::open(&first)?;
+    let names: Vec<&str> = archive.iter().map(|entry| entry.name.as_str()).collect();
+    let mut sorted = names.clone();
+    sorted.sort_unstable();
+    assert_eq!(names, sorted);
+    tree.assert_extracted(&tree.extract(&first)?);
+    Ok(())
+}
+
+#[test]
+fn applied_delta_restores_tree() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("keep.txt", "keep")?.file("edit.txt", "before")?.file("gone.txt", "gone")?.file("old/a.txt", "a")?.file("swap", "file")?;
+    let previous = tree.zip()?;
+    let dest = tree.extract(&previous)?;
+
+    /*
+     内容を変え、ファイルを消し、ディレクトリとファイルを入れ替え、新しいファイルを作る
+     */
+    tree.file("edit.txt", "after")?.remove("gone.txt")?.remove("old")?.file("old", "now a file")?;
+    tree.remove("swap")?.file("swap/inner.txt", "inner")?.file("new/b.txt", "b")?;
+    let patch = tree.scratch("delta.zip");
+    delta(previous.to_str().unwrap(), tree.root(), patch.to_str().unwrap())?;
+    let archive = ZipArchive::open(&patch)?;
+    let names: Vec<&str> = archive.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(names, [".zipper-delta.json", "edit.txt", "new/b.txt", "old", "swap/inner.txt"]);
+
+    apply_delta(patch.to_str().unwrap(), &dest)?;
+    tree.assert_extracted(&dest);
+    Ok(())
+}
+
+#[test]
+fn recompressed_stream_keeps_contents() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("data.json", "{}\n".repeat(200))?.file("notes.txt", "notes ".repeat(200))?.mode("notes.txt", 0o600)?;
+    let input = read(tree.zip()?)?;
+    let mut output = Vec::new();
+    recompress_stream(input.as_slice(), &mut output, &["*.json".to_string()], Compression::Store, &EncodeOptions::new())?;
+
+    /*
+     当てはまったエントリだけが無圧縮になり、他は元のデータのまま写される
+     */
+    let original = ZipArchive::from_slice(&input)?;
+    let mut archive = ZipArchive::from_slice(&output)?;
+    assert_eq!(archive.by_name("data.json").unwrap().method, 0);
+    let (before, after) = (original.by_name("notes.txt").unwrap(), archive.by_name("notes.txt").unwrap());
+    assert_eq!((after.method, after.compressed_size, after.crc32), (before.method, before.compressed_size, before.crc32));
+    assert_eq!(after.permissions, before.permissions);
+    let dest = tree.scratch("recompressed");
+    archive.extract_all(&dest)?;
+    tree.assert_extracted(&dest);
+    Ok(())
+}
+
+#[test]
+fn recompressed_stream_keeps_entry_comments() -> Result<(), ZipError> {
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.write_entry("data.json", EntryOptions::new().comment("json"), &mut "{}\n".repeat(200).as_bytes())?;
+    writer.write_entry("notes.txt", EntryOptions::new().comment("メモ"), &mut "notes ".repeat(200).as_bytes())?;
+    writer.write_entry("plain.txt", EntryOptions::new(), &mut &b"plain"[..])?;
+    let input = writer.finish()?.into_inner();
+    let mut output = Vec::new();
+    recompress_stream(input.as_slice(), &mut output, &["*.json".to_string()], Compression::Store, &EncodeOptions::new())?;
+
+    /*
+     圧縮し直したエントリにも、そのまま写したエントリにも元のコメントが残る
+     */
+    let mut archive = ZipArchive::from_slice(&output)?;
+    assert!(archive.verify().is_empty());
+    let comments: Vec<&str> = archive.iter().map(|entry| entry.comment.as_str()).collect();
+    assert_eq!(comments, ["json", "メモ", ""]);
+    Ok(())
+}
+
+#[test]
+fn streamed_entries_use_data_descriptors()-> Result<(), ZipError> {
+    let mut writer = ZipWriter::with_options(Vec::new(), EncodeOptions { streaming: true, ..EncodeOptions::new() });
+    writer.write_entry("text.txt", EntryOptions::new(), &mut "streamed ".repeat(100).as_bytes())?;
+    writer.write_entry("empty", EntryOptions::new(), &mut &b""[..])?;
+    let data = writer.finish()?.into_inner();
+
+    /*
+     crc32とサイズはデータディスクリプタに書かれ（汎用フラグのbit3）、読み込むとセントラルヘッダーの値と合う
+     */
+    let mut archive = ZipArchive::from_slice(&data)?;
+    assert!(archive.iter().all(|entry| entry.flags & 0x0008 != 0));
+    assert!(archive.verify().is_empty());
+    assert_eq!(archive.read_to_vec("text.txt")?, "streamed ".repeat(100).as_bytes());
+    assert_eq!(archive.read_to_vec("empty")?, b"");
+    Ok(())
+}
+
+#[test]
+fn encrypted_and_plain_entries_mix() -> Result<(), ZipError> {
+    let mut tree = TestTree::new()?;
+    tree.file("secret.txt", "secret ".repeat(50))?.file("public.txt", "public")?;
+    let zip = tree.zip_with(build().encrypt("secret.txt", "pw"))?;
+    let mut archive = ZipArchive::open(&zip)?;
+    assert!(archive.by_name("secret.txt").unwrap().is_encrypted());
+    assert!(!archive.by_name("public.txt").unwrap().is_encrypted());
+
+    /*
+     パスワードがなくても暗号化していないエントリは読め、パスワードを渡すと全て展開できる
+     */
+    assert_eq!(archive.read_to_vec("public.txt")?, b"public");
+    assert!(archive.read_to_vec("secret.txt").is_err());
+    let dest = tree.scratch("decrypted");
+    archive.extract_all_with(&dest, &DecodeOptions { password: Some("pw".to_string()), ..DecodeOptions::new() })?;
+    tree.assert_extracted(&dest);
+    Ok(())
+}
+
+#[test]
+fn more_than_65535_entries() -> Result<(), ZipError> {
+    let mut writer = ZipWriter::new(Vec::new());
+    for i in 0..70_000 {
+        writer.add_bytes(&format!("{}.txt", i), b"")?;
+    }
+    let data = writer.finish()?.into_inner();
+
+    /*
+     エントリの数はエンドセントラルヘッダーに書けないため、ZIP64のエンドセントラルヘッダーとその位置を書く
+     */
+    let zip64_end = [0x50, 0x4b, 0x06, 0x06];
+    let zip64_locator = [0x50, 0x4b, 0x06, 0x07];
+    let tail = &data[data.len() - 200..];
+    assert!(tail.windows(4).any(|window| window == zip64_end));
+    assert!(tail.windows(4).any(|window| window == zip64_locator));
+    let archive = ZipArchive::from_slice(&data)?;
+    assert_eq!(archive.entries().len(), 70_000);
+    assert_eq!(archive.entries()[69_999].name, "69999.txt");
+    Ok(())
+}
+
+#[test]
+fn entries_past_4_gib() -> Result<(), ZipError> {
+    let tree = TestTree::new()?;
+    let zip = tree.sparse_zip(0x1_0000_0000 + 100, "after.txt", b"past 4 GiB")?;
+    let mut archive = ZipArchive::open(&zip)?;
+
+    /*
+     4GiBを超えるサイズと位置はZIP64の拡張情報に書かれる
+     */
+    let gap = archive.by_name("gap").unwrap();
+    assert_eq!(gap.size, 0x1_0000_0000 + 100);
+    let after = archive.by_name("after.txt").unwrap();
+    assert!(after.offset > 0xffff_ffff);
+    assert!(parse_extra_fields(&after.extra)?.iter().any(|field| field.id == 0x0001));
+    assert_eq!(archive.read_to_vec("after.txt")?, b"past 4 GiB");
+    Ok(())
+}